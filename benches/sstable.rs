@@ -6,7 +6,7 @@ use std::fs;
 use bencher::Bencher;
 use rand::random;
 
-use sstable::{SSIterator, Table, TableBuilder, TableIterator};
+use sstable::{SSIterator, Table, TableBuilder};
 
 fn random_string(n: usize) -> String {
     let mut v = vec![0; n];
@@ -17,18 +17,18 @@ fn random_string(n: usize) -> String {
 }
 
 fn write_tmp_table(entries: usize) {
-    let mut v = vec![(String::new(), String::new()); entries];
-    for i in 0..entries {
-        v[i] = (random_string(16), random_string(16));
-    }
+    let mut v: Vec<(String, String)> = (0..entries)
+        .map(|_| (random_string(16), random_string(16)))
+        .collect();
     v.sort();
 
     let dst = fs::OpenOptions::new()
         .create(true)
+        .truncate(true)
         .write(true)
         .open("/tmp/.sstabletestfile")
         .unwrap();
-    let mut opt = sstable::Options::default();
+    let opt = sstable::Options::default();
     let mut tb = TableBuilder::new(opt, dst);
 
     for (k, v) in v {
@@ -38,13 +38,13 @@ fn write_tmp_table(entries: usize) {
 }
 
 fn rm_tmp_table() {
-    fs::remove_file("/tmp/.sstabletestfile");
+    let _ = fs::remove_file("/tmp/.sstabletestfile");
 }
 
 fn bench_write(b: &mut Bencher) {
-    let N = 100000;
+    let n = 100000;
     b.iter(|| {
-        write_tmp_table(N);
+        write_tmp_table(n);
         rm_tmp_table();
     });
 }
@@ -66,6 +66,7 @@ fn bench_read(b: &mut Bencher) {
             count += k.len() + v.len();
             entries += 1;
         }
+        bencher::black_box((count, entries));
     });
 }
 
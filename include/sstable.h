@@ -0,0 +1,150 @@
+#ifndef SSTABLE_H
+#define SSTABLE_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// The operation succeeded.
+#define SSTABLE_OK 0
+
+// The key wasn't found, or the iterator is exhausted.
+#define SSTABLE_NOT_FOUND 1
+
+// An I/O error or other unspecified error occurred.
+#define SSTABLE_ERROR -1
+
+// An argument was invalid (e.g. a NULL pointer or keys added out of order).
+#define SSTABLE_INVALID_ARGUMENT -2
+
+// The table is corrupt.
+#define SSTABLE_CORRUPTION -3
+
+// Opaque handle of a table builder writing to a file.
+typedef struct SstableBuilder SstableBuilder;
+
+// Opaque handle of an iterator over a table.
+typedef struct SstableIterator SstableIterator;
+
+// Opaque handle of an open table.
+typedef struct SstableTable SstableTable;
+
+// Returns the message of the last error that occurred on the calling thread, or NULL. The
+// returned string is valid until the next failing call on the same thread.
+const char *sstable_last_error(void);
+
+// Creates (or truncates) the file at `path` and returns a builder writing a table to it, or NULL
+// on error.
+//
+// # Safety
+//
+// `path` must be NULL or a valid NUL-terminated string.
+struct SstableBuilder *sstable_builder_open(const char *path);
+
+// Adds an entry to the table. Keys must be added in ascending order.
+//
+// # Safety
+//
+// `builder` must be a handle returned by `sstable_builder_open()`; `key` and `val` must point to
+// at least `key_len` and `val_len` readable bytes.
+int sstable_builder_add(struct SstableBuilder *builder,
+                        const uint8_t *key,
+                        size_t key_len,
+                        const uint8_t *val,
+                        size_t val_len);
+
+// Writes the remaining blocks and the footer, and releases the builder (whether successful or
+// not). If `size` is not NULL, the size of the written table is stored there.
+//
+// # Safety
+//
+// `builder` must be a handle returned by `sstable_builder_open()`; it must not be used afterwards.
+int sstable_builder_finish(struct SstableBuilder *builder, uint64_t *size);
+
+// Releases a builder without finishing the table.
+//
+// # Safety
+//
+// `builder` must be NULL or a handle returned by `sstable_builder_open()`; it must not be used
+// afterwards.
+void sstable_builder_free(struct SstableBuilder *builder);
+
+// Opens the table stored in the file at `path`. Returns NULL on error.
+//
+// # Safety
+//
+// `path` must be NULL or a valid NUL-terminated string.
+struct SstableTable *sstable_table_open(const char *path);
+
+// Looks up `key`. If it is found, `SSTABLE_OK` is returned and a newly allocated copy of the
+// value is stored in `val`/`val_len`; it must be released using `sstable_value_free()`. If the
+// key doesn't exist, `SSTABLE_NOT_FOUND` is returned.
+//
+// # Safety
+//
+// `table` must be a handle returned by `sstable_table_open()`; `key` must point to at least
+// `key_len` readable bytes; `val` and `val_len` must be valid for writes.
+int sstable_table_get(const struct SstableTable *table,
+                      const uint8_t *key,
+                      size_t key_len,
+                      uint8_t **val,
+                      size_t *val_len);
+
+// Releases a value returned by `sstable_table_get()`.
+//
+// # Safety
+//
+// `val` and `val_len` must have been returned by `sstable_table_get()`, and the value must not
+// have been released before.
+void sstable_value_free(uint8_t *val, size_t val_len);
+
+// Closes a table. Iterators created from it remain valid.
+//
+// # Safety
+//
+// `table` must be NULL or a handle returned by `sstable_table_open()`; it must not be used
+// afterwards.
+void sstable_table_free(struct SstableTable *table);
+
+// Returns an iterator positioned before the first entry of `table`, or NULL if `table` is NULL.
+//
+// # Safety
+//
+// `table` must be NULL or a handle returned by `sstable_table_open()`.
+struct SstableIterator *sstable_iter_new(const struct SstableTable *table);
+
+// Positions the iterator so that the next call to `sstable_iter_next()` returns the first entry
+// with a key greater than or equal to `key`. Fails if the block holding that entry can't be read,
+// e.g. with `SSTABLE_CORRUPTION`.
+//
+// # Safety
+//
+// `iter` must be a handle returned by `sstable_iter_new()`; `key` must point to at least
+// `key_len` readable bytes.
+int sstable_iter_seek(struct SstableIterator *iter, const uint8_t *key, size_t key_len);
+
+// Advances the iterator and stores pointers to the current key and value in the output
+// arguments. Returns `SSTABLE_NOT_FOUND` once the iterator is exhausted, and an error code such
+// as `SSTABLE_CORRUPTION` if a block can't be read. The returned pointers stay valid until the
+// next call on this iterator.
+//
+// # Safety
+//
+// `iter` must be a handle returned by `sstable_iter_new()`; all other arguments must be valid
+// for writes.
+int sstable_iter_next(struct SstableIterator *iter,
+                      const uint8_t **key,
+                      size_t *key_len,
+                      const uint8_t **val,
+                      size_t *val_len);
+
+// Releases an iterator.
+//
+// # Safety
+//
+// `iter` must be NULL or a handle returned by `sstable_iter_new()`; it must not be used
+// afterwards.
+void sstable_iter_free(struct SstableIterator *iter);
+
+#endif  /* SSTABLE_H */
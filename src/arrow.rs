@@ -0,0 +1,253 @@
+//! Export of tables to Apache Arrow, enabled by the `arrow` feature.
+//!
+//! `Table::to_arrow()` scans a table and returns its entries as a sequence of `RecordBatch`es, to
+//! be handed to columnar engines like DataFusion or Polars. Every batch has a binary `key` column,
+//! followed by the columns a `SchemaMapper` decodes from the values. Batches are produced one at
+//! a time as the iterator is advanced, so tables of any size can be streamed; wrapping the
+//! iterator (e.g. in `futures::stream::iter()`) turns it into an asynchronous stream.
+
+#![cfg(feature = "arrow")]
+
+use crate::error::{Error, Result};
+use crate::options::ReadOptions;
+use crate::table_reader::{Table, TableIterator};
+use crate::types::SSIterator;
+
+use std::sync::Arc;
+
+use arrow_array::builder::BinaryBuilder;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+/// The name of the key column of exported batches.
+pub const KEY_COLUMN: &str = "key";
+
+/// A SchemaMapper decodes the values of a table into columns.
+pub trait SchemaMapper: Send + Sync {
+    /// Returns the fields of the columns decoded from values.
+    fn fields(&self) -> Vec<Field>;
+    /// Decodes `values` into one array per field returned by `fields()`, each holding an element
+    /// per value.
+    fn map_values(&self, values: &[Vec<u8>]) -> Result<Vec<ArrayRef>>;
+}
+
+/// Exports values unchanged, as a single binary column named `value`.
+pub struct RawValues;
+
+impl SchemaMapper for RawValues {
+    fn fields(&self) -> Vec<Field> {
+        vec![Field::new("value", DataType::Binary, false)]
+    }
+
+    fn map_values(&self, values: &[Vec<u8>]) -> Result<Vec<ArrayRef>> {
+        Ok(vec![binary_array(values)])
+    }
+}
+
+fn binary_array(values: &[Vec<u8>]) -> ArrayRef {
+    let mut b = BinaryBuilder::with_capacity(values.len(), values.iter().map(Vec::len).sum());
+    for v in values {
+        b.append_value(v);
+    }
+    Arc::new(b.finish())
+}
+
+/// An iterator over the entries of a table as Arrow record batches. See `Table::to_arrow()`.
+pub struct ArrowBatches {
+    iter: TableIterator,
+    mapper: Arc<dyn SchemaMapper>,
+    schema: SchemaRef,
+    batch_size: usize,
+    // Set once the table has been read completely, or an error occurred.
+    done: bool,
+}
+
+impl ArrowBatches {
+    /// Returns the schema of the batches.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let (mut keys, mut values) = (vec![], vec![]);
+        let (mut key, mut val) = (vec![], vec![]);
+        while keys.len() < self.batch_size {
+            // An exhausted iterator would start over when advanced again.
+            if !self.iter.advance() {
+                self.done = true;
+                break;
+            }
+            self.iter.current(&mut key, &mut val);
+            keys.push(key.clone());
+            values.push(val.clone());
+        }
+        if let Some(e) = self.iter.take_error() {
+            return Err(e);
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+
+        let mut columns = vec![binary_array(&keys)];
+        columns.extend(self.mapper.map_values(&values)?);
+        RecordBatch::try_new(self.schema.clone(), columns)
+            .map(Some)
+            .map_err(|e| Error::InvalidArgument(format!("can't build record batch: {}", e)))
+    }
+}
+
+impl Iterator for ArrowBatches {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let batch = self.next_batch().transpose();
+        if !matches!(batch, Some(Ok(_))) {
+            self.done = true;
+        }
+        batch
+    }
+}
+
+impl Table {
+    /// Returns an iterator over the entries of the table as Arrow record batches of up to
+    /// `batch_size` rows: the keys in a binary column named `key` (see `KEY_COLUMN`), followed by
+    /// the columns `mapper` decodes from the values. Values are resolved as by `TableIterator`.
+    /// The scan doesn't fill the block cache (see `ReadOptions::fill_cache`). Iteration stops
+    /// after the first error.
+    pub fn to_arrow(&self, mapper: Arc<dyn SchemaMapper>, batch_size: usize) -> ArrowBatches {
+        let mut fields = vec![Field::new(KEY_COLUMN, DataType::Binary, false)];
+        fields.extend(mapper.fields());
+        let ropt = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        ArrowBatches {
+            iter: self.iter_opt(&ropt),
+            mapper,
+            schema: Arc::new(Schema::new(fields)),
+            batch_size: batch_size.max(1),
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::test_util::build_table;
+
+    use arrow_array::{Array, BinaryArray, UInt32Array};
+
+    /// Decodes values holding a little-endian u32 and a string.
+    struct CountAndName;
+
+    impl SchemaMapper for CountAndName {
+        fn fields(&self) -> Vec<Field> {
+            vec![
+                Field::new("count", DataType::UInt32, false),
+                Field::new("name", DataType::Binary, false),
+            ]
+        }
+
+        fn map_values(&self, values: &[Vec<u8>]) -> Result<Vec<ArrayRef>> {
+            let mut counts = vec![];
+            let mut names = vec![];
+            for v in values {
+                if v.len() < 4 {
+                    return Err(Error::InvalidArgument("value too short".to_string()));
+                }
+                counts.push(u32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+                names.push(v[4..].to_vec());
+            }
+            Ok(vec![
+                Arc::new(UInt32Array::from(counts)),
+                binary_array(&names),
+            ])
+        }
+    }
+
+    fn build(n: u32) -> Table {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let entries: Vec<(String, Vec<u8>)> = (0..n)
+            .map(|i| {
+                let mut v = i.to_le_bytes().to_vec();
+                v.extend_from_slice(format!("name{}", i).as_bytes());
+                (format!("key{:03}", i), v)
+            })
+            .collect();
+        build_table(&opt, &entries)
+    }
+
+    #[test]
+    fn test_arrow_export() {
+        let table = build(25);
+        let batches: Vec<RecordBatch> = table
+            .to_arrow(Arc::new(CountAndName), 10)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            vec![10, 10, 5]
+        );
+        let last = &batches[2];
+        assert_eq!(last.schema().field(0).name(), KEY_COLUMN);
+        let keys = last
+            .column(0)
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .unwrap();
+        assert_eq!(keys.value(0), b"key020");
+        let counts = last
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(counts.value(4), 24);
+        let names = last
+            .column(2)
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .unwrap();
+        assert_eq!(names.value(4), b"name24");
+
+        let raw: Vec<RecordBatch> = table
+            .to_arrow(Arc::new(RawValues), 100)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].num_columns(), 2);
+        assert_eq!(raw[0].num_rows(), 25);
+        assert_eq!(build(0).to_arrow(Arc::new(RawValues), 10).count(), 0);
+    }
+
+    #[test]
+    fn test_arrow_export_errors() {
+        /// Claims two columns, but returns one.
+        struct Mismatched;
+
+        impl SchemaMapper for Mismatched {
+            fn fields(&self) -> Vec<Field> {
+                let mut fields = RawValues.fields();
+                fields.push(Field::new("extra", DataType::Binary, false));
+                fields
+            }
+
+            fn map_values(&self, values: &[Vec<u8>]) -> Result<Vec<ArrayRef>> {
+                RawValues.map_values(values)
+            }
+        }
+
+        let table = build(25);
+        let mut batches = table.to_arrow(Arc::new(Mismatched), 10);
+        assert!(matches!(
+            batches.next(),
+            Some(Err(Error::InvalidArgument(_)))
+        ));
+        assert!(batches.next().is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Separation of large values. In tables written with `Options::blob_threshold` set, values
+//! larger than the threshold are written as separate blocks ("blobs") outside of the data blocks,
+//! and the data blocks only store a pointer to them. This keeps data blocks small and dense,
+//! which makes the block cache more effective.
+//!
+//! In such tables, every stored value starts with a tag byte: `TAG_INLINE` is followed by the
+//! value itself, `TAG_BLOB` by the encoded `BlockHandle` of the blob.
+
+use crate::blockhandle::BlockHandle;
+use crate::error::{Error, Result};
+
+pub const TAG_INLINE: u8 = 0;
+pub const TAG_BLOB: u8 = 1;
+
+/// A value as stored in a data block of a table with separated values.
+pub enum StoredValue<'a> {
+    Inline(&'a [u8]),
+    Blob(BlockHandle),
+}
+
+/// Appends an inline value to `dst`.
+pub fn encode_inline(val: &[u8], dst: &mut Vec<u8>) {
+    dst.push(TAG_INLINE);
+    dst.extend_from_slice(val);
+}
+
+/// Appends a pointer to the blob at `handle` to `dst`.
+pub fn encode_pointer(handle: &BlockHandle, dst: &mut Vec<u8>) {
+    let mut buf = [0; 16];
+    let n = handle.encode_to(&mut buf);
+    dst.push(TAG_BLOB);
+    dst.extend_from_slice(&buf[..n]);
+}
+
+/// Decodes a stored value.
+pub fn decode(stored: &[u8]) -> Result<StoredValue<'_>> {
+    match stored.first() {
+        Some(&TAG_INLINE) => Ok(StoredValue::Inline(&stored[1..])),
+        Some(&TAG_BLOB) => Ok(StoredValue::Blob(BlockHandle::decode(&stored[1..])?.0)),
+        _ => Err(Error::malformed(None, "bad value tag")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_encode_decode() {
+        let mut buf = vec![];
+        encode_inline(b"abc", &mut buf);
+        match decode(&buf).unwrap() {
+            StoredValue::Inline(v) => assert_eq!(v, b"abc"),
+            _ => panic!("expected inline value"),
+        }
+
+        buf.clear();
+        encode_pointer(&BlockHandle::new(1234, 1 << 20), &mut buf);
+        match decode(&buf).unwrap() {
+            StoredValue::Blob(h) => assert_eq!((h.offset(), h.size()), (1234, 1 << 20)),
+            _ => panic!("expected blob pointer"),
+        }
+
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[7, 1]).is_err());
+    }
+}
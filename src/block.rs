@@ -2,6 +2,8 @@ use std::cmp::Ordering;
 
 use std::sync::Arc;
 
+use crate::coding;
+use crate::error::{Error, Result};
 use crate::options::Options;
 use crate::types::SSIterator;
 
@@ -67,13 +69,66 @@ impl Block {
         assert!(contents.len() > 4);
         Block {
             block: Arc::new(contents),
-            opt: opt,
+            opt,
         }
     }
+
+    /// Like `new()`, but for contents read from a file: fails with `Error::Corruption` unless the
+    /// restart array and the headers of all entries are consistent with the size of the block, so
+    /// that iterating over it can't read beyond its entries.
+    pub fn try_new(opt: Options, contents: BlockContents) -> Result<Block> {
+        validate(&contents)?;
+        Ok(Block::new(opt, contents))
+    }
+}
+
+/// Checks the layout of a block (see `Block`).
+fn validate(block: &[u8]) -> Result<()> {
+    let malformed = |msg| Err(Error::malformed(None, msg));
+    if block.len() <= 4 {
+        return malformed("block too short");
+    }
+    let num_restarts = coding::get_fixed32(&block[block.len() - 4..])? as usize;
+    let restarts_off = match (block.len() - 4).checked_sub(num_restarts.saturating_mul(4)) {
+        Some(off) => off,
+        None => return malformed("bad number of restarts"),
+    };
+    let mut restarts = block[restarts_off..block.len() - 4].chunks(4);
+    let mut next_restart = restarts.next();
+
+    let (mut offset, mut key_len) = (0, 0);
+    while offset < restarts_off {
+        let entry = &block[offset..restarts_off];
+        let (shared, n1) = coding::get_varint32(entry)?;
+        let (non_shared, n2) = coding::get_varint32(&entry[n1..])?;
+        let (val_size, n3) = coding::get_varint32(&entry[n1 + n2..])?;
+        let size = (n1 + n2 + n3) as u64 + u64::from(non_shared) + u64::from(val_size);
+        if shared as usize > key_len || size > entry.len() as u64 {
+            return malformed("bad block entry");
+        }
+        if let Some(restart) = next_restart {
+            if coding::get_fixed32(restart)? as usize == offset {
+                if shared != 0 {
+                    return malformed("restart point at entry with shared key");
+                }
+                next_restart = restarts.next();
+            }
+        }
+        key_len = (shared + non_shared) as usize;
+        offset += size as usize;
+    }
+    // Empty blocks still have a restart point, at the end of their (no) entries.
+    for restart in next_restart.into_iter().chain(restarts) {
+        if coding::get_fixed32(restart)? as usize != restarts_off {
+            return malformed("restart point not at an entry");
+        }
+    }
+    Ok(())
 }
 
 /// BlockIter is an iterator over the entries in a block. It doesn't depend on the Block's
 /// lifetime, as it uses a refcounted block underneath.
+#[derive(Clone)]
 pub struct BlockIter {
     /// The underlying block contents.
     block: Arc<BlockContents>,
@@ -132,13 +187,15 @@ impl BlockIter {
     /// Advances self.offset to the beginning of the next entry.
     fn parse_entry_and_advance(&mut self) -> (usize, usize, usize, usize) {
         let mut i = 0;
-        let (shared, sharedlen) = usize::decode_var(&self.block[self.offset..]);
+        let (shared, sharedlen) = usize::decode_var(&self.block[self.offset..]).unwrap_or((0, 0));
         i += sharedlen;
 
-        let (non_shared, non_sharedlen) = usize::decode_var(&self.block[self.offset + i..]);
+        let (non_shared, non_sharedlen) =
+            usize::decode_var(&self.block[self.offset + i..]).unwrap_or((0, 0));
         i += non_sharedlen;
 
-        let (valsize, valsizelen) = usize::decode_var(&self.block[self.offset + i..]);
+        let (valsize, valsizelen) =
+            usize::decode_var(&self.block[self.offset + i..]).unwrap_or((0, 0));
         i += valsizelen;
 
         self.val_offset = self.offset + i + non_shared;
@@ -150,9 +207,9 @@ impl BlockIter {
     /// Assemble the current key from shared and non-shared parts (an entry usually contains only
     /// the part of the key that is different from the previous key).
     ///
-    /// `off` is the offset of the key string within the whole block (self.current_entry_offset
-    /// + entry header length); `shared` and `non_shared` are the lengths of the shared
-    /// respectively non-shared parts of the key.
+    /// `off` is the offset of the key string within the whole block (self.current_entry_offset +
+    /// entry header length); `shared` and `non_shared` are the lengths of the shared respectively
+    /// non-shared parts of the key.
     /// Only self.key is mutated.
     fn assemble_key(&mut self, off: usize, shared: usize, non_shared: usize) {
         self.key.truncate(shared);
@@ -160,6 +217,29 @@ impl BlockIter {
             .extend_from_slice(&self.block[off..off + non_shared]);
     }
 
+    /// Returns the value of the current entry without copying it.
+    pub fn current_value(&self) -> Option<&[u8]> {
+        if self.valid() {
+            Some(&self.block[self.val_offset..self.offset])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the contents of the block the iterator runs over.
+    pub fn contents(&self) -> Arc<BlockContents> {
+        self.block.clone()
+    }
+
+    /// Returns the offset and length of the current value within the block.
+    pub fn current_value_range(&self) -> Option<(usize, usize)> {
+        if self.valid() {
+            Some((self.val_offset, self.offset - self.val_offset))
+        } else {
+            None
+        }
+    }
+
     pub fn seek_to_last(&mut self) {
         if self.number_restarts() > 0 {
             let num_restarts = self.number_restarts();
@@ -256,15 +336,18 @@ impl SSIterator for BlockIter {
             self.number_restarts() - 1
         };
 
-        // Do a binary search over the restart points.
+        // Do a binary search over the restart points. Comparators may be expensive, so remember
+        // whether the key at `left` is known to be less than `to` already.
+        let mut left_is_less = false;
         while left < right {
-            let middle = (left + right + 1) / 2;
+            let middle = (left + right).div_ceil(2);
             self.seek_to_restart_point(middle);
 
             let c = self.opt.cmp.cmp(&self.key, to);
 
             if c == Ordering::Less {
                 left = middle;
+                left_is_less = true;
             } else {
                 right = middle - 1;
             }
@@ -275,8 +358,11 @@ impl SSIterator for BlockIter {
         self.offset = self.get_restart_point(left);
 
         // Linear search from here on
-        while let Some((k, _)) = self.next() {
-            if self.opt.cmp.cmp(k.as_slice(), to) >= Ordering::Equal {
+        if left_is_less {
+            self.advance();
+        }
+        while self.advance() {
+            if self.opt.cmp.cmp(&self.key, to) >= Ordering::Equal {
                 return;
             }
         }
@@ -311,6 +397,7 @@ impl SSIterator for BlockIter {
 mod tests {
     use super::*;
     use crate::block_builder::BlockBuilder;
+    use crate::cmp::CountingCmp;
     use crate::test_util::{test_iterator_properties, SSIteratorIter};
     use crate::types::{current_key_val, SSIterator};
 
@@ -343,6 +430,27 @@ mod tests {
         test_iterator_properties(block);
     }
 
+    #[test]
+    fn test_block_seek_comparisons() {
+        let mut o = Options::default();
+        o.block_restart_interval = 4;
+        o.cmp = Arc::new(Box::new(CountingCmp::new(o.cmp.clone())));
+        let mut builder = BlockBuilder::new(o.clone());
+        let key = |i: usize| format!("key{:02}", i).into_bytes();
+        for i in 0..64 {
+            builder.add(&key(i), b"value");
+        }
+        let mut iter = Block::new(o, builder.finish()).iter();
+
+        // 16 restart points take 4 comparisons to binary-search; the key at the restart point
+        // found isn't compared again.
+        for i in (5..64).step_by(4) {
+            let ((), n) = CountingCmp::count(|| iter.seek(&key(i)));
+            assert_eq!(iter.current_key(), Some(&key(i)[..]));
+            assert_eq!(n, 5);
+        }
+    }
+
     #[test]
     fn test_block_empty() {
         let mut o = Options::default();
@@ -355,9 +463,40 @@ mod tests {
 
         let block = Block::new(Options::default(), blockc);
 
-        for _ in SSIteratorIter::wrap(&mut block.iter()) {
-            panic!("expected 0 iterations");
+        assert_eq!(SSIteratorIter::wrap(&mut block.iter()).count(), 0);
+    }
+
+    #[test]
+    fn test_block_try_new() {
+        let mut o = Options::default();
+        o.block_restart_interval = 2;
+        let mut builder = BlockBuilder::new(o.clone());
+        for &(k, v) in get_data().iter() {
+            builder.add(k, v);
         }
+        let contents = builder.finish();
+        let block = Block::try_new(o.clone(), contents.clone()).unwrap();
+        assert_eq!(SSIteratorIter::wrap(&mut block.iter()).count(), 6);
+        assert!(Block::try_new(o.clone(), vec![0, 0, 0, 0, 1, 0, 0, 0]).is_ok());
+
+        let corrupt = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut c = contents.clone();
+            f(&mut c);
+            Block::try_new(o.clone(), c).err().unwrap().is_corruption()
+        };
+        let n = contents.len();
+        // Too many restarts.
+        assert!(corrupt(&|c| c[n - 4] = 200));
+        // Restart point in the middle of an entry.
+        assert!(corrupt(&|c| c[n - 8] += 1));
+        // Value extending into the restart array.
+        assert!(corrupt(&|c| c[2] = 100));
+        // Shared prefix longer than the previous key.
+        assert!(corrupt(&|c| {
+            let second = 3 + 4 + 6;
+            c[second] = 10;
+        }));
+        assert!(corrupt(&|c| c.truncate(4)));
     }
 
     #[test]
@@ -416,7 +555,7 @@ mod tests {
 
         // Verify that prev() from the last entry goes to the prev-to-last entry
         // (essentially, that next() returning None doesn't advance anything)
-        while let Some(_) = block.next() {}
+        while block.next().is_some() {}
 
         block.prev();
         assert!(block.valid());
@@ -445,7 +584,7 @@ mod tests {
 
         let mut block = Block::new(o.clone(), block_contents).iter();
 
-        block.seek(&"prefix_key2".as_bytes());
+        block.seek("prefix_key2".as_bytes());
         assert!(block.valid());
         assert_eq!(
             current_key_val(&block),
@@ -455,7 +594,7 @@ mod tests {
             ))
         );
 
-        block.seek(&"prefix_key0".as_bytes());
+        block.seek("prefix_key0".as_bytes());
         assert!(block.valid());
         assert_eq!(
             current_key_val(&block),
@@ -465,14 +604,14 @@ mod tests {
             ))
         );
 
-        block.seek(&"key1".as_bytes());
+        block.seek("key1".as_bytes());
         assert!(block.valid());
         assert_eq!(
             current_key_val(&block),
             Some(("key1".as_bytes().to_vec(), "value1".as_bytes().to_vec()))
         );
 
-        block.seek(&"prefix_key3".as_bytes());
+        block.seek("prefix_key3".as_bytes());
         assert!(block.valid());
         assert_eq!(
             current_key_val(&block),
@@ -482,7 +621,7 @@ mod tests {
             ))
         );
 
-        block.seek(&"prefix_key8".as_bytes());
+        block.seek("prefix_key8".as_bytes());
         assert!(!block.valid());
         assert_eq!(current_key_val(&block), None);
     }
@@ -492,7 +631,7 @@ mod tests {
         let mut o = Options::default();
 
         // Test with different number of restarts
-        for block_restart_interval in vec![2, 6, 10] {
+        for block_restart_interval in [2, 6, 10] {
             o.block_restart_interval = block_restart_interval;
 
             let data = get_data();
@@ -1,9 +1,10 @@
 use std::cmp::Ordering;
 
 use crate::block::BlockContents;
-use crate::options::Options;
-
-use integer_encoding::{FixedIntWriter, VarIntWriter};
+use crate::builder_state::{self, StateReader, StateWriter};
+use crate::coding;
+use crate::error::Result;
+use crate::options::{KeyOrder, Options};
 
 /// BlockBuilder contains functionality for building a block consisting of consecutive key-value
 /// entries.
@@ -32,6 +33,39 @@ impl BlockBuilder {
         }
     }
 
+    /// Serializes the state of the builder (see `TableBuilder::checkpoint()`).
+    pub(crate) fn save(&self, w: &mut StateWriter) {
+        w.put_bytes(&self.buffer);
+        w.put_u64(self.restarts.len() as u64);
+        for &r in &self.restarts {
+            w.put_u64(r as u64);
+        }
+        w.put_bytes(&self.last_key);
+        w.put_u64(self.restart_counter as u64);
+        w.put_u64(self.counter as u64);
+    }
+
+    /// Restores a builder saved using `save()`.
+    pub(crate) fn restore(opt: Options, r: &mut StateReader) -> Result<BlockBuilder> {
+        let buffer = r.get_bytes()?.to_vec();
+        let restarts = r
+            .get_usizes()?
+            .into_iter()
+            .map(|x| x as u32)
+            .collect::<Vec<u32>>();
+        if restarts.is_empty() {
+            return Err(builder_state::malformed());
+        }
+        Ok(BlockBuilder {
+            opt,
+            buffer,
+            restarts,
+            last_key: r.get_bytes()?.to_vec(),
+            restart_counter: r.get_usize()?,
+            counter: r.get_usize()?,
+        })
+    }
+
     pub fn entries(&self) -> usize {
         self.counter
     }
@@ -40,15 +74,29 @@ impl BlockBuilder {
         &self.last_key
     }
 
+    /// Returns the size of the entries added so far, without the restart array.
+    pub fn entries_size(&self) -> usize {
+        self.buffer.len()
+    }
+
     pub fn size_estimate(&self) -> usize {
         self.buffer.len() + 4 * self.restarts.len() + 4
     }
 
     pub fn add(&mut self, key: &[u8], val: &[u8]) {
         assert!(self.restart_counter <= self.opt.block_restart_interval);
-        assert!(
+        // `TableBuilder` checks the order of keys before adding them.
+        debug_assert!(
             self.buffer.is_empty()
-                || self.opt.cmp.cmp(self.last_key.as_slice(), key) == Ordering::Less
+                || match self.opt.key_order {
+                    KeyOrder::Strict => {
+                        self.opt.cmp.cmp(self.last_key.as_slice(), key) == Ordering::Less
+                    }
+                    KeyOrder::AllowEqual => {
+                        self.opt.cmp.cmp(self.last_key.as_slice(), key) != Ordering::Greater
+                    }
+                    KeyOrder::Unchecked => true,
+                }
         );
 
         let mut shared = 0;
@@ -71,15 +119,9 @@ impl BlockBuilder {
 
         let non_shared = key.len() - shared;
 
-        self.buffer
-            .write_varint(shared)
-            .expect("write to buffer failed");
-        self.buffer
-            .write_varint(non_shared)
-            .expect("write to buffer failed");
-        self.buffer
-            .write_varint(val.len())
-            .expect("write to buffer failed");
+        coding::put_varint64(&mut self.buffer, shared as u64);
+        coding::put_varint64(&mut self.buffer, non_shared as u64);
+        coding::put_varint64(&mut self.buffer, val.len() as u64);
         self.buffer.extend_from_slice(&key[shared..]);
         self.buffer.extend_from_slice(val);
 
@@ -96,15 +138,11 @@ impl BlockBuilder {
 
         // 1. Append RESTARTS
         for r in self.restarts.iter() {
-            self.buffer
-                .write_fixedint(*r as u32)
-                .expect("write to buffer failed");
+            coding::put_fixed32(&mut self.buffer, *r);
         }
 
         // 2. Append N_RESTARTS
-        self.buffer
-            .write_fixedint(self.restarts.len() as u32)
-            .expect("write to buffer failed");
+        coding::put_fixed32(&mut self.buffer, self.restarts.len() as u32);
 
         // done
         self.buffer
@@ -150,6 +188,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(debug_assertions)]
     #[should_panic]
     fn test_block_builder_panics() {
         let mut d = get_data();
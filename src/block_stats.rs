@@ -0,0 +1,125 @@
+//! Per-block statistics. A `BlockStatsAggregator` summarizes the entries of every data block into
+//! a byte string (e.g. the encoded minimum and maximum of a field embedded in the values); the
+//! summaries are stored in a meta block and allow iterators to skip blocks that can't contain
+//! interesting entries (see `TableIterator::prune_blocks()`).
+
+use crate::block::{Block, BlockContents};
+use crate::block_builder::BlockBuilder;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_properties::meta_options;
+use crate::types::SSIterator;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Key of the block statistics block in the metaindex block.
+pub const BLOCK_STATS_BLOCK_KEY: &str = "sstable.block_stats";
+
+/// A BlockStatsAggregator computes statistics over the entries of each data block.
+pub trait BlockStatsAggregator: Send {
+    /// Called for every entry added to the current block.
+    fn add(&mut self, key: &[u8], value: &[u8]);
+    /// Called when the current block is complete; returns its statistics and resets the
+    /// aggregator for the next block.
+    fn finish_block(&mut self) -> Vec<u8>;
+}
+
+/// Creates a new `BlockStatsAggregator` for every table that is built.
+pub type BlockStatsAggregatorFactory = Arc<dyn Fn() -> Box<dyn BlockStatsAggregator> + Send + Sync>;
+
+/// Predicate deciding whether a block can be skipped, given its statistics.
+pub type BlockPruner = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Collects the statistics of all blocks of a table while it's built.
+pub struct BlockStatsBuilder {
+    aggregator: Box<dyn BlockStatsAggregator>,
+    block: BlockBuilder,
+}
+
+impl BlockStatsBuilder {
+    pub fn new(opt: &Options, factory: &BlockStatsAggregatorFactory) -> BlockStatsBuilder {
+        BlockStatsBuilder {
+            aggregator: factory(),
+            block: BlockBuilder::new(meta_options(opt)),
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.aggregator.add(key, value);
+    }
+
+    /// Records the statistics of the data block that was written at `offset`.
+    pub fn finish_block(&mut self, offset: u64) {
+        let stats = self.aggregator.finish_block();
+        // Big-endian offsets sort in file order.
+        self.block.add(&offset.to_be_bytes(), &stats);
+    }
+
+    /// Returns the encoded statistics block, or None if no block has been finished.
+    pub fn finish(self) -> Option<BlockContents> {
+        if self.block.entries() == 0 {
+            None
+        } else {
+            Some(self.block.finish())
+        }
+    }
+}
+
+/// Decodes a block statistics block into a map from data block offset to statistics.
+pub fn decode_block_stats(block: &Block) -> Result<HashMap<u64, Vec<u8>>> {
+    let mut stats = HashMap::new();
+    let mut iter = block.iter();
+    let (mut key, mut val) = (vec![], vec![]);
+    while iter.advance() {
+        iter.current(&mut key, &mut val);
+        if key.len() != 8 {
+            return Err(Error::malformed(None, "bad block statistics key"));
+        }
+        let mut offset = [0; 8];
+        offset.copy_from_slice(&key);
+        stats.insert(u64::from_be_bytes(offset), val.clone());
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountAggregator(u8);
+
+    impl BlockStatsAggregator for CountAggregator {
+        fn add(&mut self, _: &[u8], _: &[u8]) {
+            self.0 += 1;
+        }
+        fn finish_block(&mut self) -> Vec<u8> {
+            vec![std::mem::replace(&mut self.0, 0)]
+        }
+    }
+
+    #[test]
+    fn test_block_stats_encode_decode() {
+        let opt = Options::default();
+        let factory: BlockStatsAggregatorFactory = Arc::new(|| Box::new(CountAggregator(0)));
+        let mut b = BlockStatsBuilder::new(&opt, &factory);
+        b.add(b"a", b"");
+        b.add(b"b", b"");
+        b.finish_block(0);
+        b.add(b"c", b"");
+        b.finish_block(300);
+
+        let block = Block::new(opt, b.finish().unwrap());
+        let stats = decode_block_stats(&block).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[&0], vec![2]);
+        assert_eq!(stats[&300], vec![1]);
+    }
+
+    #[test]
+    fn test_block_stats_empty() {
+        let factory: BlockStatsAggregatorFactory = Arc::new(|| Box::new(CountAggregator(0)));
+        let b = BlockStatsBuilder::new(&Options::default(), &factory);
+        assert!(b.finish().is_none());
+    }
+}
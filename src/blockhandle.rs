@@ -1,43 +1,44 @@
+use crate::coding;
+use crate::error::Result;
+
 use integer_encoding::VarInt;
 
 /// Contains an offset and a length (or size); can be efficiently encoded in to varints. This is
 /// used typically as file-internal pointer in table (SSTable) files. For example, the index block
 /// in an SSTable is a block of (key = largest key in block) -> (value = encoded blockhandle of
 /// block).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockHandle {
-    offset: usize,
-    size: usize,
+    offset: u64,
+    size: u64,
 }
 
 impl BlockHandle {
     /// Decodes a block handle from `from` and returns a block handle
-    /// together with how many bytes were read from the slice.
-    pub fn decode(from: &[u8]) -> (BlockHandle, usize) {
-        let (off, offsize) = usize::decode_var(from);
-        let (sz, szsize) = usize::decode_var(&from[offsize..]);
+    /// together with how many bytes were read from the slice. Fails with `Error::Corruption` if
+    /// `from` doesn't start with a valid encoding.
+    pub fn decode(from: &[u8]) -> Result<(BlockHandle, usize)> {
+        let (off, offsize) = coding::get_varint64(from)?;
+        let (sz, szsize) = coding::get_varint64(&from[offsize..])?;
 
-        (
+        Ok((
             BlockHandle {
                 offset: off,
                 size: sz,
             },
             offsize + szsize,
-        )
+        ))
     }
 
-    pub fn new(offset: usize, size: usize) -> BlockHandle {
-        BlockHandle {
-            offset: offset,
-            size: size,
-        }
+    pub fn new(offset: u64, size: u64) -> BlockHandle {
+        BlockHandle { offset, size }
     }
 
-    pub fn offset(&self) -> usize {
+    pub fn offset(&self) -> u64 {
         self.offset
     }
 
-    pub fn size(&self) -> usize {
+    pub fn size(&self) -> u64 {
         self.size
     }
 
@@ -57,13 +58,16 @@ mod tests {
     #[test]
     fn test_blockhandle() {
         let bh = BlockHandle::new(890, 777);
-        let mut dst = [0 as u8; 128];
+        let mut dst = [0_u8; 128];
         let enc_sz = bh.encode_to(&mut dst[..]);
 
-        let (bh2, dec_sz) = BlockHandle::decode(&dst);
+        let (bh2, dec_sz) = BlockHandle::decode(&dst).unwrap();
 
         assert_eq!(enc_sz, dec_sz);
         assert_eq!(bh.size(), bh2.size());
         assert_eq!(bh.offset(), bh2.offset());
+        assert!(BlockHandle::decode(&dst[..enc_sz - 1])
+            .unwrap_err()
+            .is_corruption());
     }
 }
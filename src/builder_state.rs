@@ -0,0 +1,118 @@
+//! Serialization of the state of a `TableBuilder`, for resuming it later (see
+//! `TableBuilder::checkpoint()` and `TableBuilder::resume()`).
+
+use crate::coding;
+use crate::error::{Error, Result};
+
+use integer_encoding::VarInt;
+
+/// A point from which building a table can be resumed, as returned by
+/// `TableBuilder::checkpoint()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuilderCheckpoint {
+    /// The number of bytes written to the table's sink so far. A resumed builder continues
+    /// writing after exactly these bytes.
+    pub offset: u64,
+    /// The builder's state: the data block under construction, the index and filter data of the
+    /// blocks written so far, and statistics.
+    pub state: Vec<u8>,
+}
+
+/// Serializes the parts of a builder's state.
+#[derive(Default)]
+pub struct StateWriter {
+    pub buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn put_u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.encode_var_vec());
+    }
+
+    pub fn put_bytes(&mut self, b: &[u8]) {
+        self.put_u64(b.len() as u64);
+        self.buf.extend_from_slice(b);
+    }
+
+    pub fn put_usizes(&mut self, v: &[usize]) {
+        self.put_u64(v.len() as u64);
+        for &x in v {
+            self.put_u64(x as u64);
+        }
+    }
+}
+
+/// Deserializes state written by a `StateWriter`.
+pub struct StateReader<'a> {
+    src: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(src: &'a [u8]) -> StateReader<'a> {
+        StateReader { src }
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        let (v, n) = coding::get_varint64(self.src)?;
+        self.src = &self.src[n..];
+        Ok(v)
+    }
+
+    pub fn get_usize(&mut self) -> Result<usize> {
+        Ok(self.get_u64()? as usize)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get_u64()? != 0)
+    }
+
+    pub fn get_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.get_usize()?;
+        if self.src.len() < len {
+            return Err(malformed());
+        }
+        let (b, rest) = self.src.split_at(len);
+        self.src = rest;
+        Ok(b)
+    }
+
+    pub fn get_usizes(&mut self) -> Result<Vec<usize>> {
+        let len = self.get_usize()?;
+        if len > self.src.len() {
+            return Err(malformed());
+        }
+        (0..len).map(|_| self.get_usize()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.src.is_empty()
+    }
+}
+
+pub fn malformed() -> Error {
+    Error::malformed(None, "bad table builder checkpoint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_state_roundtrip() {
+        let mut w = StateWriter::default();
+        w.put_u64(300);
+        w.put_bytes(b"abc");
+        w.put_usizes(&[1, 2, 1 << 40]);
+
+        let mut r = StateReader::new(&w.buf);
+        assert_eq!(r.get_u64().unwrap(), 300);
+        assert_eq!(r.get_bytes().unwrap(), b"abc");
+        assert_eq!(r.get_usizes().unwrap(), vec![1, 2, 1 << 40]);
+        assert!(r.is_empty());
+        assert!(r.get_u64().err().unwrap().is_corruption());
+
+        let mut r = StateReader::new(&w.buf[..4]);
+        r.get_u64().unwrap();
+        assert!(r.get_bytes().is_err());
+    }
+}
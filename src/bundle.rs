@@ -0,0 +1,425 @@
+//! Bundles pack a set of tables and a manifest describing them into a single file, e.g. to ship a
+//! snapshot as one artifact instead of thousands of files. A `BundleWriter` streams the tables to
+//! any `Write` one after the other, followed by the manifest; nothing is written out of order, so
+//! bundles can be piped to the network or object storage directly. `Bundle::open()` only reads the
+//! manifest, and `Bundle::open_table()` opens a table lazily on the byte range it occupies, so that
+//! tables are read from the bundle like from their own files.
+//!
+//! Layout: an 8-byte magic number, the tables, the manifest and a trailer holding the manifest's
+//! location and checksum and the magic number again.
+
+use crate::checksum::{self, FileChecksum};
+use crate::coding;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_reader::Table;
+use crate::types::{mask_crc, unmask_crc, RandomAccess};
+use crate::version::LevelFile;
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The magic number at the start and at the end of a bundle.
+pub const BUNDLE_MAGIC: u64 = 0x7373_7462_756e_646c;
+/// Manifest offset and length, masked CRC32C of the manifest, magic number.
+const TRAILER_LENGTH: usize = 8 + 8 + 4 + 8;
+/// The size of the reads copying tables into a bundle.
+const COPY_SIZE: usize = 64 * 1024;
+
+/// Returns a fingerprint of the options determining how tables are read: the comparator, filter
+/// policy, checksummer, value and key codecs and magic number. Tables in a bundle are opened with
+/// options of the same fingerprint as they were written with.
+pub fn options_fingerprint(opt: &Options) -> u64 {
+    let mut fp = FileChecksum::new();
+    for part in [
+        opt.cmp.id(),
+        opt.filter_policy.name(),
+        opt.checksummer.name(),
+        opt.value_codec.as_ref().map_or("", |c| c.name()),
+    ] {
+        fp.update(part.as_bytes());
+        fp.update(&[0]);
+    }
+    // Only mixed in if set, so that fingerprints of existing bundles stay the same.
+    if let Some(ref codec) = opt.key_codec {
+        fp.update(codec.name().as_bytes());
+        fp.update(&[0]);
+    }
+    fp.update(&opt.table_magic_number.to_le_bytes());
+    fp.value()
+}
+
+/// A table in a bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BundleEntry {
+    /// The name the table was added under, e.g. its file name.
+    pub name: String,
+    /// The location of the table in the bundle.
+    pub offset: u64,
+    pub size: u64,
+    /// The smallest and largest key of the table; None for empty tables.
+    pub key_range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// The manifest of a bundle, listing its tables in the order they were added.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BundleManifest {
+    /// The ID of the comparator (`Cmp::id()`) the tables are ordered by.
+    pub comparator: String,
+    /// The `options_fingerprint()` of the options the tables were written with.
+    pub options_fingerprint: u64,
+    pub tables: Vec<BundleEntry>,
+}
+
+impl BundleManifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut dst = vec![];
+        put_bytes(&mut dst, self.comparator.as_bytes());
+        coding::put_fixed64(&mut dst, self.options_fingerprint);
+        coding::put_varint64(&mut dst, self.tables.len() as u64);
+        for entry in self.tables.iter() {
+            put_bytes(&mut dst, entry.name.as_bytes());
+            coding::put_varint64(&mut dst, entry.offset);
+            coding::put_varint64(&mut dst, entry.size);
+            match entry.key_range {
+                Some((ref smallest, ref largest)) => {
+                    dst.push(1);
+                    put_bytes(&mut dst, smallest);
+                    put_bytes(&mut dst, largest);
+                }
+                None => dst.push(0),
+            }
+        }
+        dst
+    }
+
+    fn decode(src: &[u8]) -> Result<BundleManifest> {
+        let mut pos = 0;
+        let comparator = get_string(src, &mut pos)?;
+        let options_fingerprint = coding::get_fixed64(&src[pos..])?;
+        pos += 8;
+        let n = get_varint(src, &mut pos)?;
+        let mut tables = vec![];
+        for _ in 0..n {
+            let name = get_string(src, &mut pos)?;
+            let offset = get_varint(src, &mut pos)?;
+            let size = get_varint(src, &mut pos)?;
+            let has_range = src.get(pos).copied();
+            pos += 1;
+            let key_range = match has_range {
+                Some(0) => None,
+                Some(1) => Some((get_bytes(src, &mut pos)?, get_bytes(src, &mut pos)?)),
+                _ => return Err(malformed()),
+            };
+            tables.push(BundleEntry {
+                name,
+                offset,
+                size,
+                key_range,
+            });
+        }
+        if pos != src.len() {
+            return Err(malformed());
+        }
+        Ok(BundleManifest {
+            comparator,
+            options_fingerprint,
+            tables,
+        })
+    }
+}
+
+fn put_bytes(dst: &mut Vec<u8>, b: &[u8]) {
+    coding::put_varint64(dst, b.len() as u64);
+    dst.extend_from_slice(b);
+}
+
+fn get_varint(src: &[u8], pos: &mut usize) -> Result<u64> {
+    let (n, len) = coding::get_varint64(src.get(*pos..).ok_or_else(malformed)?)?;
+    *pos += len;
+    Ok(n)
+}
+
+fn get_bytes(src: &[u8], pos: &mut usize) -> Result<Vec<u8>> {
+    let len = get_varint(src, pos)? as usize;
+    let b = src
+        .get(*pos..pos.saturating_add(len))
+        .ok_or_else(malformed)?;
+    *pos += len;
+    Ok(b.to_vec())
+}
+
+fn get_string(src: &[u8], pos: &mut usize) -> Result<String> {
+    String::from_utf8(get_bytes(src, pos)?).map_err(|_| malformed())
+}
+
+fn malformed() -> Error {
+    Error::malformed(None, "malformed bundle manifest")
+}
+
+/// Writes tables into a bundle.
+pub struct BundleWriter<W: Write> {
+    dst: W,
+    offset: u64,
+    manifest: BundleManifest,
+    names: HashSet<String>,
+}
+
+impl<W: Write> BundleWriter<W> {
+    /// Starts a bundle of tables written with `opt`, writing its header to `dst`.
+    pub fn new(opt: &Options, mut dst: W) -> Result<BundleWriter<W>> {
+        dst.write_all(&BUNDLE_MAGIC.to_le_bytes())?;
+        Ok(BundleWriter {
+            dst,
+            offset: 8,
+            manifest: BundleManifest {
+                comparator: opt.cmp.id().to_string(),
+                options_fingerprint: options_fingerprint(opt),
+                tables: vec![],
+            },
+            names: HashSet::new(),
+        })
+    }
+
+    /// Appends the file of `table` under `name`, which must be unique within the bundle. The
+    /// table must have been written with options of the bundle's fingerprint.
+    pub fn add_table(&mut self, name: &str, table: &Table) -> Result<()> {
+        if options_fingerprint(table.options()) != self.manifest.options_fingerprint {
+            return Err(Error::InvalidArgument(format!(
+                "table {} was opened with options other than the bundle's",
+                name
+            )));
+        }
+        if !self.names.insert(name.to_string()) {
+            return Err(Error::InvalidArgument(format!(
+                "table {} added to bundle twice",
+                name
+            )));
+        }
+        let key_range = if table.index_handles().is_empty() {
+            None
+        } else {
+            let file = LevelFile::new(table.clone())?;
+            Some((file.smallest, file.largest))
+        };
+
+        let size = table.file_size();
+        let mut buf = vec![0; COPY_SIZE];
+        let mut copied = 0;
+        while copied < size {
+            let len = (size - copied).min(COPY_SIZE as u64) as usize;
+            if table.file().read_at(copied, &mut buf[..len])? < len {
+                return Err(Error::malformed(Some(copied), "table file is truncated"));
+            }
+            self.dst.write_all(&buf[..len])?;
+            copied += len as u64;
+        }
+
+        self.manifest.tables.push(BundleEntry {
+            name: name.to_string(),
+            offset: self.offset,
+            size,
+            key_range,
+        });
+        self.offset += size;
+        Ok(())
+    }
+
+    /// Writes the manifest and the trailer, and flushes the sink. Returns the size of the bundle.
+    pub fn finish(mut self) -> Result<u64> {
+        let manifest = self.manifest.encode();
+        let mut trailer = vec![];
+        coding::put_fixed64(&mut trailer, self.offset);
+        coding::put_fixed64(&mut trailer, manifest.len() as u64);
+        coding::put_fixed32(&mut trailer, mask_crc(checksum::crc32c(&manifest)));
+        coding::put_fixed64(&mut trailer, BUNDLE_MAGIC);
+        self.dst.write_all(&manifest)?;
+        self.dst.write_all(&trailer)?;
+        self.dst.flush()?;
+        Ok(self.offset + (manifest.len() + trailer.len()) as u64)
+    }
+}
+
+/// A bundle opened for reading.
+pub struct Bundle {
+    file: Arc<dyn RandomAccess>,
+    manifest: BundleManifest,
+}
+
+impl Bundle {
+    /// Opens the bundle in `file` of `size` bytes, reading its manifest.
+    pub fn open(file: Box<dyn RandomAccess>, size: u64) -> Result<Bundle> {
+        if size < (8 + TRAILER_LENGTH) as u64 {
+            return Err(Error::malformed(None, "file too short to be a bundle"));
+        }
+        let mut header = [0; 8];
+        let mut trailer = [0; TRAILER_LENGTH];
+        file.read_at(0, &mut header)?;
+        file.read_at(size - TRAILER_LENGTH as u64, &mut trailer)?;
+        if coding::get_fixed64(&header)? != BUNDLE_MAGIC
+            || coding::get_fixed64(&trailer[20..])? != BUNDLE_MAGIC
+        {
+            return Err(Error::malformed(None, "bad bundle magic number"));
+        }
+
+        let offset = coding::get_fixed64(&trailer)?;
+        let len = coding::get_fixed64(&trailer[8..])?;
+        if offset.checked_add(len) != Some(size - TRAILER_LENGTH as u64) {
+            return Err(Error::malformed(None, "bad bundle manifest location"));
+        }
+        let mut manifest = vec![0; len as usize];
+        file.read_at(offset, &mut manifest)?;
+        if unmask_crc(coding::get_fixed32(&trailer[16..])?) != checksum::crc32c(&manifest) {
+            return Err(Error::malformed(
+                Some(offset),
+                "bundle manifest checksum mismatch",
+            ));
+        }
+        let manifest = BundleManifest::decode(&manifest)?;
+        if manifest
+            .tables
+            .iter()
+            .any(|t| t.offset < 8 || t.offset.saturating_add(t.size) > offset)
+        {
+            return Err(Error::malformed(None, "bundled table out of bounds"));
+        }
+        Ok(Bundle {
+            file: Arc::from(file),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &BundleManifest {
+        &self.manifest
+    }
+
+    /// Opens the table added under `name`, reading it from the bundle. `opt` must match the
+    /// options the bundle was written with (see `options_fingerprint()`).
+    pub fn open_table(&self, opt: Options, name: &str) -> Result<Table> {
+        if options_fingerprint(&opt) != self.manifest.options_fingerprint {
+            return Err(Error::InvalidArgument(format!(
+                "bundle was written with other options (comparator {})",
+                self.manifest.comparator
+            )));
+        }
+        let entry = match self.manifest.tables.iter().find(|t| t.name == name) {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "no table {} in bundle",
+                    name
+                )))
+            }
+        };
+        let range = FileRange {
+            file: self.file.clone(),
+            offset: entry.offset,
+            size: entry.size,
+        };
+        Table::new(opt, Box::new(range), entry.size)
+    }
+}
+
+/// A byte range of a file, read like a file of its own.
+struct FileRange {
+    file: Arc<dyn RandomAccess>,
+    offset: u64,
+    size: u64,
+}
+
+impl RandomAccess for FileRange {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        if off >= self.size {
+            return Ok(0);
+        }
+        let len = (self.size - off).min(dst.len() as u64) as usize;
+        self.file.read_at(self.offset + off, &mut dst[..len])
+    }
+
+    fn file_id(&self) -> Option<u64> {
+        self.file.file_id().map(|id| {
+            let mut fp = FileChecksum::from_value(id);
+            fp.update(&self.offset.to_le_bytes());
+            fp.value()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::build_table;
+    use crate::types::SSIterator;
+
+    fn table(opt: &Options, keys: std::ops::Range<usize>) -> Table {
+        let entries: Vec<(String, String)> = keys
+            .map(|i| {
+                let k = format!("key{:04}", i);
+                let v = k.to_uppercase();
+                (k, v)
+            })
+            .collect();
+        build_table(opt, &entries)
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let mut opt = Options::default();
+        opt.block_size = 128;
+        let tables = [
+            table(&opt, 0..300),
+            table(&opt, 0..0),
+            table(&opt, 300..310),
+        ];
+
+        let mut d = vec![];
+        let mut w = BundleWriter::new(&opt, &mut d).unwrap();
+        for (i, t) in tables.iter().enumerate() {
+            w.add_table(&format!("{:06}.sst", i), t).unwrap();
+        }
+        assert!(matches!(
+            w.add_table("000000.sst", &tables[0]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let size = w.finish().unwrap();
+        assert_eq!(size, d.len() as u64);
+
+        let bundle = Bundle::open(Box::new(d.clone()), size).unwrap();
+        let manifest = bundle.manifest();
+        assert_eq!(manifest.comparator, opt.cmp.id());
+        assert_eq!(manifest.tables.len(), 3);
+        assert_eq!(
+            manifest.tables[0].key_range,
+            Some((b"key0000".to_vec(), b"key0299".to_vec()))
+        );
+        assert_eq!(manifest.tables[1].key_range, None);
+        assert_eq!(manifest.tables[2].size, tables[2].file_size());
+
+        let t = bundle.open_table(opt.clone(), "000002.sst").unwrap();
+        assert_eq!(t.get(b"key0305").unwrap().as_deref(), Some(&b"KEY0305"[..]));
+        assert_eq!(t.get(b"key0100").unwrap(), None);
+        let t = bundle.open_table(opt.clone(), "000000.sst").unwrap();
+        assert_eq!(t.iter().entries().count(), 300);
+        assert!(bundle.open_table(opt.clone(), "missing.sst").is_err());
+
+        let mut other = opt.clone();
+        other.table_magic_number = 1;
+        assert!(matches!(
+            bundle.open_table(other, "000000.sst"),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let mut corrupt = d.clone();
+        let n = corrupt.len();
+        corrupt[n - TRAILER_LENGTH - 2] ^= 1;
+        assert!(Bundle::open(Box::new(corrupt), size)
+            .err()
+            .unwrap()
+            .is_corruption());
+        assert!(Bundle::open(Box::new(d[..20].to_vec()), 20)
+            .err()
+            .unwrap()
+            .is_corruption());
+    }
+}
@@ -1,5 +1,11 @@
+use crate::block::Block;
+use crate::types::mix64;
+
 use std::collections::HashMap;
-use std::mem::{replace, swap};
+use std::mem::swap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 // No clone, no copy! That asserts that an LRUHandle exists only once.
 type LRUHandle<T> = *mut LRUNode<T>;
@@ -43,7 +49,7 @@ impl<T> LRUList<T> {
             // Set up the node after the new one
             self.head.next.as_mut().unwrap().prev = Some(newp);
             // Replace head.next with None and set the new node's next to that
-            new.next = replace(&mut self.head.next, None);
+            new.next = self.head.next.take();
             self.head.next = Some(new);
 
             newp
@@ -67,17 +73,12 @@ impl<T> LRUList<T> {
 
     fn remove_last(&mut self) -> Option<T> {
         if self.head.prev.is_some() {
-            let mut lasto = unsafe {
-                replace(
-                    &mut (*((*self.head.prev.unwrap()).prev.unwrap())).next,
-                    None,
-                )
-            };
+            let mut lasto = unsafe { (*((*self.head.prev.unwrap()).prev.unwrap())).next.take() };
 
             if let Some(ref mut last) = lasto {
                 self.head.prev = last.prev;
                 self.count -= 1;
-                return replace(&mut (*last).data, None);
+                last.data.take()
             } else {
                 None
             }
@@ -88,21 +89,21 @@ impl<T> LRUList<T> {
 
     fn remove(&mut self, node_handle: LRUHandle<T>) -> T {
         unsafe {
-            // If has next
-            if let Some(ref mut nextp) = (*node_handle).next {
-                swap(&mut (**nextp).prev, &mut (*node_handle).prev);
-            }
-            // If has prev
-            if let Some(ref mut prevp) = (*node_handle).prev {
-                // swap prev.next
-                // (node_handle will own itself now)
-                swap(&mut (**prevp).next, &mut (*node_handle).next);
+            // Every node has a prev (possibly the head node), which owns it.
+            let prevp = (*node_handle).prev.unwrap();
+            let mut node = (*prevp).next.take().unwrap();
+            let mut next = node.next.take();
+
+            if let Some(ref mut nextp) = next {
+                nextp.prev = Some(prevp);
+            } else {
+                // Removing the last node
+                self.head.prev = Some(prevp);
             }
+            (*prevp).next = next;
 
             self.count -= 1;
-            // node_handle now only has references/objects that point to itself,
-            // so it's safe to drop
-            replace(&mut (*node_handle).data, None).unwrap()
+            node.data.take().unwrap()
         }
     }
 
@@ -152,12 +153,50 @@ impl<T> LRUList<T> {
     }
 }
 
+/// Key of a cached block: 8 bytes of cache ID (identifying the table) followed by 8 bytes of
+/// block offset.
 pub type CacheKey = [u8; 16];
 pub type CacheID = u64;
-type CacheEntry<T> = (T, LRUHandle<CacheKey>);
+/// Returns the cache ID of a table with the stable ID `id` (see `Table::with_cache_id()`). These
+/// cache IDs have the highest bit set, so they don't collide with IDs returned by
+/// `BlockCache::new_cache_id()`.
+pub fn stable_cache_id(id: u64) -> CacheID {
+    mix64(id) | 1 << 63
+}
+
+/// Returns the cache ID a key starts with.
+pub fn cache_id_of(key: &CacheKey) -> CacheID {
+    let mut id = [0; 8];
+    id.copy_from_slice(&key[..8]);
+    CacheID::from_le_bytes(id)
+}
+
+/// Identifies a pin taken on a cache element (see `Cache::pin()`).
+pub type PinID = u64;
+/// Called with the key and value of every element evicted from a cache to make room for another
+/// one. It's called while the cache is locked, so it must not access the cache.
+pub type EvictionCallback<T> = Box<dyn Fn(&CacheKey, &T) + Send + Sync>;
+
+struct CacheEntry<T> {
+    elem: T,
+    // None while the element is pinned: pinned elements aren't in the LRU list, so they can't
+    // be evicted.
+    lru_handle: Option<LRUHandle<CacheKey>>,
+    charge: usize,
+    // Distinguishes elements inserted under the same key, so that stale pins are ignored.
+    id: PinID,
+    pins: usize,
+}
 
 /// Implementation of `ShardedLRUCache`.
 /// Based on a HashMap; the elements are linked in order to support the LRU ordering.
+///
+/// Every element has a charge (1 unless specified otherwise). The capacity bounds either the
+/// number of elements (`new()`) or the sum of their charges (`new_charged()`).
+///
+/// Elements can be pinned (`pin()`), which exempts them from eviction until they're unpinned.
+/// Pinned elements still count towards the capacity; if only pinned elements are left, inserting
+/// exceeds the capacity.
 pub struct Cache<T> {
     // note: CacheKeys (Vec<u8>) are duplicated between list and map. If this turns out to be a
     // performance bottleneck, another layer of indirection™ can solve this by mapping the key
@@ -165,7 +204,11 @@ pub struct Cache<T> {
     list: LRUList<CacheKey>,
     map: HashMap<CacheKey, CacheEntry<T>>,
     cap: usize,
+    usage: usize,
+    cap_is_charge: bool,
     id: u64,
+    next_entry_id: PinID,
+    on_evict: Option<EvictionCallback<T>>,
 }
 
 impl<T> Cache<T> {
@@ -175,7 +218,19 @@ impl<T> Cache<T> {
             list: LRUList::new(),
             map: HashMap::with_capacity(1024),
             cap: capacity,
+            usage: 0,
+            cap_is_charge: false,
             id: 0,
+            next_entry_id: 0,
+            on_evict: None,
+        }
+    }
+
+    /// Returns a cache whose capacity bounds the sum of the charges of its elements.
+    pub fn new_charged(capacity: usize) -> Cache<T> {
+        Cache {
+            cap_is_charge: true,
+            ..Cache::new(capacity)
         }
     }
 
@@ -183,17 +238,51 @@ impl<T> Cache<T> {
     /// among several users.
     pub fn new_cache_id(&mut self) -> CacheID {
         self.id += 1;
-        return self.id;
+        self.id
+    }
+
+    /// Sets a callback that is called for every element evicted to make room for another one.
+    /// Elements that are removed or replaced explicitly aren't passed to it.
+    pub fn set_eviction_callback(&mut self, f: EvictionCallback<T>) {
+        self.on_evict = Some(f);
     }
 
     /// How many the cache currently contains
     pub fn count(&self) -> usize {
-        return self.list.count();
+        self.map.len()
+    }
+
+    /// How many elements are currently pinned.
+    pub fn pinned(&self) -> usize {
+        self.map.len() - self.list.count()
     }
 
     /// The capacity of this cache
     pub fn cap(&self) -> usize {
-        return self.cap;
+        self.cap
+    }
+
+    /// The sum of the charges of all elements in the cache.
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    /// The sum of the charges of the elements whose keys start with cache ID `id`. This walks all
+    /// elements.
+    pub fn usage_of(&self, id: CacheID) -> usize {
+        self.map
+            .iter()
+            .filter(|(key, _)| cache_id_of(key) == id)
+            .map(|(_, entry)| entry.charge)
+            .sum()
+    }
+
+    /// Evicts least recently used elements that aren't pinned until the sum of the charges is at
+    /// most `usage`.
+    pub fn shrink_to(&mut self, usage: usize) {
+        while self.list.count() > 0 && self.usage > usage {
+            self.evict_last();
+        }
     }
 
     /// Insert a new element into the cache. The returned `CacheHandle` can be used for further
@@ -201,16 +290,57 @@ impl<T> Cache<T> {
     /// If the capacity has been reached, the least recently used element is removed from the
     /// cache.
     pub fn insert(&mut self, key: &CacheKey, elem: T) {
-        if self.list.count() >= self.cap {
-            if let Some(removed_key) = self.list.remove_last() {
-                assert!(self.map.remove(&removed_key).is_some());
-            } else {
-                panic!("could not remove_last(); bug!");
+        self.insert_charged(key, elem, 1)
+    }
+
+    /// Insert a new element with the given charge into the cache, replacing an existing element
+    /// with the same key. Least recently used elements are removed until the element fits.
+    pub fn insert_charged(&mut self, key: &CacheKey, elem: T, charge: usize) {
+        self.remove(key);
+
+        while self.list.count() > 0 && self.is_full(charge) {
+            self.evict_last();
+        }
+
+        self.next_entry_id += 1;
+        let lru_handle = self.list.insert(*key);
+        self.map.insert(
+            *key,
+            CacheEntry {
+                elem,
+                lru_handle: Some(lru_handle),
+                charge,
+                id: self.next_entry_id,
+                pins: 0,
+            },
+        );
+        self.usage += charge;
+    }
+
+    fn is_full(&self, charge: usize) -> bool {
+        if self.cap_is_charge {
+            self.usage + charge > self.cap
+        } else {
+            self.map.len() >= self.cap
+        }
+    }
+
+    /// Evicts the least recently used element that isn't pinned.
+    fn evict_last(&mut self) {
+        if let Some(removed_key) = self.list.remove_last() {
+            let removed = self.map.remove(&removed_key).unwrap();
+            self.usage -= removed.charge;
+            if let Some(ref f) = self.on_evict {
+                f(&removed_key, &removed.elem);
             }
+        } else {
+            panic!("could not remove_last(); bug!");
         }
+    }
 
-        let lru_handle = self.list.insert(key.clone());
-        self.map.insert(key.clone(), (elem, lru_handle));
+    /// Returns whether an element is stored under `key`, without marking it as recently used.
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        self.map.contains_key(key)
     }
 
     /// Retrieve an element from the cache.
@@ -218,20 +348,63 @@ impl<T> Cache<T> {
     pub fn get<'a>(&'a mut self, key: &CacheKey) -> Option<&'a T> {
         match self.map.get(key) {
             None => None,
-            Some(&(ref elem, ref lru_handle)) => {
-                self.list.reinsert_front(*lru_handle);
-                Some(elem)
+            Some(entry) => {
+                if let Some(lru_handle) = entry.lru_handle {
+                    self.list.reinsert_front(lru_handle);
+                }
+                Some(&entry.elem)
             }
         }
     }
 
+    /// Retrieves an element from the cache and pins it: it isn't evicted until `unpin()` is
+    /// called with the returned ID (as often as it has been pinned). Removing or replacing the
+    /// element explicitly is still possible.
+    pub fn pin<'a>(&'a mut self, key: &CacheKey) -> Option<(&'a T, PinID)> {
+        let entry = self.map.get_mut(key)?;
+        if let Some(lru_handle) = entry.lru_handle.take() {
+            self.list.remove(lru_handle);
+        }
+        entry.pins += 1;
+        Some((&entry.elem, entry.id))
+    }
+
+    /// Releases a pin taken using `pin()`. Pins of elements that have been removed or replaced
+    /// since are ignored. Once an element isn't pinned anymore, it becomes the most recently used
+    /// one, and elements are evicted if the cache exceeds its capacity.
+    pub fn unpin(&mut self, key: &CacheKey, id: PinID) {
+        let entry = match self.map.get_mut(key) {
+            Some(entry) if entry.id == id && entry.pins > 0 => entry,
+            _ => return,
+        };
+        entry.pins -= 1;
+        if entry.pins == 0 {
+            entry.lru_handle = Some(self.list.insert(*key));
+            while self.list.count() > 0 && !self.fits() {
+                self.evict_last();
+            }
+        }
+    }
+
+    /// Returns true if the cache doesn't exceed its capacity.
+    fn fits(&self) -> bool {
+        if self.cap_is_charge {
+            self.usage <= self.cap
+        } else {
+            self.map.len() <= self.cap
+        }
+    }
+
     /// Remove an element from the cache (for invalidation).
     pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
         match self.map.remove(key) {
             None => None,
-            Some((elem, lru_handle)) => {
-                self.list.remove(lru_handle);
-                Some(elem)
+            Some(entry) => {
+                if let Some(lru_handle) = entry.lru_handle {
+                    self.list.remove(lru_handle);
+                }
+                self.usage -= entry.charge;
+                Some(entry.elem)
             }
         }
     }
@@ -241,11 +414,328 @@ impl<T> Cache<T> {
 // raw pointers.
 // These raw pointers are only pointing to the elements hold in the same cache and insertion
 // clones the values. It is therefore safe to implement Send for Cache.
-// Since all functions that access these raw pointers are mutable member functions, it is also safe to implement Sync
-// (Sync is defined as "if &T is Send-able")
+// Since all functions that access these raw pointers are mutable member functions, it is also
+// safe to implement Sync (Sync is defined as "if &T is Send-able")
 unsafe impl<T: Send> Send for Cache<T> {}
 unsafe impl<T: Sync> Sync for Cache<T> {}
 
+/// Counts of the accesses to a `BlockCache` since it was created (see `BlockCache::stats()`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups (including pins) that found their block.
+    pub hits: u64,
+    /// Lookups that didn't find their block.
+    pub misses: u64,
+    /// Blocks inserted.
+    pub inserts: u64,
+    /// Blocks the cache's admission policy refused to insert.
+    pub rejections: u64,
+}
+
+impl CacheStats {
+    /// Returns the fraction of lookups that found their block, or 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+/// The counters behind `CacheStats`, updated without locking.
+#[derive(Default)]
+pub struct StatsCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    rejections: AtomicU64,
+}
+
+impl StatsCounters {
+    /// Counts a lookup with the given result, and returns it.
+    pub fn lookup<T>(&self, found: Option<T>) -> Option<T> {
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Counts an insertion, which the admission policy may have rejected.
+    pub fn insert(&self, admitted: bool) {
+        let counter = if admitted {
+            &self.inserts
+        } else {
+            &self.rejections
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            rejections: self.rejections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// BlockCache is the interface of the cache holding uncompressed blocks read from tables. It can
+/// be implemented to plug in a custom cache (e.g. one that is shared with other parts of an
+/// application); the default is `LRUBlockCache`.
+///
+/// Every cached block has a charge, which is the size of its contents in bytes; implementations
+/// may use it to bound their memory usage.
+pub trait BlockCache: Send + Sync {
+    /// Inserts a block into the cache, replacing any block already stored under `key`.
+    fn insert(&self, key: &CacheKey, block: Block, charge: usize);
+    /// Returns the block stored under `key`, if any.
+    fn lookup(&self, key: &CacheKey) -> Option<Block>;
+    /// Removes the block stored under `key`, if any.
+    fn erase(&self, key: &CacheKey);
+    /// Returns whether a block is stored under `key`. Unlike `lookup()`, this should not count as
+    /// an access for the eviction policy; the default implementation uses `lookup()`, though.
+    fn contains(&self, key: &CacheKey) -> bool {
+        self.lookup(key).is_some()
+    }
+    /// Returns the number of blocks in the cache.
+    fn count(&self) -> usize;
+    /// Returns the sum of the charges of all blocks in the cache.
+    fn total_charge(&self) -> usize;
+    /// Returns the sum of the charges of the blocks cached under cache ID `id`, i.e. of one table
+    /// (see `Table::approximate_memory_usage()`). The default implementation returns 0.
+    fn charge_of(&self, _id: CacheID) -> usize {
+        0
+    }
+    /// Evicts blocks until the sum of the charges is at most `charge`, if possible; pinned blocks
+    /// are kept. Called when a `MemoryBudget` is exceeded. The default implementation does
+    /// nothing.
+    fn shrink_to(&self, _charge: usize) {}
+
+    /// Returns the block stored under `key` and pins it, so that it isn't evicted until
+    /// `unpin()` is called with the returned ID. Usually, `PinnedBlock::lookup()` is used
+    /// instead, which unpins automatically. The default implementation doesn't pin blocks.
+    fn pin(&self, key: &CacheKey) -> Option<(Block, PinID)> {
+        self.lookup(key).map(|block| (block, 0))
+    }
+    /// Releases a pin taken using `pin()`.
+    fn unpin(&self, _key: &CacheKey, _pin: PinID) {}
+
+    /// Returns the counts of lookups and insertions since the cache was created. The default
+    /// implementation doesn't count them and returns zeros.
+    fn stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+
+    /// Returns an ID that is unique for this cache and that is used to partition the cache among
+    /// tables (it makes up the first 8 bytes of every key). The default implementation returns
+    /// IDs unique in this process. IDs must be below 2^63; the others are reserved for tables
+    /// with a stable ID (see `stable_cache_id()`).
+    fn new_cache_id(&self) -> CacheID {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// A block pinned in a `BlockCache`: it stays in the cache at least as long as this handle is
+/// alive, so that references to its contents remain cheap to recreate.
+pub struct PinnedBlock {
+    cache: Arc<dyn BlockCache>,
+    key: CacheKey,
+    pin: PinID,
+    block: Block,
+}
+
+impl PinnedBlock {
+    /// Returns the block stored under `key` in `cache`, pinned until the returned handle is
+    /// dropped.
+    pub fn lookup(cache: &Arc<dyn BlockCache>, key: &CacheKey) -> Option<PinnedBlock> {
+        let (block, pin) = cache.pin(key)?;
+        Some(PinnedBlock {
+            cache: cache.clone(),
+            key: *key,
+            pin,
+            block,
+        })
+    }
+
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+}
+
+impl Deref for PinnedBlock {
+    type Target = Block;
+
+    fn deref(&self) -> &Block {
+        &self.block
+    }
+}
+
+impl Drop for PinnedBlock {
+    fn drop(&mut self) {
+        self.cache.unpin(&self.key, self.pin);
+    }
+}
+
+/// A limit on the memory used by a set of tables sharing it through `Options::memory_budget`:
+/// the memory the tables pin (their index blocks, filters and other metadata, see
+/// `Table::approximate_memory_usage()`) plus the charges of the blocks in the block cache. Tables
+/// register the memory they pin when they're opened; whenever one inserts a block into the block
+/// cache, the cache is shrunk to what the pinned memory leaves of the budget.
+pub struct MemoryBudget {
+    limit: usize,
+    pinned: AtomicUsize,
+}
+
+impl MemoryBudget {
+    /// Returns a budget of `limit` bytes.
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            pinned: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the memory pinned by the open tables using the budget.
+    pub fn pinned(&self) -> usize {
+        self.pinned.load(Ordering::Relaxed)
+    }
+
+    /// Accounts for `size` bytes pinned until the returned reservation is dropped.
+    pub fn reserve(self: &Arc<Self>, size: usize) -> MemoryReservation {
+        self.pinned.fetch_add(size, Ordering::Relaxed);
+        MemoryReservation {
+            budget: self.clone(),
+            size,
+        }
+    }
+
+    /// Shrinks `cache` so that it fits into the budget together with the pinned memory.
+    pub fn enforce(&self, cache: &dyn BlockCache) {
+        let available = self.limit.saturating_sub(self.pinned());
+        if cache.total_charge() > available {
+            cache.shrink_to(available);
+        }
+    }
+}
+
+/// Memory accounted for in a `MemoryBudget`, released when this is dropped.
+pub struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    size: usize,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.pinned.fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+/// The default `BlockCache`, an LRU cache.
+pub struct LRUBlockCache {
+    cache: Mutex<Cache<Block>>,
+    stats: StatsCounters,
+}
+
+impl LRUBlockCache {
+    /// Returns a cache holding up to `capacity` blocks. The minimal allowed capacity is 1.
+    pub fn new(capacity: usize) -> LRUBlockCache {
+        LRUBlockCache {
+            cache: Mutex::new(Cache::new(capacity)),
+            stats: StatsCounters::default(),
+        }
+    }
+
+    /// Returns a cache holding blocks up to a total charge of `capacity` bytes. The minimal
+    /// allowed capacity is 1.
+    pub fn with_charge_capacity(capacity: usize) -> LRUBlockCache {
+        LRUBlockCache {
+            cache: Mutex::new(Cache::new_charged(capacity)),
+            stats: StatsCounters::default(),
+        }
+    }
+
+    /// Sets a callback that is called for every block evicted from the cache to make room for
+    /// another one.
+    pub fn with_eviction_callback(mut self, f: EvictionCallback<Block>) -> LRUBlockCache {
+        self.cache
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_eviction_callback(f);
+        self
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache<Block>> {
+        // A panic while holding the lock can't leave the cache in an inconsistent state that
+        // matters more than losing its contents, so we carry on with a poisoned lock.
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl BlockCache for LRUBlockCache {
+    fn insert(&self, key: &CacheKey, block: Block, charge: usize) {
+        self.cache().insert_charged(key, block, charge);
+        self.stats.insert(true);
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Block> {
+        self.stats.lookup(self.cache().get(key).cloned())
+    }
+
+    fn erase(&self, key: &CacheKey) {
+        self.cache().remove(key);
+    }
+
+    fn contains(&self, key: &CacheKey) -> bool {
+        self.cache().contains(key)
+    }
+
+    fn count(&self) -> usize {
+        self.cache().count()
+    }
+
+    fn total_charge(&self) -> usize {
+        self.cache().usage()
+    }
+
+    fn charge_of(&self, id: CacheID) -> usize {
+        self.cache().usage_of(id)
+    }
+
+    fn shrink_to(&self, charge: usize) {
+        self.cache().shrink_to(charge);
+    }
+
+    fn pin(&self, key: &CacheKey) -> Option<(Block, PinID)> {
+        let pinned = self
+            .cache()
+            .pin(key)
+            .map(|(block, pin)| (block.clone(), pin));
+        self.stats.lookup(pinned)
+    }
+
+    fn unpin(&self, key: &CacheKey, pin: PinID) {
+        self.cache().unpin(key, pin);
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    fn new_cache_id(&self) -> CacheID {
+        self.cache().new_cache_id()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::LRUList;
@@ -308,6 +798,173 @@ mod tests {
         assert_eq!(cache.get(&h_899), Some(&899));
     }
 
+    #[test]
+    fn test_blockcache_cache_charge() {
+        let mut cache = Cache::new_charged(100);
+
+        let h_123 = make_key(1, 2, 3);
+        let h_521 = make_key(1, 2, 4);
+        let h_372 = make_key(3, 4, 5);
+
+        cache.insert_charged(&h_123, 123, 40);
+        cache.insert_charged(&h_521, 521, 40);
+        assert_eq!(cache.usage(), 80);
+
+        // Replacing an element updates its charge.
+        cache.insert_charged(&h_521, 522, 30);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.usage(), 70);
+
+        // Evicts the least recently used element to make room.
+        cache.insert_charged(&h_372, 372, 50);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.usage(), 80);
+        assert_eq!(cache.get(&h_123), None);
+        assert_eq!(cache.get(&h_521), Some(&522));
+
+        // An element larger than the capacity replaces everything else.
+        cache.insert_charged(&h_123, 123, 150);
+        assert_eq!(cache.count(), 1);
+        assert_eq!(cache.usage(), 150);
+        assert_eq!(cache.remove(&h_123), Some(123));
+        assert_eq!(cache.usage(), 0);
+    }
+
+    #[test]
+    fn test_blockcache_cache_usage_of_shrink() {
+        let mut cache = Cache::new_charged(100);
+        let key = |id: CacheID, off: u8| {
+            let mut k = [0; 16];
+            k[..8].copy_from_slice(&id.to_le_bytes());
+            k[8] = off;
+            k
+        };
+        cache.insert_charged(&key(1, 0), 10, 10);
+        cache.insert_charged(&key(2, 0), 20, 20);
+        cache.insert_charged(&key(1, 1), 30, 30);
+        assert_eq!(cache_id_of(&key(7, 3)), 7);
+        assert_eq!(cache.usage_of(1), 40);
+        assert_eq!(cache.usage_of(3), 0);
+
+        cache.pin(&key(1, 0)).unwrap();
+        cache.shrink_to(40);
+        // The pinned element stays; the least recently used other one goes first.
+        assert_eq!(cache.usage(), 40);
+        assert_eq!(cache.get(&key(2, 0)), None);
+        cache.shrink_to(0);
+        assert_eq!(cache.usage(), 10);
+    }
+
+    #[test]
+    fn test_blockcache_memory_budget() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let cache = LRUBlockCache::with_charge_capacity(1000);
+        let block = Block::new(Default::default(), vec![0, 0, 0, 0, 1, 0, 0, 0]);
+        for i in 0..5 {
+            cache.insert(&make_key(i, 0, 0), block.clone(), 30);
+        }
+        let reservation = budget.reserve(40);
+        assert_eq!(budget.pinned(), 40);
+        budget.enforce(&cache);
+        assert_eq!(cache.total_charge(), 60);
+        drop(reservation);
+        assert_eq!(budget.pinned(), 0);
+    }
+
+    #[test]
+    fn test_blockcache_cache_count_capacity_tracks_charge() {
+        let mut cache = Cache::new(2);
+        let h_123 = make_key(1, 2, 3);
+        let h_521 = make_key(1, 2, 4);
+        let h_372 = make_key(3, 4, 5);
+
+        cache.insert_charged(&h_123, 123, 1000);
+        cache.insert_charged(&h_521, 521, 1000);
+        cache.insert_charged(&h_372, 372, 10);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.usage(), 1010);
+    }
+
+    #[test]
+    fn test_blockcache_cache_pin() {
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let mut cache = Cache::new(2);
+        let e = evicted.clone();
+        cache.set_eviction_callback(Box::new(move |_, v: &usize| e.lock().unwrap().push(*v)));
+
+        let h_123 = make_key(1, 2, 3);
+        let h_521 = make_key(1, 2, 4);
+        let h_372 = make_key(3, 4, 5);
+        let h_332 = make_key(6, 3, 1);
+
+        cache.insert(&h_123, 123);
+        cache.insert(&h_521, 521);
+        let (v, pin) = cache.pin(&h_123).unwrap();
+        assert_eq!(*v, 123);
+        let (_, pin2) = cache.pin(&h_123).unwrap();
+        assert_eq!(pin, pin2);
+        assert_eq!(cache.pinned(), 1);
+
+        // The pinned element is skipped when evicting.
+        cache.insert(&h_372, 372);
+        assert_eq!(*evicted.lock().unwrap(), vec![521]);
+        cache.insert(&h_332, 332);
+        assert_eq!(*evicted.lock().unwrap(), vec![521, 372]);
+        assert_eq!(cache.get(&h_123), Some(&123));
+        assert_eq!(cache.count(), 2);
+
+        // Only pinned elements left: the cache exceeds its capacity.
+        cache.pin(&h_332).unwrap();
+        cache.insert(&h_372, 372);
+        assert_eq!(cache.count(), 3);
+
+        // Unpinning makes the elements evictable again, and the cache shrinks back to its
+        // capacity.
+        cache.unpin(&h_123, pin);
+        assert_eq!(cache.count(), 3);
+        cache.unpin(&h_123, pin);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(*evicted.lock().unwrap(), vec![521, 372, 372]);
+        assert_eq!(cache.pinned(), 1);
+
+        // Pins of replaced elements are ignored; removing pinned elements works.
+        let (_, old_pin) = cache.pin(&h_123).unwrap();
+        cache.insert(&h_123, 124);
+        cache.unpin(&h_123, old_pin);
+        assert_eq!(cache.remove(&h_332), Some(332));
+        assert_eq!(cache.pinned(), 0);
+        assert_eq!(cache.get(&h_123), Some(&124));
+    }
+
+    #[test]
+    fn test_blockcache_pinned_block() {
+        let evictions = Arc::new(AtomicU64::new(0));
+        let e = evictions.clone();
+        let cache: Arc<dyn BlockCache> = Arc::new(LRUBlockCache::new(1).with_eviction_callback(
+            Box::new(move |_, _| {
+                e.fetch_add(1, Ordering::SeqCst);
+            }),
+        ));
+        let opt = crate::options::Options::default();
+        let block = |c: u8| Block::new(opt.clone(), vec![c; 8]);
+
+        let (k1, k2) = (make_key(1, 0, 0), make_key(2, 0, 0));
+        cache.insert(&k1, block(1), 8);
+        {
+            let pinned = PinnedBlock::lookup(&cache, &k1).unwrap();
+            assert_eq!(pinned.contents()[0], 1);
+            cache.insert(&k2, block(2), 8);
+            assert_eq!(evictions.load(Ordering::SeqCst), 0);
+            assert_eq!(cache.count(), 2);
+            assert!(cache.lookup(&k1).is_some());
+        }
+        // Dropping the handle unpins the block, which becomes the most recently used one.
+        assert_eq!(cache.count(), 1);
+        assert_eq!(evictions.load(Ordering::SeqCst), 1);
+        assert!(cache.lookup(&k2).is_none());
+        assert!(PinnedBlock::lookup(&cache, &k1).is_some());
+    }
+
     #[test]
     fn test_blockcache_lru_remove() {
         let mut lru = LRUList::<usize>::new();
@@ -326,6 +983,12 @@ mod tests {
         assert_eq!(lru.count(), 4);
         assert_eq!(56, lru.remove(h_56));
         assert_eq!(lru.count(), 3);
+
+        assert_eq!(lru._testing_head_ref().copied(), Some(1111));
+        assert_eq!(lru.remove_last(), Some(22));
+        assert_eq!(lru.remove_last(), Some(223));
+        assert_eq!(lru.remove_last(), Some(1111));
+        assert_eq!(lru.remove_last(), None);
     }
 
     #[test]
@@ -360,19 +1023,19 @@ mod tests {
         let handle2 = lru.insert(22);
         let handle3 = lru.insert(244);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 244);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 244);
 
         lru.reinsert_front(handle1);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 56);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 56);
 
         lru.reinsert_front(handle3);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 244);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 244);
 
         lru.reinsert_front(handle2);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 22);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 22);
 
         assert_eq!(lru.remove_last(), Some(56));
         assert_eq!(lru.remove_last(), Some(244));
@@ -395,9 +1058,9 @@ mod tests {
             lru.insert(8),
         ];
 
-        for i in 0..9 {
-            lru.reinsert_front(handles[i]);
-            assert_eq!(lru._testing_head_ref().map(|x| *x), Some(i));
+        for (i, handle) in handles.into_iter().enumerate() {
+            lru.reinsert_front(handle);
+            assert_eq!(lru._testing_head_ref().copied(), Some(i));
         }
     }
 
@@ -408,7 +1071,7 @@ mod tests {
         let handle = lru.insert(3);
 
         lru.reinsert_front(handle);
-        assert_eq!(lru._testing_head_ref().map(|x| *x), Some(3));
+        assert_eq!(lru._testing_head_ref().copied(), Some(3));
         assert_eq!(lru.remove_last(), Some(3));
         assert_eq!(lru.remove_last(), None);
         assert_eq!(lru.remove_last(), None);
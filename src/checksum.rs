@@ -0,0 +1,510 @@
+//! Block checksums (see `Options::checksummer`). Every block of a table is followed by a 32-bit
+//! checksum of its contents and compression type. By default this is a CRC32C; deployments can
+//! plug in other algorithms (e.g. a CRC64 folded to 32 bits, or a hardware-offloaded one) or
+//! store checksums unmasked for compatibility with other formats. Tables written by LevelDB,
+//! which masks its CRC32Cs, are read using `LevelDbCrc32c`.
+//!
+//! Tables written with a checksummer other than `Crc32c` are marked as such in their footer, and
+//! the checksummer's name is stored in their properties. Reading them requires options carrying a
+//! checksummer of the same name; tables using `Crc32c` can be read with any options.
+//!
+//! Independently of the block checksums, tables built with `Options::file_checksum` carry a
+//! `FileChecksum` of all bytes preceding the meta block storing it, which
+//! `Table::verify_file_checksum()` checks, and tables built with `Options::value_checksums` store
+//! a masked CRC32C with every value.
+//!
+//! The CRCs are computed by the `crc` crate if the `crc` feature is enabled (as it is by default),
+//! and by the slower table-driven implementation of this module otherwise.
+
+use crate::coding;
+use crate::error::{CorruptionKind, Error, Result};
+use crate::types::{mask_crc, unmask_crc};
+
+use std::sync::Arc;
+
+/// A Checksummer computes the checksums stored after every block of a table.
+pub trait Checksummer: Send + Sync {
+    /// The name of the checksummer, stored in the properties of tables written with it. It
+    /// should change whenever the checksums change.
+    fn name(&self) -> &str;
+    /// Returns a digest computing a new checksum.
+    fn digest(&self) -> Box<dyn ChecksumDigest>;
+    /// Transforms a checksum into the form stored in the table. The default implementation
+    /// applies the same masking as `Crc32c`.
+    fn mask(&self, checksum: u32) -> u32 {
+        mask_crc(checksum)
+    }
+    /// Reverses `mask()`.
+    fn unmask(&self, stored: u32) -> u32 {
+        unmask_crc(stored)
+    }
+}
+
+/// A ChecksumDigest computes a checksum over data passed to it in pieces.
+pub trait ChecksumDigest: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> u32;
+}
+
+pub type BoxedChecksummer = Arc<dyn Checksummer>;
+
+/// The name of `Crc32c`.
+pub const CRC32C_NAME: &str = "sstable.Crc32c";
+/// The name of `LevelDbCrc32c`.
+pub const LEVELDB_CRC32C_NAME: &str = "leveldb.MaskedCrc32c";
+
+#[cfg(feature = "crc")]
+const CASTAGNOLI: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+#[cfg(feature = "crc")]
+const CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+/// Lookup tables of the reflected CRC32C and CRC-64/XZ polynomials, used without the `crc`
+/// feature.
+#[cfg(not(feature = "crc"))]
+mod tables {
+    pub static CRC32C: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0x82f6_3b78
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    pub static CRC64: [u64; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u64;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xc96c_5795_d787_0f42
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+}
+
+/// Returns the CRC32C (Castagnoli) of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut digest = Crc32c.digest();
+    digest.update(data);
+    digest.finalize()
+}
+
+/// The default checksummer, computing CRC32C (Castagnoli) checksums.
+pub struct Crc32c;
+
+#[cfg(feature = "crc")]
+struct Crc32cDigest(crc::Digest<'static, u32>);
+
+/// The inverted CRC register.
+#[cfg(not(feature = "crc"))]
+struct Crc32cDigest(u32);
+
+impl ChecksumDigest for Crc32cDigest {
+    #[cfg(feature = "crc")]
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    #[cfg(not(feature = "crc"))]
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = tables::CRC32C[((self.0 ^ b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    #[cfg(feature = "crc")]
+    fn finalize(self: Box<Self>) -> u32 {
+        self.0.finalize()
+    }
+
+    #[cfg(not(feature = "crc"))]
+    fn finalize(self: Box<Self>) -> u32 {
+        !self.0
+    }
+}
+
+impl Checksummer for Crc32c {
+    fn name(&self) -> &str {
+        CRC32C_NAME
+    }
+
+    #[cfg(feature = "crc")]
+    fn digest(&self) -> Box<dyn ChecksumDigest> {
+        Box::new(Crc32cDigest(CASTAGNOLI.digest()))
+    }
+
+    #[cfg(not(feature = "crc"))]
+    fn digest(&self) -> Box<dyn ChecksumDigest> {
+        Box::new(Crc32cDigest(!0))
+    }
+}
+
+/// CRC32C checksums masked the way LevelDB stores them (this crate stores them unmasked).
+/// `Table::new()` uses it for LevelDB tables, recognized by their index block verifying only
+/// with the masked checksum.
+pub struct LevelDbCrc32c;
+
+const LEVELDB_MASK_DELTA: u32 = 0xa282ead8;
+
+impl Checksummer for LevelDbCrc32c {
+    fn name(&self) -> &str {
+        LEVELDB_CRC32C_NAME
+    }
+
+    fn digest(&self) -> Box<dyn ChecksumDigest> {
+        Crc32c.digest()
+    }
+
+    fn mask(&self, checksum: u32) -> u32 {
+        checksum.rotate_right(15).wrapping_add(LEVELDB_MASK_DELTA)
+    }
+
+    fn unmask(&self, stored: u32) -> u32 {
+        stored.wrapping_sub(LEVELDB_MASK_DELTA).rotate_left(15)
+    }
+}
+
+/// Returns true if `block`, read including its trailer, carries a checksum masked by
+/// `LevelDbCrc32c` instead of the unmasked one `Crc32c` stores.
+pub fn is_leveldb_masked(block: &[u8]) -> Result<bool> {
+    let len = block
+        .len()
+        .checked_sub(5)
+        .ok_or_else(|| Error::malformed(None, "block shorter than its trailer"))?;
+    let stored = coding::get_fixed32(&block[len + 1..])?;
+    let crc = block_checksum(&Crc32c, &block[..len], block[len]);
+    Ok(stored != Crc32c.mask(crc) && stored == LevelDbCrc32c.mask(crc))
+}
+
+/// Returns true if `cs` is the default checksummer.
+pub fn is_default(cs: &dyn Checksummer) -> bool {
+    cs.name() == CRC32C_NAME
+}
+
+/// Calculates the checksum of a block's contents and compression type byte.
+pub fn block_checksum(cs: &dyn Checksummer, data: &[u8], compression: u8) -> u32 {
+    let mut digest = cs.digest();
+    digest.update(data);
+    digest.update(&[compression]);
+    digest.finalize()
+}
+
+/// The name of the meta block holding the file checksum.
+pub const FILE_CHECKSUM_BLOCK_KEY: &str = "sstable.file_checksum";
+/// The name of the algorithm computing `FileChecksum`s, stored along with them.
+pub const FILE_CHECKSUM_NAME: &str = "sstable.Crc64Xz";
+
+/// A rolling checksum (CRC-64/XZ) over the bytes of a table file, updated as they are written.
+/// `TableBuilder::finish_with_meta()` returns the checksum of the whole file, for applications
+/// recording it in their manifests; they can compute the same checksum over a copy of the file to
+/// verify it, e.g. after a backup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileChecksum(u64);
+
+impl FileChecksum {
+    /// Returns the checksum of no bytes.
+    pub fn new() -> FileChecksum {
+        FileChecksum(0)
+    }
+
+    /// Continues a checksum from its `value()`.
+    pub fn from_value(value: u64) -> FileChecksum {
+        FileChecksum(value)
+    }
+
+    /// Extends the checksum by `data`.
+    #[cfg(feature = "crc")]
+    pub fn update(&mut self, data: &[u8]) {
+        // Starting from the register the finalized value was derived from.
+        let mut digest = CRC64.digest_with_initial((!self.0).reverse_bits());
+        digest.update(data);
+        self.0 = digest.finalize();
+    }
+
+    /// Extends the checksum by `data`.
+    #[cfg(not(feature = "crc"))]
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = !self.0;
+        for &b in data {
+            crc = tables::CRC64[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.0 = !crc;
+    }
+
+    /// Returns the checksum of all bytes passed to `update()`.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for FileChecksum {
+    fn default() -> FileChecksum {
+        FileChecksum::new()
+    }
+}
+
+/// Encodes the contents of the file checksum meta block.
+pub fn encode_file_checksum(checksum: u64) -> Vec<u8> {
+    let mut dst = vec![];
+    coding::put_fixed64(&mut dst, checksum);
+    dst.extend_from_slice(FILE_CHECKSUM_NAME.as_bytes());
+    dst
+}
+
+/// Decodes the contents of the file checksum meta block.
+pub fn decode_file_checksum(src: &[u8]) -> Result<u64> {
+    let checksum = coding::get_fixed64(src)?;
+    let name = &src[8..];
+    if name != FILE_CHECKSUM_NAME.as_bytes() {
+        return Err(Error::NotSupported(format!(
+            "file checksum algorithm {}",
+            String::from_utf8_lossy(name)
+        )));
+    }
+    Ok(checksum)
+}
+
+/// The size of the checksum stored with every value of tables written with
+/// `Options::value_checksums`.
+pub const VALUE_CHECKSUM_SIZE: usize = 4;
+
+/// Appends the checksum of `stored`, a value as stored in a data block (after the expiration
+/// timestamp), to `dst`. The checksum precedes the value.
+pub fn put_value_checksum(stored: &[u8], dst: &mut Vec<u8>) {
+    coding::put_fixed32(dst, mask_crc(crc32c(stored)));
+}
+
+/// Verifies the checksum at the start of `src`, written by `put_value_checksum()`, and returns the
+/// value following it.
+pub fn check_value_checksum(src: &[u8]) -> std::result::Result<&[u8], CorruptionKind> {
+    if src.len() < VALUE_CHECKSUM_SIZE {
+        return Err(CorruptionKind::Malformed(
+            "value too short for its checksum".to_string(),
+        ));
+    }
+    let (checksum, stored) = src.split_at(VALUE_CHECKSUM_SIZE);
+    let expected = unmask_crc(u32::from_le_bytes([
+        checksum[0],
+        checksum[1],
+        checksum[2],
+        checksum[3],
+    ]));
+    let actual = crc32c(stored);
+    if expected != actual {
+        return Err(CorruptionKind::ChecksumMismatch { expected, actual });
+    }
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::options::Options;
+    use crate::table_builder::TableBuilder;
+    use crate::table_reader::Table;
+
+    /// Stores plain, unmasked CRC32 (ISO-HDLC) checksums.
+    #[cfg(feature = "crc")]
+    struct PlainCrc32;
+
+    #[cfg(feature = "crc")]
+    struct PlainCrc32Digest(crc::Digest<'static, u32>);
+
+    #[cfg(feature = "crc")]
+    const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+    #[cfg(feature = "crc")]
+    impl ChecksumDigest for PlainCrc32Digest {
+        fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        fn finalize(self: Box<Self>) -> u32 {
+            self.0.finalize()
+        }
+    }
+
+    #[cfg(feature = "crc")]
+    impl Checksummer for PlainCrc32 {
+        fn name(&self) -> &str {
+            "test.PlainCrc32"
+        }
+
+        fn digest(&self) -> Box<dyn ChecksumDigest> {
+            Box::new(PlainCrc32Digest(CRC32.digest()))
+        }
+
+        fn mask(&self, checksum: u32) -> u32 {
+            checksum
+        }
+
+        fn unmask(&self, stored: u32) -> u32 {
+            stored
+        }
+    }
+
+    fn build(opt: Options) -> Vec<u8> {
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt, &mut d);
+            for i in 0..50 {
+                b.add(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            b.finish().unwrap();
+        }
+        d
+    }
+
+    fn open(opt: Options, d: &[u8]) -> crate::error::Result<Table> {
+        Table::new(opt, Box::new(d.to_vec()), d.len() as u64)
+    }
+
+    #[cfg(feature = "crc")]
+    #[test]
+    fn test_custom_checksummer() {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let mut custom = opt.clone();
+        custom.checksummer = Arc::new(PlainCrc32);
+
+        let d = build(custom.clone());
+        let table = open(custom.clone(), &d).unwrap();
+        assert_eq!(
+            table.properties().unwrap().checksummer.as_deref(),
+            Some("test.PlainCrc32")
+        );
+        assert_eq!(
+            table.get(b"key042").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+        assert!(matches!(
+            open(opt.clone(), &d),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // The default checksummer keeps writing identical tables, which can be read with any
+        // checksummer configured.
+        let plain = build(opt.clone());
+        assert_ne!(plain, d);
+        let table = open(custom, &plain).unwrap();
+        assert!(table.properties().unwrap().checksummer.is_none());
+        assert_eq!(
+            table.get(b"key007").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+
+        // Corruption is still detected by the custom checksummer.
+        let mut corrupt = d.clone();
+        corrupt[3] ^= 1;
+        let mut custom = opt;
+        custom.checksummer = Arc::new(PlainCrc32);
+        let table = open(custom, &corrupt).unwrap();
+        assert!(table.get(b"key000").is_err());
+    }
+
+    #[test]
+    fn test_crc_check_values() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        let mut digest = Crc32c.digest();
+        digest.update(b"1234");
+        digest.update(b"56789");
+        assert_eq!(digest.finalize(), 0xe306_9283);
+
+        assert_eq!(FileChecksum::new().value(), 0);
+        let mut sum = FileChecksum::new();
+        sum.update(b"123456789");
+        assert_eq!(sum.value(), 0x995d_c9bb_df19_39fa);
+    }
+
+    #[test]
+    fn test_leveldb_crc32c() {
+        // As in LevelDB's crc32c_test.cc.
+        let crc = crc32c(b"foo");
+        let cs = LevelDbCrc32c;
+        assert_ne!(cs.mask(crc), crc);
+        assert_ne!(cs.mask(cs.mask(crc)), crc);
+        assert_eq!(cs.unmask(cs.mask(crc)), crc);
+        assert_eq!(cs.unmask(cs.unmask(cs.mask(cs.mask(crc)))), crc);
+
+        let mut block = b"data".to_vec();
+        block.push(0);
+        let crc = block_checksum(&Crc32c, b"data", 0);
+        let mut leveldb = block.clone();
+        leveldb.extend_from_slice(&LevelDbCrc32c.mask(crc).to_le_bytes());
+        assert!(is_leveldb_masked(&leveldb).unwrap());
+        block.extend_from_slice(&Crc32c.mask(crc).to_le_bytes());
+        assert!(!is_leveldb_masked(&block).unwrap());
+        assert!(is_leveldb_masked(&block[..4]).is_err());
+    }
+
+    #[test]
+    fn test_file_checksum() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut whole = FileChecksum::new();
+        whole.update(&data);
+        let mut pieces = FileChecksum::default();
+        for chunk in data.chunks(33) {
+            pieces = FileChecksum::from_value(pieces.value());
+            pieces.update(chunk);
+        }
+        assert_eq!(pieces, whole);
+
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        opt.file_checksum = true;
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d);
+        for i in 0..50 {
+            b.add(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        let meta = b.finish_with_meta().unwrap();
+        assert_eq!(meta.size, d.len() as u64);
+        let mut expected = FileChecksum::new();
+        expected.update(&d);
+        assert_eq!(meta.file_checksum, Some(expected.value()));
+
+        let table = open(opt.clone(), &d).unwrap();
+        assert!(table
+            .meta_block_names()
+            .contains(&FILE_CHECKSUM_BLOCK_KEY.to_string()));
+        table.verify_file_checksum().unwrap();
+        assert_eq!(
+            table.get(b"key042").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+
+        let mut corrupt = d.clone();
+        corrupt[3] ^= 1;
+        let table = open(opt, &corrupt).unwrap();
+        assert!(table.verify_file_checksum().unwrap_err().is_corruption());
+
+        let table = open(Options::default(), &build(Options::default())).unwrap();
+        assert!(matches!(
+            table.verify_file_checksum(),
+            Err(Error::NotSupported(_))
+        ));
+    }
+}
@@ -0,0 +1,656 @@
+//! A block cache using the CLOCK eviction algorithm, optionally combined with TinyLFU admission.
+//!
+//! Pure LRU caches are easily flushed by large scans: every block read by the scan becomes the
+//! most recently used one, pushing out the blocks that are frequently accessed. CLOCK keeps a
+//! reference bit per element instead of an ordered list, which makes hits cheap and gives
+//! elements accessed again a second chance before they are evicted. TinyLFU additionally tracks
+//! how often keys have been accessed recently (including keys not in the cache) in a compact
+//! sketch, and only admits a new element if it has been accessed more often than the element it
+//! would replace. Blocks read once by a scan are then not admitted at all.
+
+#![cfg(feature = "clock_cache")]
+
+use crate::block::Block;
+use crate::cache::{
+    cache_id_of, BlockCache, CacheID, CacheKey, CacheStats, EvictionCallback, PinID, StatsCounters,
+};
+use crate::types::mix64;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+// Number of rows of the frequency sketch; every key increments one counter per row.
+const SKETCH_DEPTH: usize = 4;
+// Counters per row and expected element; the sketch counts many more keys than the cache holds.
+const SKETCH_WIDTH_FACTOR: usize = 8;
+// Counters saturate at this value, as in TinyLFU's 4 bit counters.
+const MAX_FREQUENCY: u8 = 15;
+// The charge assumed per element when sizing the sketch of a cache bounded by charge.
+const ASSUMED_CHARGE: usize = 4096;
+
+/// A count-min sketch estimating how often keys have been accessed recently. All counters are
+/// halved once the number of increments reaches ten times the number of elements the
+/// cache is expected to hold, so that old
+/// accesses are forgotten.
+struct FrequencySketch {
+    counters: Vec<u8>,
+    width: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(entries: usize) -> FrequencySketch {
+        let entries = entries.max(16);
+        let width = (entries * SKETCH_WIDTH_FACTOR).next_power_of_two();
+        FrequencySketch {
+            counters: vec![0; width * SKETCH_DEPTH],
+            width,
+            additions: 0,
+            sample_size: 10 * entries,
+        }
+    }
+
+    fn indexes(&self, key: &CacheKey) -> impl Iterator<Item = usize> {
+        let h = key_hash(key);
+        let h2 = (h >> 32) | 1;
+        let width = self.width;
+        (0..SKETCH_DEPTH).map(move |i| {
+            i * width + (h.wrapping_add((i as u64).wrapping_mul(h2)) as usize & (width - 1))
+        })
+    }
+
+    fn increment(&mut self, key: &CacheKey) {
+        let mut added = false;
+        for i in self.indexes(key).collect::<Vec<_>>() {
+            if self.counters[i] < MAX_FREQUENCY {
+                self.counters[i] += 1;
+                added = true;
+            }
+        }
+        if added {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.reset();
+            }
+        }
+    }
+
+    fn frequency(&self, key: &CacheKey) -> u8 {
+        self.indexes(key).map(|i| self.counters[i]).min().unwrap()
+    }
+
+    fn reset(&mut self) {
+        for c in self.counters.iter_mut() {
+            *c /= 2;
+        }
+        self.additions /= 2;
+    }
+}
+
+fn key_hash(key: &CacheKey) -> u64 {
+    let mut a = [0; 8];
+    let mut b = [0; 8];
+    a.copy_from_slice(&key[..8]);
+    b.copy_from_slice(&key[8..]);
+    mix64(u64::from_le_bytes(a) ^ mix64(u64::from_le_bytes(b)))
+}
+
+struct Slot<T> {
+    key: CacheKey,
+    elem: T,
+    charge: usize,
+    // Set on every access, cleared when the clock hand passes.
+    referenced: bool,
+    id: PinID,
+    pins: usize,
+}
+
+/// A cache evicting elements using the CLOCK algorithm: elements are kept in a ring of slots
+/// that a clock hand sweeps to find a victim. An element that has been accessed since the hand
+/// last passed it is spared once; newly inserted elements aren't marked as accessed.
+///
+/// Like `Cache`, the capacity bounds either the number of elements (`new()`) or the sum of their
+/// charges (`new_charged()`), and elements can be pinned.
+pub struct ClockCache<T> {
+    slots: Vec<Option<Slot<T>>>,
+    free: Vec<usize>,
+    map: HashMap<CacheKey, usize>,
+    hand: usize,
+    cap: usize,
+    usage: usize,
+    cap_is_charge: bool,
+    id: u64,
+    next_entry_id: PinID,
+    sketch: Option<FrequencySketch>,
+    on_evict: Option<EvictionCallback<T>>,
+}
+
+impl<T> ClockCache<T> {
+    pub fn new(capacity: usize) -> ClockCache<T> {
+        assert!(capacity > 0);
+        ClockCache {
+            slots: vec![],
+            free: vec![],
+            map: HashMap::with_capacity(1024),
+            hand: 0,
+            cap: capacity,
+            usage: 0,
+            cap_is_charge: false,
+            id: 0,
+            next_entry_id: 0,
+            sketch: None,
+            on_evict: None,
+        }
+    }
+
+    /// Returns a cache whose capacity bounds the sum of the charges of its elements.
+    pub fn new_charged(capacity: usize) -> ClockCache<T> {
+        ClockCache {
+            cap_is_charge: true,
+            ..ClockCache::new(capacity)
+        }
+    }
+
+    /// Enables TinyLFU admission: once the cache is full, a new element is only inserted if its
+    /// key has been accessed more often recently than the keys of all elements it would replace.
+    /// Accesses are counted by `get()` and `pin()`, whether they hit or not; as elements are
+    /// usually inserted after a lookup missed, insertions aren't counted.
+    pub fn enable_admission(&mut self) {
+        let entries = if self.cap_is_charge {
+            self.cap / ASSUMED_CHARGE
+        } else {
+            self.cap
+        };
+        self.sketch = Some(FrequencySketch::new(entries));
+    }
+
+    /// Returns an ID that is unique for this cache and that can be used to partition the cache
+    /// among several users.
+    pub fn new_cache_id(&mut self) -> CacheID {
+        self.id += 1;
+        self.id
+    }
+
+    /// Sets a callback that is called for every element evicted to make room for another one.
+    pub fn set_eviction_callback(&mut self, f: EvictionCallback<T>) {
+        self.on_evict = Some(f);
+    }
+
+    /// How many elements the cache currently contains.
+    pub fn count(&self) -> usize {
+        self.map.len()
+    }
+
+    /// The sum of the charges of all elements in the cache.
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    /// The sum of the charges of the elements whose keys start with cache ID `id` (see
+    /// `Cache::usage_of()`).
+    pub fn usage_of(&self, id: CacheID) -> usize {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|slot| cache_id_of(&slot.key) == id)
+            .map(|slot| slot.charge)
+            .sum()
+    }
+
+    /// Evicts elements that aren't pinned, in clock order, until the sum of the charges is at
+    /// most `usage`.
+    pub fn shrink_to(&mut self, usage: usize) {
+        while self.usage > usage {
+            match self.next_victim(&[]) {
+                Some(idx) => self.evict(idx),
+                None => break,
+            }
+        }
+    }
+
+    /// Insert a new element with the given charge into the cache, replacing an existing element
+    /// with the same key. Elements are evicted until the element fits; if admission is enabled
+    /// and the element isn't admitted, nothing is inserted and false is returned.
+    pub fn insert_charged(&mut self, key: &CacheKey, elem: T, charge: usize) -> bool {
+        self.remove(key);
+
+        let victims = match self.select_victims(key, charge) {
+            Some(victims) => victims,
+            None => return false,
+        };
+        for idx in victims {
+            self.evict(idx);
+        }
+
+        self.next_entry_id += 1;
+        let slot = Slot {
+            key: *key,
+            elem,
+            charge,
+            referenced: false,
+            id: self.next_entry_id,
+            pins: 0,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.slots[idx] = Some(slot);
+                idx
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.map.insert(*key, idx);
+        self.usage += charge;
+        true
+    }
+
+    /// Returns the slots to evict to make room for an element with the given charge, or None if
+    /// the admission policy rejects the element.
+    fn select_victims(&mut self, key: &CacheKey, charge: usize) -> Option<Vec<usize>> {
+        let (mut count, mut usage) = (self.map.len(), self.usage);
+        let mut victims = vec![];
+        while self.is_full(count, usage, charge) {
+            let idx = match self.next_victim(&victims) {
+                Some(idx) => idx,
+                None => break,
+            };
+            let victim = self.slots[idx].as_ref().unwrap();
+            if let Some(ref sketch) = self.sketch {
+                if sketch.frequency(key) <= sketch.frequency(&victim.key) {
+                    return None;
+                }
+            }
+            count -= 1;
+            usage -= victim.charge;
+            victims.push(idx);
+        }
+        Some(victims)
+    }
+
+    fn is_full(&self, count: usize, usage: usize, charge: usize) -> bool {
+        if self.cap_is_charge {
+            usage + charge > self.cap
+        } else {
+            count >= self.cap
+        }
+    }
+
+    /// Advances the clock hand to the next element that is neither pinned, referenced nor in
+    /// `exclude`, clearing the reference bits of the elements passed.
+    fn next_victim(&mut self, exclude: &[usize]) -> Option<usize> {
+        let n = self.slots.len();
+        // Two rounds: the first one may only clear reference bits.
+        for _ in 0..2 * n {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % n;
+            if exclude.contains(&idx) {
+                continue;
+            }
+            match self.slots[idx] {
+                Some(ref mut slot) if slot.pins == 0 => {
+                    if !slot.referenced {
+                        return Some(idx);
+                    }
+                    slot.referenced = false;
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    fn evict(&mut self, idx: usize) {
+        let slot = self.take_slot(idx);
+        if let Some(ref f) = self.on_evict {
+            f(&slot.key, &slot.elem);
+        }
+    }
+
+    fn take_slot(&mut self, idx: usize) -> Slot<T> {
+        let slot = self.slots[idx].take().unwrap();
+        self.map.remove(&slot.key);
+        self.free.push(idx);
+        self.usage -= slot.charge;
+        slot
+    }
+
+    /// Returns whether an element is stored under `key`, without marking it as accessed.
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Retrieve an element from the cache, marking it as accessed.
+    pub fn get<'a>(&'a mut self, key: &CacheKey) -> Option<&'a T> {
+        if let Some(ref mut sketch) = self.sketch {
+            sketch.increment(key);
+        }
+        let idx = *self.map.get(key)?;
+        let slot = self.slots[idx].as_mut().unwrap();
+        slot.referenced = true;
+        Some(&slot.elem)
+    }
+
+    /// Retrieves an element from the cache and pins it (see `Cache::pin()`).
+    pub fn pin<'a>(&'a mut self, key: &CacheKey) -> Option<(&'a T, PinID)> {
+        if let Some(ref mut sketch) = self.sketch {
+            sketch.increment(key);
+        }
+        let idx = *self.map.get(key)?;
+        let slot = self.slots[idx].as_mut().unwrap();
+        slot.referenced = true;
+        slot.pins += 1;
+        Some((&slot.elem, slot.id))
+    }
+
+    /// Releases a pin taken using `pin()`. Pins of elements that have been removed or replaced
+    /// since are ignored. Elements are evicted if the cache exceeds its capacity.
+    pub fn unpin(&mut self, key: &CacheKey, id: PinID) {
+        let idx = match self.map.get(key) {
+            Some(idx) => *idx,
+            None => return,
+        };
+        match self.slots[idx] {
+            Some(ref mut slot) if slot.id == id && slot.pins > 0 => slot.pins -= 1,
+            _ => return,
+        }
+        while !self.fits() {
+            match self.next_victim(&[]) {
+                Some(idx) => self.evict(idx),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns true if the cache doesn't exceed its capacity.
+    fn fits(&self) -> bool {
+        if self.cap_is_charge {
+            self.usage <= self.cap
+        } else {
+            self.map.len() <= self.cap
+        }
+    }
+
+    /// Remove an element from the cache (for invalidation).
+    pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
+        let idx = *self.map.get(key)?;
+        Some(self.take_slot(idx).elem)
+    }
+}
+
+/// A `BlockCache` using CLOCK eviction (see `ClockCache`), optionally with TinyLFU admission.
+/// Usually created using `Options::with_cache_policy()`.
+pub struct ClockBlockCache {
+    cache: Mutex<ClockCache<Block>>,
+    stats: StatsCounters,
+}
+
+impl ClockBlockCache {
+    /// Returns a cache holding up to `capacity` blocks. The minimal allowed capacity is 1.
+    pub fn new(capacity: usize) -> ClockBlockCache {
+        ClockBlockCache {
+            cache: Mutex::new(ClockCache::new(capacity)),
+            stats: StatsCounters::default(),
+        }
+    }
+
+    /// Returns a cache holding blocks up to a total charge of `capacity` bytes. The minimal
+    /// allowed capacity is 1.
+    pub fn with_charge_capacity(capacity: usize) -> ClockBlockCache {
+        ClockBlockCache {
+            cache: Mutex::new(ClockCache::new_charged(capacity)),
+            stats: StatsCounters::default(),
+        }
+    }
+
+    /// Enables TinyLFU admission (see `ClockCache::enable_admission()`): blocks read only once,
+    /// e.g. by a scan, don't displace frequently read blocks.
+    pub fn with_admission(mut self) -> ClockBlockCache {
+        self.cache
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .enable_admission();
+        self
+    }
+
+    /// Sets a callback that is called for every block evicted from the cache to make room for
+    /// another one.
+    pub fn with_eviction_callback(mut self, f: EvictionCallback<Block>) -> ClockBlockCache {
+        self.cache
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_eviction_callback(f);
+        self
+    }
+
+    fn cache(&self) -> MutexGuard<'_, ClockCache<Block>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl BlockCache for ClockBlockCache {
+    fn insert(&self, key: &CacheKey, block: Block, charge: usize) {
+        let admitted = self.cache().insert_charged(key, block, charge);
+        self.stats.insert(admitted);
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Block> {
+        self.stats.lookup(self.cache().get(key).cloned())
+    }
+
+    fn erase(&self, key: &CacheKey) {
+        self.cache().remove(key);
+    }
+
+    fn contains(&self, key: &CacheKey) -> bool {
+        self.cache().contains(key)
+    }
+
+    fn count(&self) -> usize {
+        self.cache().count()
+    }
+
+    fn total_charge(&self) -> usize {
+        self.cache().usage()
+    }
+
+    fn charge_of(&self, id: CacheID) -> usize {
+        self.cache().usage_of(id)
+    }
+
+    fn shrink_to(&self, charge: usize) {
+        self.cache().shrink_to(charge);
+    }
+
+    fn pin(&self, key: &CacheKey) -> Option<(Block, PinID)> {
+        let pinned = self
+            .cache()
+            .pin(key)
+            .map(|(block, pin)| (block.clone(), pin));
+        self.stats.lookup(pinned)
+    }
+
+    fn unpin(&self, key: &CacheKey, pin: PinID) {
+        self.cache().unpin(key, pin);
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    fn new_cache_id(&self) -> CacheID {
+        self.cache().new_cache_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+
+    fn make_key(n: u32) -> CacheKey {
+        let mut key = [0; 16];
+        key[8..12].copy_from_slice(&n.to_be_bytes());
+        key
+    }
+
+    #[test]
+    fn test_clock_cache_second_chance() {
+        let mut cache = ClockCache::new(3);
+        for i in 0..3 {
+            cache.insert_charged(&make_key(i), i, 1);
+        }
+        assert_eq!(cache.get(&make_key(0)), Some(&0));
+        assert!(cache.contains(&make_key(1)));
+
+        // 0 has been accessed and is spared; 1 is evicted instead (contains() isn't an access).
+        cache.insert_charged(&make_key(3), 3, 1);
+        assert_eq!(cache.count(), 3);
+        assert_eq!(cache.get(&make_key(1)), None);
+        assert!(!cache.contains(&make_key(1)));
+        assert_eq!(cache.get(&make_key(0)), Some(&0));
+
+        assert_eq!(cache.remove(&make_key(0)), Some(0));
+        assert_eq!(cache.remove(&make_key(0)), None);
+        assert_eq!(cache.count(), 2);
+    }
+
+    #[test]
+    fn test_clock_cache_charge_and_pin() {
+        let mut cache = ClockCache::new_charged(100);
+        cache.insert_charged(&make_key(1), 1, 40);
+        cache.insert_charged(&make_key(2), 2, 40);
+        let (_, pin) = cache.pin(&make_key(1)).unwrap();
+
+        // The pinned element is skipped.
+        cache.insert_charged(&make_key(3), 3, 50);
+        assert_eq!(cache.usage(), 90);
+        assert_eq!(cache.get(&make_key(2)), None);
+
+        // Only pinned elements left: the cache exceeds its capacity until unpinned.
+        cache.pin(&make_key(3)).unwrap();
+        cache.insert_charged(&make_key(4), 4, 30);
+        assert_eq!(cache.usage(), 120);
+        // Unpinning evicts an unreferenced element first.
+        cache.unpin(&make_key(1), pin);
+        assert_eq!(cache.usage(), 90);
+        assert_eq!(cache.get(&make_key(4)), None);
+        assert_eq!(cache.get(&make_key(1)), Some(&1));
+    }
+
+    #[test]
+    fn test_clock_block_cache_stats() {
+        let cache = ClockBlockCache::new(2).with_admission();
+        let block = Block::new(crate::options::Options::default(), vec![0; 8]);
+        cache.insert(&make_key(1), block.clone(), 8);
+        cache.insert(&make_key(2), block.clone(), 8);
+        assert!(cache.lookup(&make_key(1)).is_some());
+        assert!(cache.pin(&make_key(2)).is_some());
+        assert!(cache.lookup(&make_key(3)).is_none());
+
+        // 3 hasn't been accessed more often than the blocks it would replace.
+        cache.insert(&make_key(3), block, 8);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                inserts: 2,
+                rejections: 1,
+            }
+        );
+        assert!((cache.stats().hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clock_cache_sketch() {
+        let mut sketch = FrequencySketch::new(64);
+        for _ in 0..5 {
+            sketch.increment(&make_key(1));
+        }
+        sketch.increment(&make_key(2));
+        assert_eq!(sketch.frequency(&make_key(1)), 5);
+        assert_eq!(sketch.frequency(&make_key(2)), 1);
+        assert_eq!(sketch.frequency(&make_key(3)), 0);
+
+        for _ in 0..20 {
+            sketch.increment(&make_key(1));
+        }
+        assert_eq!(sketch.frequency(&make_key(1)), MAX_FREQUENCY);
+
+        // Aging halves all counters.
+        for i in 10..sketch.sample_size as u32 {
+            sketch.increment(&make_key(i));
+        }
+        assert!(sketch.frequency(&make_key(1)) <= MAX_FREQUENCY / 2 + 1);
+    }
+
+    // Accesses a hot set of keys interleaved with scans over keys that are never read again, and
+    // returns the hit rate of the accesses to the hot set.
+    fn hot_set_hit_rate(
+        mut lookup: impl FnMut(&CacheKey) -> bool,
+        mut insert: impl FnMut(&CacheKey),
+    ) -> f64 {
+        let (hot, scan, rounds) = (50, 500, 20);
+        let (mut hits, mut accesses) = (0, 0);
+        let mut next_scan_key = 1_000_000;
+        for _ in 0..rounds {
+            for _ in 0..2 {
+                for i in 0..hot {
+                    accesses += 1;
+                    if lookup(&make_key(i)) {
+                        hits += 1;
+                    } else {
+                        insert(&make_key(i));
+                    }
+                }
+            }
+            for _ in 0..scan {
+                let key = make_key(next_scan_key);
+                next_scan_key += 1;
+                if !lookup(&key) {
+                    insert(&key);
+                }
+            }
+        }
+        f64::from(hits) / f64::from(accesses)
+    }
+
+    #[test]
+    fn test_clock_cache_hit_rates() {
+        let cap = 100;
+
+        let lru = std::cell::RefCell::new(Cache::new(cap));
+        let lru_rate = hot_set_hit_rate(
+            |k| lru.borrow_mut().get(k).is_some(),
+            |k| lru.borrow_mut().insert(k, ()),
+        );
+
+        let clock = std::cell::RefCell::new(ClockCache::new(cap));
+        let clock_rate = hot_set_hit_rate(
+            |k| clock.borrow_mut().get(k).is_some(),
+            |k| {
+                clock.borrow_mut().insert_charged(k, (), 1);
+            },
+        );
+
+        let mut c = ClockCache::new(cap);
+        c.enable_admission();
+        let tinylfu = std::cell::RefCell::new(c);
+        let tinylfu_rate = hot_set_hit_rate(
+            |k| tinylfu.borrow_mut().get(k).is_some(),
+            |k| {
+                tinylfu.borrow_mut().insert_charged(k, (), 1);
+            },
+        );
+
+        // The scans flush the hot set from the LRU and CLOCK caches; only the second access of
+        // every round hits.
+        assert!(lru_rate <= 0.5);
+        assert!(clock_rate <= 0.5);
+        // With admission, the scanned keys don't displace the hot set.
+        assert!(tinylfu_rate > 0.9);
+    }
+}
@@ -1,4 +1,9 @@
+use crate::key_types::{self, ValueType, MAX_SEQUENCE_NUMBER};
+
+use std::cell::Cell;
 use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 /// Comparator trait, supporting types that can be nested (i.e., add additional functionality on
 /// top of an inner comparator)
@@ -15,6 +20,12 @@ pub trait Cmp: Sync + Send {
     /// A unique identifier for a comparator. A comparator wrapper (like InternalKeyCmp) may
     /// return the id of its inner comparator.
     fn id(&self) -> &'static str;
+
+    /// Returns true if the comparator orders internal keys (see `key_types`). Tables built with
+    /// such a comparator record statistics about deletions and overwritten versions.
+    fn internal_keys(&self) -> bool {
+        false
+    }
 }
 
 /// Lexical comparator.
@@ -79,6 +90,119 @@ impl Cmp for DefaultCmp {
     }
 }
 
+/// Comparator for internal keys (see `key_types`), ordering them by user key using the wrapped
+/// comparator and then by descending sequence number.
+#[derive(Clone)]
+pub struct InternalKeyCmp(pub Arc<Box<dyn Cmp>>);
+
+impl Cmp for InternalKeyCmp {
+    fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+        key_types::cmp_internal_key(self.0.as_ref().as_ref(), a, b)
+    }
+
+    fn id(&self) -> &'static str {
+        self.0.id()
+    }
+
+    fn internal_keys(&self) -> bool {
+        true
+    }
+
+    fn find_shortest_sep(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        if a == b {
+            return a.to_vec();
+        }
+        let (seqa, keya, keyb) = match (
+            key_types::parse_internal_key(a),
+            key_types::parse_internal_key(b),
+        ) {
+            (Some((_, seqa, keya)), Some((_, _, keyb))) => (seqa, keya, keyb),
+            _ => return a.to_vec(),
+        };
+        let sep = self.0.find_shortest_sep(keya, keyb);
+        if sep.len() < keya.len() && self.0.cmp(keya, &sep) == Ordering::Less {
+            // A shorter user key sorts after `a` with any sequence number; use the largest one
+            // so that it sorts before all versions of that user key.
+            return key_types::build_internal_key(&sep, MAX_SEQUENCE_NUMBER, ValueType::TypeValue);
+        }
+        key_types::build_internal_key(&sep, seqa, ValueType::TypeValue)
+    }
+
+    fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+        match key_types::parse_internal_key(a) {
+            Some((_, seq, key)) => {
+                let succ = self.0.find_short_succ(key);
+                key_types::build_internal_key(&succ, seq, ValueType::TypeValue)
+            }
+            None => a.to_vec(),
+        }
+    }
+}
+
+thread_local! {
+    // Comparisons made through any CountingCmp on this thread.
+    static THREAD_COMPARISONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Comparator wrapping another one and counting how often it compares keys, to diagnose expensive
+/// comparators. Tables built with the wrapped comparator can be read with this one, and vice
+/// versa, as it reports the wrapped comparator's id. Only `cmp()` calls are counted.
+///
+/// Counts are kept for the comparator (shared by its clones, see `comparisons()`) and for the
+/// calling thread, which allows counting the comparisons of a single operation:
+/// `CountingCmp::count(|| table.get(key))`.
+#[derive(Clone)]
+pub struct CountingCmp {
+    inner: Arc<Box<dyn Cmp>>,
+    comparisons: Arc<AtomicU64>,
+}
+
+impl CountingCmp {
+    pub fn new(inner: Arc<Box<dyn Cmp>>) -> CountingCmp {
+        CountingCmp {
+            inner,
+            comparisons: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the number of comparisons made through this comparator and its clones so far.
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Runs `f` and returns its result along with the number of comparisons made through any
+    /// `CountingCmp` on this thread while it ran.
+    pub fn count<R>(f: impl FnOnce() -> R) -> (R, u64) {
+        let before = THREAD_COMPARISONS.with(|c| c.get());
+        let result = f();
+        (result, THREAD_COMPARISONS.with(|c| c.get()) - before)
+    }
+}
+
+impl Cmp for CountingCmp {
+    fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.comparisons.fetch_add(1, AtomicOrdering::Relaxed);
+        THREAD_COMPARISONS.with(|c| c.set(c.get() + 1));
+        self.inner.cmp(a, b)
+    }
+
+    fn find_shortest_sep(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        self.inner.find_shortest_sep(a, b)
+    }
+
+    fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+        self.inner.find_short_succ(a)
+    }
+
+    fn id(&self) -> &'static str {
+        self.inner.id()
+    }
+
+    fn internal_keys(&self) -> bool {
+        self.inner.internal_keys()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,4 +259,48 @@ mod tests {
             &[0xff, 0xff, 0xff, 0xff]
         );
     }
+
+    #[test]
+    fn test_cmp_internalkeycmp() {
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
+        let k = |key: &[u8], seq| key_types::build_internal_key(key, seq, ValueType::TypeValue);
+
+        assert_eq!(cmp.cmp(&k(b"abc", 2), &k(b"abc", 1)), Ordering::Less);
+        assert_eq!(cmp.cmp(&k(b"abc", 1), &k(b"abd", 2)), Ordering::Less);
+
+        let sep = cmp.find_shortest_sep(&k(b"abcdefg", 5), &k(b"abcz", 1));
+        assert_eq!(sep, k(b"abce", MAX_SEQUENCE_NUMBER));
+        assert_eq!(cmp.cmp(&k(b"abcdefg", 5), &sep), Ordering::Less);
+        assert_eq!(cmp.cmp(&sep, &k(b"abcz", 1)), Ordering::Less);
+
+        let sep = cmp.find_shortest_sep(&k(b"abc", 5), &k(b"abc", 1));
+        assert_eq!(sep, k(b"abc", 5));
+
+        let succ = cmp.find_short_succ(&k(b"abcd", 5));
+        assert_eq!(cmp.cmp(&k(b"abcd", 5), &succ), Ordering::Less);
+    }
+
+    #[test]
+    fn test_cmp_countingcmp() {
+        let cmp = CountingCmp::new(Arc::new(Box::new(DefaultCmp)));
+        let clone = cmp.clone();
+        assert_eq!(cmp.id(), DefaultCmp.id());
+        let (order, n) = CountingCmp::count(|| {
+            assert_eq!(clone.cmp(b"a", b"b"), Ordering::Less);
+            cmp.cmp(b"b", b"b")
+        });
+        assert_eq!((order, n), (Ordering::Equal, 2));
+        assert_eq!(cmp.find_short_succ(b"abc"), b"b");
+        assert_eq!(cmp.comparisons(), 2);
+
+        // Other threads' comparisons aren't counted by count().
+        let ((), n) = CountingCmp::count(|| {
+            let other = cmp.clone();
+            std::thread::spawn(move || other.cmp(b"a", b"b"))
+                .join()
+                .unwrap();
+        });
+        assert_eq!(n, 0);
+        assert_eq!(cmp.comparisons(), 3);
+    }
 }
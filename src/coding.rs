@@ -0,0 +1,139 @@
+//! Encoding of integers in tables: little-endian fixed-size integers and LEB128 varints, as in
+//! LevelDB. Decoding is checked; truncated or overlong input, which can only come from a corrupt
+//! file, is reported as `Error::Corruption` instead of being read past or misinterpreted.
+
+use crate::error::{Error, Result};
+
+use integer_encoding::VarInt;
+
+/// The maximum encoded length of a 32-bit varint.
+const MAX_VARINT32_LEN: usize = 5;
+/// The maximum encoded length of a 64-bit varint.
+const MAX_VARINT64_LEN: usize = 10;
+
+/// Decodes a 32-bit varint from the start of `src`, returning it and its encoded length.
+pub fn get_varint32(src: &[u8]) -> Result<(u32, usize)> {
+    let (v, n) = get_varint(src, MAX_VARINT32_LEN)?;
+    if v > u64::from(u32::MAX) {
+        return Err(Error::malformed(None, "varint32 overflow"));
+    }
+    Ok((v as u32, n))
+}
+
+/// Decodes a 64-bit varint from the start of `src`, returning it and its encoded length.
+pub fn get_varint64(src: &[u8]) -> Result<(u64, usize)> {
+    get_varint(src, MAX_VARINT64_LEN)
+}
+
+fn get_varint(src: &[u8], max_len: usize) -> Result<(u64, usize)> {
+    let mut v: u64 = 0;
+    for (i, &b) in src.iter().take(max_len).enumerate() {
+        if i == MAX_VARINT64_LEN - 1 && b > 1 {
+            return Err(Error::malformed(None, "varint64 overflow"));
+        }
+        v |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+    }
+    if src.len() < max_len {
+        Err(Error::malformed(None, "truncated varint"))
+    } else {
+        Err(Error::malformed(None, "varint too long"))
+    }
+}
+
+/// Decodes a little-endian u32 from the start of `src`.
+pub fn get_fixed32(src: &[u8]) -> Result<u32> {
+    match src.get(..4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(Error::malformed(None, "truncated fixed32")),
+    }
+}
+
+/// Decodes a little-endian u64 from the start of `src`.
+pub fn get_fixed64(src: &[u8]) -> Result<u64> {
+    match src.get(..8) {
+        Some(b) => {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(b);
+            Ok(u64::from_le_bytes(buf))
+        }
+        None => Err(Error::malformed(None, "truncated fixed64")),
+    }
+}
+
+/// Appends `v` as varint to `dst`.
+pub fn put_varint64(dst: &mut Vec<u8>, v: u64) {
+    let mut buf = [0; MAX_VARINT64_LEN];
+    let n = v.encode_var(&mut buf);
+    dst.extend_from_slice(&buf[..n]);
+}
+
+/// Appends `v` as little-endian u32 to `dst`.
+pub fn put_fixed32(dst: &mut Vec<u8>, v: u32) {
+    dst.extend_from_slice(&v.to_le_bytes());
+}
+
+/// Appends `v` as little-endian u64 to `dst`.
+pub fn put_fixed64(dst: &mut Vec<u8>, v: u64) {
+    dst.extend_from_slice(&v.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_coding_varint() {
+        for &v in &[
+            0,
+            1,
+            127,
+            128,
+            300,
+            1 << 31,
+            u64::from(u32::MAX),
+            1 << 40,
+            u64::MAX,
+        ] {
+            let mut buf = vec![];
+            put_varint64(&mut buf, v);
+            assert_eq!(buf, v.encode_var_vec());
+            assert_eq!(get_varint64(&buf).unwrap(), (v, buf.len()));
+            buf.push(0x55);
+            assert_eq!(get_varint64(&buf).unwrap(), (v, buf.len() - 1));
+            buf.pop();
+            assert!(get_varint64(&buf[..buf.len() - 1]).is_err());
+            match u32::try_from(v) {
+                Ok(v32) => assert_eq!(get_varint32(&buf).unwrap(), (v32, buf.len())),
+                Err(_) => assert!(get_varint32(&buf).unwrap_err().is_corruption()),
+            }
+        }
+        let mut buf = vec![];
+        put_varint64(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+
+        assert!(get_varint64(&[]).unwrap_err().is_corruption());
+        // Eleven bytes, or a tenth byte with more than one bit.
+        assert!(get_varint64(&[0x80; 11]).is_err());
+        let mut overflow = vec![0xff; 9];
+        overflow.push(0x02);
+        assert!(get_varint64(&overflow).is_err());
+        assert!(get_varint32(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_coding_fixed() {
+        let mut buf = vec![];
+        put_fixed32(&mut buf, 0x0403_0201);
+        put_fixed64(&mut buf, 0x0c0b_0a09_0807_0605);
+        assert_eq!(buf, (1..=12).collect::<Vec<u8>>());
+        assert_eq!(get_fixed32(&buf).unwrap(), 0x0403_0201);
+        assert_eq!(get_fixed64(&buf[4..]).unwrap(), 0x0c0b_0a09_0807_0605);
+        assert!(get_fixed32(&buf[10..]).unwrap_err().is_corruption());
+        assert!(get_fixed64(&buf[5..]).unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,378 @@
+//! Concatenation of tables whose key ranges don't overlap, e.g. the shards of a parallel build or
+//! the files of one level: `ConcatIterator` reads them as if they were one table, and
+//! `concatenate()` writes them into one table without merging them.
+
+use crate::cmp::Cmp;
+use crate::error::{Error, Result};
+use crate::options::{CompressionType, Options};
+use crate::table_builder::TableBuilder;
+use crate::table_reader::{Table, TableIterator};
+use crate::ttl;
+use crate::types::{current_key_val, SSIterator};
+use crate::version::LevelFile;
+
+use std::cmp::Ordering;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Returns the tables that aren't empty, with their key ranges. Fails with
+/// `Error::InvalidArgument` unless all tables use `cmp` and are ordered by it without
+/// overlapping.
+fn level_files(cmp: &Arc<Box<dyn Cmp>>, tables: &[Table]) -> Result<Vec<LevelFile>> {
+    let mut files: Vec<LevelFile> = vec![];
+    for table in tables {
+        if table.options().cmp.id() != cmp.id() {
+            return Err(Error::InvalidArgument(format!(
+                "table ordered by {} can't be concatenated with tables ordered by {}",
+                table.options().cmp.id(),
+                cmp.id()
+            )));
+        }
+        if table.index_handles().is_empty() {
+            continue;
+        }
+        let file = LevelFile::new(table.clone())?;
+        if let Some(prev) = files.last() {
+            if cmp.cmp(&prev.largest, &file.smallest) != Ordering::Less {
+                return Err(Error::InvalidArgument(format!(
+                    "table starting at {:?} overlaps or precedes the previous one",
+                    String::from_utf8_lossy(&file.smallest)
+                )));
+            }
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
+
+/// ConcatIterator iterates over the entries of several tables whose key ranges don't overlap,
+/// given in key order, opening one table at a time. Seeks find the table containing the key
+/// without reading the others.
+pub struct ConcatIterator {
+    files: Vec<LevelFile>,
+    // The index of the table the iterator is positioned in, and an iterator over it.
+    current: Option<(usize, TableIterator)>,
+    err: Option<Error>,
+}
+
+impl ConcatIterator {
+    /// Returns an iterator over `tables`, reading the first and last key of each. Fails with
+    /// `Error::InvalidArgument` if the tables don't share a comparator or are out of order.
+    pub fn new(tables: &[Table]) -> Result<ConcatIterator> {
+        let files = match tables.first() {
+            Some(first) => level_files(&first.options().cmp, tables)?,
+            None => vec![],
+        };
+        Ok(ConcatIterator::with_files(files))
+    }
+
+    /// Returns an iterator over `files`, which must be ordered by key without overlapping and
+    /// share a comparator, like the files of a level of a `Version`.
+    pub(crate) fn with_files(files: Vec<LevelFile>) -> ConcatIterator {
+        ConcatIterator {
+            files,
+            current: None,
+            err: None,
+        }
+    }
+
+    /// Positions the iterator at the first entry of the `i`-th table, or of the next table that
+    /// isn't exhausted.
+    fn enter(&mut self, mut i: usize) -> bool {
+        while i < self.files.len() {
+            let mut iter = self.files[i].table.iter();
+            if iter.advance() {
+                self.current = Some((i, iter));
+                return true;
+            }
+            if let Some(e) = iter.take_error() {
+                return self.fail(e);
+            }
+            i += 1;
+        }
+        self.current = None;
+        false
+    }
+
+    fn fail(&mut self, e: Error) -> bool {
+        self.err = Some(e);
+        self.current = None;
+        false
+    }
+}
+
+impl SSIterator for ConcatIterator {
+    fn advance(&mut self) -> bool {
+        let next = match self.current {
+            Some((i, ref mut iter)) => {
+                if iter.advance() {
+                    return true;
+                }
+                if let Some(e) = iter.take_error() {
+                    return self.fail(e);
+                }
+                i + 1
+            }
+            None => {
+                self.err = None;
+                0
+            }
+        };
+        self.enter(next)
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        match self.current {
+            Some((_, ref iter)) => iter.current(key, val),
+            None => false,
+        }
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.current.as_ref()?.1.current_key()
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.reset();
+        let cmp = match self.files.first() {
+            Some(file) => file.table.options().cmp.clone(),
+            None => return,
+        };
+        let i = self
+            .files
+            .partition_point(|f| cmp.cmp(&f.largest, key) == Ordering::Less);
+        if i == self.files.len() {
+            return;
+        }
+        let mut iter = self.files[i].table.iter();
+        iter.seek(key);
+        if iter.valid() {
+            self.current = Some((i, iter));
+        } else if let Some(e) = iter.take_error() {
+            self.fail(e);
+        } else {
+            self.enter(i + 1);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = None;
+        self.err = None;
+    }
+
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn prev(&mut self) -> bool {
+        let i = match self.current {
+            Some((i, ref mut iter)) => {
+                if iter.prev() {
+                    return true;
+                }
+                if let Some(e) = iter.take_error() {
+                    return self.fail(e);
+                }
+                i
+            }
+            None => return false,
+        };
+        self.current = None;
+        if i == 0 {
+            return false;
+        }
+        // The previous table's last entry is its largest key.
+        let mut iter = self.files[i - 1].table.iter();
+        iter.seek(&self.files[i - 1].largest);
+        if iter.valid() {
+            self.current = Some((i - 1, iter));
+            return true;
+        }
+        let e = iter
+            .take_error()
+            .unwrap_or_else(|| Error::malformed(None, "largest key of table not found"));
+        self.fail(e)
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.err
+            .as_ref()
+            .or_else(|| self.current.as_ref().and_then(|(_, iter)| iter.status()))
+    }
+}
+
+/// Writes the entries of `tables`, which must not overlap and be given in key order, to `builder`
+/// and finishes it, returning the size of the new table. The tables must use the builder's
+/// comparator, and their keys must follow the entries added to `builder` before.
+///
+/// The data blocks of a table are read once and copied as they are (see
+/// `TableBuilder::add_raw_block()`) if it encodes entries like the builder would (entry
+/// expiration, value compression, codec and checksums, no blobs) and the builder supports raw
+/// blocks; otherwise its entries are added one by one. Either way, no merge is needed. Expirations
+/// of whole tables (see `TableBuilder::set_expiration()`) aren't carried over.
+pub fn concatenate<Dst: Write>(tables: &[Table], mut builder: TableBuilder<Dst>) -> Result<u64> {
+    let cmp = builder.options().cmp.clone();
+    for file in level_files(&cmp, tables)? {
+        if copies_blocks(builder.options(), &file.table) {
+            copy_blocks(&file.table, &mut builder)?;
+        } else {
+            copy_entries(&file.table, &mut builder)?;
+        }
+    }
+    builder.finish()
+}
+
+/// Returns true if the data blocks of `table` can be added to a builder using `opt` as they are.
+fn copies_blocks(opt: &Options, table: &Table) -> bool {
+    let props = match table.properties() {
+        Some(props) => props,
+        None => return false,
+    };
+    opt.blob_threshold.is_none()
+        && opt.properties_collectors.is_empty()
+        && opt.block_stats.is_none()
+        && !props.blob_values
+        && props.entry_expiration == opt.entry_expiration
+        && props.value_compression == (opt.value_compression != CompressionType::CompressionNone)
+        && props.value_checksums == opt.value_checksums
+        && props.value_codec.as_deref() == opt.value_codec.as_ref().map(|c| c.name())
+        && props.key_codec.as_deref() == opt.key_codec.as_ref().map(|c| c.name())
+}
+
+fn copy_blocks<Dst: Write>(table: &Table, builder: &mut TableBuilder<Dst>) -> Result<()> {
+    for handle in table.index_handles() {
+        let (contents, ctype) = table.read_encoded_block(&handle)?;
+        builder.add_encoded_block(&contents, None, ctype)?;
+    }
+    Ok(())
+}
+
+fn copy_entries<Dst: Write>(table: &Table, builder: &mut TableBuilder<Dst>) -> Result<()> {
+    let mut iter = table.iter();
+    while iter.advance() {
+        let (key, val) =
+            current_key_val(&iter).ok_or_else(|| Error::malformed(None, "couldn't read value"))?;
+        let expiration = iter.current_expiration();
+        if expiration == ttl::NO_EXPIRATION {
+            builder.add(&key, &val)?;
+        } else {
+            builder.add_with_expiration(&key, &val, expiration)?;
+        }
+    }
+    match iter.take_error() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, test_iterator_properties, SSIteratorIter};
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:04}", i).into_bytes()
+    }
+
+    fn build_table(opt: &Options, keys: std::ops::Range<usize>) -> Table {
+        let entries: Vec<(Vec<u8>, String)> =
+            keys.map(|i| (key(i), format!("value{}", i))).collect();
+        test_util::build_table(opt, &entries)
+    }
+
+    fn tables(opt: &Options) -> Vec<Table> {
+        vec![
+            build_table(opt, 0..100),
+            build_table(opt, 100..100),
+            build_table(opt, 200..300),
+            build_table(opt, 300..301),
+        ]
+    }
+
+    fn expected_keys() -> Vec<Vec<u8>> {
+        (0..100).chain(200..301).map(key).collect()
+    }
+
+    #[test]
+    fn test_concat_iterator() {
+        let mut opt = Options::default();
+        opt.block_size = 128;
+        let tables = tables(&opt);
+
+        let mut iter = ConcatIterator::new(&tables).unwrap();
+        let keys: Vec<Vec<u8>> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(keys, expected_keys());
+        assert!(!iter.valid());
+
+        iter.seek(&key(150));
+        assert_eq!(iter.current_key(), Some(&key(200)[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&key(99)[..]));
+        assert_eq!(iter.value(), Some(b"value99".to_vec()));
+        iter.seek(&key(299));
+        assert!(iter.advance());
+        assert_eq!(iter.current_key(), Some(&key(300)[..]));
+        assert!(!iter.advance());
+        iter.seek(&key(301));
+        assert!(!iter.valid());
+        iter.seek(&key(0));
+        assert!(!iter.prev());
+
+        // Four entries spread over three tables.
+        let four = [
+            build_table(&opt, 0..2),
+            build_table(&opt, 2..3),
+            build_table(&opt, 3..4),
+        ];
+        test_iterator_properties(ConcatIterator::new(&four).unwrap());
+
+        let mut empty = ConcatIterator::new(&[]).unwrap();
+        assert!(!empty.advance());
+
+        let overlapping = [build_table(&opt, 0..10), build_table(&opt, 9..20)];
+        assert!(matches!(
+            ConcatIterator::new(&overlapping),
+            Err(Error::InvalidArgument(_))
+        ));
+        let reversed = [build_table(&opt, 10..20), build_table(&opt, 0..10)];
+        assert!(matches!(
+            ConcatIterator::new(&reversed),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_concatenate() {
+        let mut opt = Options::default();
+        opt.block_size = 128;
+        opt.compression_type = CompressionType::CompressionSnappy;
+        let tables = tables(&opt);
+        let num_blocks: u64 = tables
+            .iter()
+            .map(|t| t.properties().unwrap().num_data_blocks)
+            .sum();
+
+        // With the same options, blocks are copied; with value compression, entries are.
+        let mut compressed = opt.clone();
+        compressed.value_compression = CompressionType::CompressionSnappy;
+        for (opt, copied) in [(opt, true), (compressed, false)] {
+            let mut d = vec![];
+            let size = concatenate(&tables, TableBuilder::new(opt.clone(), &mut d)).unwrap();
+            let table = Table::new(opt, Box::new(d), size).unwrap();
+            let props = table.properties().unwrap();
+            assert_eq!(props.num_entries, 201);
+            assert_eq!(props.num_data_blocks == num_blocks, copied);
+            let mut iter = table.iter();
+            let keys: Vec<Vec<u8>> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+            assert_eq!(keys, expected_keys());
+            assert_eq!(table.get(&key(250)).unwrap(), Some(b"value250".to_vec()));
+        }
+
+        let overlapping = [build_table(&Options::default(), 0..10), tables[0].clone()];
+        assert!(matches!(
+            concatenate(&overlapping, TableBuilder::new(Options::default(), vec![])),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
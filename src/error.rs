@@ -1,104 +1,207 @@
+use crate::blockhandle::BlockHandle;
+
 use std::convert::From;
-use std::error::Error;
+use std::error;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::result;
 use std::sync;
 
-use snap;
-
-/// StatusCode describes various failure modes of database operations.
-#[derive(Clone, Debug, PartialEq)]
-#[allow(dead_code)]
-pub enum StatusCode {
-    OK,
-
-    AlreadyExists,
-    Corruption,
-    CompressionError,
-    IOError,
-    InvalidArgument,
-    InvalidData,
-    LockError,
-    NotFound,
-    NotSupported,
-    PermissionDenied,
-    Unknown,
+/// CorruptionKind describes what exactly was found to be wrong with data read from a table.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CorruptionKind {
+    /// The checksum stored after a block doesn't match the block's contents.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A block is tagged with a compression type unknown to this implementation.
+    UnknownCompressionType(u8),
+    /// A compressed block couldn't be decompressed.
+    #[cfg(feature = "snappy")]
+    Decompression(snap::Error),
+    /// A structure (footer, block, block handle, ...) couldn't be decoded.
+    Malformed(String),
+    /// A compressed block or value claims to decompress to more than
+    /// `Options::max_decompressed_block_size` bytes.
+    DecompressedSizeExceeded { size: usize, limit: usize },
+}
+
+impl Display for CorruptionKind {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            CorruptionKind::ChecksumMismatch { expected, actual } => write!(
+                fmt,
+                "checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+            CorruptionKind::UnknownCompressionType(t) => {
+                write!(fmt, "unknown compression type {}", t)
+            }
+            #[cfg(feature = "snappy")]
+            CorruptionKind::Decompression(e) => write!(fmt, "decompression failed: {}", e),
+            CorruptionKind::Malformed(msg) => write!(fmt, "malformed data: {}", msg),
+            CorruptionKind::DecompressedSizeExceeded { size, limit } => write!(
+                fmt,
+                "decompressed size {} exceeds limit of {} bytes",
+                size, limit
+            ),
+        }
+    }
 }
 
-/// Status encapsulates a `StatusCode` and an error message. It can be displayed, and also
-/// implements `Error`. io::Error can be converted into Status.
-#[derive(Clone, Debug, PartialEq)]
-pub struct Status {
-    pub code: StatusCode,
-    pub err: String,
+/// Error describes the failure modes of table operations. Corruption errors carry enough context
+/// (where in the file, which block) to act on them without parsing error messages; I/O errors
+/// wrap the original `io::Error`, which is available via `Error::source()`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Data read from a table is corrupt.
+    Corruption {
+        /// Offset in the file at which the corrupt structure starts, if known.
+        file_offset: Option<u64>,
+        /// Handle of the corrupt block, if the corruption was detected in a block.
+        block_handle: Option<BlockHandle>,
+        kind: CorruptionKind,
+    },
+    /// An I/O operation on the underlying file or sink failed.
+    Io(io::Error),
+    /// An argument or option passed by the caller is invalid.
+    InvalidArgument(String),
+    /// The requested operation or format feature isn't supported.
+    NotSupported(String),
+    /// Compressing a block failed.
+    #[cfg(feature = "snappy")]
+    Compression(snap::Error),
+    /// A lock protecting shared state (e.g. the block cache) was poisoned.
+    LockPoisoned,
 }
 
-impl Default for Status {
-    fn default() -> Status {
-        Status {
-            code: StatusCode::OK,
-            err: String::new(),
+impl Error {
+    /// Returns a corruption error for the block at `handle`.
+    pub fn corrupt_block(handle: &BlockHandle, kind: CorruptionKind) -> Error {
+        Error::Corruption {
+            file_offset: Some(handle.offset()),
+            block_handle: Some(handle.clone()),
+            kind,
+        }
+    }
+
+    /// Returns a corruption error for malformed data found at `file_offset`.
+    pub fn malformed(file_offset: Option<u64>, msg: &str) -> Error {
+        Error::Corruption {
+            file_offset,
+            block_handle: None,
+            kind: CorruptionKind::Malformed(msg.to_string()),
         }
     }
-}
 
-impl Display for Status {
-    fn fmt(&self, fmt: &mut Formatter) -> result::Result<(), fmt::Error> {
-        fmt.write_str(&ToString::to_string(&self))
+    /// Returns true if this error was caused by corrupt data.
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, Error::Corruption { .. })
     }
 }
 
-impl Error for Status {
-    fn description(&self) -> &str {
-        &self.err
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Corruption {
+                file_offset,
+                block_handle,
+                kind,
+            } => {
+                write!(fmt, "corruption: {}", kind)?;
+                if let Some(h) = block_handle {
+                    write!(fmt, " in block at {} (size {})", h.offset(), h.size())
+                } else if let Some(off) = file_offset {
+                    write!(fmt, " at offset {}", off)
+                } else {
+                    Ok(())
+                }
+            }
+            Error::Io(e) => write!(fmt, "I/O error: {}", e),
+            Error::InvalidArgument(msg) => write!(fmt, "invalid argument: {}", msg),
+            Error::NotSupported(msg) => write!(fmt, "not supported: {}", msg),
+            #[cfg(feature = "snappy")]
+            Error::Compression(e) => write!(fmt, "compression failed: {}", e),
+            Error::LockPoisoned => fmt.write_str("lock poisoned"),
+        }
     }
 }
 
-impl Status {
-    pub fn new(code: StatusCode, msg: &str) -> Status {
-        let err = if msg.is_empty() {
-            format!("{:?}", code)
-        } else {
-            format!("{:?}: {}", code, msg)
-        };
-        Status { code, err }
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            #[cfg(feature = "snappy")]
+            Error::Compression(e) => Some(e),
+            #[cfg(feature = "snappy")]
+            Error::Corruption {
+                kind: CorruptionKind::Decompression(e),
+                ..
+            } => Some(e),
+            _ => None,
+        }
     }
 }
 
-impl From<io::Error> for Status {
-    fn from(e: io::Error) -> Status {
-        let c = match e.kind() {
-            io::ErrorKind::NotFound => StatusCode::NotFound,
-            io::ErrorKind::InvalidData => StatusCode::Corruption,
-            io::ErrorKind::InvalidInput => StatusCode::InvalidArgument,
-            io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
-            _ => StatusCode::IOError,
-        };
-
-        Status::new(c, &e.to_string())
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
     }
 }
 
-impl<T> From<sync::PoisonError<T>> for Status {
-    fn from(_: sync::PoisonError<T>) -> Status {
-        Status::new(StatusCode::LockError, "lock poisoned")
+impl<T> From<sync::PoisonError<T>> for Error {
+    fn from(_: sync::PoisonError<T>) -> Error {
+        Error::LockPoisoned
     }
 }
 
-impl From<snap::Error> for Status {
-    fn from(e: snap::Error) -> Status {
-        Status {
-            code: StatusCode::CompressionError,
-            err: e.to_string(),
-        }
+#[cfg(feature = "snappy")]
+impl From<snap::Error> for Error {
+    fn from(e: snap::Error) -> Error {
+        Error::Compression(e)
     }
 }
 
 /// The sstable result type.
-pub type Result<T> = result::Result<T, Status>;
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error as _;
 
-/// err returns a new Status wrapped in a Result.
-pub fn err<T>(code: StatusCode, msg: &str) -> Result<T> {
-    Err(Status::new(code, msg))
+    #[test]
+    fn test_error_corruption_context() {
+        let h = BlockHandle::new(123, 45);
+        let e = Error::corrupt_block(
+            &h,
+            CorruptionKind::ChecksumMismatch {
+                expected: 1,
+                actual: 2,
+            },
+        );
+        assert!(e.is_corruption());
+        match e {
+            Error::Corruption {
+                file_offset: Some(123),
+                block_handle: Some(ref bh),
+                kind: CorruptionKind::ChecksumMismatch { .. },
+            } => assert_eq!(bh.size(), 45),
+            _ => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(
+            e.to_string(),
+            "corruption: checksum mismatch (expected 0x00000001, got 0x00000002) in block at 123 \
+             (size 45)"
+        );
+        assert!(e.source().is_none());
+    }
+
+    #[test]
+    fn test_error_io_source() {
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert!(!e.is_corruption());
+        assert_eq!(e.source().unwrap().to_string(), "gone");
+    }
 }
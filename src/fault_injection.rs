@@ -0,0 +1,317 @@
+//! Failure injection for testing applications built on this crate, enabled by the
+//! `fault_injection` feature.
+//!
+//! `FaultInjectingRandomAccess` wraps a file tables are read from, `FaultInjectingSink` one they
+//! are written to. Both make I/O operations fail or come up short as described by a `FaultPlan`:
+//! at fixed file offsets, or randomly at a given rate. Random faults are drawn from a generator
+//! seeded by the plan, so a failing test can be reproduced exactly.
+
+#![cfg(feature = "fault_injection")]
+
+use crate::error::{Error, Result};
+use crate::types::{mix64, RandomAccess};
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Describes the faults to inject. An operation covers the byte range it reads or writes; faults
+/// at a fixed offset affect every operation covering that offset.
+#[derive(Clone, Debug, Default)]
+pub struct FaultPlan {
+    /// Operations covering one of these offsets fail with an I/O error, without transferring any
+    /// bytes.
+    pub fail_at: Vec<u64>,
+    /// Operations covering one of these offsets (other than at their first byte) return early,
+    /// having transferred only the bytes before the offset.
+    pub short_at: Vec<u64>,
+    /// Writes covering one of these offsets (other than at their first byte) are torn: the bytes
+    /// before the offset are written, then the write fails with an I/O error.
+    pub torn_at: Vec<u64>,
+    /// Probability (0.0 to 1.0) of any operation failing.
+    pub error_rate: f64,
+    /// Probability (0.0 to 1.0) of any operation transferring only part of its bytes.
+    pub short_rate: f64,
+    /// Seed of the generator deciding on random faults.
+    pub seed: u64,
+}
+
+/// The fault to inject into an operation.
+#[derive(Debug, PartialEq)]
+enum Fault {
+    None,
+    Fail,
+    /// Transfer only this many bytes.
+    Short(usize),
+    /// Write this many bytes, then fail.
+    Torn(usize),
+}
+
+impl FaultPlan {
+    /// Returns the fault for the `op`-th operation, covering `len` bytes at `off`.
+    fn fault(&self, op: u64, off: u64, len: usize) -> Fault {
+        let end = off + len as u64;
+        let inside = |o: &u64| off < *o && *o < end;
+        if self.fail_at.iter().any(|o| off <= *o && *o < end)
+            || draw(self.seed, 2 * op) < self.error_rate
+        {
+            return Fault::Fail;
+        }
+        if let Some(o) = self.torn_at.iter().filter(|o| inside(o)).min() {
+            return Fault::Torn((o - off) as usize);
+        }
+        if let Some(o) = self.short_at.iter().filter(|o| inside(o)).min() {
+            return Fault::Short((o - off) as usize);
+        }
+        if len > 1 && draw(self.seed, 2 * op + 1) < self.short_rate {
+            let r = mix64(self.seed ^ mix64(op)) as usize;
+            return Fault::Short(1 + r % (len - 1));
+        }
+        Fault::None
+    }
+}
+
+/// Returns a number in [0, 1) derived from `seed` and `n`.
+fn draw(seed: u64, n: u64) -> f64 {
+    (mix64(seed.wrapping_add(mix64(n))) >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+fn injected_error(what: &str, off: u64) -> io::Error {
+    io::Error::other(format!("injected {} at offset {}", what, off))
+}
+
+/// A `RandomAccess` injecting faults into the reads of the wrapped file. Short reads return the
+/// number of bytes actually read, like reads at the end of a file; `FaultPlan::torn_at` offsets
+/// cause short reads as well.
+pub struct FaultInjectingRandomAccess<R: RandomAccess> {
+    inner: R,
+    plan: FaultPlan,
+    enabled: AtomicBool,
+    ops: AtomicU64,
+    injected: AtomicU64,
+}
+
+impl<R: RandomAccess> FaultInjectingRandomAccess<R> {
+    pub fn new(inner: R, plan: FaultPlan) -> FaultInjectingRandomAccess<R> {
+        FaultInjectingRandomAccess {
+            inner,
+            plan,
+            enabled: AtomicBool::new(true),
+            ops: AtomicU64::new(0),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Enables or disables fault injection, e.g. to check that an application recovers once the
+    /// faults are gone. Injection is enabled initially.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Returns the number of faults injected so far.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::SeqCst)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: RandomAccess> RandomAccess for FaultInjectingRandomAccess<R> {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        if !self.enabled.load(Ordering::SeqCst) {
+            return self.inner.read_at(off, dst);
+        }
+        let op = self.ops.fetch_add(1, Ordering::SeqCst);
+        match self.plan.fault(op, off, dst.len()) {
+            Fault::None => self.inner.read_at(off, dst),
+            Fault::Fail => {
+                self.injected.fetch_add(1, Ordering::SeqCst);
+                Err(Error::Io(injected_error("read error", off)))
+            }
+            Fault::Short(n) | Fault::Torn(n) => {
+                self.injected.fetch_add(1, Ordering::SeqCst);
+                self.inner.read_at(off, &mut dst[..n])
+            }
+        }
+    }
+
+    fn file_id(&self) -> Option<u64> {
+        self.inner.file_id()
+    }
+}
+
+/// A sink injecting faults into the writes to the wrapped sink. Offsets count the bytes written
+/// through the `FaultInjectingSink`. Short writes return the number of bytes written, as
+/// `Write::write()` may; torn writes persist part of the buffer and then fail.
+pub struct FaultInjectingSink<W: Write> {
+    inner: W,
+    plan: FaultPlan,
+    enabled: bool,
+    offset: u64,
+    ops: u64,
+    injected: u64,
+}
+
+impl<W: Write> FaultInjectingSink<W> {
+    pub fn new(inner: W, plan: FaultPlan) -> FaultInjectingSink<W> {
+        FaultInjectingSink {
+            inner,
+            plan,
+            enabled: true,
+            offset: 0,
+            ops: 0,
+            injected: 0,
+        }
+    }
+
+    /// Enables or disables fault injection. Injection is enabled initially.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Returns the number of faults injected so far.
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Returns the number of bytes written to the wrapped sink.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for FaultInjectingSink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let fault = if self.enabled {
+            self.ops += 1;
+            self.plan.fault(self.ops - 1, self.offset, buf.len())
+        } else {
+            Fault::None
+        };
+        if fault != Fault::None {
+            self.injected += 1;
+        }
+        match fault {
+            Fault::None => {
+                let n = self.inner.write(buf)?;
+                self.offset += n as u64;
+                Ok(n)
+            }
+            Fault::Fail => Err(injected_error("write error", self.offset)),
+            Fault::Short(n) => {
+                let n = self.inner.write(&buf[..n])?;
+                self.offset += n as u64;
+                Ok(n)
+            }
+            Fault::Torn(n) => {
+                self.inner.write_all(&buf[..n])?;
+                self.offset += n as u64;
+                Err(injected_error("torn write", self.offset))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::table_builder::TableBuilder;
+    use crate::table_reader::Table;
+
+    fn build(dst: &mut dyn Write) -> Result<()> {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let mut b = TableBuilder::new(opt, dst);
+        for i in 0..100 {
+            let k = format!("key{:03}", i);
+            b.add(k.as_bytes(), b"value")?;
+        }
+        b.finish().map(|_| ())
+    }
+
+    #[test]
+    fn test_fault_plan_deterministic() {
+        let plan = FaultPlan {
+            error_rate: 0.2,
+            short_rate: 0.2,
+            seed: 42,
+            ..Default::default()
+        };
+        let faults: Vec<Fault> = (0..100).map(|op| plan.fault(op, 0, 100)).collect();
+        let again: Vec<Fault> = (0..100).map(|op| plan.fault(op, 0, 100)).collect();
+        assert_eq!(faults, again);
+        let failed = faults.iter().filter(|f| **f == Fault::Fail).count();
+        assert!(failed > 5 && failed < 40, "{}", failed);
+        assert!(faults.iter().any(|f| matches!(f, Fault::Short(1..=99))));
+
+        let plan = FaultPlan {
+            fail_at: vec![10],
+            short_at: vec![50],
+            ..Default::default()
+        };
+        assert_eq!(plan.fault(0, 0, 10), Fault::None);
+        assert_eq!(plan.fault(0, 5, 10), Fault::Fail);
+        assert_eq!(plan.fault(0, 40, 20), Fault::Short(10));
+        assert_eq!(plan.fault(0, 50, 20), Fault::None);
+    }
+
+    #[test]
+    fn test_fault_injecting_sink() {
+        let mut plan = FaultPlan {
+            torn_at: vec![100],
+            ..Default::default()
+        };
+        let mut sink = FaultInjectingSink::new(vec![], plan.clone());
+        assert!(build(&mut sink).is_err());
+        assert_eq!(sink.injected(), 1);
+        assert_eq!(sink.get_ref().len(), 100);
+
+        plan.torn_at.clear();
+        plan.fail_at.push(0);
+        let mut sink = FaultInjectingSink::new(vec![], plan);
+        assert!(build(&mut sink).is_err());
+        assert!(sink.get_ref().is_empty());
+        sink.set_enabled(false);
+        assert!(build(&mut sink).is_ok());
+        assert_eq!(sink.offset(), sink.get_ref().len() as u64);
+    }
+
+    #[test]
+    fn test_fault_injecting_random_access() {
+        let mut d = vec![];
+        build(&mut d).unwrap();
+        let size = d.len() as u64;
+
+        // Every read fails, so opening the table fails.
+        let plan = FaultPlan {
+            error_rate: 1.0,
+            ..Default::default()
+        };
+        let file = FaultInjectingRandomAccess::new(d.clone(), plan);
+        assert!(Table::new(Options::default(), Box::new(file), size).is_err());
+
+        // Short reads of the first data block are detected as corruption.
+        let plan = FaultPlan {
+            short_at: vec![10],
+            ..Default::default()
+        };
+        let file = FaultInjectingRandomAccess::new(d, plan);
+        let table = Table::new(Options::default(), Box::new(file), size).unwrap();
+        assert!(table.get(b"key000").err().unwrap().is_corruption());
+        assert!(table.get(b"key099").unwrap().is_some());
+    }
+}
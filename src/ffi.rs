@@ -0,0 +1,558 @@
+//! A C API for building and reading tables, enabled by the `ffi` feature.
+//!
+//! All objects are handed out as opaque handles (pointers) which must be released using the
+//! matching `*_free()` function. Functions returning `int` return `SSTABLE_OK` on success, a
+//! negative error code on failure; the message of the most recent error on the calling thread is
+//! available from `sstable_last_error()`. The header `include/sstable.h` is generated from this
+//! module using cbindgen (see `cbindgen.toml`).
+
+#![cfg(all(feature = "ffi", any(unix, windows)))]
+
+use crate::error::Error;
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::table_reader::{Table, TableIterator};
+use crate::types::SSIterator;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+/// The operation succeeded.
+pub const SSTABLE_OK: c_int = 0;
+/// The key wasn't found, or the iterator is exhausted.
+pub const SSTABLE_NOT_FOUND: c_int = 1;
+/// An I/O error or other unspecified error occurred.
+pub const SSTABLE_ERROR: c_int = -1;
+/// An argument was invalid (e.g. a NULL pointer or keys added out of order).
+pub const SSTABLE_INVALID_ARGUMENT: c_int = -2;
+/// The table is corrupt.
+pub const SSTABLE_CORRUPTION: c_int = -3;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: &str) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Records `e` as the last error and returns the matching error code.
+fn error_code(e: &Error) -> c_int {
+    set_last_error(&e.to_string());
+    match e {
+        Error::Corruption { .. } => SSTABLE_CORRUPTION,
+        Error::InvalidArgument(_) => SSTABLE_INVALID_ARGUMENT,
+        _ => SSTABLE_ERROR,
+    }
+}
+
+fn invalid_argument(msg: &str) -> c_int {
+    set_last_error(msg);
+    SSTABLE_INVALID_ARGUMENT
+}
+
+/// Returns a byte slice for a pointer/length pair; NULL is accepted for empty slices.
+unsafe fn bytes<'a>(p: *const u8, len: usize) -> Option<&'a [u8]> {
+    if p.is_null() {
+        if len == 0 {
+            Some(&[])
+        } else {
+            None
+        }
+    } else {
+        Some(slice::from_raw_parts(p, len))
+    }
+}
+
+unsafe fn to_path<'a>(p: *const c_char) -> Option<&'a Path> {
+    if p.is_null() {
+        return None;
+    }
+    CStr::from_ptr(p).to_str().ok().map(Path::new)
+}
+
+/// Opaque handle of a table builder writing to a file.
+pub struct SstableBuilder {
+    inner: TableBuilder<fs::File>,
+}
+
+/// Opaque handle of an open table.
+pub struct SstableTable {
+    inner: Table,
+}
+
+/// Opaque handle of an iterator over a table.
+pub struct SstableIterator {
+    inner: TableIterator,
+    // If set, the iterator has been positioned by a seek and the next call to
+    // sstable_iter_next() returns the current entry instead of advancing.
+    positioned: bool,
+    // Set once the iterator has run past the last entry; only a seek resets it.
+    exhausted: bool,
+    key: Vec<u8>,
+    val: Vec<u8>,
+}
+
+/// Returns the message of the last error that occurred on the calling thread, or NULL. The
+/// returned string is valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn sstable_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Creates (or truncates) the file at `path` and returns a builder writing a table to it, or NULL
+/// on error.
+///
+/// # Safety
+///
+/// `path` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_builder_open(path: *const c_char) -> *mut SstableBuilder {
+    let p = match to_path(path) {
+        Some(p) => p,
+        None => {
+            invalid_argument("path is NULL or not valid UTF-8");
+            return ptr::null_mut();
+        }
+    };
+    match fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(p)
+    {
+        Ok(f) => Box::into_raw(Box::new(SstableBuilder {
+            inner: TableBuilder::new(Options::default(), f),
+        })),
+        Err(e) => {
+            error_code(&e.into());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Adds an entry to the table. Keys must be added in ascending order.
+///
+/// # Safety
+///
+/// `builder` must be a handle returned by `sstable_builder_open()`; `key` and `val` must point to
+/// at least `key_len` and `val_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_builder_add(
+    builder: *mut SstableBuilder,
+    key: *const u8,
+    key_len: usize,
+    val: *const u8,
+    val_len: usize,
+) -> c_int {
+    let b = match builder.as_mut() {
+        Some(b) => b,
+        None => return invalid_argument("builder is NULL"),
+    };
+    let (k, v) = match (bytes(key, key_len), bytes(val, val_len)) {
+        (Some(k), Some(v)) => (k, v),
+        _ => return invalid_argument("key or value is NULL"),
+    };
+    // The builder asserts on keys added out of order; a panic must not unwind into C code.
+    match panic::catch_unwind(AssertUnwindSafe(|| b.inner.add(k, v))) {
+        Ok(Ok(())) => SSTABLE_OK,
+        Ok(Err(e)) => error_code(&e),
+        Err(_) => invalid_argument("key added out of order"),
+    }
+}
+
+/// Writes the remaining blocks and the footer, and releases the builder (whether successful or
+/// not). If `size` is not NULL, the size of the written table is stored there.
+///
+/// # Safety
+///
+/// `builder` must be a handle returned by `sstable_builder_open()`; it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_builder_finish(
+    builder: *mut SstableBuilder,
+    size: *mut u64,
+) -> c_int {
+    if builder.is_null() {
+        return invalid_argument("builder is NULL");
+    }
+    let b = Box::from_raw(builder);
+    match b.inner.finish() {
+        Ok(written) => {
+            if let Some(s) = size.as_mut() {
+                *s = written;
+            }
+            SSTABLE_OK
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Releases a builder without finishing the table.
+///
+/// # Safety
+///
+/// `builder` must be NULL or a handle returned by `sstable_builder_open()`; it must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_builder_free(builder: *mut SstableBuilder) {
+    if !builder.is_null() {
+        drop(Box::from_raw(builder));
+    }
+}
+
+/// Opens the table stored in the file at `path`. Returns NULL on error.
+///
+/// # Safety
+///
+/// `path` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_table_open(path: *const c_char) -> *mut SstableTable {
+    let p = match to_path(path) {
+        Some(p) => p,
+        None => {
+            invalid_argument("path is NULL or not valid UTF-8");
+            return ptr::null_mut();
+        }
+    };
+    match Table::new_from_file(Options::default(), p) {
+        Ok(t) => Box::into_raw(Box::new(SstableTable { inner: t })),
+        Err(e) => {
+            error_code(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Looks up `key`. If it is found, `SSTABLE_OK` is returned and a newly allocated copy of the
+/// value is stored in `val`/`val_len`; it must be released using `sstable_value_free()`. If the
+/// key doesn't exist, `SSTABLE_NOT_FOUND` is returned.
+///
+/// # Safety
+///
+/// `table` must be a handle returned by `sstable_table_open()`; `key` must point to at least
+/// `key_len` readable bytes; `val` and `val_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_table_get(
+    table: *const SstableTable,
+    key: *const u8,
+    key_len: usize,
+    val: *mut *mut u8,
+    val_len: *mut usize,
+) -> c_int {
+    let t = match table.as_ref() {
+        Some(t) => t,
+        None => return invalid_argument("table is NULL"),
+    };
+    let k = match bytes(key, key_len) {
+        Some(k) => k,
+        None => return invalid_argument("key is NULL"),
+    };
+    if val.is_null() || val_len.is_null() {
+        return invalid_argument("val or val_len is NULL");
+    }
+    match t.inner.get(k) {
+        Ok(Some(v)) => {
+            let v = v.into_boxed_slice();
+            *val_len = v.len();
+            *val = Box::into_raw(v) as *mut u8;
+            SSTABLE_OK
+        }
+        Ok(None) => SSTABLE_NOT_FOUND,
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Releases a value returned by `sstable_table_get()`.
+///
+/// # Safety
+///
+/// `val` and `val_len` must have been returned by `sstable_table_get()`, and the value must not
+/// have been released before.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_value_free(val: *mut u8, val_len: usize) {
+    if !val.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(val, val_len)));
+    }
+}
+
+/// Closes a table. Iterators created from it remain valid.
+///
+/// # Safety
+///
+/// `table` must be NULL or a handle returned by `sstable_table_open()`; it must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_table_free(table: *mut SstableTable) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+/// Returns an iterator positioned before the first entry of `table`, or NULL if `table` is NULL.
+///
+/// # Safety
+///
+/// `table` must be NULL or a handle returned by `sstable_table_open()`.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_iter_new(table: *const SstableTable) -> *mut SstableIterator {
+    match table.as_ref() {
+        Some(t) => Box::into_raw(Box::new(SstableIterator {
+            inner: t.inner.iter(),
+            positioned: false,
+            exhausted: false,
+            key: vec![],
+            val: vec![],
+        })),
+        None => {
+            invalid_argument("table is NULL");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Positions the iterator so that the next call to `sstable_iter_next()` returns the first entry
+/// with a key greater than or equal to `key`. Fails if the block holding that entry can't be read,
+/// e.g. with `SSTABLE_CORRUPTION`.
+///
+/// # Safety
+///
+/// `iter` must be a handle returned by `sstable_iter_new()`; `key` must point to at least
+/// `key_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_iter_seek(
+    iter: *mut SstableIterator,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    let it = match iter.as_mut() {
+        Some(it) => it,
+        None => return invalid_argument("iter is NULL"),
+    };
+    let k = match bytes(key, key_len) {
+        Some(k) => k,
+        None => return invalid_argument("key is NULL"),
+    };
+    it.inner.seek(k);
+    it.positioned = it.inner.valid();
+    it.exhausted = !it.positioned;
+    match it.inner.status() {
+        Some(e) => error_code(e),
+        None => SSTABLE_OK,
+    }
+}
+
+/// Advances the iterator and stores pointers to the current key and value in the output
+/// arguments. Returns `SSTABLE_NOT_FOUND` once the iterator is exhausted, and an error code such
+/// as `SSTABLE_CORRUPTION` if a block can't be read. The returned pointers stay valid until the
+/// next call on this iterator.
+///
+/// # Safety
+///
+/// `iter` must be a handle returned by `sstable_iter_new()`; all other arguments must be valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_iter_next(
+    iter: *mut SstableIterator,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    val: *mut *const u8,
+    val_len: *mut usize,
+) -> c_int {
+    let it = match iter.as_mut() {
+        Some(it) => it,
+        None => return invalid_argument("iter is NULL"),
+    };
+    if key.is_null() || key_len.is_null() || val.is_null() || val_len.is_null() {
+        return invalid_argument("output argument is NULL");
+    }
+    if it.exhausted {
+        return SSTABLE_NOT_FOUND;
+    }
+    if it.positioned {
+        it.positioned = false;
+    } else if !it.inner.advance() {
+        it.exhausted = true;
+        return match it.inner.status() {
+            Some(e) => error_code(e),
+            None => SSTABLE_NOT_FOUND,
+        };
+    }
+    if !it.inner.current(&mut it.key, &mut it.val) {
+        return match it.inner.status() {
+            Some(e) => error_code(e),
+            None => SSTABLE_NOT_FOUND,
+        };
+    }
+    *key = it.key.as_ptr();
+    *key_len = it.key.len();
+    *val = it.val.as_ptr();
+    *val_len = it.val.len();
+    SSTABLE_OK
+}
+
+/// Releases an iterator.
+///
+/// # Safety
+///
+/// `iter` must be NULL or a handle returned by `sstable_iter_new()`; it must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sstable_iter_free(iter: *mut SstableIterator) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn c_path(name: &str) -> CString {
+        let p = env::temp_dir().join(format!("sstable_ffi_{}_{}", name, std::process::id()));
+        CString::new(p.to_str().unwrap()).unwrap()
+    }
+
+    unsafe fn next(it: *mut SstableIterator) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (mut k, mut kl, mut v, mut vl) = (ptr::null(), 0, ptr::null(), 0);
+        match sstable_iter_next(it, &mut k, &mut kl, &mut v, &mut vl) {
+            SSTABLE_OK => Some((bytes(k, kl)?.to_vec(), bytes(v, vl)?.to_vec())),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_ffi_build_and_read() {
+        let path = c_path("build_read");
+        let data = [("abc", "1"), ("abd", "22"), ("bcd", "333")];
+
+        unsafe {
+            let b = sstable_builder_open(path.as_ptr());
+            assert!(!b.is_null());
+            for (k, v) in data.iter() {
+                assert_eq!(
+                    SSTABLE_OK,
+                    sstable_builder_add(b, k.as_ptr(), k.len(), v.as_ptr(), v.len())
+                );
+            }
+            let mut size = 0;
+            assert_eq!(SSTABLE_OK, sstable_builder_finish(b, &mut size));
+            assert!(size > 0);
+
+            let t = sstable_table_open(path.as_ptr());
+            assert!(!t.is_null());
+
+            let (mut val, mut val_len) = (ptr::null_mut(), 0);
+            assert_eq!(
+                SSTABLE_OK,
+                sstable_table_get(t, b"abd".as_ptr(), 3, &mut val, &mut val_len)
+            );
+            assert_eq!(bytes(val, val_len).unwrap(), b"22");
+            sstable_value_free(val, val_len);
+            assert_eq!(
+                SSTABLE_NOT_FOUND,
+                sstable_table_get(t, b"xyz".as_ptr(), 3, &mut val, &mut val_len)
+            );
+
+            let it = sstable_iter_new(t);
+            sstable_table_free(t);
+            for (k, v) in data.iter() {
+                assert_eq!(
+                    Some((k.as_bytes().to_vec(), v.as_bytes().to_vec())),
+                    next(it)
+                );
+            }
+            assert_eq!(None, next(it));
+
+            assert_eq!(SSTABLE_OK, sstable_iter_seek(it, b"abe".as_ptr(), 3));
+            assert_eq!(Some((b"bcd".to_vec(), b"333".to_vec())), next(it));
+            assert_eq!(SSTABLE_OK, sstable_iter_seek(it, b"zzz".as_ptr(), 3));
+            assert_eq!(None, next(it));
+            sstable_iter_free(it);
+        }
+        fs::remove_file(path.to_str().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_ffi_corrupt_block() {
+        let path = c_path("corrupt_block");
+        let key = |i: usize| format!("key{:05}", i);
+        unsafe {
+            let b = sstable_builder_open(path.as_ptr());
+            for i in 0..2000 {
+                let k = key(i);
+                assert_eq!(
+                    SSTABLE_OK,
+                    sstable_builder_add(b, k.as_ptr(), k.len(), b"value".as_ptr(), 5)
+                );
+            }
+            let mut size = 0;
+            assert_eq!(SSTABLE_OK, sstable_builder_finish(b, &mut size));
+        }
+        // Corrupt a data block in the middle of the table.
+        let mut contents = fs::read(path.to_str().unwrap()).unwrap();
+        let off = contents.len() / 3;
+        contents[off] ^= 0xff;
+        fs::write(path.to_str().unwrap(), &contents).unwrap();
+
+        unsafe {
+            let t = sstable_table_open(path.as_ptr());
+            assert!(!t.is_null());
+            let it = sstable_iter_new(t);
+            sstable_table_free(t);
+            let (mut k, mut kl, mut v, mut vl) = (ptr::null(), 0, ptr::null(), 0);
+            let mut n = 0;
+            let code = loop {
+                match sstable_iter_next(it, &mut k, &mut kl, &mut v, &mut vl) {
+                    SSTABLE_OK => n += 1,
+                    code => break code,
+                }
+            };
+            assert_eq!(code, SSTABLE_CORRUPTION);
+            assert!(n > 0 && n < 2000);
+
+            let k = key(n);
+            assert_eq!(
+                SSTABLE_CORRUPTION,
+                sstable_iter_seek(it, k.as_ptr(), k.len())
+            );
+            let msg = CStr::from_ptr(sstable_last_error()).to_str().unwrap();
+            assert!(msg.contains("checksum"), "{}", msg);
+            sstable_iter_free(it);
+        }
+        fs::remove_file(path.to_str().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let path = c_path("errors");
+        unsafe {
+            assert!(sstable_builder_open(ptr::null()).is_null());
+            assert!(!sstable_last_error().is_null());
+
+            let b = sstable_builder_open(path.as_ptr());
+            assert_eq!(
+                SSTABLE_OK,
+                sstable_builder_add(b, b"b".as_ptr(), 1, ptr::null(), 0)
+            );
+            assert_eq!(
+                SSTABLE_INVALID_ARGUMENT,
+                sstable_builder_add(b, ptr::null(), 1, ptr::null(), 0)
+            );
+            sstable_builder_free(b);
+
+            assert!(sstable_table_open(c_path("does_not_exist").as_ptr()).is_null());
+            let msg = CStr::from_ptr(sstable_last_error()).to_str().unwrap();
+            assert!(msg.starts_with("I/O error"), "{}", msg);
+        }
+        fs::remove_file(path.to_str().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Sharing of open files between tables. Applications opening the same table several times (e.g.
+//! one `Table` per thread, or per shard referring to a common file) would otherwise use a file
+//! descriptor for every instance. A `FileRegistry` hands out one shared handle per file, which is
+//! closed once the last table using it is dropped, and bounds the number of files open through it.
+
+#![cfg(any(unix, windows))]
+
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_reader::Table;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+/// FileRegistry keeps track of the files opened through it, by canonical path. Opening a file
+/// that is still open returns the existing handle instead of a new file descriptor, unless the
+/// file at the path has been replaced since: then the new file is opened, while tables using the
+/// old one keep reading it. At most `max_open` files (including replaced ones still in use) are
+/// open through a registry at any time; opening another one fails until a file is closed by
+/// dropping all handles (and tables) referring to it.
+pub struct FileRegistry {
+    max_open: usize,
+    files: Mutex<OpenFiles>,
+}
+
+#[derive(Default)]
+struct OpenFiles {
+    by_path: HashMap<PathBuf, Weak<File>>,
+    // Files that were replaced at their path while open.
+    replaced: Vec<Weak<File>>,
+}
+
+impl OpenFiles {
+    /// Forgets the files that have been closed and returns the number of open ones.
+    fn retain_open(&mut self) -> usize {
+        self.by_path.retain(|_, f| f.strong_count() > 0);
+        self.replaced.retain(|f| f.strong_count() > 0);
+        self.by_path.len() + self.replaced.len()
+    }
+}
+
+/// Returns true if `f` is the file at `path`, i.e. it hasn't been replaced since it was opened.
+fn is_file_at(f: &File, path: &Path) -> Result<bool> {
+    let (open, current) = (f.metadata()?, fs::metadata(path)?);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(open.dev() == current.dev() && open.ino() == current.ino())
+    }
+    #[cfg(not(unix))]
+    Ok(open.len() == current.len() && open.modified().ok() == current.modified().ok())
+}
+
+impl FileRegistry {
+    /// Returns a registry keeping up to `max_open` files open. The minimal allowed budget is 1.
+    pub fn new(max_open: usize) -> FileRegistry {
+        FileRegistry {
+            max_open: max_open.max(1),
+            files: Mutex::new(OpenFiles::default()),
+        }
+    }
+
+    /// Returns a handle to the file at `path`, opening it if it isn't open yet. Fails with an
+    /// `io::ErrorKind::Other` error if the file isn't open and the budget of open files is
+    /// exhausted.
+    pub fn open(&self, path: &Path) -> Result<Arc<File>> {
+        let path = fs::canonicalize(path)?;
+        let mut files = self.files();
+        if let Some(f) = files.by_path.get(&path).and_then(Weak::upgrade) {
+            if is_file_at(&f, &path)? {
+                return Ok(f);
+            }
+            event!(debug, path = %path.display(), "shared file was replaced");
+            files.replaced.push(Arc::downgrade(&f));
+            files.by_path.remove(&path);
+        }
+        let open = files.retain_open();
+        if open >= self.max_open {
+            return Err(Error::Io(io::Error::other(format!(
+                "can't open {}: {} files are open already",
+                path.display(),
+                open
+            ))));
+        }
+        event!(debug, path = %path.display(), "opening shared file");
+        let f = Arc::new(fs::OpenOptions::new().read(true).open(&path)?);
+        files.by_path.insert(path, Arc::downgrade(&f));
+        Ok(f)
+    }
+
+    /// Opens the table at `path` using the registry's handle to the file. Tables opened this way
+    /// share the file descriptor and, as their file IDs are the same, their cached blocks.
+    pub fn open_table(&self, opt: Options, path: &Path) -> Result<Table> {
+        let f = self.open(path)?;
+        let size = f.metadata()?.len();
+        Table::new(opt, Box::new(f), size)
+    }
+
+    /// Returns the number of files currently open through the registry.
+    pub fn count(&self) -> usize {
+        self.files().retain_open()
+    }
+
+    fn files(&self) -> MutexGuard<'_, OpenFiles> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_builder::TableBuilder;
+
+    fn write_table(path: &Path, val: &str) {
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(Options::default(), &mut d);
+            b.add(b"key", val.as_bytes()).unwrap();
+            b.finish().unwrap();
+        }
+        fs::write(path, d).unwrap();
+    }
+
+    #[test]
+    fn test_file_registry() {
+        let dir = std::env::temp_dir().join(format!("sstable_registry_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (p1, p2) = (dir.join("1.sst"), dir.join("2.sst"));
+        write_table(&p1, "one");
+        write_table(&p2, "two");
+
+        let registry = FileRegistry::new(1);
+        let t1 = registry.open_table(Options::default(), &p1).unwrap();
+        // Another path to the same file shares its handle.
+        let t2 = registry
+            .open_table(Options::default(), &dir.join(".").join("1.sst"))
+            .unwrap();
+        assert_eq!(registry.count(), 1);
+        assert_eq!(t2.get(b"key").unwrap().as_deref(), Some(&b"one"[..]));
+        assert!(matches!(
+            registry.open_table(Options::default(), &p2),
+            Err(Error::Io(_))
+        ));
+
+        drop(t1);
+        assert_eq!(registry.count(), 1);
+        drop(t2);
+        assert_eq!(registry.count(), 0);
+        let t = registry.open_table(Options::default(), &p2).unwrap();
+        assert_eq!(t.get(b"key").unwrap().as_deref(), Some(&b"two"[..]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_registry_replaced_file() {
+        let dir = std::env::temp_dir().join(format!("sstable_replaced_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (p1, p2) = (dir.join("1.sst"), dir.join("2.sst"));
+        write_table(&p1, "one");
+        write_table(&p2, "two");
+
+        // A file replaced at its path is opened again; tables using the old one keep it open.
+        let registry = FileRegistry::new(2);
+        let old = registry.open_table(Options::default(), &p2).unwrap();
+        let tmp = dir.join("2.sst.tmp");
+        write_table(&tmp, "new two");
+        fs::rename(&tmp, &p2).unwrap();
+        let new = registry.open_table(Options::default(), &p2).unwrap();
+        assert_eq!(registry.count(), 2);
+        assert_eq!(old.get(b"key").unwrap().as_deref(), Some(&b"two"[..]));
+        assert_eq!(new.get(b"key").unwrap().as_deref(), Some(&b"new two"[..]));
+        assert!(registry.open(&p1).is_err());
+        drop(old);
+        assert_eq!(registry.count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
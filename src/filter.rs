@@ -13,8 +13,58 @@ pub trait FilterPolicy: Send + Sync {
     fn create_filter(&self, keys: &[u8], key_offsets: &[usize]) -> Vec<u8>;
     /// Check whether the given key may match the filter.
     fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool;
+    /// Whether the policy creates a single filter for all keys of a table (like RocksDB's full
+    /// filters) instead of one filter per 2 KiB of data blocks.
+    fn full_filter(&self) -> bool {
+        false
+    }
 }
 
+/// Returns the key under which the filter block created by `policy` is stored in the metaindex
+/// block.
+pub fn meta_block_name(policy: &dyn FilterPolicy) -> String {
+    if policy.full_filter() {
+        format!("fullfilter.{}", policy.name())
+    } else {
+        format!("filter.{}", policy.name())
+    }
+}
+
+/// Returns a policy able to read the filter block stored under `meta_block_name` if it's one of
+/// the builtin filters of LevelDB, RocksDB or this crate. This allows using the filters of tables
+/// written with a policy other than the one configured in the reader's options.
+#[cfg(feature = "filters")]
+pub fn builtin_policy(meta_block_name: &str) -> Option<BoxedFilterPolicy> {
+    // The number of bits per key doesn't matter for reading.
+    let policy: Box<dyn FilterPolicy> = match meta_block_name {
+        "filter.leveldb.BuiltinBloomFilter2" => Box::new(BloomPolicy::new(DEFAULT_BITS_PER_KEY)),
+        "filter.rocksdb.BuiltinBloomFilter" => {
+            Box::new(RocksDBBloomPolicy::new(DEFAULT_BITS_PER_KEY))
+        }
+        "fullfilter.rocksdb.BuiltinBloomFilter" => {
+            Box::new(RocksDBFullFilterPolicy::new(DEFAULT_BITS_PER_KEY))
+        }
+        _ => return None,
+    };
+    Some(Arc::new(policy))
+}
+
+/// Without the `filters` feature, there are no builtin policies.
+#[cfg(not(feature = "filters"))]
+pub fn builtin_policy(_meta_block_name: &str) -> Option<BoxedFilterPolicy> {
+    None
+}
+
+/// The metaindex keys of the filter blocks understood by `builtin_policy()`.
+pub const BUILTIN_FILTER_META_BLOCKS: [&str; 3] = [
+    "filter.leveldb.BuiltinBloomFilter2",
+    "filter.rocksdb.BuiltinBloomFilter",
+    "fullfilter.rocksdb.BuiltinBloomFilter",
+];
+
+#[cfg(feature = "filters")]
+const DEFAULT_BITS_PER_KEY: u32 = 10;
+
 /// A boxed and refcounted filter policy (reference-counted because a Box with unsized content
 /// couldn't be cloned otherwise)
 pub type BoxedFilterPolicy = Arc<Box<dyn FilterPolicy>>;
@@ -23,6 +73,12 @@ pub type BoxedFilterPolicy = Arc<Box<dyn FilterPolicy>>;
 #[derive(Clone)]
 pub struct NoFilterPolicy;
 
+impl Default for NoFilterPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NoFilterPolicy {
     pub fn new() -> NoFilterPolicy {
         NoFilterPolicy
@@ -43,7 +99,141 @@ impl FilterPolicy for NoFilterPolicy {
 
 const BLOOM_SEED: u32 = 0xbc9f1d34;
 
-/// A filter policy using a bloom filter internally.
+/// The hash function used by LevelDB's bloom filters (LevelDB's `Hash()` with the bloom seed).
+fn leveldb_hash(data: &[u8]) -> u32 {
+    murmur_hash(data, false)
+}
+
+/// The hash function used by RocksDB's legacy bloom filters. It differs from LevelDB's in that
+/// the trailing bytes of keys whose length isn't a multiple of 4 are sign-extended.
+#[cfg(feature = "filters")]
+fn rocksdb_hash(data: &[u8]) -> u32 {
+    murmur_hash(data, true)
+}
+
+fn murmur_hash(data: &[u8], sign_extend: bool) -> u32 {
+    let m: u32 = 0xc6a4a793;
+    let r: u32 = 24;
+
+    let mut ix = 0;
+    let limit = data.len();
+
+    let mut h: u32 = BLOOM_SEED ^ (limit as u64 * m as u64) as u32;
+
+    while ix + 4 <= limit {
+        let w = u32::decode_fixed(&data[ix..ix + 4]);
+        ix += 4;
+
+        h = h.wrapping_add(w);
+        h = h.wrapping_mul(m);
+        h ^= h >> 16;
+    }
+
+    // Process left-over bytes
+    assert!(limit - ix < 4);
+
+    if limit - ix > 0 {
+        for (i, b) in data[ix..].iter().enumerate() {
+            let b = if sign_extend {
+                *b as i8 as u32
+            } else {
+                *b as u32
+            };
+            h = h.wrapping_add(b << (8 * i));
+        }
+
+        h = h.wrapping_mul(m);
+        h ^= h >> r;
+    }
+    h
+}
+
+/// Returns the number of probes used for `bits_per_key`, as LevelDB and RocksDB compute it.
+fn num_probes(bits_per_key: u32) -> u32 {
+    ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30)
+}
+
+/// Returns an empty bloom filter in LevelDB's format for `num_keys` keys: the filter bits,
+/// followed by the number of probes `k`.
+fn new_bloom_filter(num_keys: usize, bits_per_key: u32, k: u32) -> Vec<u8> {
+    let filter_bits = (num_keys * bits_per_key as usize).max(64);
+    let mut filter = Vec::with_capacity(1 + filter_bits.div_ceil(8));
+    filter.resize(filter_bits.div_ceil(8), 0);
+    // Encode k at the end of the filter.
+    filter.push(k as u8);
+    filter
+}
+
+/// Adds the key with hash `h` to a filter created by `new_bloom_filter()`.
+fn bloom_add(filter: &mut [u8], mut h: u32) {
+    let (k, bits) = filter.split_last_mut().unwrap();
+    let adj_filter_bits = (bits.len() * 8) as u32;
+    let delta = h.rotate_left(15);
+    for _ in 0..*k {
+        let bitpos = (h % adj_filter_bits) as usize;
+        bits[bitpos / 8] |= 1 << (bitpos % 8);
+        h = h.wrapping_add(delta);
+    }
+}
+
+/// Returns the hash of `key` as used by `bloom_filter_from_hashes()`.
+pub(crate) fn bloom_hash(key: &[u8]) -> u32 {
+    leveldb_hash(key)
+}
+
+/// Builds a bloom filter over the keys with the given `bloom_hash()`es. The filter is
+/// bit-compatible with those of `BloomPolicy`, so `bloom_key_may_match()` can check it.
+pub(crate) fn bloom_filter_from_hashes(hashes: &[u32], bits_per_key: u32) -> Vec<u8> {
+    let mut filter = new_bloom_filter(hashes.len(), bits_per_key, num_probes(bits_per_key));
+    for &h in hashes {
+        bloom_add(&mut filter, h);
+    }
+    filter
+}
+
+/// Checks whether `key` may be in a filter built by `bloom_filter_from_hashes()`.
+pub(crate) fn bloom_key_may_match(key: &[u8], filter: &[u8]) -> bool {
+    bloom_may_match(leveldb_hash, key, filter)
+}
+
+/// Checks whether the key with the given `hash` function may be in a filter in LevelDB's format.
+fn bloom_may_match(hash: fn(&[u8]) -> u32, key: &[u8], filter: &[u8]) -> bool {
+    if filter.is_empty() {
+        return true;
+    }
+
+    let bits = (filter.len() - 1) as u32 * 8;
+    let k = filter[filter.len() - 1];
+    let filter_adj = &filter[0..filter.len() - 1];
+
+    if k > 30 || bits == 0 {
+        return true;
+    }
+
+    let mut h = hash(key);
+    let delta = h.rotate_left(15);
+    for _ in 0..k {
+        let bitpos = (h % bits) as usize;
+        if (filter_adj[bitpos / 8] & (1 << (bitpos % 8))) == 0 {
+            return false;
+        }
+        h = h.wrapping_add(delta);
+    }
+    true
+}
+
+/// Returns the number of bits per key giving bloom filters a false positive rate of about
+/// `fp_rate` (between 0 and 1).
+pub fn bloom_bits_per_key(fp_rate: f64) -> u32 {
+    let ln2 = std::f64::consts::LN_2;
+    (-fp_rate.clamp(1e-9, 1.0).ln() / (ln2 * ln2))
+        .ceil()
+        .max(1.0) as u32
+}
+
+/// A filter policy using a bloom filter internally. Its filters are bit-compatible with those of
+/// LevelDB's builtin `BloomFilterPolicy`.
+#[cfg(feature = "filters")]
 #[derive(Clone)]
 pub struct BloomPolicy {
     bits_per_key: u32,
@@ -51,6 +241,7 @@ pub struct BloomPolicy {
 }
 
 /// Beware the magic numbers...
+#[cfg(feature = "filters")]
 impl BloomPolicy {
     /// Returns a new boxed BloomPolicy.
     pub fn new(bits_per_key: u32) -> BloomPolicy {
@@ -59,111 +250,166 @@ impl BloomPolicy {
 
     /// Returns a new BloomPolicy with the given parameter.
     fn new_unwrapped(bits_per_key: u32) -> BloomPolicy {
-        let mut k = (bits_per_key as f32 * 0.69) as u32;
-
-        if k < 1 {
-            k = 1;
-        } else if k > 30 {
-            k = 30;
-        }
-
         BloomPolicy {
-            bits_per_key: bits_per_key,
-            k: k,
+            bits_per_key,
+            k: num_probes(bits_per_key),
         }
     }
 
-    fn bloom_hash(&self, data: &[u8]) -> u32 {
-        let m: u32 = 0xc6a4a793;
-        let r: u32 = 24;
+    fn create_filter_with(
+        &self,
+        hash: fn(&[u8]) -> u32,
+        keys: &[u8],
+        key_offsets: &[usize],
+    ) -> Vec<u8> {
+        let mut filter = new_bloom_filter(key_offsets.len(), self.bits_per_key, self.k);
+        // Add all keys to the filter.
+        offset_data_iterate(keys, key_offsets, |key| bloom_add(&mut filter, hash(key)));
+        filter
+    }
+}
 
-        let mut ix = 0;
-        let limit = data.len();
+#[cfg(feature = "filters")]
+impl FilterPolicy for BloomPolicy {
+    fn name(&self) -> &'static str {
+        "leveldb.BuiltinBloomFilter2"
+    }
+    fn create_filter(&self, keys: &[u8], key_offsets: &[usize]) -> Vec<u8> {
+        self.create_filter_with(leveldb_hash, keys, key_offsets)
+    }
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        bloom_may_match(leveldb_hash, key, filter)
+    }
+}
 
-        let mut h: u32 = BLOOM_SEED ^ (limit as u64 * m as u64) as u32;
+/// A filter policy compatible with RocksDB's legacy block-based bloom filters
+/// (`NewBloomFilterPolicy(bits, true)`). They use LevelDB's filter layout, but a different hash.
+#[cfg(feature = "filters")]
+#[derive(Clone)]
+pub struct RocksDBBloomPolicy(BloomPolicy);
 
-        while ix + 4 <= limit {
-            let w = u32::decode_fixed(&data[ix..ix + 4]);
-            ix += 4;
+#[cfg(feature = "filters")]
+impl RocksDBBloomPolicy {
+    pub fn new(bits_per_key: u32) -> RocksDBBloomPolicy {
+        RocksDBBloomPolicy(BloomPolicy::new(bits_per_key))
+    }
+}
 
-            h = (h as u64 + w as u64) as u32;
-            h = (h as u64 * m as u64) as u32;
-            h ^= h >> 16;
-        }
+#[cfg(feature = "filters")]
+impl FilterPolicy for RocksDBBloomPolicy {
+    fn name(&self) -> &'static str {
+        "rocksdb.BuiltinBloomFilter"
+    }
+    fn create_filter(&self, keys: &[u8], key_offsets: &[usize]) -> Vec<u8> {
+        self.0.create_filter_with(rocksdb_hash, keys, key_offsets)
+    }
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        bloom_may_match(rocksdb_hash, key, filter)
+    }
+}
 
-        // Process left-over bytes
-        assert!(limit - ix < 4);
+/// The cache line size assumed by RocksDB's full filters, in bytes.
+#[cfg(feature = "filters")]
+const CACHE_LINE_SIZE: u32 = 64;
 
-        if limit - ix > 0 {
-            let mut i = 0;
+/// A filter policy compatible with RocksDB's legacy full filters (written by RocksDB before
+/// format_version 5): a single bloom filter for the whole table, whose probes for a key all fall
+/// into one cache line. Filters in RocksDB's newer formats match every key.
+#[cfg(feature = "filters")]
+#[derive(Clone)]
+pub struct RocksDBFullFilterPolicy {
+    bits_per_key: u32,
+    k: u32,
+}
 
-            for b in data[ix..].iter() {
-                h = h.overflowing_add((*b as u32) << (8 * i)).0;
-                i += 1;
-            }
+#[cfg(feature = "filters")]
+impl RocksDBFullFilterPolicy {
+    pub fn new(bits_per_key: u32) -> RocksDBFullFilterPolicy {
+        RocksDBFullFilterPolicy {
+            bits_per_key,
+            k: num_probes(bits_per_key),
+        }
+    }
 
-            h = (h as u64 * m as u64) as u32;
-            h ^= h >> r;
+    /// Returns the number of cache lines in a filter for `num_keys` keys; always odd, so that
+    /// more bits of the hash determine the line.
+    fn num_lines(&self, num_keys: usize) -> u32 {
+        let total_bits = (num_keys as u64 * self.bits_per_key as u64) as u32;
+        let lines = total_bits.div_ceil(CACHE_LINE_SIZE * 8);
+        if lines.is_multiple_of(2) {
+            lines + 1
+        } else {
+            lines
         }
-        h
     }
 }
 
-impl FilterPolicy for BloomPolicy {
+#[cfg(feature = "filters")]
+impl FilterPolicy for RocksDBFullFilterPolicy {
     fn name(&self) -> &'static str {
-        "leveldb.BuiltinBloomFilter2"
+        "rocksdb.BuiltinBloomFilter"
+    }
+    fn full_filter(&self) -> bool {
+        true
     }
     fn create_filter(&self, keys: &[u8], key_offsets: &[usize]) -> Vec<u8> {
-        let filter_bits = key_offsets.len() * self.bits_per_key as usize;
-        let mut filter: Vec<u8>;
-
-        if filter_bits < 64 {
-            filter = Vec::with_capacity(8 + 1);
-            filter.resize(8, 0);
-        } else {
-            filter = Vec::with_capacity(1 + ((filter_bits + 7) / 8));
-            filter.resize((filter_bits + 7) / 8, 0);
-        }
-
-        let adj_filter_bits = (filter.len() * 8) as u32;
-
-        // Encode k at the end of the filter.
-        filter.push(self.k as u8);
-
-        // Add all keys to the filter.
+        // Like RocksDB, skip consecutive duplicate hashes.
+        let mut hashes: Vec<u32> = Vec::with_capacity(key_offsets.len());
         offset_data_iterate(keys, key_offsets, |key| {
-            let mut h = self.bloom_hash(key);
-            let delta = (h >> 17) | (h << 15);
-            for _ in 0..self.k {
-                let bitpos = (h % adj_filter_bits) as usize;
-                filter[bitpos / 8] |= 1 << (bitpos % 8);
-                h = (h as u64 + delta as u64) as u32;
+            let h = rocksdb_hash(key);
+            if hashes.last() != Some(&h) {
+                hashes.push(h);
             }
         });
 
+        let num_lines = if hashes.is_empty() {
+            0
+        } else {
+            self.num_lines(hashes.len())
+        };
+        let mut filter = vec![0; (num_lines * CACHE_LINE_SIZE) as usize + 5];
+        for mut h in hashes {
+            let line = ((h % num_lines) * CACHE_LINE_SIZE) as usize;
+            let delta = h.rotate_left(15);
+            for _ in 0..self.k {
+                let bitpos = (h & (CACHE_LINE_SIZE * 8 - 1)) as usize;
+                filter[line + bitpos / 8] |= 1 << (bitpos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        let len = filter.len();
+        filter[len - 5] = self.k as u8;
+        num_lines.encode_fixed(&mut filter[len - 4..]);
         filter
     }
     fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
-        if filter.len() == 0 {
+        if filter.len() <= 5 {
+            // No keys were added.
+            return false;
+        }
+        let len = (filter.len() - 5) as u32;
+        let k = filter[len as usize] as i8;
+        if k < 1 {
+            // Newer formats (or a corrupt filter).
             return true;
         }
-
-        let bits = (filter.len() - 1) as u32 * 8;
-        let k = filter[filter.len() - 1];
-        let filter_adj = &filter[0..filter.len() - 1];
-
-        if k > 30 {
+        let num_lines = u32::decode_fixed(&filter[len as usize + 1..]);
+        // Filters written on other platforms may use other cache line sizes.
+        if num_lines == 0 || !len.is_multiple_of(num_lines) || !(len / num_lines).is_power_of_two()
+        {
             return true;
         }
+        let line_size = len / num_lines;
 
-        let mut h = self.bloom_hash(key);
-        let delta = (h >> 17) | (h << 15);
+        let mut h = rocksdb_hash(key);
+        let line = ((h % num_lines) * line_size) as usize;
+        let delta = h.rotate_left(15);
         for _ in 0..k {
-            let bitpos = (h % bits) as usize;
-            if (filter_adj[bitpos / 8] & (1 << (bitpos % 8))) == 0 {
+            let bitpos = (h & (line_size * 8 - 1)) as usize;
+            if filter[line + bitpos / 8] & (1 << (bitpos % 8)) == 0 {
                 return false;
             }
-            h = (h as u64 + delta as u64) as u32;
+            h = h.wrapping_add(delta);
         }
         true
     }
@@ -171,6 +417,7 @@ impl FilterPolicy for BloomPolicy {
 
 /// offset_data_iterate iterates over the entries in data that are indexed by the offsets given in
 /// offsets. This is e.g. the internal format of a FilterBlock.
+#[cfg(feature = "filters")]
 fn offset_data_iterate<F: FnMut(&[u8])>(data: &[u8], offsets: &[usize], mut f: F) {
     for offix in 0..offsets.len() {
         let upper = if offix == offsets.len() - 1 {
@@ -183,7 +430,7 @@ fn offset_data_iterate<F: FnMut(&[u8])>(data: &[u8], offsets: &[usize], mut f: F
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "filters"))]
 mod tests {
     use super::*;
 
@@ -230,16 +477,104 @@ mod tests {
 
     #[test]
     fn test_filter_bloom_hash() {
+        // The test vectors of LevelDB's hash_test.cc.
         let d1 = vec![0x62];
         let d2 = vec![0xc3, 0x97];
         let d3 = vec![0xe2, 0x99, 0xa5];
         let d4 = vec![0xe1, 0x80, 0xb9, 0x32];
 
-        let fp = BloomPolicy::new_unwrapped(_BITS_PER_KEY);
+        assert_eq!(leveldb_hash(&[]), BLOOM_SEED);
+        assert_eq!(leveldb_hash(&d1), 0xef1345c4);
+        assert_eq!(leveldb_hash(&d2), 0x5b663814);
+        assert_eq!(leveldb_hash(&d3), 0x323c078f);
+        assert_eq!(leveldb_hash(&d4), 0xed21633a);
+
+        // RocksDB's hash only differs for trailing bytes >= 0x80.
+        assert_eq!(rocksdb_hash(&d1), leveldb_hash(&d1));
+        assert_eq!(rocksdb_hash(&d4), leveldb_hash(&d4));
+        assert_ne!(rocksdb_hash(&d2), leveldb_hash(&d2));
+        assert_ne!(rocksdb_hash(&d3), leveldb_hash(&d3));
+        assert_eq!(rocksdb_hash(b"abc\xff"), leveldb_hash(b"abc\xff"));
+        assert_ne!(rocksdb_hash(b"abcd\xff"), leveldb_hash(b"abcd\xff"));
+    }
+
+    #[test]
+    fn test_filter_num_probes() {
+        // LevelDB and RocksDB compute k = bits_per_key * ln(2), rounded down, using doubles.
+        assert_eq!(num_probes(0), 1);
+        assert_eq!(num_probes(10), 6);
+        assert_eq!(num_probes(20), 13);
+        assert_eq!(num_probes(100), 30);
+    }
 
-        assert_eq!(fp.bloom_hash(&d1), 0xef1345c4);
-        assert_eq!(fp.bloom_hash(&d2), 0x5b663814);
-        assert_eq!(fp.bloom_hash(&d3), 0x323c078f);
-        assert_eq!(fp.bloom_hash(&d4), 0xed21633a);
+    #[test]
+    fn test_filter_rocksdb_bloom() {
+        let fp = RocksDBBloomPolicy::new(_BITS_PER_KEY);
+        let (data, offs) = input_data();
+        let f = fp.create_filter(&data, &offs);
+        // All keys are 4-aligned or ASCII, so the filter equals LevelDB's.
+        assert_eq!(f, create_filter());
+
+        let keys: Vec<&[u8]> = vec![b"\xc3\x97", b"k\xff", b"abcde\x80"];
+        let mut binary_data = vec![];
+        let mut binary_offs = vec![];
+        for k in keys.iter() {
+            binary_offs.push(binary_data.len());
+            binary_data.extend_from_slice(k);
+        }
+        let binary = fp.create_filter(&binary_data, &binary_offs);
+        for k in keys.iter() {
+            assert!(fp.key_may_match(k, &binary));
+        }
+    }
+
+    #[test]
+    fn test_filter_rocksdb_full_filter() {
+        let fp = RocksDBFullFilterPolicy::new(_BITS_PER_KEY);
+        assert!(fp.full_filter());
+        assert_eq!(
+            meta_block_name(&fp),
+            "fullfilter.rocksdb.BuiltinBloomFilter"
+        );
+
+        let empty = fp.create_filter(&[], &[]);
+        assert_eq!(empty, vec![8, 0, 0, 0, 0]);
+        assert!(!fp.key_may_match(b"abc", &empty));
+
+        let mut data = vec![];
+        let mut offs = vec![];
+        for i in 0..1000 {
+            offs.push(data.len());
+            data.extend_from_slice(format!("key{}", i).as_bytes());
+        }
+        let f = fp.create_filter(&data, &offs);
+        // 1000 keys * 12 bits = 12000 bits = 23.4 cache lines, rounded up to an odd number.
+        let num_lines = u32::decode_fixed(&f[f.len() - 4..]);
+        assert_eq!(num_lines, 25);
+        assert_eq!(f.len(), 25 * 64 + 5);
+        assert_eq!(f[f.len() - 5], 8);
+
+        offset_data_iterate(&data, &offs, |key| {
+            assert!(fp.key_may_match(key, &f));
+        });
+        let false_positives = (1000..11000)
+            .filter(|i| fp.key_may_match(format!("key{}", i).as_bytes(), &f))
+            .count();
+        assert!(false_positives < 200, "{}", false_positives);
+
+        // Filters of newer formats match everything.
+        let mut newer = f.clone();
+        let len = newer.len();
+        newer[len - 5] = 0xff;
+        assert!(fp.key_may_match(b"key1000000", &newer));
+    }
+
+    #[test]
+    fn test_filter_builtin_policy() {
+        for name in BUILTIN_FILTER_META_BLOCKS.iter() {
+            let policy = builtin_policy(name).unwrap();
+            assert_eq!(&meta_block_name(policy.as_ref().as_ref()), name);
+        }
+        assert!(builtin_policy("filter._").is_none());
     }
 }
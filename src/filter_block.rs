@@ -1,5 +1,10 @@
 use crate::block::BlockContents;
-use crate::filter::BoxedFilterPolicy;
+use crate::blockhandle::BlockHandle;
+use crate::builder_state::{StateReader, StateWriter};
+use crate::error::{Error, Result};
+use crate::filter::{self, BoxedFilterPolicy};
+use crate::table_block;
+use crate::types::RandomAccess;
 
 use std::sync::Arc;
 
@@ -9,9 +14,9 @@ const FILTER_BASE_LOG2: u32 = 11;
 
 /// For a given byte offset, returns the index of the filter that includes the key at that offset.
 #[inline]
-fn get_filter_index(offset: usize, base_lg2: u32) -> u32 {
+fn get_filter_index(offset: u64, base_lg2: u32) -> u32 {
     // divide by 2048
-    (offset >> base_lg2 as usize) as u32
+    (offset >> base_lg2) as u32
 }
 
 /// A Filter Block is built like this:
@@ -22,6 +27,9 @@ fn get_filter_index(offset: usize, base_lg2: u32) -> u32 {
 /// where offsets are 4 bytes, offset of offsets is 4 bytes, and log2 of FILTER_BASE is 1 byte.
 /// Two consecutive filter offsets may be the same.
 ///
+/// Policies creating full filters (see `FilterPolicy::full_filter()`) instead get a block
+/// consisting of a single filter for all keys.
+///
 /// TODO: See if we can remove the lifetime parameter.
 pub struct FilterBlockBuilder {
     policy: BoxedFilterPolicy,
@@ -32,11 +40,25 @@ pub struct FilterBlockBuilder {
     // Reset on every start_block()
     key_offsets: Vec<usize>,
     keys: Vec<u8>,
+
+    // The approximate size of filters per key, for size estimates.
+    bytes_per_key: f64,
+}
+
+/// Returns the approximate number of bytes `policy` spends per key in its filters.
+fn filter_bytes_per_key(policy: &BoxedFilterPolicy) -> f64 {
+    const SAMPLE_KEYS: usize = 1000;
+    let keys: Vec<u8> = (0..SAMPLE_KEYS as u32)
+        .flat_map(|i| i.encode_fixed_vec())
+        .collect();
+    let offsets: Vec<usize> = (0..SAMPLE_KEYS).map(|i| 4 * i).collect();
+    policy.create_filter(&keys, &offsets).len() as f64 / SAMPLE_KEYS as f64
 }
 
 impl FilterBlockBuilder {
     pub fn new(fp: BoxedFilterPolicy) -> FilterBlockBuilder {
         FilterBlockBuilder {
+            bytes_per_key: filter_bytes_per_key(&fp),
             policy: fp,
             // some pre-allocation
             filters: Vec::with_capacity(1024),
@@ -46,12 +68,39 @@ impl FilterBlockBuilder {
         }
     }
 
-    pub fn size_estimate(&self) -> usize {
-        self.filters.len() + 4 * self.filter_offsets.len() + 4 + 1
+    /// Serializes the state of the builder (see `TableBuilder::checkpoint()`).
+    pub fn save(&self, w: &mut StateWriter) {
+        w.put_bytes(&self.filters);
+        w.put_usizes(&self.filter_offsets);
+        w.put_usizes(&self.key_offsets);
+        w.put_bytes(&self.keys);
     }
 
-    pub fn filter_name(&self) -> &'static str {
-        self.policy.name()
+    /// Restores a builder saved using `save()`.
+    pub fn restore(fp: BoxedFilterPolicy, r: &mut StateReader) -> Result<FilterBlockBuilder> {
+        Ok(FilterBlockBuilder {
+            bytes_per_key: filter_bytes_per_key(&fp),
+            policy: fp,
+            filters: r.get_bytes()?.to_vec(),
+            filter_offsets: r.get_usizes()?,
+            key_offsets: r.get_usizes()?,
+            keys: r.get_bytes()?.to_vec(),
+        })
+    }
+
+    /// Returns the approximate size of the finished filter block if `more_keys` further keys are
+    /// added.
+    pub fn size_estimate(&self, more_keys: usize) -> usize {
+        let pending = (self.bytes_per_key * (self.key_offsets.len() + more_keys) as f64) as usize;
+        if self.policy.full_filter() {
+            return pending;
+        }
+        self.filters.len() + pending + 4 * (self.filter_offsets.len() + 1) + 4 + 1
+    }
+
+    /// Returns the key of the filter block in the metaindex block.
+    pub fn meta_block_name(&self) -> String {
+        filter::meta_block_name(self.policy.as_ref().as_ref())
     }
 
     pub fn add_key(&mut self, key: &[u8]) {
@@ -59,7 +108,10 @@ impl FilterBlockBuilder {
         self.keys.extend_from_slice(key);
     }
 
-    pub fn start_block(&mut self, offset: usize) {
+    pub fn start_block(&mut self, offset: u64) {
+        if self.policy.full_filter() {
+            return;
+        }
         let filter_ix = get_filter_index(offset, FILTER_BASE_LOG2);
         assert!(filter_ix >= self.filter_offsets.len() as u32);
 
@@ -82,6 +134,9 @@ impl FilterBlockBuilder {
     }
 
     pub fn finish(mut self) -> Vec<u8> {
+        if self.policy.full_filter() {
+            return self.policy.create_filter(&self.keys, &self.key_offsets);
+        }
         if !self.keys.is_empty() {
             self.generate_filter();
         }
@@ -105,6 +160,10 @@ impl FilterBlockBuilder {
     }
 }
 
+/// FilterBlockReader answers whether a key may be contained in a data block of a table, using
+/// the table's filter block. Filter blocks can be loaded on their own (see `read()` and
+/// `Table::filter_block_handle()`), so that negative lookups can be answered without opening a
+/// table.
 #[derive(Clone)]
 pub struct FilterBlockReader {
     policy: BoxedFilterPolicy,
@@ -112,29 +171,66 @@ pub struct FilterBlockReader {
 
     offsets_offset: usize,
     filter_base_lg2: u32,
+    // Whether the block is a single filter covering all blocks.
+    full: bool,
 }
 
 impl FilterBlockReader {
-    pub fn new_owned(pol: BoxedFilterPolicy, data: Vec<u8>) -> FilterBlockReader {
+    /// Reads the filter block at `location` from a table file of `file_size` bytes. The policy
+    /// must be the one the table was written with.
+    pub fn read(
+        file: &dyn RandomAccess,
+        file_size: u64,
+        location: &BlockHandle,
+        pol: BoxedFilterPolicy,
+    ) -> Result<FilterBlockReader> {
+        table_block::read_filter_block(file, file_size, location, pol)
+    }
+
+    pub fn new_owned(pol: BoxedFilterPolicy, data: Vec<u8>) -> Result<FilterBlockReader> {
         FilterBlockReader::new(pol, Arc::new(data))
     }
 
-    pub fn new(pol: BoxedFilterPolicy, data: Arc<Vec<u8>>) -> FilterBlockReader {
-        assert!(data.len() >= 5);
+    /// Creates a reader for the filter block `data`. Fails if `data` isn't a valid filter block.
+    pub fn new(pol: BoxedFilterPolicy, data: Arc<Vec<u8>>) -> Result<FilterBlockReader> {
+        if pol.full_filter() {
+            return Ok(FilterBlockReader {
+                policy: pol,
+                offsets_offset: data.len(),
+                block: data,
+                filter_base_lg2: 0,
+                full: true,
+            });
+        }
+        if data.len() < 5 {
+            return Err(Error::malformed(None, "filter block too short"));
+        }
 
         let fbase = data[data.len() - 1] as u32;
         let offset = u32::decode_fixed(&data[data.len() - 5..data.len() - 1]) as usize;
+        if fbase >= 64 || offset > data.len() - 5 || !(data.len() - 5 - offset).is_multiple_of(4) {
+            return Err(Error::malformed(None, "bad filter block trailer"));
+        }
 
-        FilterBlockReader {
+        Ok(FilterBlockReader {
             policy: pol,
             block: data,
             filter_base_lg2: fbase,
             offsets_offset: offset,
-        }
+            full: false,
+        })
     }
 
     /// Returns number of filters
+    /// Returns the size of the filter block in bytes.
+    pub fn size(&self) -> usize {
+        self.block.len()
+    }
+
     pub fn num(&self) -> u32 {
+        if self.full {
+            return 1;
+        }
         ((self.block.len() - self.offsets_offset - 5) / 4) as u32
     }
 
@@ -146,23 +242,32 @@ impl FilterBlockReader {
 
     /// blk_offset is the offset of the block containing key. Returns whether the key matches the
     /// filter for the block at blk_offset.
-    pub fn key_may_match(&self, blk_offset: usize, key: &[u8]) -> bool {
-        if get_filter_index(blk_offset, self.filter_base_lg2) > self.num() {
+    /// Blocks not covered by the filter block, and corrupt filters, match every key.
+    pub fn key_may_match(&self, blk_offset: u64, key: &[u8]) -> bool {
+        if self.full {
+            return self.policy.key_may_match(key, &self.block);
+        }
+        let index = get_filter_index(blk_offset, self.filter_base_lg2);
+        if index >= self.num() {
             return true;
         }
 
-        let filter_begin = self.offset_of(get_filter_index(blk_offset, self.filter_base_lg2));
-        let filter_end = self.offset_of(get_filter_index(blk_offset, self.filter_base_lg2) + 1);
-
-        assert!(filter_begin < filter_end);
-        assert!(filter_end <= self.offsets_offset);
-
-        self.policy
-            .key_may_match(key, &self.block[filter_begin..filter_end])
+        let filter_begin = self.offset_of(index);
+        let filter_end = self.offset_of(index + 1);
+
+        if filter_begin == filter_end {
+            // Empty filters belong to ranges without blocks.
+            false
+        } else if filter_begin < filter_end && filter_end <= self.offsets_offset {
+            self.policy
+                .key_may_match(key, &self.block[filter_begin..filter_end])
+        } else {
+            true
+        }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "filters"))]
 mod tests {
     use super::get_filter_index;
     use super::FILTER_BASE_LOG2;
@@ -223,14 +328,15 @@ mod tests {
     #[test]
     fn test_filter_block_build_read() {
         let result = produce_filter_block();
-        let reader = FilterBlockReader::new_owned(Arc::new(Box::new(BloomPolicy::new(32))), result);
+        let reader =
+            FilterBlockReader::new_owned(Arc::new(Box::new(BloomPolicy::new(32))), result).unwrap();
 
         assert_eq!(
             reader.offset_of(get_filter_index(5121, FILTER_BASE_LOG2)),
             17
         ); // third block in third filter
 
-        let unknown_keys = vec![
+        let unknown_keys = [
             "xsb".as_bytes(),
             "9sad".as_bytes(),
             "assssaaaass".as_bytes(),
@@ -240,7 +346,9 @@ mod tests {
             for key in get_keys().iter() {
                 assert!(
                     reader.key_may_match(block_offset, key),
-                    format!("{} {:?} ", block_offset, key)
+                    "{} {:?} ",
+                    block_offset,
+                    key
                 );
             }
             for key in unknown_keys.iter() {
@@ -248,4 +356,24 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_filter_block_reader_invalid() {
+        let policy: BoxedFilterPolicy = Arc::new(Box::new(BloomPolicy::new(32)));
+        assert!(FilterBlockReader::new_owned(policy.clone(), vec![0, 0, 0]).is_err());
+        assert!(FilterBlockReader::new_owned(policy.clone(), vec![9, 0, 0, 0, 11]).is_err());
+        assert!(FilterBlockReader::new_owned(policy.clone(), vec![0, 0, 0, 0, 0, 11]).is_err());
+
+        // A filter block without filters matches everything.
+        let reader = FilterBlockReader::new_owned(policy.clone(), vec![0, 0, 0, 0, 11]).unwrap();
+        assert_eq!(reader.num(), 0);
+        assert!(reader.key_may_match(0, b"abc"));
+
+        // Filters whose offsets are out of order match everything, too.
+        let mut block = produce_filter_block();
+        block[34] = 40;
+        let reader = FilterBlockReader::new_owned(policy, block).unwrap();
+        assert!(reader.key_may_match(0, b"xsb"));
+        assert!(!reader.key_may_match(5000, b"xsb"));
+    }
 }
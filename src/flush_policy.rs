@@ -0,0 +1,114 @@
+//! Policies deciding where `TableBuilder` ends a data block (see `Options::flush_block_policy`).
+//! By default, a block is ended once it grows beyond `Options::block_size`. Other policies can
+//! align blocks with the structure of the keys, so that the entries a point lookup needs are found
+//! in one block: for example, all versions of a user key, or all keys sharing a prefix.
+
+use crate::key_types;
+
+use std::sync::Arc;
+
+/// The state of the data block being built, as presented to a `FlushBlockPolicy`.
+pub struct BlockState<'a> {
+    /// The estimated size of the block if it were finished now.
+    pub size_estimate: usize,
+    /// The number of entries in the block; always at least one.
+    pub entries: usize,
+    /// The last key added to the block.
+    pub last_key: &'a [u8],
+    /// The target block size, `Options::block_size`.
+    pub block_size: usize,
+}
+
+/// A FlushBlockPolicy decides whether the current data block is finished before the next key is
+/// added to the table. It isn't consulted for empty blocks.
+pub trait FlushBlockPolicy: Send + Sync {
+    /// Returns true if `block` should be written before `next_key` is added, which then starts a
+    /// new block.
+    fn should_flush(&self, block: &BlockState, next_key: &[u8]) -> bool;
+}
+
+pub type BoxedFlushBlockPolicy = Arc<dyn FlushBlockPolicy>;
+
+/// The default policy: ends blocks once they're larger than `Options::block_size`.
+pub struct SizeFlushPolicy;
+
+impl FlushBlockPolicy for SizeFlushPolicy {
+    fn should_flush(&self, block: &BlockState, _: &[u8]) -> bool {
+        block.size_estimate > block.block_size
+    }
+}
+
+/// Ends blocks like `SizeFlushPolicy`, but never between two versions of the same user key, for
+/// tables of internal keys (see `key_types`). Blocks holding many versions of a key may therefore
+/// grow beyond `Options::block_size`.
+pub struct UserKeyFlushPolicy;
+
+impl FlushBlockPolicy for UserKeyFlushPolicy {
+    fn should_flush(&self, block: &BlockState, next_key: &[u8]) -> bool {
+        let user_key = |k| key_types::parse_internal_key(k).map(|(_, _, ukey)| ukey);
+        SizeFlushPolicy.should_flush(block, next_key)
+            && user_key(block.last_key) != user_key(next_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmp::InternalKeyCmp;
+    use crate::key_types::{build_internal_key, ValueType};
+    use crate::options::Options;
+    use crate::table_reader::Table;
+    use crate::test_util::build_table;
+
+    /// Ends a block whenever the first byte of the key changes.
+    struct FirstByteFlushPolicy;
+
+    impl FlushBlockPolicy for FirstByteFlushPolicy {
+        fn should_flush(&self, block: &BlockState, next_key: &[u8]) -> bool {
+            block.last_key.first() != next_key.first()
+        }
+    }
+
+    fn build(opt: &Options, keys: &[Vec<u8>]) -> Table {
+        let entries: Vec<(&[u8], &str)> = keys.iter().map(|k| (k.as_slice(), "value")).collect();
+        build_table(opt, &entries)
+    }
+
+    #[test]
+    fn test_flush_block_policy_prefix() {
+        let mut opt = Options::default();
+        opt.flush_block_policy = Arc::new(FirstByteFlushPolicy);
+        let keys: Vec<Vec<u8>> = ["a1", "a2", "a3", "b1", "c1", "c2"]
+            .iter()
+            .map(|k| k.as_bytes().to_vec())
+            .collect();
+        let table = build(&opt, &keys);
+        assert_eq!(table.index_handles().len(), 3);
+        assert_eq!(table.get(b"c2").unwrap().as_deref(), Some(&b"value"[..]));
+    }
+
+    #[test]
+    fn test_flush_block_policy_user_keys() {
+        let mut opt = Options::default();
+        opt.block_size = 16;
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        let mut keys = vec![];
+        for k in ["a", "b", "c"] {
+            for seq in (1..=5).rev() {
+                keys.push(build_internal_key(k.as_bytes(), seq, ValueType::TypeValue));
+            }
+        }
+
+        let sized = build(&opt, &keys);
+        assert!(sized.index_handles().len() > 3);
+
+        opt.flush_block_policy = Arc::new(UserKeyFlushPolicy);
+        let table = build(&opt, &keys);
+        // One block per user key.
+        assert_eq!(table.index_handles().len(), 3);
+        assert_eq!(
+            table.get_at(b"b", 3).unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! The format of data blocks compressed with `CompressionType::CompressionSnappyFramed`. The
+//! entries of such a block are cut at restart points into frames of about
+//! `Options::compression_frame_size` bytes, and every frame is compressed with snappy on its own,
+//! so that a point lookup only decompresses the frame that may hold its key.
+//!
+//! A framed block looks like this:
+//!
+//! [FRAME...] [RESTARTS] [INDEX] [ENTRIES_LEN] [RESTARTS_LEN] [INDEX_LEN] [N_FRAMES]
+//!
+//! A FRAME is the snappy-compressed form of a range of entries of the uncompressed block (see
+//! `Block`). RESTARTS is the restart array and restart count of the uncompressed block, as is.
+//! The INDEX holds, for every frame, a varint length and the first key of the frame, the fixed
+//! u32 offset of its entries in the uncompressed block and the fixed u32 offset of its end in the
+//! framed block. The remaining fields are fixed u32s; ENTRIES_LEN is the size of the entries of
+//! the uncompressed block.
+
+#![cfg(feature = "snappy")]
+
+use crate::cmp::Cmp;
+use crate::coding;
+use crate::error::{CorruptionKind, Result};
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use snap::{Decoder, Encoder};
+
+const TRAILER_LEN: usize = 16;
+
+type DecodeResult<T> = std::result::Result<T, CorruptionKind>;
+
+/// A frame as described by the index of a framed block.
+struct Frame<'a> {
+    first_key: &'a [u8],
+    /// The offset of the frame's entries in the uncompressed block.
+    entries_off: usize,
+    /// The location of the compressed frame in the framed block.
+    data: Range<usize>,
+}
+
+/// A parsed framed block.
+struct Layout<'a> {
+    frames: Vec<Frame<'a>>,
+    restarts: &'a [u8],
+    entries_len: usize,
+}
+
+impl<'a> Layout<'a> {
+    /// Returns the range of the entries of frame `i` in the uncompressed block.
+    fn entries(&self, i: usize) -> Range<usize> {
+        let end = self
+            .frames
+            .get(i + 1)
+            .map_or(self.entries_len, |f| f.entries_off);
+        self.frames[i].entries_off..end
+    }
+
+    /// Appends the decompressed entries of frame `i` of `data` to `dst`.
+    fn decompress_frame(&self, data: &[u8], i: usize, dst: &mut Vec<u8>) -> DecodeResult<()> {
+        let frame = &data[self.frames[i].data.clone()];
+        let expected = self.entries(i).len();
+        if snap::decompress_len(frame).map_err(CorruptionKind::Decompression)? != expected {
+            return Err(malformed("frame size mismatch"));
+        }
+        let start = dst.len();
+        dst.resize(start + expected, 0);
+        Decoder::new()
+            .decompress(frame, &mut dst[start..])
+            .map_err(CorruptionKind::Decompression)?;
+        Ok(())
+    }
+}
+
+fn malformed(msg: &str) -> CorruptionKind {
+    CorruptionKind::Malformed(msg.to_string())
+}
+
+fn fixed32(src: &[u8]) -> DecodeResult<usize> {
+    coding::get_fixed32(src)
+        .map(|v| v as usize)
+        .map_err(|_| malformed("truncated framed block"))
+}
+
+fn check_limit(size: usize, limit: Option<usize>) -> DecodeResult<()> {
+    match limit {
+        Some(limit) if size > limit => {
+            event!(error, size, limit, "decompressed size exceeds limit");
+            Err(CorruptionKind::DecompressedSizeExceeded { size, limit })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the key of the entry at the start of `entries`, which must be at a restart point.
+fn restart_key(entries: &[u8]) -> Result<&[u8]> {
+    let (_, n1) = coding::get_varint32(entries)?;
+    let (non_shared, n2) = coding::get_varint32(&entries[n1..])?;
+    let (_, n3) = coding::get_varint32(&entries[n1 + n2..])?;
+    let start = n1 + n2 + n3;
+    Ok(&entries[start..start + non_shared as usize])
+}
+
+/// Returns the framed form of the uncompressed block `contents`, cutting frames at the first
+/// restart point at least `frame_size` bytes after the start of the previous frame. Returns None
+/// if all entries fall into a single frame.
+pub fn compress(contents: &[u8], frame_size: usize) -> Result<Option<Vec<u8>>> {
+    let num_restarts = coding::get_fixed32(&contents[contents.len() - 4..])? as usize;
+    let entries_len = contents.len() - 4 * (num_restarts + 1);
+    let restarts = &contents[entries_len..];
+
+    let mut starts: Vec<usize> = vec![];
+    for restart in restarts[..4 * num_restarts].chunks(4) {
+        let off = coding::get_fixed32(restart)? as usize;
+        if off < entries_len && starts.last().is_none_or(|&s| off - s >= frame_size) {
+            starts.push(off);
+        }
+    }
+    if starts.len() <= 1 {
+        return Ok(None);
+    }
+
+    let (mut framed, mut index) = (vec![], vec![]);
+    let mut encoder = Encoder::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let entries = &contents[start..starts.get(i + 1).copied().unwrap_or(entries_len)];
+        framed.extend_from_slice(&encoder.compress_vec(entries)?);
+        let key = restart_key(entries)?;
+        coding::put_varint64(&mut index, key.len() as u64);
+        index.extend_from_slice(key);
+        coding::put_fixed32(&mut index, start as u32);
+        coding::put_fixed32(&mut index, framed.len() as u32);
+    }
+    framed.extend_from_slice(restarts);
+    framed.extend_from_slice(&index);
+    for &n in &[entries_len, restarts.len(), index.len(), starts.len()] {
+        coding::put_fixed32(&mut framed, n as u32);
+    }
+    event!(
+        trace,
+        raw = contents.len(),
+        compressed = framed.len(),
+        frames = starts.len(),
+        "compressed block with framed snappy"
+    );
+    Ok(Some(framed))
+}
+
+fn parse(data: &[u8]) -> DecodeResult<Layout<'_>> {
+    let index_end = data
+        .len()
+        .checked_sub(TRAILER_LEN)
+        .ok_or_else(|| malformed("framed block too short"))?;
+    let trailer = &data[index_end..];
+    let entries_len = fixed32(trailer)?;
+    let restarts_len = fixed32(&trailer[4..])?;
+    let index_len = fixed32(&trailer[8..])?;
+    let num_frames = fixed32(&trailer[12..])?;
+    let restarts_off = match index_end
+        .checked_sub(index_len)
+        .and_then(|index_off| index_off.checked_sub(restarts_len))
+    {
+        Some(off) if restarts_len >= 4 && restarts_len.is_multiple_of(4) => off,
+        _ => return Err(malformed("bad framed block lengths")),
+    };
+    let restarts = &data[restarts_off..restarts_off + restarts_len];
+    let mut index = &data[restarts_off + restarts_len..index_end];
+
+    let mut frames: Vec<Frame> = Vec::with_capacity(num_frames.min(index.len()));
+    let mut data_off = 0;
+    for _ in 0..num_frames {
+        let (key_len, n) =
+            coding::get_varint32(index).map_err(|_| malformed("bad framed block index"))?;
+        let key_end = n + key_len as usize;
+        if index.len() < key_end + 8 {
+            return Err(malformed("truncated framed block index"));
+        }
+        let entries_off = fixed32(&index[key_end..])?;
+        let data_end = fixed32(&index[key_end + 4..])?;
+        let prev_entries_off = frames.last().map(|f| f.entries_off);
+        if data_end < data_off
+            || data_end > restarts_off
+            || entries_off > entries_len
+            || prev_entries_off.is_some_and(|prev| entries_off < prev)
+            || (prev_entries_off.is_none() && entries_off != 0)
+        {
+            return Err(malformed("bad framed block index"));
+        }
+        frames.push(Frame {
+            first_key: &index[n..key_end],
+            entries_off,
+            data: data_off..data_end,
+        });
+        data_off = data_end;
+        index = &index[key_end + 8..];
+    }
+    if frames.is_empty() || !index.is_empty() || data_off != restarts_off {
+        return Err(malformed("bad framed block index"));
+    }
+    Ok(Layout {
+        frames,
+        restarts,
+        entries_len,
+    })
+}
+
+/// Returns the uncompressed block of the framed block `data`, failing if it would be larger than
+/// `limit` (see `Options::max_decompressed_block_size`).
+pub fn decompress(data: &[u8], limit: Option<usize>) -> DecodeResult<Vec<u8>> {
+    let layout = parse(data)?;
+    let size = layout.entries_len + layout.restarts.len();
+    check_limit(size, limit)?;
+    let mut block = Vec::with_capacity(size);
+    for i in 0..layout.frames.len() {
+        layout.decompress_frame(data, i, &mut block)?;
+    }
+    block.extend_from_slice(layout.restarts);
+    Ok(block)
+}
+
+/// Returns a block holding the entries of the frame of the framed block `data` that `key` falls
+/// into: the first entry not less than `key` is in that block if it is in the framed block. The
+/// frame is decompressed only if it's not larger than `limit`.
+pub fn decompress_frame_for(
+    cmp: &dyn Cmp,
+    data: &[u8],
+    key: &[u8],
+    limit: Option<usize>,
+) -> DecodeResult<Vec<u8>> {
+    let layout = parse(data)?;
+    let frames = &layout.frames;
+    let next = frames.partition_point(|f| cmp.cmp(f.first_key, key) == Ordering::Less);
+    let first = next.saturating_sub(1);
+    // With KeyOrder::AllowEqual, the entries with `key` may continue in the following frame.
+    let last = match frames.get(next) {
+        Some(f) if cmp.cmp(f.first_key, key) == Ordering::Equal => next,
+        _ => first,
+    };
+    let entries = layout.entries(first).start..layout.entries(last).end;
+    let num_restarts = layout.restarts.len() / 4 - 1;
+    check_limit(entries.len() + 4 * (num_restarts + 1), limit)?;
+
+    let mut block = Vec::with_capacity(entries.len());
+    for i in first..=last {
+        layout.decompress_frame(data, i, &mut block)?;
+    }
+    let mut frame_restarts = 0;
+    for restart in layout.restarts[..4 * num_restarts].chunks(4) {
+        let off = fixed32(restart)?;
+        if entries.contains(&off) {
+            coding::put_fixed32(&mut block, (off - entries.start) as u32);
+            frame_restarts += 1;
+        }
+    }
+    coding::put_fixed32(&mut block, frame_restarts);
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::block_builder::BlockBuilder;
+    use crate::cmp::DefaultCmp;
+    use crate::options::Options;
+    use crate::types::SSIterator;
+
+    fn build_block(n: usize) -> Vec<u8> {
+        let mut opt = Options::default();
+        opt.block_restart_interval = 4;
+        let mut b = BlockBuilder::new(opt);
+        for i in 0..n {
+            let key = format!("key{:05}", i);
+            b.add(key.as_bytes(), format!("value of {}", key).as_bytes());
+        }
+        b.finish()
+    }
+
+    #[test]
+    fn test_framed_block_roundtrip() {
+        let block = build_block(1000);
+        let framed = compress(&block, 1024).unwrap().unwrap();
+        assert!(framed.len() < block.len());
+        assert_eq!(decompress(&framed, None).unwrap(), block);
+        assert!(matches!(
+            decompress(&framed, Some(block.len() - 1)),
+            Err(CorruptionKind::DecompressedSizeExceeded { .. })
+        ));
+
+        // Blocks that fit into a single frame aren't framed.
+        assert!(compress(&block, block.len()).unwrap().is_none());
+        assert!(compress(&build_block(0), 16).unwrap().is_none());
+
+        let mut truncated = framed.clone();
+        truncated.truncate(framed.len() - 1);
+        assert!(decompress(&truncated, None).is_err());
+    }
+
+    #[test]
+    fn test_framed_block_frame_for_key() {
+        let block = build_block(1000);
+        let framed = compress(&block, 1024).unwrap().unwrap();
+        let cmp = DefaultCmp;
+        for i in (0..1000).step_by(37).chain(Some(999)) {
+            let key = format!("key{:05}", i);
+            let contents = decompress_frame_for(&cmp, &framed, key.as_bytes(), None).unwrap();
+            assert!(contents.len() < block.len() / 2);
+            let frame = Block::try_new(Options::default(), contents).unwrap();
+            let mut iter = frame.iter();
+            iter.seek(key.as_bytes());
+            assert_eq!(iter.current_key(), Some(key.as_bytes()));
+        }
+
+        // Keys before the first or after the last entry aren't found.
+        for key in [&b"a"[..], b"key01000"] {
+            let contents = decompress_frame_for(&cmp, &framed, key, None).unwrap();
+            let mut iter = Block::try_new(Options::default(), contents).unwrap().iter();
+            iter.seek(key);
+            assert!(iter.current_key() != Some(key));
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Hash index (see `IndexType::HashSearch`). Tables built with a `PrefixExtractor` and the hash
+//! index type carry a meta block mapping every key prefix to the range of data blocks holding
+//! keys with that prefix. Point lookups then find their block by hashing the prefix instead of
+//! binary-searching the index block; lookups of keys whose prefix isn't in the table don't touch
+//! the index at all.
+//!
+//! The meta block consists of the name of the prefix extractor (varint length and bytes),
+//! followed by one entry per prefix: varint length and bytes of the prefix, then varints of the
+//! first data block's number and the number of further blocks.
+
+use crate::block::Block;
+use crate::blockhandle::BlockHandle;
+use crate::builder_state::{StateReader, StateWriter};
+use crate::cmp::Cmp;
+use crate::coding;
+use crate::error::{Error, Result};
+use crate::types::SSIterator;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use integer_encoding::VarInt;
+
+/// Key of the hash index block in the metaindex block.
+pub const HASH_INDEX_BLOCK_KEY: &str = "sstable.hash_index";
+
+/// A PrefixExtractor maps keys to prefixes that group them (e.g. a tenant or table ID at the
+/// start of every key). Keys sharing a prefix must be ordered contiguously by the comparator.
+pub trait PrefixExtractor: Send + Sync {
+    /// The name of the extractor. It is stored in tables using it; it should change whenever
+    /// the extracted prefixes change.
+    fn name(&self) -> &str;
+    /// Returns the prefix of `key`, or None if `key` has none.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+pub type BoxedPrefixExtractor = Arc<dyn PrefixExtractor>;
+
+/// Extracts the first `n` bytes of keys; shorter keys have no prefix.
+pub struct FixedPrefix(pub usize);
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        "sstable.FixedPrefix"
+    }
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.0)
+    }
+}
+
+/// Collects the prefixes of all keys of a table while it's built.
+pub struct HashIndexBuilder {
+    extractor: BoxedPrefixExtractor,
+    // Prefixes and the numbers of the first and last data block containing them.
+    prefixes: Vec<(Vec<u8>, u64, u64)>,
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+impl HashIndexBuilder {
+    pub fn new(extractor: BoxedPrefixExtractor) -> HashIndexBuilder {
+        HashIndexBuilder {
+            extractor,
+            prefixes: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Records that `key` is stored in the data block with number `block`.
+    pub fn add(&mut self, key: &[u8], block: u64) {
+        let prefix = match self.extractor.prefix(key) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        if let Some(last) = self.prefixes.last_mut() {
+            if last.0 == prefix {
+                last.2 = block;
+                return;
+            }
+        }
+        // A prefix seen before but not contiguously (with an unsuitable comparator) covers all
+        // blocks in between; lookups are still correct, only slower.
+        match self.positions.get(prefix) {
+            Some(&i) => self.prefixes[i].2 = block,
+            None => {
+                self.positions.insert(prefix.to_vec(), self.prefixes.len());
+                self.prefixes.push((prefix.to_vec(), block, block));
+            }
+        }
+    }
+
+    /// Serializes the state of the builder (see `TableBuilder::checkpoint()`).
+    pub fn save(&self, w: &mut StateWriter) {
+        w.put_u64(self.prefixes.len() as u64);
+        for (prefix, first, last) in &self.prefixes {
+            w.put_bytes(prefix);
+            w.put_u64(*first);
+            w.put_u64(*last);
+        }
+    }
+
+    /// Restores a builder saved using `save()`.
+    pub fn restore(
+        extractor: BoxedPrefixExtractor,
+        r: &mut StateReader,
+    ) -> Result<HashIndexBuilder> {
+        let mut b = HashIndexBuilder::new(extractor);
+        for _ in 0..r.get_u64()? {
+            let prefix = r.get_bytes()?.to_vec();
+            let (first, last) = (r.get_u64()?, r.get_u64()?);
+            b.positions.insert(prefix.clone(), b.prefixes.len());
+            b.prefixes.push((prefix, first, last));
+        }
+        Ok(b)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut dst = vec![];
+        put_bytes(&mut dst, self.extractor.name().as_bytes());
+        for (prefix, first, last) in self.prefixes {
+            put_bytes(&mut dst, &prefix);
+            dst.extend_from_slice(&first.encode_var_vec());
+            dst.extend_from_slice(&(last - first).encode_var_vec());
+        }
+        dst
+    }
+}
+
+fn put_bytes(dst: &mut Vec<u8>, b: &[u8]) {
+    dst.extend_from_slice(&b.len().encode_var_vec());
+    dst.extend_from_slice(b);
+}
+
+fn get_varint(src: &mut &[u8]) -> Result<u64> {
+    let (v, n) = coding::get_varint64(src)?;
+    *src = &src[n..];
+    Ok(v)
+}
+
+fn get_bytes<'a>(src: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = get_varint(src)? as usize;
+    if src.len() < len {
+        return Err(malformed());
+    }
+    let (b, rest) = src.split_at(len);
+    *src = rest;
+    Ok(b)
+}
+
+fn malformed() -> Error {
+    Error::malformed(None, "bad hash index block")
+}
+
+/// The result of looking up a key in a `HashIndex`.
+pub enum HashLookup {
+    /// The key has no prefix; the index block has to be searched.
+    NotIndexed,
+    /// No key of the table has the key's prefix.
+    Absent,
+    /// The key can only be in the block at this location.
+    Block(BlockHandle),
+}
+
+/// A decoded hash index, together with the entries of the index block it refers to.
+pub struct HashIndex {
+    extractor: BoxedPrefixExtractor,
+    // Separator keys and locations of all data blocks.
+    blocks: Vec<(Vec<u8>, BlockHandle)>,
+    prefixes: HashMap<Vec<u8>, (usize, usize)>,
+}
+
+impl HashIndex {
+    /// Decodes the hash index block `contents` of a table with the index block `index`. Returns
+    /// None if the index was built with another prefix extractor than `extractor`.
+    pub fn decode(
+        contents: &[u8],
+        index: &Block,
+        extractor: &BoxedPrefixExtractor,
+    ) -> Result<Option<HashIndex>> {
+        let mut src = contents;
+        if get_bytes(&mut src)? != extractor.name().as_bytes() {
+            return Ok(None);
+        }
+
+        let mut blocks = vec![];
+        let mut iter = index.iter();
+        while let Some((sep, handle)) = iter.next() {
+            blocks.push((sep, BlockHandle::decode(&handle)?.0));
+        }
+
+        let mut prefixes = HashMap::new();
+        while !src.is_empty() {
+            let prefix = get_bytes(&mut src)?.to_vec();
+            let first = get_varint(&mut src)? as usize;
+            let last = first.saturating_add(get_varint(&mut src)? as usize);
+            if last >= blocks.len() {
+                return Err(malformed());
+            }
+            prefixes.insert(prefix, (first, last));
+        }
+        Ok(Some(HashIndex {
+            extractor: extractor.clone(),
+            blocks,
+            prefixes,
+        }))
+    }
+
+    /// Returns the approximate number of bytes the index occupies in memory.
+    pub fn approximate_memory_usage(&self) -> usize {
+        let entry = std::mem::size_of::<(Vec<u8>, BlockHandle)>();
+        let blocks: usize = self.blocks.iter().map(|(sep, _)| sep.len() + entry).sum();
+        let prefix = std::mem::size_of::<(Vec<u8>, (usize, usize))>();
+        let prefixes: usize = self.prefixes.keys().map(|p| p.len() + prefix).sum();
+        blocks + prefixes
+    }
+
+    /// Returns the block that may contain `key`. Like a search of the index block, this is the
+    /// first block whose index key is not less than `key`.
+    pub fn lookup(&self, cmp: &dyn Cmp, key: &[u8]) -> HashLookup {
+        let prefix = match self.extractor.prefix(key) {
+            Some(prefix) => prefix,
+            None => return HashLookup::NotIndexed,
+        };
+        let (first, last) = match self.prefixes.get(prefix) {
+            Some(&range) => range,
+            None => return HashLookup::Absent,
+        };
+        let candidates = &self.blocks[first..=last];
+        let i = candidates.partition_point(|(sep, _)| cmp.cmp(sep, key) == Ordering::Less);
+        match candidates.get(i) {
+            Some((sep, handle)) if cmp.cmp(key, sep) != Ordering::Greater => {
+                HashLookup::Block(handle.clone())
+            }
+            _ => HashLookup::Absent,
+        }
+    }
+}
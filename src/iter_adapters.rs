@@ -0,0 +1,409 @@
+//! Adapters changing which entries an `SSIterator` yields. They wrap any iterator, e.g. a
+//! `TableIterator` or a `VersionIterator`, and can be stacked.
+//!
+//! For iterators over internal keys (see `key_types`), `DedupUserKeys` keeps the newest version
+//! of every user key and `DropTombstones` hides deletions. Stacked in this order, they yield the
+//! live entries:
+//!
+//! ```ignore
+//! let live = DropTombstones::new(DedupUserKeys::new(table.iter(), user_cmp));
+//! ```
+//!
+//! Dropping tombstones first would make older versions of deleted keys reappear.
+
+use crate::cmp::Cmp;
+use crate::error::Error;
+use crate::key_types::{self, ValueType, MAX_SEQUENCE_NUMBER};
+use crate::types::SSIterator;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Returns the user key of an internal key, or the key itself if it's malformed.
+fn user_key(key: &[u8]) -> &[u8] {
+    key_types::parse_internal_key(key).map_or(key, |(_, _, k)| k)
+}
+
+/// Yields the entries of the wrapped iterator whose keys start with a prefix, and becomes invalid
+/// at the first key that doesn't. Seeks before the prefix go to its first key. The wrapped
+/// iterator's comparator must order keys sharing a prefix contiguously, as bytewise comparators
+/// do.
+pub struct TakeWhilePrefix<I: SSIterator> {
+    inner: I,
+    cmp: Arc<Box<dyn Cmp>>,
+    prefix: Vec<u8>,
+    // Whether keys are internal keys, whose user keys have to start with the prefix.
+    internal_keys: bool,
+    started: bool,
+}
+
+impl<I: SSIterator> TakeWhilePrefix<I> {
+    /// Returns an iterator over the keys of `inner` that start with `prefix`. `cmp` is the
+    /// comparator of `inner`.
+    pub fn new(inner: I, cmp: Arc<Box<dyn Cmp>>, prefix: &[u8]) -> TakeWhilePrefix<I> {
+        TakeWhilePrefix {
+            inner,
+            cmp,
+            prefix: prefix.to_vec(),
+            internal_keys: false,
+            started: false,
+        }
+    }
+
+    /// Like `new()`, for an iterator over internal keys: the prefix applies to user keys. A plain
+    /// prefix check on internal keys would also match the sequence numbers of short user keys.
+    pub fn for_internal_keys(
+        inner: I,
+        cmp: Arc<Box<dyn Cmp>>,
+        prefix: &[u8],
+    ) -> TakeWhilePrefix<I> {
+        TakeWhilePrefix {
+            internal_keys: true,
+            ..TakeWhilePrefix::new(inner, cmp, prefix)
+        }
+    }
+
+    /// Returns the smallest key with the prefix.
+    fn first_key(&self) -> Vec<u8> {
+        if self.internal_keys {
+            key_types::build_internal_key(&self.prefix, MAX_SEQUENCE_NUMBER, ValueType::TypeValue)
+        } else {
+            self.prefix.clone()
+        }
+    }
+
+    fn in_range(&self, key: &[u8]) -> bool {
+        if self.internal_keys {
+            key_types::parse_internal_key(key).is_some_and(|(_, _, k)| k.starts_with(&self.prefix))
+        } else {
+            key.starts_with(&self.prefix)
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: SSIterator> SSIterator for TakeWhilePrefix<I> {
+    fn advance(&mut self) -> bool {
+        if !self.started {
+            self.started = true;
+            let first = self.first_key();
+            self.inner.seek(&first);
+        } else if self.valid() {
+            self.inner.advance();
+        }
+        self.valid()
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.valid() && self.inner.current(key, val)
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.inner.current_key().filter(|_| self.valid())
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.started = true;
+        let first = self.first_key();
+        if self.cmp.cmp(key, &first) == Ordering::Less {
+            self.inner.seek(&first);
+        } else {
+            self.inner.seek(key);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.started = false;
+        self.inner.reset();
+    }
+
+    fn valid(&self) -> bool {
+        self.started && self.inner.current_key().is_some_and(|k| self.in_range(k))
+    }
+
+    fn prev(&mut self) -> bool {
+        self.valid() && self.inner.prev() && self.valid()
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.inner.status()
+    }
+}
+
+/// Yields only the first entry of every user key of an iterator over internal keys, i.e. the
+/// newest version of every key. User keys are compared using the user comparator `ucmp`, not the
+/// internal key comparator of the wrapped iterator. Entries with malformed keys are treated as
+/// user keys of their own.
+pub struct DedupUserKeys<I: SSIterator> {
+    inner: I,
+    ucmp: Arc<Box<dyn Cmp>>,
+}
+
+impl<I: SSIterator> DedupUserKeys<I> {
+    pub fn new(inner: I, ucmp: Arc<Box<dyn Cmp>>) -> DedupUserKeys<I> {
+        DedupUserKeys { inner, ucmp }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    fn same_user_key(&self, a: &[u8], b: &[u8]) -> bool {
+        self.ucmp.cmp(user_key(a), user_key(b)) == Ordering::Equal
+    }
+}
+
+impl<I: SSIterator> SSIterator for DedupUserKeys<I> {
+    fn advance(&mut self) -> bool {
+        let current = match self.inner.current_key() {
+            Some(key) => key.to_vec(),
+            None => return self.inner.advance(),
+        };
+        while self.inner.advance() {
+            if !self.same_user_key(self.inner.current_key().unwrap(), &current) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.inner.current(key, val)
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.inner.current_key()
+    }
+
+    /// Seeks to the newest version of the first user key at or after `key`'s. If `key` is an
+    /// internal key, versions of its user key newer than it are skipped.
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key);
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn prev(&mut self) -> bool {
+        let current = match self.inner.current_key() {
+            Some(key) => key.to_vec(),
+            None => return false,
+        };
+        // Find the previous user key, then its newest version.
+        let mut prev: Option<Vec<u8>> = None;
+        while self.inner.prev() {
+            let key = self.inner.current_key().unwrap();
+            match prev {
+                None if self.same_user_key(key, &current) => {}
+                Some(ref p) if !self.same_user_key(key, p) => break,
+                _ => prev = Some(key.to_vec()),
+            }
+        }
+        match prev {
+            Some(p) => {
+                let newest = match key_types::parse_internal_key(&p) {
+                    Some((_, _, k)) => {
+                        key_types::build_internal_key(k, MAX_SEQUENCE_NUMBER, ValueType::TypeValue)
+                    }
+                    None => p,
+                };
+                self.inner.seek(&newest);
+                self.inner.valid()
+            }
+            None => {
+                self.inner.reset();
+                false
+            }
+        }
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.inner.status()
+    }
+}
+
+/// Skips the deletion markers (`ValueType::TypeDeletion` entries) of an iterator over internal
+/// keys.
+pub struct DropTombstones<I: SSIterator> {
+    inner: I,
+}
+
+impl<I: SSIterator> DropTombstones<I> {
+    pub fn new(inner: I) -> DropTombstones<I> {
+        DropTombstones { inner }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+
+    fn at_tombstone(&self) -> bool {
+        self.inner
+            .current_key()
+            .and_then(key_types::parse_internal_key)
+            .is_some_and(|(t, _, _)| t == ValueType::TypeDeletion)
+    }
+
+    fn skip_forward(&mut self) -> bool {
+        while self.at_tombstone() {
+            if !self.inner.advance() {
+                return false;
+            }
+        }
+        self.inner.valid()
+    }
+}
+
+impl<I: SSIterator> SSIterator for DropTombstones<I> {
+    fn advance(&mut self) -> bool {
+        self.inner.advance() && self.skip_forward()
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.inner.current(key, val)
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.inner.current_key()
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(key);
+        self.skip_forward();
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn valid(&self) -> bool {
+        self.inner.valid()
+    }
+
+    fn prev(&mut self) -> bool {
+        while self.inner.prev() {
+            if !self.at_tombstone() {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.inner.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmp::{DefaultCmp, InternalKeyCmp};
+    use crate::key_types::build_internal_key;
+    use crate::options::Options;
+    use crate::table_reader::Table;
+    use crate::test_util::{build_table, test_iterator_properties, SSIteratorIter};
+
+    fn internal_table() -> (Table, Arc<Box<dyn Cmp>>) {
+        let ucmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(DefaultCmp));
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(ucmp.clone())));
+        let ik = |k: &str, seq, t| build_internal_key(k.as_bytes(), seq, t);
+        let entries = vec![
+            (ik("a", 5, ValueType::TypeValue), "a5"),
+            (ik("a", 2, ValueType::TypeValue), "a2"),
+            (ik("b", 7, ValueType::TypeDeletion), ""),
+            (ik("b", 3, ValueType::TypeValue), "b3"),
+            (ik("bb", 1, ValueType::TypeValue), "bb1"),
+            (ik("c", 9, ValueType::TypeValue), "c9"),
+            (ik("d", 4, ValueType::TypeDeletion), ""),
+            (ik("e", 1, ValueType::TypeValue), "e1"),
+        ];
+        (build_table(&opt, &entries), ucmp)
+    }
+
+    fn user_keys<I: SSIterator>(it: &mut I) -> Vec<String> {
+        SSIteratorIter::wrap(it)
+            .map(|(k, _)| String::from_utf8(user_key(&k).to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_take_while_prefix() {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let entries: Vec<(Vec<u8>, &str)> = ["aa", "abc", "abd", "abe", "abf", "b"]
+            .iter()
+            .map(|k| (k.as_bytes().to_vec(), "v"))
+            .collect();
+        let table = build_table(&opt, &entries);
+        let keys = |it: &mut TakeWhilePrefix<_>| -> Vec<Vec<u8>> {
+            SSIteratorIter::wrap(it).map(|(k, _)| k).collect()
+        };
+
+        let mut it = TakeWhilePrefix::new(table.iter(), opt.cmp.clone(), b"abc");
+        assert_eq!(keys(&mut it), vec![b"abc".to_vec()]);
+        let mut it = TakeWhilePrefix::new(table.iter(), opt.cmp.clone(), b"ab");
+        it.seek(b"a");
+        assert_eq!(it.current_key(), Some(&b"abc"[..]));
+        it.seek(b"abe");
+        assert_eq!(it.current_key(), Some(&b"abe"[..]));
+        assert!(it.prev() && it.prev());
+        assert!(!it.prev());
+        it.seek(b"abz");
+        assert!(!it.valid());
+        test_iterator_properties(TakeWhilePrefix::new(
+            TakeWhilePrefix::new(table.iter(), opt.cmp.clone(), b"ab"),
+            opt.cmp.clone(),
+            b"ab",
+        ));
+        let mut it = TakeWhilePrefix::new(table.iter(), opt.cmp.clone(), b"c");
+        assert!(!it.advance());
+
+        // The sequence number of "b"@3 starts with 0x03, and that of "bb"@1 with 0x01.
+        let (table, _) = internal_table();
+        let cmp =
+            Arc::new(Box::new(InternalKeyCmp(Arc::new(Box::new(DefaultCmp)))) as Box<dyn Cmp>);
+        let mut it = TakeWhilePrefix::for_internal_keys(table.iter(), cmp.clone(), b"b\x03");
+        assert!(!it.advance());
+        let mut it = TakeWhilePrefix::for_internal_keys(table.iter(), cmp, b"b");
+        assert_eq!(user_keys(&mut it), vec!["b", "b", "bb"]);
+    }
+
+    #[test]
+    fn test_dedup_and_drop_tombstones() {
+        let (table, ucmp) = internal_table();
+        let mut it = DedupUserKeys::new(table.iter(), ucmp.clone());
+        assert_eq!(user_keys(&mut it), vec!["a", "b", "bb", "c", "d", "e"]);
+        let mut it = DropTombstones::new(table.iter());
+        assert_eq!(user_keys(&mut it), vec!["a", "a", "b", "bb", "c", "e"]);
+
+        let mut live = DropTombstones::new(DedupUserKeys::new(table.iter(), ucmp.clone()));
+        assert_eq!(user_keys(&mut live), vec!["a", "bb", "c", "e"]);
+        let mut val = vec![];
+        let mut key = vec![];
+        live.seek(&build_internal_key(b"a", 3, ValueType::TypeValue));
+        assert!(live.current(&mut key, &mut val));
+        assert_eq!(val, b"a2");
+        live.seek(&build_internal_key(b"b", 9, ValueType::TypeValue));
+        assert_eq!(user_key(live.current_key().unwrap()), b"bb");
+
+        live.seek(&build_internal_key(b"e", 9, ValueType::TypeValue));
+        let mut back = vec![];
+        while live.valid() {
+            live.current(&mut key, &mut val);
+            back.push(String::from_utf8(val.clone()).unwrap());
+            live.prev();
+        }
+        assert_eq!(back, vec!["e1", "c9", "bb1", "a5"]);
+        test_iterator_properties(live);
+    }
+}
@@ -0,0 +1,393 @@
+//! Joins of two tables sharing a comparator, e.g. a base table and a delta table written on top
+//! of it.
+
+use crate::cmp::Cmp;
+use crate::error::{Error, Result};
+use crate::key_types::{self, ValueType};
+use crate::table_builder::TableBuilder;
+use crate::table_reader::{Table, TableIterator};
+use crate::types::{current_key_val, SSIterator};
+
+use std::cmp::Ordering;
+use std::io::Write;
+use std::sync::Arc;
+
+/// How many entries a side that is behind the other is advanced before it's repositioned using a
+/// seek (which goes through the index block).
+const SKIP_AHEAD_STEPS: usize = 8;
+
+/// Which keys a `ZipIterator` yields.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JoinType {
+    /// Keys present in either table.
+    Outer,
+    /// Keys present in the first table.
+    Left,
+    /// Keys present in both tables.
+    Inner,
+}
+
+/// ZipIterator walks two tables in lockstep, yielding `(key, value in a, value in b)` for every
+/// key in the union of both tables' keys (or a subset of it, see `JoinType`). Keys are ordered by
+/// the first table's comparator, which both tables must share.
+///
+/// For left and inner joins, a side that falls far behind the other is moved forward by seeking,
+/// which skips the blocks in between without reading them.
+pub struct ZipIterator {
+    a: TableIterator,
+    b: TableIterator,
+    cmp: Arc<Box<dyn Cmp>>,
+    join: JoinType,
+    started: bool,
+    advance_a: bool,
+    advance_b: bool,
+}
+
+impl ZipIterator {
+    /// Returns an iterator over the union of the keys of `a` and `b`.
+    pub fn new(a: TableIterator, b: TableIterator) -> ZipIterator {
+        ZipIterator::with_join(a, b, JoinType::Outer)
+    }
+
+    /// Returns an iterator over the keys of `a` and `b` selected by `join`.
+    pub fn with_join(mut a: TableIterator, mut b: TableIterator, join: JoinType) -> ZipIterator {
+        a.reset();
+        b.reset();
+        ZipIterator {
+            cmp: a.cmp(),
+            a,
+            b,
+            join,
+            started: false,
+            advance_a: false,
+            advance_b: false,
+        }
+    }
+
+    /// Positions both sides at the first key at or after `key`; the next item returned is the
+    /// first one with such a key.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.a.seek(key);
+        self.b.seek(key);
+        self.started = true;
+        self.advance_a = false;
+        self.advance_b = false;
+    }
+
+    /// Returns the first error encountered by either side (see `SSIterator::status()`).
+    pub fn status(&self) -> Option<&Error> {
+        self.a.status().or_else(|| self.b.status())
+    }
+
+    /// Moves `it` forward to the first key at or after `target`.
+    fn catch_up(cmp: &dyn Cmp, it: &mut TableIterator, target: &[u8]) {
+        for _ in 0..SKIP_AHEAD_STEPS {
+            if !it.advance() {
+                return;
+            }
+            if it
+                .current_key()
+                .is_some_and(|k| cmp.cmp(k, target) != Ordering::Less)
+            {
+                return;
+            }
+        }
+        event!(trace, "zip iterator skipping ahead");
+        it.seek(target);
+    }
+}
+
+impl Iterator for ZipIterator {
+    type Item = (Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            self.a.advance();
+            self.b.advance();
+        }
+        if self.advance_a {
+            self.advance_a = false;
+            self.a.advance();
+        }
+        if self.advance_b {
+            self.advance_b = false;
+            self.b.advance();
+        }
+
+        loop {
+            let order = match (self.a.current_key(), self.b.current_key()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(ka), Some(kb)) => self.cmp.cmp(ka, kb),
+            };
+
+            match order {
+                Ordering::Equal => {
+                    let (key, va) = current_key_val(&self.a)?;
+                    let (_, vb) = current_key_val(&self.b)?;
+                    self.advance_a = true;
+                    self.advance_b = true;
+                    return Some((key, Some(va), Some(vb)));
+                }
+                Ordering::Less => {
+                    if self.join == JoinType::Inner {
+                        if !self.b.valid() {
+                            return None;
+                        }
+                        let target = self.b.current_key()?.to_vec();
+                        ZipIterator::catch_up(self.cmp.as_ref().as_ref(), &mut self.a, &target);
+                        continue;
+                    }
+                    let (key, va) = current_key_val(&self.a)?;
+                    self.advance_a = true;
+                    return Some((key, Some(va), None));
+                }
+                Ordering::Greater => {
+                    if self.join != JoinType::Outer {
+                        if !self.a.valid() {
+                            return None;
+                        }
+                        let target = self.a.current_key()?.to_vec();
+                        ZipIterator::catch_up(self.cmp.as_ref().as_ref(), &mut self.b, &target);
+                        continue;
+                    }
+                    let (key, vb) = current_key_val(&self.b)?;
+                    self.advance_b = true;
+                    return Some((key, None, Some(vb)));
+                }
+            }
+        }
+    }
+}
+
+/// Writes the result of applying `delta` onto `base` to `builder` and finishes it, returning the
+/// size of the new table. Both tables must store internal keys (see `key_types`) and use
+/// `InternalKeyCmp`, as must `builder`. For every user key, the newest version in `delta` replaces
+/// all versions in `base`; only the newest version of every key is written, and deleted keys are
+/// dropped. The tables are streamed, so memory use doesn't depend on their size.
+pub fn patch<Dst: Write>(
+    base: &Table,
+    delta: &Table,
+    mut builder: TableBuilder<Dst>,
+) -> Result<u64> {
+    let (mut b, mut d) = (base.iter(), delta.iter());
+    let cmp = b.cmp();
+    b.advance();
+    d.advance();
+
+    loop {
+        let order = match (b.current_key(), d.current_key()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(kb), Some(kd)) if user_key(kb)? == user_key(kd)? => Ordering::Equal,
+            (Some(kb), Some(kd)) => cmp.cmp(kb, kd),
+        };
+        match order {
+            Ordering::Less => {
+                copy_newest(&mut b, &mut builder)?;
+            }
+            Ordering::Greater => {
+                copy_newest(&mut d, &mut builder)?;
+            }
+            Ordering::Equal => {
+                // The delta's versions shadow all of the base's.
+                let ukey = copy_newest(&mut d, &mut builder)?;
+                skip_user_key(&mut b, &ukey)?;
+            }
+        }
+    }
+
+    if let Some(e) = b.take_error().or_else(|| d.take_error()) {
+        return Err(e);
+    }
+    builder.finish()
+}
+
+fn user_key(ikey: &[u8]) -> Result<&[u8]> {
+    key_types::parse_internal_key(ikey)
+        .map(|(_, _, ukey)| ukey)
+        .ok_or_else(|| Error::malformed(None, "bad internal key"))
+}
+
+/// Adds the entry `it` is positioned at to `builder` unless it's a deletion, and moves `it` past
+/// all older versions of its user key. Returns the user key.
+fn copy_newest<Dst: Write>(
+    it: &mut TableIterator,
+    builder: &mut TableBuilder<Dst>,
+) -> Result<Vec<u8>> {
+    let (key, val) =
+        current_key_val(it).ok_or_else(|| Error::malformed(None, "couldn't read value"))?;
+    let ukey = user_key(&key)?.to_vec();
+    if let Some((ValueType::TypeValue, _, _)) = key_types::parse_internal_key(&key) {
+        builder.add(&key, &val)?;
+    }
+    skip_user_key(it, &ukey)?;
+    Ok(ukey)
+}
+
+/// Moves `it` to the first entry whose user key isn't `ukey`.
+fn skip_user_key(it: &mut TableIterator, ukey: &[u8]) -> Result<()> {
+    while let Some(key) = it.current_key() {
+        if user_key(key)? != ukey {
+            break;
+        }
+        it.advance();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmp::InternalKeyCmp;
+    use crate::key_types::{build_internal_key, SequenceNumber, MAX_SEQUENCE_NUMBER};
+    use crate::options::Options;
+    use crate::test_util::{self, SSIteratorIter};
+
+    fn build_table(keys: &[usize], tag: &str) -> Table {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let entries: Vec<(String, String)> = keys
+            .iter()
+            .map(|k| (format!("key{:04}", k), format!("{}{}", tag, k)))
+            .collect();
+        test_util::build_table(&opt, &entries)
+    }
+
+    fn keys(it: ZipIterator) -> Vec<(String, Option<String>, Option<String>)> {
+        it.map(|(k, a, b)| {
+            let s = |v: Vec<u8>| String::from_utf8(v).unwrap();
+            (s(k), a.map(s), b.map(s))
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_zip_iterator() {
+        let base: Vec<usize> = (0..200).step_by(2).collect();
+        let a = build_table(&base, "a");
+        let b = build_table(&[1, 2, 3, 150, 250], "b");
+
+        let all = keys(ZipIterator::new(a.iter(), b.iter()));
+        assert_eq!(all.len(), 100 + 3);
+        assert_eq!(
+            &all[..4],
+            &[
+                ("key0000".to_string(), Some("a0".to_string()), None),
+                ("key0001".to_string(), None, Some("b1".to_string())),
+                (
+                    "key0002".to_string(),
+                    Some("a2".to_string()),
+                    Some("b2".to_string())
+                ),
+                ("key0003".to_string(), None, Some("b3".to_string())),
+            ]
+        );
+        assert_eq!(
+            all.last().unwrap(),
+            &("key0250".to_string(), None, Some("b250".to_string()))
+        );
+
+        let left = keys(ZipIterator::with_join(a.iter(), b.iter(), JoinType::Left));
+        assert_eq!(left.len(), 100);
+        assert!(left.iter().all(|(_, a, _)| a.is_some()));
+        assert_eq!(left.iter().filter(|(_, _, b)| b.is_some()).count(), 2);
+
+        let inner = keys(ZipIterator::with_join(a.iter(), b.iter(), JoinType::Inner));
+        assert_eq!(
+            inner.iter().map(|(k, _, _)| k.as_str()).collect::<Vec<_>>(),
+            vec!["key0002", "key0150"]
+        );
+        // Joining against a table with few keys works the other way around, too.
+        let inner = keys(ZipIterator::with_join(b.iter(), a.iter(), JoinType::Inner));
+        assert_eq!(inner.len(), 2);
+
+        let mut it = ZipIterator::new(a.iter(), b.iter());
+        it.seek(b"key0149");
+        let rest = keys(it);
+        assert_eq!(rest[0].0, "key0150");
+        assert_eq!(rest.len(), 25 + 1);
+
+        let empty = build_table(&[], "e");
+        assert_eq!(keys(ZipIterator::new(empty.iter(), b.iter())).len(), 5);
+        assert!(keys(ZipIterator::with_join(
+            a.iter(),
+            empty.iter(),
+            JoinType::Inner
+        ))
+        .is_empty());
+    }
+
+    fn build_versioned_table(
+        opt: &Options,
+        entries: &[(&str, SequenceNumber, ValueType, &str)],
+    ) -> Table {
+        let entries: Vec<(Vec<u8>, &str)> = entries
+            .iter()
+            .map(|&(k, seq, t, v)| (build_internal_key(k.as_bytes(), seq, t), v))
+            .collect();
+        test_util::build_table(opt, &entries)
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        let (del, val) = (ValueType::TypeDeletion, ValueType::TypeValue);
+        let base = build_versioned_table(
+            &opt,
+            &[
+                ("a", 1, val, "a1"),
+                ("b", 3, val, "b3"),
+                ("b", 2, val, "b2"),
+                ("c", 3, val, "c3"),
+                ("d", 4, del, ""),
+                ("d", 1, val, "d1"),
+                ("f", 2, val, "f2"),
+            ],
+        );
+        let delta = build_versioned_table(
+            &opt,
+            &[
+                ("b", 10, del, ""),
+                ("c", 12, val, "c12"),
+                ("c", 11, val, "c11"),
+                ("e", 13, val, "e13"),
+                ("g", 14, del, ""),
+                ("h", 15, val, "h15"),
+            ],
+        );
+
+        let mut d = vec![];
+        let size = patch(&base, &delta, TableBuilder::new(opt.clone(), &mut d)).unwrap();
+        assert_eq!(size, d.len() as u64);
+        let patched = Table::new(opt, Box::new(d), size).unwrap();
+
+        let mut iter = patched.iter();
+        let entries: Vec<_> = SSIteratorIter::wrap(&mut iter)
+            .map(|(k, v)| {
+                let (_, seq, ukey) = key_types::parse_internal_key(&k).unwrap();
+                (String::from_utf8(ukey.to_vec()).unwrap(), seq, v)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("a".to_string(), 1, b"a1".to_vec()),
+                ("c".to_string(), 12, b"c12".to_vec()),
+                ("e".to_string(), 13, b"e13".to_vec()),
+                ("f".to_string(), 2, b"f2".to_vec()),
+                ("h".to_string(), 15, b"h15".to_vec()),
+            ]
+        );
+        assert_eq!(
+            patched.get_at(b"c", MAX_SEQUENCE_NUMBER).unwrap(),
+            Some(b"c12".to_vec())
+        );
+        assert_eq!(patched.get_at(b"b", MAX_SEQUENCE_NUMBER).unwrap(), None);
+    }
+}
@@ -0,0 +1,52 @@
+//! Application-defined encoding of keys (see `Options::key_codec`). A codec maps the keys added to
+//! a table to the form they are stored in, and maps stored keys back on reads. This lets
+//! applications store escaped or composite keys while working with their logical keys.
+
+use crate::error::Result;
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// A KeyCodec transforms keys on their way into and out of a table. Encoding must preserve the
+/// order of keys under the table's comparator: for all keys `a` and `b`, `cmp(encode(a),
+/// encode(b)) == cmp(a, b)`. The comparator therefore orders both stored and logical keys.
+///
+/// Lookups, iterators and `Table::approx_offset_of()` take and return logical keys. Everything
+/// derived from the keys as they are stored -- the index, filters, block stats, properties
+/// collectors, and the keys seen by `Table::raw_iter()` and `Table::index_iter()` -- uses the
+/// stored form. The codec's name is stored in the table's properties; tables written with a codec
+/// can only be read with options carrying a codec of the same name.
+pub trait KeyCodec: Send + Sync {
+    /// The name of the codec. It should change whenever the encoding changes incompatibly.
+    fn name(&self) -> &str;
+    /// Appends the stored form of `key` to `dst`.
+    fn encode(&self, key: &[u8], dst: &mut Vec<u8>) -> Result<()>;
+    /// Appends the logical key stored as `stored` by `encode()` to `dst`.
+    fn decode(&self, stored: &[u8], dst: &mut Vec<u8>) -> Result<()>;
+}
+
+pub type BoxedKeyCodec = Arc<dyn KeyCodec>;
+
+/// Returns the stored form of `key`, which is `key` itself without a codec.
+pub fn encode<'a>(codec: Option<&dyn KeyCodec>, key: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    match codec {
+        Some(codec) => {
+            let mut dst = Vec::with_capacity(key.len());
+            codec.encode(key, &mut dst)?;
+            Ok(Cow::Owned(dst))
+        }
+        None => Ok(Cow::Borrowed(key)),
+    }
+}
+
+/// Returns the logical key stored as `stored`, which is `stored` itself without a codec.
+pub fn decode<'a>(codec: Option<&dyn KeyCodec>, stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    match codec {
+        Some(codec) => {
+            let mut dst = Vec::with_capacity(stored.len());
+            codec.decode(stored, &mut dst)?;
+            Ok(Cow::Owned(dst))
+        }
+        None => Ok(Cow::Borrowed(stored)),
+    }
+}
@@ -0,0 +1,99 @@
+//! Internal keys, as used by LevelDB: a user key followed by an 8 byte tag holding a sequence
+//! number and a `ValueType`. Tables storing several versions of a key (and tombstones) use
+//! internal keys together with `InternalKeyCmp`, which orders them by user key and then by
+//! descending sequence number, so that the newest version of a key comes first.
+
+use crate::cmp::Cmp;
+use crate::coding;
+
+use std::cmp::Ordering;
+
+pub type SequenceNumber = u64;
+
+/// The largest sequence number that can be encoded in an internal key.
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+const TAG_LENGTH: usize = 8;
+
+/// The kind of an entry with an internal key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    TypeDeletion = 0,
+    TypeValue = 1,
+}
+
+/// Returns the internal key for version `seq` of `user_key`.
+pub fn build_internal_key(user_key: &[u8], seq: SequenceNumber, t: ValueType) -> Vec<u8> {
+    assert!(seq <= MAX_SEQUENCE_NUMBER);
+    let mut key = Vec::with_capacity(user_key.len() + TAG_LENGTH);
+    key.extend_from_slice(user_key);
+    coding::put_fixed64(&mut key, (seq << 8) | t as u64);
+    key
+}
+
+/// Splits an internal key into value type, sequence number and user key. Returns None if `ikey`
+/// is not a valid internal key.
+pub fn parse_internal_key(ikey: &[u8]) -> Option<(ValueType, SequenceNumber, &[u8])> {
+    if ikey.len() < TAG_LENGTH {
+        return None;
+    }
+    let (user_key, tag) = ikey.split_at(ikey.len() - TAG_LENGTH);
+    let tag = coding::get_fixed64(tag).ok()?;
+    let t = match tag & 0xff {
+        0 => ValueType::TypeDeletion,
+        1 => ValueType::TypeValue,
+        _ => return None,
+    };
+    Some((t, tag >> 8, user_key))
+}
+
+/// Compares two internal keys: by user key using `ucmp`, then by descending sequence number.
+/// Malformed keys are compared bytewise.
+pub fn cmp_internal_key(ucmp: &dyn Cmp, a: &[u8], b: &[u8]) -> Ordering {
+    match (parse_internal_key(a), parse_internal_key(b)) {
+        (Some((_, seqa, keya)), Some((_, seqb, keyb))) => {
+            ucmp.cmp(keya, keyb).then_with(|| seqb.cmp(&seqa))
+        }
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmp::DefaultCmp;
+
+    #[test]
+    fn test_key_types_internal_key() {
+        let k = build_internal_key(b"abc", 123, ValueType::TypeValue);
+        assert_eq!(k.len(), 11);
+        assert_eq!(
+            parse_internal_key(&k),
+            Some((ValueType::TypeValue, 123, &b"abc"[..]))
+        );
+        let k = build_internal_key(b"", MAX_SEQUENCE_NUMBER, ValueType::TypeDeletion);
+        assert_eq!(
+            parse_internal_key(&k),
+            Some((ValueType::TypeDeletion, MAX_SEQUENCE_NUMBER, &b""[..]))
+        );
+        assert_eq!(parse_internal_key(b"short"), None);
+        assert_eq!(parse_internal_key(&[7, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_key_types_cmp_internal_key() {
+        let k = |key: &[u8], seq| build_internal_key(key, seq, ValueType::TypeValue);
+        assert_eq!(
+            cmp_internal_key(&DefaultCmp, &k(b"abc", 1), &k(b"abd", 5)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp_internal_key(&DefaultCmp, &k(b"abc", 5), &k(b"abc", 1)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp_internal_key(&DefaultCmp, &k(b"abc", 5), &k(b"abc", 5)),
+            Ordering::Equal
+        );
+    }
+}
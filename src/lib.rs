@@ -1,28 +1,104 @@
+#![cfg_attr(test, allow(clippy::field_reassign_with_default))]
+
 #[cfg(test)]
 #[macro_use]
 extern crate time_test;
 
+#[macro_use]
+mod trace;
+
+pub mod arrow;
+mod blob;
 pub mod block;
 pub mod block_builder;
+mod block_stats;
 mod blockhandle;
+mod builder_state;
+pub mod bundle;
 mod cache;
+pub mod checksum;
+mod clock_cache;
+mod coding;
+pub mod concat;
 pub mod error;
+pub mod fault_injection;
+pub mod ffi;
+mod file_registry;
 pub mod filter;
 mod filter_block;
+mod flush_policy;
+mod framed_block;
+mod hash_index;
+pub mod iter_adapters;
+pub mod join;
+mod key_codec;
+pub mod key_types;
+pub mod manifest;
+mod memtable;
 mod table_block;
 mod types;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
 
 mod cmp;
 mod options;
+mod partitioned_builder;
 mod table_builder;
+mod table_cache;
+mod table_filter;
+mod table_properties;
 mod table_reader;
+pub mod testutil;
+mod ttl;
+mod two_level;
+mod value_codec;
+mod value_compression;
+mod value_reader;
+mod version;
+mod version_set;
 
-pub use crate::cmp::{Cmp, DefaultCmp};
-pub use crate::error::{Result, Status, StatusCode};
-pub use crate::options::{CompressionType, Options};
-pub use crate::table_builder::TableBuilder;
-pub use crate::table_reader::{Table, TableIterator};
-pub use crate::types::{current_key_val, RandomAccess, SSIterator};
+pub use crate::block_stats::{BlockPruner, BlockStatsAggregator, BlockStatsAggregatorFactory};
+pub use crate::blockhandle::BlockHandle;
+pub use crate::builder_state::BuilderCheckpoint;
+pub use crate::cache::{
+    BlockCache, CacheID, CacheKey, CacheStats, EvictionCallback, LRUBlockCache, MemoryBudget,
+    MemoryReservation, PinID, PinnedBlock,
+};
+#[cfg(feature = "clock_cache")]
+pub use crate::clock_cache::ClockBlockCache;
+pub use crate::cmp::{Cmp, CountingCmp, DefaultCmp, InternalKeyCmp};
+pub use crate::error::{CorruptionKind, Error, Result};
+#[cfg(any(unix, windows))]
+pub use crate::file_registry::FileRegistry;
+pub use crate::filter_block::FilterBlockReader;
+pub use crate::flush_policy::{
+    BlockState, BoxedFlushBlockPolicy, FlushBlockPolicy, SizeFlushPolicy, UserKeyFlushPolicy,
+};
+pub use crate::hash_index::{BoxedPrefixExtractor, FixedPrefix, PrefixExtractor};
+pub use crate::key_codec::{BoxedKeyCodec, KeyCodec};
+pub use crate::memtable::{MemTable, MemTableIterator};
+pub use crate::options::{
+    CachePolicy, CompressionType, DedupPolicy, IndexShortening, IndexType, KeyOrder, Options,
+    ReadOptions, Temperature, MAX_AUTO_RESTART_INTERVAL,
+};
+pub use crate::partitioned_builder::{Partition, PartitionBuilder, PartitionedTableBuilder};
+pub use crate::table_builder::{TableBuilder, TableFileMeta};
+pub use crate::table_cache::{TableCache, TableOpener};
+pub use crate::table_properties::{
+    TableProperties, TablePropertiesCollector, TablePropertiesCollectorFactory,
+};
+pub use crate::table_reader::{
+    BlockBounds, DataBlocks, EntryMeta, IndexIterator, LazyValue, PinnedSlice, PrefixIterator,
+    SeekHint, SnapshotIterator, Table, TableIterator, TablePin,
+};
+pub use crate::two_level::{BlockFunction, TwoLevelIterator};
+pub use crate::types::{current_key_val, Entries, FnRandomAccess, RandomAccess, SSIterator};
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub use crate::uring::{UringFile, DEFAULT_RING_ENTRIES};
+pub use crate::value_codec::{BoxedValueCodec, ValueCodec};
+pub use crate::value_reader::ValueReader;
+pub use crate::version::{LevelFile, Version, VersionIterator};
+pub use crate::version_set::{VersionEdit, VersionSet};
 
 #[cfg(test)]
 mod test_util;
@@ -0,0 +1,69 @@
+//! Checksum manifests list the location and checksum of every block of a table (see
+//! `Table::checksum_manifest()`). Stored separately from the table, e.g. next to an archived
+//! file, a manifest allows detecting bit rot later on (`Table::verify_against_manifest()`),
+//! including corruption that happens to leave a block's own checksum valid.
+
+use crate::blockhandle::BlockHandle;
+use crate::coding;
+use crate::error::Result;
+
+/// The location of a block and the CRC32C of its bytes as stored in the file, including the
+/// block trailer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockChecksum {
+    pub handle: BlockHandle,
+    pub crc: u32,
+}
+
+/// Serializes a manifest: every entry is stored as varint offset, varint size and fixed32 CRC.
+pub fn encode_manifest(manifest: &[BlockChecksum]) -> Vec<u8> {
+    let mut dst = vec![];
+    let mut buf = [0; 16];
+    for entry in manifest {
+        let n = entry.handle.encode_to(&mut buf);
+        dst.extend_from_slice(&buf[..n]);
+        coding::put_fixed32(&mut dst, entry.crc);
+    }
+    dst
+}
+
+/// Deserializes a manifest written by `encode_manifest()`.
+pub fn decode_manifest(mut src: &[u8]) -> Result<Vec<BlockChecksum>> {
+    let mut manifest = vec![];
+    while !src.is_empty() {
+        let (handle, n) = BlockHandle::decode(src)?;
+        src = &src[n..];
+        manifest.push(BlockChecksum {
+            handle,
+            crc: coding::get_fixed32(src)?,
+        });
+        src = &src[4..];
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_encode_decode() {
+        let manifest = vec![
+            BlockChecksum {
+                handle: BlockHandle::new(0, 120),
+                crc: 0xdead_beef,
+            },
+            BlockChecksum {
+                handle: BlockHandle::new(125, 1 << 40),
+                crc: 7,
+            },
+        ];
+        let encoded = encode_manifest(&manifest);
+        assert_eq!(decode_manifest(&encoded).unwrap(), manifest);
+        assert!(decode_manifest(&[]).unwrap().is_empty());
+        assert!(decode_manifest(&encoded[..encoded.len() - 1])
+            .err()
+            .unwrap()
+            .is_corruption());
+    }
+}
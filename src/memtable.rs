@@ -0,0 +1,283 @@
+//! An in-memory write buffer. A `MemTable` collects entries in any order, keeps them sorted by
+//! the comparator of its options and writes them to a table in one go (`flush_into()`).
+
+use crate::cmp::Cmp;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::types::SSIterator;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// A key ordered by the comparator it carries.
+#[derive(Clone)]
+struct MemKey {
+    key: Vec<u8>,
+    cmp: Arc<Box<dyn Cmp>>,
+}
+
+impl PartialEq for MemKey {
+    fn eq(&self, other: &MemKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MemKey {}
+
+impl PartialOrd for MemKey {
+    fn partial_cmp(&self, other: &MemKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MemKey {
+    fn cmp(&self, other: &MemKey) -> Ordering {
+        self.cmp.cmp(&self.key, &other.key)
+    }
+}
+
+/// MemTable is a sorted map of keys to values held in memory. Adding a key that is present
+/// already replaces its value.
+pub struct MemTable {
+    opt: Options,
+    map: BTreeMap<MemKey, Vec<u8>>,
+    approx_size: usize,
+}
+
+impl MemTable {
+    /// Returns an empty MemTable ordering keys by `opt.cmp`.
+    pub fn new(opt: Options) -> MemTable {
+        MemTable {
+            opt,
+            map: BTreeMap::new(),
+            approx_size: 0,
+        }
+    }
+
+    fn mem_key(&self, key: &[u8]) -> MemKey {
+        MemKey {
+            key: key.to_vec(),
+            cmp: self.opt.cmp.clone(),
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], val: &[u8]) {
+        match self.map.insert(self.mem_key(key), val.to_vec()) {
+            Some(old) => self.approx_size = self.approx_size - old.len() + val.len(),
+            None => self.approx_size += key.len() + val.len(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.map.get(&self.mem_key(key)).map(|v| v.as_slice())
+    }
+
+    /// Removes `key` and returns its value, if present.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let val = self.map.remove(&self.mem_key(key))?;
+        self.approx_size -= key.len() + val.len();
+        Some(val)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the sum of the sizes of all keys and values, which can be used to decide when to
+    /// flush the MemTable.
+    pub fn approximate_size(&self) -> usize {
+        self.approx_size
+    }
+
+    /// Returns an iterator over the entries in key order.
+    pub fn iter(&self) -> MemTableIterator<'_> {
+        MemTableIterator {
+            table: self,
+            current: None,
+        }
+    }
+
+    /// Adds all entries to `builder` and finishes the table, returning its size (see
+    /// `TableBuilder::finish()`). The builder must use the same comparator as the MemTable; its
+    /// options decide on everything else, like filters and compression.
+    pub fn flush_into<Dst: Write>(&self, mut builder: TableBuilder<Dst>) -> Result<u64> {
+        if builder.options().cmp.id() != self.opt.cmp.id() {
+            return Err(Error::InvalidArgument(format!(
+                "table builder uses comparator {}, but the memtable {}",
+                builder.options().cmp.id(),
+                self.opt.cmp.id()
+            )));
+        }
+        for (k, v) in self.map.iter() {
+            builder.add(&k.key, v)?;
+        }
+        builder.finish()
+    }
+}
+
+/// An iterator over the entries of a `MemTable`.
+pub struct MemTableIterator<'a> {
+    table: &'a MemTable,
+    current: Option<(&'a MemKey, &'a Vec<u8>)>,
+}
+
+impl<'a> MemTableIterator<'a> {
+    fn first_in(&mut self, lower: Bound<MemKey>) -> bool {
+        self.current = self.table.map.range((lower, Bound::Unbounded)).next();
+        self.current.is_some()
+    }
+}
+
+impl SSIterator for MemTableIterator<'_> {
+    fn advance(&mut self) -> bool {
+        match self.current {
+            Some((k, _)) => self.first_in(Bound::Excluded(k.clone())),
+            None => self.first_in(Bound::Unbounded),
+        }
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        match self.current {
+            Some((k, v)) => {
+                key.clear();
+                key.extend_from_slice(&k.key);
+                val.clear();
+                val.extend_from_slice(v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.current.map(|(k, _)| k.key.as_slice())
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.first_in(Bound::Included(self.table.mem_key(key)));
+    }
+
+    fn reset(&mut self) {
+        self.current = None;
+    }
+
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn prev(&mut self) -> bool {
+        let (k, _) = match self.current {
+            Some(current) => current,
+            None => return false,
+        };
+        self.current = self
+            .table
+            .map
+            .range((Bound::Unbounded, Bound::Excluded(k.clone())))
+            .next_back();
+        self.current.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_reader::Table;
+    use crate::test_util::{test_iterator_properties, SSIteratorIter};
+
+    #[test]
+    fn test_memtable_add_get_remove() {
+        let mut mt = MemTable::new(Options::default());
+        assert!(mt.is_empty());
+        mt.add(b"def", b"1");
+        mt.add(b"abc", b"22");
+        mt.add(b"def", b"333");
+        assert_eq!(mt.len(), 2);
+        assert_eq!(mt.approximate_size(), 11);
+        assert_eq!(mt.get(b"def"), Some(&b"333"[..]));
+        assert_eq!(mt.get(b"xyz"), None);
+        assert_eq!(mt.remove(b"abc"), Some(b"22".to_vec()));
+        assert_eq!(mt.remove(b"abc"), None);
+        assert_eq!(mt.approximate_size(), 6);
+    }
+
+    #[test]
+    fn test_memtable_iterator() {
+        let mut mt = MemTable::new(Options::default());
+        for k in &["zzz", "abc", "bcd", "abd"] {
+            mt.add(k.as_bytes(), b"val");
+        }
+        test_iterator_properties(mt.iter());
+
+        let keys: Vec<Vec<u8>> = SSIteratorIter::wrap(&mut mt.iter())
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                b"abc".to_vec(),
+                b"abd".to_vec(),
+                b"bcd".to_vec(),
+                b"zzz".to_vec()
+            ]
+        );
+        let mut iter = mt.iter();
+        iter.seek(b"b");
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+        iter.seek(b"zzzz");
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_memtable_flush_into() {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let mut mt = MemTable::new(opt.clone());
+        for i in (0..100).rev() {
+            mt.add(
+                format!("key{:03}", i).as_bytes(),
+                format!("{}", i).as_bytes(),
+            );
+        }
+
+        let mut dst = vec![];
+        let size = mt
+            .flush_into(TableBuilder::new(opt.clone(), &mut dst))
+            .unwrap();
+        assert_eq!(size, dst.len() as u64);
+        let table = Table::new(opt.clone(), Box::new(dst), size).unwrap();
+        assert_eq!(table.properties().unwrap().num_entries, 100);
+        assert_eq!(table.get(b"key042").unwrap(), Some(b"42".to_vec()));
+
+        struct ReverseCmp;
+        impl Cmp for ReverseCmp {
+            fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+                b.cmp(a)
+            }
+            fn find_shortest_sep(&self, a: &[u8], _: &[u8]) -> Vec<u8> {
+                a.to_vec()
+            }
+            fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+                a.to_vec()
+            }
+            fn id(&self) -> &'static str {
+                "test.ReverseCmp"
+            }
+        }
+        let mut other = opt.clone();
+        other.cmp = Arc::new(Box::new(ReverseCmp));
+        let mut dst = vec![];
+        assert!(matches!(
+            mt.flush_into(TableBuilder::new(other, &mut dst)),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
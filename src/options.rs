@@ -1,8 +1,18 @@
-use crate::block::Block;
-use crate::cache::Cache;
+use crate::block_stats::BlockStatsAggregatorFactory;
+use crate::cache::{BlockCache, Cache, LRUBlockCache, MemoryBudget};
+use crate::checksum::{BoxedChecksummer, Crc32c};
+#[cfg(feature = "clock_cache")]
+use crate::clock_cache::ClockBlockCache;
 use crate::cmp::{Cmp, DefaultCmp};
 use crate::filter;
+use crate::flush_policy::{BoxedFlushBlockPolicy, SizeFlushPolicy};
+use crate::hash_index::BoxedPrefixExtractor;
+use crate::key_codec::BoxedKeyCodec;
+use crate::table_block::RawBlock;
+use crate::table_builder::LEVELDB_MAGIC_NUMBER;
+use crate::table_properties::TablePropertiesCollectorFactory;
 use crate::types::{share, Shared};
+use crate::value_codec::BoxedValueCodec;
 
 use std::default::Default;
 use std::sync::Arc;
@@ -13,18 +23,109 @@ const MB: usize = KB * KB;
 const BLOCK_MAX_SIZE: usize = 4 * KB;
 const BLOCK_CACHE_CAPACITY: usize = 8 * MB;
 const WRITE_BUFFER_SIZE: usize = 4 * MB;
+const MAX_DECOMPRESSED_BLOCK_SIZE: usize = 256 * MB;
+#[cfg(feature = "filters")]
 const DEFAULT_BITS_PER_KEY: u32 = 10; // NOTE: This may need to be optimized.
+/// The largest restart interval chosen with `Options::auto_restart_interval`.
+pub const MAX_AUTO_RESTART_INTERVAL: usize = 128;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum CompressionType {
     CompressionNone = 0,
     CompressionSnappy = 1,
+    /// Compresses the entries of every data block with snappy in frames of about
+    /// `Options::compression_frame_size` bytes, so that point lookups in blocks that aren't in
+    /// the block cache only decompress one frame. Data blocks that fit into a single frame, and
+    /// all other blocks, are compressed like with `CompressionSnappy`, as are values with
+    /// `Options::value_compression`.
+    CompressionSnappyFramed = 2,
+}
+
+/// How a `TableBuilder` treats a key that is added more than once in a row.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DedupPolicy {
+    /// Reject duplicate keys with an `Error::InvalidArgument`.
+    Error,
+    /// Keep the entry added first and ignore later ones.
+    KeepFirst,
+    /// Keep the entry added last. This buffers one entry in the `TableBuilder`.
+    KeepLast,
+}
+
+/// How often entries are expected to be read (see `TableBuilder::set_temperature()`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Temperature {
+    /// Rarely read. This is the temperature of entries without a hint.
+    #[default]
+    Cold = 0,
+    /// Frequently read.
+    Hot = 1,
+}
+
+/// How strictly a `TableBuilder` checks that keys are added in order.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyOrder {
+    /// Every key must be greater than the previous one; duplicates are handled according to
+    /// `Options::dedup_policy`.
+    Strict,
+    /// Every key must be greater than or equal to the previous one. Equal keys are all kept, in
+    /// the order they were added, and `Options::dedup_policy` doesn't apply. This suits
+    /// comparators that consider distinct keys equal, e.g. when keys carry a version that the
+    /// comparator ignores. Point lookups return the first of the equal entries.
+    AllowEqual,
+    /// Keys are not compared at all, saving a comparison per entry for input known to be sorted.
+    /// Keys added out of order produce a table that returns wrong results.
+    Unchecked,
+}
+
+/// How much the `TableBuilder` shortens the keys of index entries. Every data block's index key
+/// lies between the block's last key and the next block's first key; shorter keys make for a
+/// smaller index block.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IndexShortening {
+    /// Use the last key of every block.
+    NoShortening,
+    /// Use the shortest separator between adjacent blocks (`Cmp::find_shortest_sep()`), and the
+    /// last key of the final block.
+    ShortenSeparators,
+    /// Like `ShortenSeparators`, and use the shortest successor of the table's last key
+    /// (`Cmp::find_short_succ()`) for the final block.
+    ShortenSeparatorsAndSuccessor,
+}
+
+/// How `Table::get()` finds the data block that may contain a key.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IndexType {
+    /// Binary search in the index block.
+    BinarySearch,
+    /// Look up the key's prefix (see `Options::prefix_extractor`) in a hash index stored in a
+    /// meta block; keys without prefix fall back to binary search. Tables are only built with a
+    /// hash index if a prefix extractor is configured.
+    HashSearch,
+}
+
+/// The eviction policy of a block cache created by `Options::with_cache_policy()`. The CLOCK
+/// based policies require the `clock_cache` feature.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CachePolicy {
+    /// Evict the least recently used block (`LRUBlockCache`).
+    LRU,
+    /// Evict using the CLOCK algorithm (`ClockBlockCache`), which spares blocks that have been
+    /// read again since the clock hand last passed them.
+    #[cfg(feature = "clock_cache")]
+    Clock,
+    /// CLOCK eviction with TinyLFU admission: a block read into a full cache only replaces blocks
+    /// that have been accessed less often recently. This keeps large scans from flushing the
+    /// frequently read blocks.
+    #[cfg(feature = "clock_cache")]
+    TinyLFU,
 }
 
 pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
     match i {
         0 => Some(CompressionType::CompressionNone),
         1 => Some(CompressionType::CompressionSnappy),
+        2 => Some(CompressionType::CompressionSnappyFramed),
         _ => None,
     }
 }
@@ -35,11 +136,109 @@ pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
 pub struct Options {
     pub cmp: Arc<Box<dyn Cmp>>,
     pub write_buffer_size: usize,
-    pub block_cache: Shared<Cache<Block>>,
+    /// The cache holding uncompressed blocks read from tables. Defaults to an `LRUBlockCache`;
+    /// any `BlockCache` implementation can be supplied using `with_block_cache()`.
+    pub block_cache: Arc<dyn BlockCache>,
+    /// An optional second cache tier holding blocks as stored in the file (i.e., compressed if
+    /// compression is enabled). It is consulted on `block_cache` misses before reading from the
+    /// file; as compressed blocks are smaller, it can hold more blocks in the same memory.
+    pub compressed_block_cache: Option<Shared<Cache<RawBlock>>>,
+    /// If set, tables opened with these options account for the memory they pin in this budget,
+    /// and shrink `block_cache` to fit into it whenever they insert a block (see `MemoryBudget`).
+    /// Tables sharing a block cache should share the budget, too. A `TableCache` closes tables to
+    /// stay within it.
+    pub memory_budget: Option<Arc<MemoryBudget>>,
     pub block_size: usize,
+    /// Decides where data blocks end (see `FlushBlockPolicy`). The default ends blocks once they
+    /// exceed `block_size`.
+    pub flush_block_policy: BoxedFlushBlockPolicy,
     pub block_restart_interval: usize,
+    /// If set, the builder picks the restart interval of every data block so that a seek decodes
+    /// about this many bytes of entries after the restart point it finds: each block's interval
+    /// is derived from the average size of the entries of the previous block, as prefix
+    /// compression made them, up to `MAX_AUTO_RESTART_INTERVAL`. `block_restart_interval` is used
+    /// for the first data block and for index blocks. The chosen intervals are recorded in
+    /// `TableProperties::restart_intervals`.
+    pub auto_restart_interval: Option<usize>,
     pub compression_type: CompressionType,
+    /// The approximate size of the frames data blocks are compressed in with
+    /// `CompressionType::CompressionSnappyFramed`.
+    pub compression_frame_size: usize,
+    /// Defaults to a `BloomPolicy`, or to `NoFilterPolicy` without the `filters` feature.
     pub filter_policy: filter::BoxedFilterPolicy,
+    /// If set, tables get a bloom filter over all their keys, with about this false positive rate
+    /// (e.g. 0.01), in addition to the filter block of `filter_policy`. Readers check it before
+    /// searching the index (see `Table::key_may_exist()`), which makes lookups of keys missing
+    /// from most tables, as in the lower levels of an LSM tree, cheap.
+    pub table_filter_fp_rate: Option<f64>,
+    /// If set, every entry written by a `TableBuilder` carries an expiration timestamp (see
+    /// `TableBuilder::add_with_expiration()`), and readers skip expired entries if a read time is
+    /// given in `ReadOptions`. Costs one byte per entry that doesn't expire.
+    pub entry_expiration: bool,
+    /// Factories of collectors adding custom properties to every table written.
+    pub properties_collectors: Vec<TablePropertiesCollectorFactory>,
+    /// If set, the statistics computed by the aggregators created by this factory are stored for
+    /// every data block, allowing iterators to skip blocks (see `TableIterator::prune_blocks()`).
+    pub block_stats: Option<BlockStatsAggregatorFactory>,
+    /// If set, values larger than this many bytes are stored outside of the data blocks, which
+    /// only hold a pointer to them. Readers resolve these pointers transparently (or lazily, see
+    /// `Table::get_lazy()`). Costs one byte per value that is stored inline.
+    pub blob_threshold: Option<usize>,
+    /// Compression applied to every value individually (in addition to block compression, which
+    /// is usually disabled when using this). Useful for large values in tables with small blocks.
+    /// Costs one byte per value.
+    pub value_compression: CompressionType,
+    /// If set, every value is encoded by this codec before it's stored, and decoded (or projected,
+    /// see `ReadOptions::projection`) when it's read. Tables written with a codec must be read
+    /// with a codec of the same name.
+    pub value_codec: Option<BoxedValueCodec>,
+    /// If set, every key is encoded by this codec before it's stored, and decoded when it's read,
+    /// so that lookups and iterators work with logical keys while the table holds e.g. escaped
+    /// or composite keys. The encoding must preserve the order of keys (see `KeyCodec`). Tables
+    /// written with a codec must be read with a codec of the same name.
+    pub key_codec: Option<BoxedKeyCodec>,
+    /// If set, every value is stored with a checksum (a masked CRC32C of the value as stored in
+    /// its data block), which readers verify unless `ReadOptions::verify_value_checksums` is
+    /// unset. This detects corruption of single values in memory, e.g. in cached blocks, which
+    /// block checksums only catch when a block is read from the file. Costs four bytes per value.
+    pub value_checksums: bool,
+    /// How index keys are shortened. The statistics in `TableProperties` (`index_key_size` and
+    /// `raw_index_key_size`) tell how many bytes shortening saves.
+    pub index_shortening: IndexShortening,
+    /// The index type of tables built with these options. Readers use a table's hash index if it
+    /// was built with a prefix extractor of the same name as `prefix_extractor`.
+    pub index_type: IndexType,
+    /// Maps keys to prefixes for the hash index (see `IndexType::HashSearch`).
+    pub prefix_extractor: Option<BoxedPrefixExtractor>,
+    /// What `TableBuilder::add()` does with duplicate consecutive keys.
+    pub dedup_policy: DedupPolicy,
+    /// How `TableBuilder::add()` checks the order of keys.
+    pub key_order: KeyOrder,
+    /// If set, `TableBuilder::add()` rejects keys outside of this range (from the first key up
+    /// to, but excluding, the second), e.g. the partition of the key space a writer is assigned.
+    /// The keys actually written are returned by `TableBuilder::finish_with_meta()`.
+    pub expected_key_range: Option<(Vec<u8>, Vec<u8>)>,
+    /// If set, `TableBuilder::add()` rejects keys longer than this.
+    pub max_key_size: Option<usize>,
+    /// If set, `TableBuilder::add()` rejects values longer than this.
+    pub max_value_size: Option<usize>,
+    /// If set, readers refuse to decompress blocks (including blobs) and values whose compressed
+    /// form claims a larger uncompressed size, reporting them as corrupt instead of allocating
+    /// memory for them. This protects against malformed or hostile files. Defaults to 256 MiB;
+    /// tables with larger (compressed) blobs or values need a higher limit, or None.
+    pub max_decompressed_block_size: Option<usize>,
+    /// The magic number written at the end of every table, and expected by `Table::new()`.
+    /// Applications embedding tables in their own storage can use their own number to tell their
+    /// files apart from others. Defaults to LevelDB's magic number.
+    pub table_magic_number: u64,
+    /// Computes the checksums of the blocks of tables built with these options (see the
+    /// `checksum` module), and verifies those of tables written with a custom checksummer.
+    pub checksummer: BoxedChecksummer,
+    /// If set, tables are built with a checksum of the whole file up to a meta block storing it
+    /// (see `checksum::FileChecksum`), which `Table::verify_file_checksum()` checks, and
+    /// `TableBuilder::finish_with_meta()` returns the checksum of the finished file. Costs one pass
+    /// of CRC-64 over every byte written. Off by default.
+    pub file_checksum: bool,
 }
 
 impl Options {
@@ -47,7 +246,50 @@ impl Options {
     /// The capacity is given as number of items in the cache
     /// and the minimal allowed capacity is 1.
     pub fn with_cache_capacity(mut self, capacity: usize) -> Options {
-        self.block_cache = share(Cache::new(capacity));
+        self.block_cache = Arc::new(LRUBlockCache::new(capacity));
+        self
+    }
+
+    /// Configure to use a block cache with the given eviction policy, holding up to `capacity`
+    /// blocks. The minimal allowed capacity is 1.
+    pub fn with_cache_policy(mut self, policy: CachePolicy, capacity: usize) -> Options {
+        self.block_cache = match policy {
+            CachePolicy::LRU => Arc::new(LRUBlockCache::new(capacity)),
+            #[cfg(feature = "clock_cache")]
+            CachePolicy::Clock => Arc::new(ClockBlockCache::new(capacity)),
+            #[cfg(feature = "clock_cache")]
+            CachePolicy::TinyLFU => Arc::new(ClockBlockCache::new(capacity).with_admission()),
+        };
+        self
+    }
+
+    /// Register a factory of `TablePropertiesCollector`s; every `TableBuilder` created with these
+    /// options uses a new collector created by it.
+    pub fn with_properties_collector(
+        mut self,
+        factory: TablePropertiesCollectorFactory,
+    ) -> Options {
+        self.properties_collectors.push(factory);
+        self
+    }
+
+    /// Configure to use a custom block cache implementation.
+    pub fn with_block_cache(mut self, cache: Arc<dyn BlockCache>) -> Options {
+        self.block_cache = cache;
+        self
+    }
+
+    /// Configure a compressed block cache (see `compressed_block_cache`) holding up to `capacity`
+    /// blocks. The minimal allowed capacity is 1.
+    pub fn with_compressed_cache_capacity(mut self, capacity: usize) -> Options {
+        self.compressed_block_cache = Some(share(Cache::new(capacity)));
+        self
+    }
+
+    /// Configure tables to only accept keys from `start` up to, but excluding, `end` (see
+    /// `expected_key_range`).
+    pub fn expected_key_range(mut self, start: &[u8], end: &[u8]) -> Options {
+        self.expected_key_range = Some((start.to_vec(), end.to_vec()));
         self
     }
 }
@@ -58,12 +300,101 @@ impl Default for Options {
             cmp: Arc::new(Box::new(DefaultCmp)),
             write_buffer_size: WRITE_BUFFER_SIZE,
             // 2000 elements by default
-            block_cache: share(Cache::new(BLOCK_CACHE_CAPACITY / BLOCK_MAX_SIZE)),
+            block_cache: Arc::new(LRUBlockCache::new(BLOCK_CACHE_CAPACITY / BLOCK_MAX_SIZE)),
+            compressed_block_cache: None,
+            memory_budget: None,
             block_size: BLOCK_MAX_SIZE,
+            flush_block_policy: Arc::new(SizeFlushPolicy),
             block_restart_interval: 16,
+            auto_restart_interval: None,
             compression_type: CompressionType::CompressionNone,
+            compression_frame_size: 16 * KB,
+            #[cfg(feature = "filters")]
             filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            #[cfg(not(feature = "filters"))]
+            filter_policy: Arc::new(Box::new(filter::NoFilterPolicy::new())),
+            table_filter_fp_rate: None,
+            entry_expiration: false,
+            properties_collectors: vec![],
+            block_stats: None,
+            blob_threshold: None,
+            value_compression: CompressionType::CompressionNone,
+            value_codec: None,
+            key_codec: None,
+            value_checksums: false,
+            index_shortening: IndexShortening::ShortenSeparatorsAndSuccessor,
+            index_type: IndexType::BinarySearch,
+            prefix_extractor: None,
+            dedup_policy: DedupPolicy::Error,
+            key_order: KeyOrder::Strict,
+            expected_key_range: None,
+            max_key_size: None,
+            max_value_size: None,
+            max_decompressed_block_size: Some(MAX_DECOMPRESSED_BLOCK_SIZE),
+            table_magic_number: LEVELDB_MAGIC_NUMBER,
+            checksummer: Arc::new(Crc32c),
+            file_checksum: false,
         }
     }
 }
 
+/// ReadOptions contains parameters for individual reads (`Table::get_opt()`, `Table::iter_opt()`).
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// If set, entries (and tables) that have expired at this time are skipped. Timestamps are
+    /// opaque to this crate; they only need to be consistent with those given to the
+    /// `TableBuilder`.
+    pub read_time: Option<u64>,
+    /// If set, iterators skip data blocks and values that can't be read (e.g. because they're
+    /// corrupt) instead of stopping at them. The first such error is still reported by
+    /// `SSIterator::status()`.
+    pub skip_corrupt_blocks: bool,
+    /// If set, values of tables written with a `ValueCodec` are projected to these fields (see
+    /// `ValueCodec::project()`) instead of being decoded completely. Ignored for other tables.
+    pub projection: Option<Vec<usize>>,
+    /// Whether blocks read from the file are inserted into the block cache (true by default).
+    /// Large scans should unset it, so that they don't evict the blocks other reads depend on;
+    /// blocks already in the cache are still used.
+    pub fill_cache: bool,
+    /// If set, iterators detect scans: once an iterator has read more than this many data blocks
+    /// since it was last seeked or reset, it stops inserting the blocks it reads into the block
+    /// cache, as if `fill_cache` were unset. Point lookups and short range reads still fill the
+    /// cache, while long scans can't evict their working set.
+    pub max_scan_cached_blocks: Option<usize>,
+    /// If set, `TableIterator::current_pinned_value()` returns values as slices of the iterator's
+    /// data block instead of copying them. Such a slice keeps its whole block in memory, even
+    /// after the block has been evicted from the block cache and the iterator has moved on.
+    pub pin_data: bool,
+    /// When an iterator reads a data block that isn't cached, it also reads up to this many
+    /// following blocks in the same call (see `RandomAccess::read_vectored()`). They are kept
+    /// compressed until the iterator reaches them. 0 (the default) disables read-ahead; tight
+    /// scans over small blocks save most of their reads with it.
+    pub readahead_blocks: usize,
+    /// Whether the checksums of values in tables written with `Options::value_checksums` are
+    /// verified (true by default). Values failing verification are reported as corrupt.
+    pub verify_value_checksums: bool,
+    /// Iterators read and decode the value of every entry they move to, so that values that can't
+    /// be read are reported by `SSIterator::status()` (or skipped with `skip_corrupt_blocks`). If
+    /// set, values stored separately from the data blocks (see `Options::blob_threshold`) are only
+    /// read once `current()` or the `LazyValue::fetch()` of `TableIterator::current_lazy()` asks
+    /// for them; `current()` then returns false for values it can't read and reports the error
+    /// through `SSIterator::status()`, which stops the iterator unless `skip_corrupt_blocks` is
+    /// set.
+    pub lazy_values: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            read_time: None,
+            skip_corrupt_blocks: false,
+            projection: None,
+            fill_cache: true,
+            max_scan_cached_blocks: None,
+            pin_data: false,
+            readahead_blocks: 0,
+            verify_value_checksums: true,
+            lazy_values: false,
+        }
+    }
+}
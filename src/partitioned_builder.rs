@@ -0,0 +1,297 @@
+//! Building one table from several threads. A `PartitionedTableBuilder` hands out a
+//! `PartitionBuilder` for every partition of the input, e.g. the ranges of a parallel sort. The
+//! partitions must be disjoint and each sorted; they're built concurrently into data blocks in
+//! memory, encoding, compressing and checksumming them in parallel.
+//! `PartitionedTableBuilder::add()` writes the blocks of a finished partition to the sink and
+//! indexes them, in key order; `PartitionedTableBuilder::finish()` then builds the filters and
+//! other meta blocks. Writing and indexing is the only sequential part.
+//!
+//! A partition's blocks are held in memory until the partition is written, so the memory used is
+//! bounded by the size of the partitions being built plus that of the finished partitions waiting
+//! for their predecessors. Callers bound it by splitting the input into more, smaller partitions
+//! and adding each as soon as it and the ones before it are finished.
+
+use crate::blockhandle::BlockHandle;
+use crate::error::{Error, Result};
+use crate::options::{KeyOrder, Options};
+use crate::table_builder::{BuiltPartition, PartitionBlock, TableBuilder, TableFileMeta};
+
+use std::cmp::Ordering;
+use std::io::Write;
+
+/// Builds a table from partitions built concurrently by `PartitionBuilder`s.
+///
+/// Tables are identical to ones built by a single `TableBuilder` from the same entries, except
+/// that data blocks don't span partitions and that `TableProperties::num_overwritten` doesn't count
+/// values overwritten across a partition boundary. Options writing blobs
+/// (`Options::blob_threshold`), block statistics or custom properties aren't supported.
+pub struct PartitionedTableBuilder<Dst: Write> {
+    builder: TableBuilder<Dst>,
+    // The last key of the partition written last.
+    last_key: Option<Vec<u8>>,
+    // The last data block of the partition written last, indexed once the first key of the next
+    // partition is known.
+    last_block: Option<(PartitionBlock, BlockHandle)>,
+}
+
+impl<Dst: Write> PartitionedTableBuilder<Dst> {
+    /// Returns a builder writing the table to `dst`.
+    pub fn new(opt: Options, dst: Dst) -> Result<PartitionedTableBuilder<Dst>> {
+        if opt.blob_threshold.is_some()
+            || opt.block_stats.is_some()
+            || !opt.properties_collectors.is_empty()
+        {
+            return Err(Error::NotSupported(
+                "partitioned tables with blobs, block stats or properties collectors".to_string(),
+            ));
+        }
+        Ok(PartitionedTableBuilder {
+            builder: TableBuilder::new(opt, dst),
+            last_key: None,
+            last_block: None,
+        })
+    }
+
+    /// Returns a builder for one partition, which can be moved to another thread.
+    pub fn partition(&self) -> PartitionBuilder {
+        PartitionBuilder {
+            builder: TableBuilder::new_partition(self.builder.options().clone()),
+        }
+    }
+
+    /// Sets an expiration timestamp for the whole table (see `TableBuilder::set_expiration()`).
+    pub fn set_expiration(&mut self, expiration: u64) {
+        self.builder.set_expiration(expiration);
+    }
+
+    /// Writes a finished partition to the sink, after the partitions written before, and releases
+    /// its blocks. Fails with `Error::InvalidArgument`, writing nothing, if its keys overlap with
+    /// or precede those of the partitions written before.
+    pub fn add(&mut self, partition: Partition) -> Result<()> {
+        let part = partition.0;
+        let first_key = match part.first_key() {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        if let Some(last_key) = self.last_key.as_ref() {
+            let opt = self.builder.options();
+            let order = opt.cmp.cmp(last_key, first_key);
+            let in_order = match opt.key_order {
+                KeyOrder::Strict => order == Ordering::Less,
+                KeyOrder::AllowEqual => order != Ordering::Greater,
+                KeyOrder::Unchecked => true,
+            };
+            if !in_order {
+                return Err(Error::InvalidArgument(format!(
+                    "partition starting at {:?} overlaps or precedes the previous one",
+                    String::from_utf8_lossy(first_key)
+                )));
+            }
+        }
+        if let Some(block) = self.last_block.take() {
+            self.builder.index_partition_block(block, Some(first_key));
+        }
+        self.last_key = Some(part.last_key().to_vec());
+        self.last_block = self.builder.add_partition(part)?;
+        Ok(())
+    }
+
+    /// Writes the remaining `partitions`, given in key order (see `add()`), and the rest of the
+    /// table, and flushes the sink. Returns the size of the table.
+    pub fn finish(self, partitions: Vec<Partition>) -> Result<u64> {
+        Ok(self.finish_with_meta(partitions)?.size)
+    }
+
+    /// Like `finish()`, but also returns the range of keys and the file checksum (see
+    /// `TableBuilder::finish_with_meta()`).
+    pub fn finish_with_meta(mut self, partitions: Vec<Partition>) -> Result<TableFileMeta> {
+        for partition in partitions {
+            self.add(partition)?;
+        }
+        if let Some(block) = self.last_block.take() {
+            self.builder.index_partition_block(block, None);
+        }
+        self.builder.finish_with_meta()
+    }
+}
+
+/// Builds the data blocks of one partition of a table (see `PartitionedTableBuilder`). Entries are
+/// added like to a `TableBuilder`, in order.
+pub struct PartitionBuilder {
+    builder: TableBuilder<Vec<u8>>,
+}
+
+impl PartitionBuilder {
+    /// Adds an entry (see `TableBuilder::add()`).
+    pub fn add(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.builder.add(key, val)
+    }
+
+    /// Adds an entry expiring at `expiration` (see `TableBuilder::add_with_expiration()`).
+    pub fn add_with_expiration(&mut self, key: &[u8], val: &[u8], expiration: u64) -> Result<()> {
+        self.builder.add_with_expiration(key, val, expiration)
+    }
+
+    /// Returns the number of entries added so far.
+    pub fn entries(&self) -> usize {
+        self.builder.entries()
+    }
+
+    /// Writes the remaining data blocks and returns the partition, to be passed to
+    /// `PartitionedTableBuilder::finish()`.
+    pub fn finish(self) -> Result<Partition> {
+        Ok(Partition(self.builder.finish_partition()?))
+    }
+}
+
+/// The data blocks of a finished partition, held in memory until passed to
+/// `PartitionedTableBuilder::add()` or `PartitionedTableBuilder::finish()`.
+pub struct Partition(BuiltPartition);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::IndexType;
+    use crate::table_reader::Table;
+    use crate::types::SSIterator;
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    fn build(opt: &Options, ranges: &[std::ops::Range<usize>]) -> Result<Vec<u8>> {
+        let mut d = vec![];
+        let b = PartitionedTableBuilder::new(opt.clone(), &mut d)?;
+        let handles: Vec<_> = ranges
+            .iter()
+            .cloned()
+            .map(|range| {
+                let mut p = b.partition();
+                thread::spawn(move || {
+                    for i in range {
+                        p.add(&key(i), format!("value{}", i).as_bytes())?;
+                    }
+                    p.finish()
+                })
+            })
+            .collect();
+        let parts = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Result<Vec<_>>>()?;
+        b.finish(parts)?;
+        Ok(d)
+    }
+
+    #[test]
+    fn test_partitioned_table_builder() {
+        let mut opt = Options::default();
+        opt.block_size = 256;
+        opt.table_filter_fp_rate = Some(0.01);
+        opt.index_type = IndexType::HashSearch;
+        opt.prefix_extractor = Some(Arc::new(crate::hash_index::FixedPrefix(6)));
+        let d = build(&opt, &[0..1000, 1000..1000, 1000..2500, 2500..2501]).unwrap();
+
+        let table = Table::new(opt.clone(), Box::new(d.clone()), d.len() as u64).unwrap();
+        let props = table.properties().unwrap();
+        assert_eq!(props.num_entries, 2501);
+        assert_eq!(props.num_data_blocks as usize, table.index_handles().len());
+        for i in [0, 999, 1000, 1001, 2499, 2500] {
+            assert_eq!(
+                table.get(&key(i)).unwrap(),
+                Some(format!("value{}", i).into_bytes())
+            );
+        }
+        assert_eq!(table.get(&key(2501)).unwrap(), None);
+        let mut iter = table.iter();
+        let keys: Vec<Vec<u8>> = iter.entries().map(|(k, _)| k).collect();
+        assert_eq!(keys, (0..2501).map(key).collect::<Vec<_>>());
+
+        // Without partition boundaries, the table is the same as one built sequentially.
+        let mut single = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut single);
+        for i in 0..2501 {
+            b.add(&key(i), format!("value{}", i).as_bytes()).unwrap();
+        }
+        b.finish().unwrap();
+        assert_eq!(
+            build(&opt, std::slice::from_ref(&(0..2501))).unwrap(),
+            single
+        );
+    }
+
+    /// A sink whose contents can be inspected while a builder writes to it.
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_partitioned_table_builder_add() {
+        let mut opt = Options::default();
+        opt.block_size = 256;
+        let sink = SharedSink::default();
+        let mut b = PartitionedTableBuilder::new(opt.clone(), sink.clone()).unwrap();
+        let ranges = [0..1000, 1000..1000, 1000..2500, 2500..2501];
+        let mut written = 0;
+        for range in &ranges {
+            let mut p = b.partition();
+            for i in range.clone() {
+                p.add(&key(i), format!("value{}", i).as_bytes()).unwrap();
+            }
+            b.add(p.finish().unwrap()).unwrap();
+            // The partition's blocks are written right away.
+            let len = sink.0.lock().unwrap().len();
+            assert_eq!(len > written, !range.is_empty());
+            written = len;
+        }
+
+        let mut p = b.partition();
+        p.add(&key(100), b"value").unwrap();
+        assert!(matches!(
+            b.add(p.finish().unwrap()),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(sink.0.lock().unwrap().len(), written);
+
+        b.finish(vec![]).unwrap();
+        let d = sink.0.lock().unwrap().clone();
+        assert_eq!(d, build(&opt, &ranges).unwrap());
+    }
+
+    #[test]
+    fn test_partitioned_table_builder_errors() {
+        let opt = Options::default();
+        assert!(matches!(
+            build(&opt, &[0..10, 5..20]),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            build(&opt, &[10..20, 0..10]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let ranged = opt.clone().expected_key_range(&key(0), &key(10));
+        assert!(build(&ranged, &[0..5, 5..10]).is_ok());
+        assert!(matches!(
+            build(&ranged, &[0..5, 5..11]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let mut blobs = opt;
+        blobs.blob_threshold = Some(10);
+        assert!(matches!(
+            PartitionedTableBuilder::new(blobs, vec![]),
+            Err(Error::NotSupported(_))
+        ));
+    }
+}
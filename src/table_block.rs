@@ -1,88 +1,382 @@
 use crate::block::Block;
 use crate::blockhandle::BlockHandle;
-use crate::error::{err, Result, StatusCode};
+use crate::checksum::{self, Checksummer};
+use crate::error::{CorruptionKind, Error, Result};
 use crate::filter;
 use crate::filter_block::FilterBlockReader;
+#[cfg(feature = "snappy")]
+use crate::framed_block;
 use crate::options::{self, CompressionType, Options};
 use crate::table_builder;
-use crate::types::{unmask_crc, RandomAccess};
+use crate::types::RandomAccess;
+
+use std::convert::TryFrom;
+use std::sync::Arc;
 
 use integer_encoding::FixedInt;
+#[cfg(feature = "snappy")]
 use snap::Decoder;
 
-/// Reads the data for the specified block handle from a file.
-fn read_bytes(f: &dyn RandomAccess, location: &BlockHandle) -> Result<Vec<u8>> {
-    let mut buf = vec![0; location.size()];
+/// Returns the size of the block at `location` in memory; fails if it exceeds the address space
+/// (on 32-bit platforms).
+pub fn block_len(location: &BlockHandle) -> Result<usize> {
+    usize::try_from(location.size()).map_err(|_| {
+        Error::NotSupported(format!(
+            "block of size {} at {} exceeds the address space",
+            location.size(),
+            location.offset()
+        ))
+    })
+}
+
+/// Fails if `location` doesn't lie within a file of `file_size` bytes. Checked before allocating
+/// buffers for blocks, so that a corrupt handle can't cause huge allocations.
+fn check_in_file(location: &BlockHandle, file_size: u64) -> Result<()> {
+    match location.offset().checked_add(location.size()) {
+        Some(end) if end <= file_size => Ok(()),
+        _ => Err(Error::corrupt_block(
+            location,
+            CorruptionKind::Malformed(format!(
+                "block handle points past the end of the file (size {})",
+                file_size
+            )),
+        )),
+    }
+}
+
+/// Reads the data for the specified block handle from a file of `file_size` bytes.
+fn read_bytes(f: &dyn RandomAccess, file_size: u64, location: &BlockHandle) -> Result<Vec<u8>> {
+    check_in_file(location, file_size)?;
+    let mut buf = vec![0; block_len(location)?];
     f.read_at(location.offset(), &mut buf).map(|_| buf)
 }
 
 /// Reads a serialized filter block from a file and returns a FilterBlockReader.
 pub fn read_filter_block(
     src: &dyn RandomAccess,
+    file_size: u64,
     location: &BlockHandle,
     policy: filter::BoxedFilterPolicy,
 ) -> Result<FilterBlockReader> {
     if location.size() == 0 {
-        return err(
-            StatusCode::InvalidArgument,
-            "no filter block in empty location",
-        );
+        return Err(Error::InvalidArgument(
+            "no filter block in empty location".to_string(),
+        ));
     }
-    let buf = read_bytes(src, location)?;
-    Ok(FilterBlockReader::new_owned(policy, buf))
+    let buf = read_bytes(src, file_size, location)?;
+    FilterBlockReader::new_owned(policy, buf).map_err(|e| match e {
+        Error::Corruption {
+            kind: CorruptionKind::Malformed(msg),
+            ..
+        } => Error::corrupt_block(location, CorruptionKind::Malformed(msg)),
+        e => e,
+    })
 }
 
-/// Reads a table block from a random-access source.
+/// A table block as stored in the file: its (possibly compressed) contents and its compression
+/// type. A RawBlock's checksum has already been verified.
+#[derive(Clone)]
+pub struct RawBlock {
+    pub compression: u8,
+    pub data: Arc<Vec<u8>>,
+}
+
+/// Reads a table block from a random-access source of `file_size` bytes.
 /// A table block consists of [bytes..., compress (1B), checksum (4B)]; the handle only refers to
 /// the location and length of [bytes...].
 pub fn read_table_block(
     opt: Options,
     f: &dyn RandomAccess,
+    file_size: u64,
     location: &BlockHandle,
 ) -> Result<Block> {
+    let raw = read_raw_table_block(f, file_size, opt.checksummer.as_ref(), location)?;
+    decode_table_block(opt, location, raw)
+}
+
+/// Reads a table block from a random-access source and verifies its checksum using `cs`, but
+/// doesn't decompress it.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        level = "debug",
+        target = "sstable",
+        skip(f, cs, location),
+        fields(offset = location.offset(), size = location.size())
+    )
+)]
+pub fn read_raw_table_block(
+    f: &dyn RandomAccess,
+    file_size: u64,
+    cs: &dyn Checksummer,
+    location: &BlockHandle,
+) -> Result<RawBlock> {
+    let buf = read_bytes(f, file_size, &full_block_location(location, file_size)?)?;
+    verify_raw_block(location, cs, buf)
+}
+
+/// Reads the block at `location` including its trailer, without verifying it.
+pub fn read_block_with_trailer(
+    f: &dyn RandomAccess,
+    file_size: u64,
+    location: &BlockHandle,
+) -> Result<Vec<u8>> {
+    read_bytes(f, file_size, &full_block_location(location, file_size)?)
+}
+
+/// Reads the table blocks at `locations` and verifies their checksums using `cs`, but doesn't
+/// decompress them. Adjacent blocks (each starting where the previous one's trailer ends) are read
+/// using a single `RandomAccess::read_vectored()` call, others using a single
+/// `RandomAccess::read_batch()` call.
+pub fn read_raw_table_blocks(
+    f: &dyn RandomAccess,
+    file_size: u64,
+    cs: &dyn Checksummer,
+    locations: &[BlockHandle],
+) -> Result<Vec<RawBlock>> {
+    let mut bufs = vec![];
+    let mut offsets = vec![];
+    for location in locations {
+        let full_location = full_block_location(location, file_size)?;
+        bufs.push(vec![0; block_len(&full_location)?]);
+        offsets.push(full_location.offset());
+    }
+    let adjacent = offsets
+        .windows(2)
+        .zip(&bufs)
+        .all(|(w, buf)| w[0] + buf.len() as u64 == w[1]);
+    if locations.len() > 1 && adjacent {
+        let mut slices: Vec<&mut [u8]> = bufs.iter_mut().map(|b| b.as_mut_slice()).collect();
+        f.read_vectored(offsets[0], &mut slices)?;
+    } else {
+        let mut reqs: Vec<(u64, &mut [u8])> = offsets
+            .into_iter()
+            .zip(bufs.iter_mut().map(|b| b.as_mut_slice()))
+            .collect();
+        f.read_batch(&mut reqs)?;
+    }
+    locations
+        .iter()
+        .zip(bufs)
+        .map(|(location, buf)| verify_raw_block(location, cs, buf))
+        .collect()
+}
+
+/// Returns the location of the block at `location` including its trailer, failing if it doesn't
+/// lie within a file of `file_size` bytes.
+fn full_block_location(location: &BlockHandle, file_size: u64) -> Result<BlockHandle> {
     // The block is denoted by offset and length in BlockHandle. A block in an encoded
     // table is followed by 1B compression type and 4B checksum.
     // The checksum refers to the compressed contents.
+    let full_size = location
+        .size()
+        .checked_add(
+            (table_builder::TABLE_BLOCK_CKSUM_LEN + table_builder::TABLE_BLOCK_COMPRESS_LEN) as u64,
+        )
+        .filter(|&n| location.offset().checked_add(n).is_some())
+        .ok_or_else(|| {
+            Error::corrupt_block(
+                location,
+                CorruptionKind::Malformed("block handle out of range".to_string()),
+            )
+        })?;
+    let full_location = BlockHandle::new(location.offset(), full_size);
+    check_in_file(&full_location, file_size)?;
+    Ok(full_location)
+}
 
-    let block_data_size = location.size();
-    let location = BlockHandle::new(
-        location.offset(),
-        block_data_size
-            + table_builder::TABLE_BLOCK_CKSUM_LEN
-            + table_builder::TABLE_BLOCK_COMPRESS_LEN,
-    );
-    let mut buf = read_bytes(f, &location)?;
+/// Fails if the block at `location`, including its trailer, doesn't lie within a file of
+/// `file_size` bytes.
+pub fn check_block_in_file(location: &BlockHandle, file_size: u64) -> Result<()> {
+    full_block_location(location, file_size).map(|_| ())
+}
 
-    let mut compress = buf.split_off(block_data_size);
+/// Splits the trailer off the block at `location`, read including its trailer into `buf`, and
+/// verifies the checksum.
+fn verify_raw_block(
+    location: &BlockHandle,
+    cs: &dyn Checksummer,
+    mut buf: Vec<u8>,
+) -> Result<RawBlock> {
+    let mut compress = buf.split_off(block_len(location)?);
     let cksum = compress.split_off(table_builder::TABLE_BLOCK_COMPRESS_LEN);
 
-    if !verify_table_block(&buf, compress[0], unmask_crc(u32::decode_fixed(&cksum))) {
-        return err(
-            StatusCode::Corruption,
-            &format!(
-                "checksum verification failed for block at {}",
-                location.offset()
-            ),
+    let expected = cs.unmask(u32::decode_fixed(&cksum));
+    let actual = checksum::block_checksum(cs, &buf, compress[0]);
+    if expected != actual {
+        event!(
+            error,
+            offset = location.offset(),
+            size = location.size(),
+            compression = compress[0],
+            "checksum verification failed"
         );
+        return Err(Error::corrupt_block(
+            location,
+            CorruptionKind::ChecksumMismatch { expected, actual },
+        ));
+    }
+
+    Ok(RawBlock {
+        compression: compress[0],
+        data: Arc::new(buf),
+    })
+}
+
+/// Decompresses a raw block read from `location`.
+pub fn decode_table_block(opt: Options, location: &BlockHandle, raw: RawBlock) -> Result<Block> {
+    let contents = decompress(location, raw, opt.max_decompressed_block_size)?;
+    Block::try_new(opt, contents).map_err(|e| match e {
+        Error::Corruption { kind, .. } => Error::corrupt_block(location, kind),
+        e => e,
+    })
+}
+
+/// Returns a block holding the frame of the framed raw block read from `location` that `key`
+/// falls into (see `CompressionType::CompressionSnappyFramed`). Other blocks are decoded whole,
+/// like with `decode_table_block()`.
+#[cfg(feature = "snappy")]
+pub fn decode_table_block_for(
+    opt: Options,
+    location: &BlockHandle,
+    raw: RawBlock,
+    key: &[u8],
+) -> Result<Block> {
+    if raw.compression != CompressionType::CompressionSnappyFramed as u8 {
+        return decode_table_block(opt, location, raw);
     }
+    let contents = framed_block::decompress_frame_for(
+        opt.cmp.as_ref().as_ref(),
+        &raw.data,
+        key,
+        opt.max_decompressed_block_size,
+    )
+    .map_err(|kind| Error::corrupt_block(location, kind))?;
+    event!(
+        trace,
+        offset = location.offset(),
+        decompressed = contents.len(),
+        "decompressed block frame"
+    );
+    Block::try_new(opt, contents).map_err(|e| match e {
+        Error::Corruption { kind, .. } => Error::corrupt_block(location, kind),
+        e => e,
+    })
+}
 
-    if let Some(ctype) = options::int_to_compressiontype(compress[0] as u32) {
+/// Without the `snappy` feature, framed blocks can't be read, so all blocks are decoded whole.
+#[cfg(not(feature = "snappy"))]
+pub fn decode_table_block_for(
+    opt: Options,
+    location: &BlockHandle,
+    raw: RawBlock,
+    _key: &[u8],
+) -> Result<Block> {
+    decode_table_block(opt, location, raw)
+}
+
+/// Returns the error for compressing or decompressing data with `ctype` in builds without the
+/// `snappy` feature.
+#[cfg(not(feature = "snappy"))]
+pub fn compression_not_supported(ctype: CompressionType) -> Error {
+    Error::NotSupported(format!(
+        "{:?} requires the snappy feature of the sstable crate",
+        ctype
+    ))
+}
+
+/// Reads a blob (a separated value, see the `blob` module) or any other block from a random-access
+/// source, returning its uncompressed contents.
+pub fn read_blob(
+    f: &dyn RandomAccess,
+    file_size: u64,
+    opt: &Options,
+    location: &BlockHandle,
+) -> Result<Vec<u8>> {
+    let raw = read_raw_table_block(f, file_size, opt.checksummer.as_ref(), location)?;
+    decompress(location, raw, opt.max_decompressed_block_size)
+}
+
+/// Returns the uncompressed contents of a raw block read from `location`, failing if they would
+/// be larger than `limit`.
+fn decompress(location: &BlockHandle, raw: RawBlock, limit: Option<usize>) -> Result<Vec<u8>> {
+    if let Some(ctype) = options::int_to_compressiontype(raw.compression as u32) {
         match ctype {
-            CompressionType::CompressionNone => Ok(Block::new(opt, buf)),
+            CompressionType::CompressionNone => {
+                Ok(Arc::try_unwrap(raw.data).unwrap_or_else(|shared| (*shared).clone()))
+            }
+            #[cfg(feature = "snappy")]
             CompressionType::CompressionSnappy => {
-                let decoded = Decoder::new().decompress_vec(&buf)?;
-                Ok(Block::new(opt, decoded))
+                check_decompressed_len(&raw.data, limit)
+                    .map_err(|kind| Error::corrupt_block(location, kind))?;
+                let decoded = match Decoder::new().decompress_vec(&raw.data) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        event!(
+                            error,
+                            offset = location.offset(),
+                            error = %e,
+                            "snappy decompression failed"
+                        );
+                        return Err(Error::corrupt_block(
+                            location,
+                            CorruptionKind::Decompression(e),
+                        ));
+                    }
+                };
+                event!(
+                    trace,
+                    compressed = raw.data.len(),
+                    decompressed = decoded.len(),
+                    "decompressed snappy block"
+                );
+                Ok(decoded)
+            }
+            #[cfg(feature = "snappy")]
+            CompressionType::CompressionSnappyFramed => framed_block::decompress(&raw.data, limit)
+                .map_err(|kind| {
+                    event!(
+                        error,
+                        offset = location.offset(),
+                        "framed block decompression failed"
+                    );
+                    Error::corrupt_block(location, kind)
+                }),
+            #[cfg(not(feature = "snappy"))]
+            ctype => {
+                let _ = limit;
+                Err(compression_not_supported(ctype))
             }
         }
     } else {
-        err(StatusCode::InvalidData, "invalid compression type")
+        event!(
+            error,
+            offset = location.offset(),
+            compression = raw.compression,
+            "invalid compression type"
+        );
+        Err(Error::corrupt_block(
+            location,
+            CorruptionKind::UnknownCompressionType(raw.compression),
+        ))
     }
 }
 
-/// Verify checksum of block
-fn verify_table_block(data: &[u8], compression: u8, want: u32) -> bool {
-    let mut digest = super::table_builder::CASTAGNOLI.digest();
-    digest.update(data);
-    digest.update(&[compression; 1]);
-    digest.finalize() == want
+/// Checks the uncompressed size claimed by the header of snappy-compressed `data` against
+/// `limit` (see `Options::max_decompressed_block_size`).
+#[cfg(feature = "snappy")]
+pub fn check_decompressed_len(
+    data: &[u8],
+    limit: Option<usize>,
+) -> std::result::Result<(), CorruptionKind> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let size = snap::decompress_len(data).map_err(CorruptionKind::Decompression)?;
+    if size > limit {
+        event!(error, size, limit, "decompressed size exceeds limit");
+        return Err(CorruptionKind::DecompressedSizeExceeded { size, limit });
+    }
+    Ok(())
 }
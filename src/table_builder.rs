@@ -1,57 +1,139 @@
-use crate::block::BlockContents;
+use crate::blob;
+use crate::block::{Block, BlockContents};
 use crate::block_builder::BlockBuilder;
+use crate::block_stats::{BlockStatsBuilder, BLOCK_STATS_BLOCK_KEY};
 use crate::blockhandle::BlockHandle;
-use crate::error::Result;
+use crate::builder_state::{self, BuilderCheckpoint, StateReader, StateWriter};
+use crate::checksum::{self, FileChecksum, FILE_CHECKSUM_BLOCK_KEY};
+use crate::coding;
+use crate::error::{Error, Result};
 use crate::filter::NoFilterPolicy;
 use crate::filter_block::FilterBlockBuilder;
-use crate::options::{CompressionType, Options};
-use crate::types::mask_crc;
+use crate::flush_policy::BlockState;
+#[cfg(feature = "snappy")]
+use crate::framed_block;
+use crate::hash_index::{HashIndexBuilder, HASH_INDEX_BLOCK_KEY};
+use crate::key_codec;
+use crate::key_types::{self, ValueType};
+use crate::options::{
+    CompressionType, DedupPolicy, IndexShortening, IndexType, KeyOrder, Options, Temperature,
+    MAX_AUTO_RESTART_INTERVAL,
+};
+use crate::table_block::{self, RawBlock};
+use crate::table_filter::{TableFilterBuilder, TABLE_FILTER_BLOCK_KEY};
+use crate::table_properties::{
+    meta_options, TableProperties, TablePropertiesCollector, PROPERTIES_BLOCK_KEY, RESERVED_PREFIX,
+};
+use crate::ttl;
+use crate::types::SSIterator;
+use crate::value_compression;
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::sync::Arc;
 
-use integer_encoding::FixedIntWriter;
+use integer_encoding::FixedInt;
+#[cfg(feature = "snappy")]
 use snap::Encoder;
 
 pub const FOOTER_LENGTH: usize = 40;
 pub const FULL_FOOTER_LENGTH: usize = FOOTER_LENGTH + 8;
-const MAGIC_FOOTER_ENCODED: [u8; 8] = [0x57, 0xfb, 0x80, 0x8b, 0x24, 0x75, 0x47, 0xdb];
+/// LevelDB's magic number, the default for `Options::table_magic_number`.
+pub const LEVELDB_MAGIC_NUMBER: u64 = 0xdb4775248b80fb57;
+
+/// The footer format written by this version. Version 0 is LevelDB's footer (no version and
+/// checksum type fields; CRC32C checksums).
+pub const FORMAT_VERSION: u32 = 1;
+/// Checksum types recorded in the footer. Tables with custom checksums (see `Options::checksummer`)
+/// store the name of their checksummer in their properties.
+pub const CHECKSUM_CRC32C: u8 = 1;
+pub const CHECKSUM_CUSTOM: u8 = 0xff;
+// Offsets of the version fields within the (otherwise zero) padding of the footer. Two block
+// handles take up to 40 bytes, but at most 32 as long as their offsets and sizes are below 2^56,
+// i.e. in files smaller than 64 PiB, which leaves the last 8 bytes of the footer to the padding.
+const CHECKSUM_TYPE_OFFSET: usize = FOOTER_LENGTH - 5;
+const FORMAT_VERSION_OFFSET: usize = FOOTER_LENGTH - 4;
 
 pub const TABLE_BLOCK_COMPRESS_LEN: usize = 1;
 pub const TABLE_BLOCK_CKSUM_LEN: usize = 4;
 
-pub const CASTAGNOLI: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+/// Version of the state serialized by `TableBuilder::checkpoint()`.
+const CHECKPOINT_VERSION: u64 = 5;
 
 /// Footer is a helper for encoding/decoding a table footer.
 #[derive(Debug, Clone)]
 pub struct Footer {
     pub meta_index: BlockHandle,
     pub index: BlockHandle,
+    pub format_version: u32,
+    pub checksum_type: u8,
+    pub magic_number: u64,
 }
 
 /// A Table footer contains a pointer to the metaindex block, another pointer to the index block,
-/// and a magic number:
-/// [ { table data ... , METAINDEX blockhandle, INDEX blockhandle, PADDING bytes } = 40 bytes,
-/// MAGIC_FOOTER_ENCODED ]
+/// the checksum type and format version, and a magic number:
+/// [ { table data ... , METAINDEX blockhandle, INDEX blockhandle, PADDING bytes, CHECKSUM TYPE
+/// (1 byte), FORMAT VERSION (4 bytes) } = 40 bytes, MAGIC NUMBER (8 bytes) ]
+///
+/// In LevelDB's footer (format version 0), the checksum type and version are part of the padding
+/// and therefore zero; readers ignoring them (like LevelDB) can still read newer tables as long as
+/// their format is compatible.
 impl Footer {
     pub fn new(metaix: BlockHandle, index: BlockHandle) -> Footer {
         Footer {
             meta_index: metaix,
             index,
+            format_version: FORMAT_VERSION,
+            checksum_type: CHECKSUM_CRC32C,
+            magic_number: LEVELDB_MAGIC_NUMBER,
         }
     }
 
-    pub fn decode(from: &[u8]) -> Footer {
-        assert!(from.len() >= FULL_FOOTER_LENGTH);
-        assert_eq!(&from[FOOTER_LENGTH..], &MAGIC_FOOTER_ENCODED);
-        let (meta, metalen) = BlockHandle::decode(&from[0..]);
-        let (ix, _) = BlockHandle::decode(&from[metalen..]);
+    /// Decodes a footer, failing if it doesn't carry `magic_number` or with `Error::NotSupported`
+    /// if it was written by a future format version.
+    pub fn decode(from: &[u8], magic_number: u64) -> Result<Footer> {
+        if from.len() < FULL_FOOTER_LENGTH {
+            return Err(Error::malformed(None, "footer too short"));
+        }
+        let found = coding::get_fixed64(&from[FOOTER_LENGTH..])?;
+        if found != magic_number {
+            return Err(Error::malformed(
+                None,
+                &format!(
+                    "bad table magic number {:#018x} (expected {:#018x})",
+                    found, magic_number
+                ),
+            ));
+        }
+        let (meta, metalen) = BlockHandle::decode(&from[0..])?;
+        let (ix, _) = BlockHandle::decode(&from[metalen..])?;
 
-        Footer {
+        let format_version = coding::get_fixed32(&from[FORMAT_VERSION_OFFSET..])?;
+        let checksum_type = match (format_version, from[CHECKSUM_TYPE_OFFSET]) {
+            (0, _) => CHECKSUM_CRC32C,
+            (_, t) => t,
+        };
+        if format_version > FORMAT_VERSION {
+            return Err(Error::NotSupported(format!(
+                "table format version {} (supported up to {})",
+                format_version, FORMAT_VERSION
+            )));
+        }
+        if checksum_type != CHECKSUM_CRC32C && checksum_type != CHECKSUM_CUSTOM {
+            return Err(Error::NotSupported(format!(
+                "checksum type {}",
+                checksum_type
+            )));
+        }
+
+        Ok(Footer {
             meta_index: meta,
             index: ix,
-        }
+            format_version,
+            checksum_type,
+            magic_number,
+        })
     }
 
     pub fn encode(&self, to: &mut [u8]) {
@@ -63,15 +145,37 @@ impl Footer {
         (s1 + s2..FOOTER_LENGTH).for_each(|i| {
             to[i] = 0;
         });
-        (FOOTER_LENGTH..FULL_FOOTER_LENGTH).for_each(|i| {
-            to[i] = MAGIC_FOOTER_ENCODED[i - FOOTER_LENGTH];
-        });
+        if self.format_version > 0 {
+            assert!(
+                s1 + s2 <= CHECKSUM_TYPE_OFFSET,
+                "block handles overlap the footer's version fields"
+            );
+            to[CHECKSUM_TYPE_OFFSET] = self.checksum_type;
+            self.format_version
+                .encode_fixed(&mut to[FORMAT_VERSION_OFFSET..FORMAT_VERSION_OFFSET + 4]);
+        }
+        self.magic_number
+            .encode_fixed(&mut to[FOOTER_LENGTH..FULL_FOOTER_LENGTH]);
     }
 }
 
+/// An entry buffered by the `TableBuilder`: key, value, expiration and temperature.
+type PendingEntry = (Vec<u8>, Vec<u8>, Option<u64>, Temperature);
+
 /// A TableBuilder is used to create a table from a set of sorted string pairs and write it to a
 /// file or a buffer.
-
+///
+/// Blocks are compressed and written to the destination on the calling thread as soon as they are
+/// full, so a slow destination slows down `add()` rather than making the builder buffer data.
+/// Besides the index and filters, which grow with the table, a builder holds at most one data
+/// block in memory. (A `PartitionedTableBuilder` is different: its partitions are held in memory
+/// until they're written.)
+///
+/// The output only depends on the entries added and the options: building the same entries
+/// with equal options yields the same bytes, whether the table is built in one go or resumed from
+/// a checkpoint, so that tables can be content-addressed. User-supplied property collectors,
+/// block statistics aggregators and value codecs must be deterministic, too.
+//
 // A table consists of DATA BLOCKs, META BLOCKs, a METAINDEX BLOCK, an INDEX BLOCK and a FOOTER.
 //
 // DATA BLOCKs, META BLOCKs, INDEX BLOCK and METAINDEX BLOCK are built using the code in
@@ -84,13 +188,103 @@ pub struct TableBuilder<Dst: Write> {
     opt: Options,
     dst: Dst,
 
-    offset: usize,
+    offset: u64,
     num_entries: usize,
+    // The first key written to the table, in its stored form.
+    first_key: Vec<u8>,
     prev_block_last_key: Vec<u8>,
+    props: TableProperties,
+    // Whether every entry added so far has an expiration timestamp.
+    all_entries_expire: bool,
+    // The sizes of the data blocks written so far, before and after compression.
+    raw_data_size: u64,
+    compressed_data_size: u64,
+    value_buf: Vec<u8>,
+    collectors: Vec<Box<dyn TablePropertiesCollector>>,
+    block_stats: Option<BlockStatsBuilder>,
 
     data_block: Option<BlockBuilder>,
+    // The restart interval of `data_block` (see `Options::auto_restart_interval`).
+    restart_interval: usize,
+    // The temperature of entries added from now on, and of the entries in `data_block`.
+    temperature: Temperature,
+    block_temperature: Temperature,
     index_block: Option<BlockBuilder>,
     filter_block: Option<FilterBlockBuilder>,
+    hash_index: Option<HashIndexBuilder>,
+    table_filter: Option<TableFilterBuilder>,
+    // Keys of the current data block (concatenated, and their offsets into `block_keys`). They are
+    // added to the filter block once the data block's offset is known, as blobs written in the
+    // meantime may shift the block.
+    block_keys: Vec<u8>,
+    block_key_offsets: Vec<usize>,
+    // The entry added last, if not written yet (with `DedupPolicy::KeepLast`).
+    pending: Option<PendingEntry>,
+    // The checksum of the bytes written so far, with `Options::file_checksum`.
+    file_checksum: Option<FileChecksum>,
+    // The data blocks written so far, if building a partition (see `PartitionedTableBuilder`).
+    partition_blocks: Option<Vec<PartitionBlock>>,
+}
+
+/// A data block of a partition (see `PartitionedTableBuilder`), located relative to the start of
+/// the partition, with its index key and the keys it holds.
+pub(crate) struct PartitionBlock {
+    handle: BlockHandle,
+    separator: Vec<u8>,
+    keys: Vec<u8>,
+    key_offsets: Vec<usize>,
+}
+
+impl PartitionBlock {
+    fn key(&self, i: usize) -> &[u8] {
+        let end = self
+            .key_offsets
+            .get(i + 1)
+            .copied()
+            .unwrap_or(self.keys.len());
+        &self.keys[self.key_offsets[i]..end]
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.key_offsets.len()).map(move |i| self.key(i))
+    }
+}
+
+/// The data blocks of a partition and their statistics, as returned by
+/// `TableBuilder::finish_partition()`.
+pub(crate) struct BuiltPartition {
+    data: Vec<u8>,
+    blocks: Vec<PartitionBlock>,
+    last_key: Vec<u8>,
+    num_entries: usize,
+    all_entries_expire: bool,
+    raw_data_size: u64,
+    compressed_data_size: u64,
+    props: TableProperties,
+}
+
+impl BuiltPartition {
+    pub(crate) fn first_key(&self) -> Option<&[u8]> {
+        self.blocks.first().map(|b| b.key(0))
+    }
+
+    pub(crate) fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+}
+
+/// Information about a finished table, returned by `TableBuilder::finish_with_meta()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableFileMeta {
+    /// The size of the table, i.e. the number of bytes written to the sink.
+    pub size: u64,
+    /// The number of entries in the table; 0 for an empty table.
+    pub num_entries: u64,
+    /// The `FileChecksum` of the whole table, if built with `Options::file_checksum`.
+    pub file_checksum: Option<u64>,
+    /// The smallest and the largest key in the table, or None for an empty table. They can be
+    /// checked against `Options::expected_key_range`, or recorded in a manifest.
+    pub key_range: Option<(Vec<u8>, Vec<u8>)>,
 }
 
 impl<Dst: Write> TableBuilder<Dst> {
@@ -100,6 +294,61 @@ impl<Dst: Write> TableBuilder<Dst> {
     }
 }
 
+/// Returns whether an internal key written after `prev` is a deletion, and whether it overwrites
+/// the value of `prev`, i.e. has the same user key.
+fn garbage(prev: Option<&[u8]>, key: &[u8]) -> (bool, bool) {
+    let (t, _, ukey) = match key_types::parse_internal_key(key) {
+        Some(parsed) => parsed,
+        None => return (false, false),
+    };
+    let overwritten = prev
+        .and_then(key_types::parse_internal_key)
+        .is_some_and(|(_, _, prev)| prev == ukey);
+    (t == ValueType::TypeDeletion, overwritten)
+}
+
+fn get_temperature(r: &mut StateReader) -> Result<Temperature> {
+    match r.get_u64()? {
+        0 => Ok(Temperature::Cold),
+        1 => Ok(Temperature::Hot),
+        _ => Err(builder_state::malformed()),
+    }
+}
+
+impl TableBuilder<Vec<u8>> {
+    /// Returns a builder for a partition of a table (see `PartitionedTableBuilder`), which writes
+    /// only data blocks and keeps their keys for the filters and indexes of the final table.
+    pub(crate) fn new_partition(opt: Options) -> TableBuilder<Vec<u8>> {
+        let mut b = TableBuilder::new(opt, vec![]);
+        b.filter_block = None;
+        b.hash_index = None;
+        b.table_filter = None;
+        b.file_checksum = None;
+        b.partition_blocks = Some(vec![]);
+        b
+    }
+
+    /// Writes the remaining entries of a partition and returns its data blocks.
+    pub(crate) fn finish_partition(mut self) -> Result<BuiltPartition> {
+        if let Some((k, v, e, t)) = self.pending.take() {
+            self.write_value(&k, &v, e, t)?;
+        }
+        if self.data_block.as_ref().unwrap().entries() > 0 {
+            self.write_data_block(None)?;
+        }
+        Ok(BuiltPartition {
+            last_key: self.prev_block_last_key,
+            blocks: self.partition_blocks.unwrap(),
+            data: self.dst,
+            num_entries: self.num_entries,
+            all_entries_expire: self.all_entries_expire,
+            raw_data_size: self.raw_data_size,
+            compressed_data_size: self.compressed_data_size,
+            props: self.props,
+        })
+    }
+}
+
 /// TableBuilder is used for building a new SSTable. It groups entries into blocks,
 /// calculating checksums and bloom filters.
 impl<Dst: Write> TableBuilder<Dst> {
@@ -109,10 +358,48 @@ impl<Dst: Write> TableBuilder<Dst> {
             opt: opt.clone(),
             dst,
             offset: 0,
+            first_key: vec![],
             prev_block_last_key: vec![],
             num_entries: 0,
+            props: TableProperties {
+                entry_expiration: opt.entry_expiration,
+                blob_values: opt.blob_threshold.is_some(),
+                value_compression: opt.value_compression != CompressionType::CompressionNone,
+                value_codec: opt.value_codec.as_ref().map(|c| c.name().to_string()),
+                key_codec: opt.key_codec.as_ref().map(|c| c.name().to_string()),
+                value_checksums: opt.value_checksums,
+                checksummer: Some(opt.checksummer.name().to_string())
+                    .filter(|_| !checksum::is_default(opt.checksummer.as_ref())),
+                num_deletions: Some(0).filter(|_| opt.cmp.internal_keys()),
+                num_overwritten: Some(0).filter(|_| opt.cmp.internal_keys()),
+                ..TableProperties::default()
+            },
+            all_entries_expire: true,
+            raw_data_size: 0,
+            compressed_data_size: 0,
+            value_buf: vec![],
+            collectors: opt.properties_collectors.iter().map(|f| f()).collect(),
+            block_stats: opt
+                .block_stats
+                .as_ref()
+                .map(|f| BlockStatsBuilder::new(&opt, f)),
             data_block: Some(BlockBuilder::new(opt.clone())),
+            restart_interval: opt.block_restart_interval,
+            temperature: Temperature::Cold,
+            block_temperature: Temperature::Cold,
             filter_block: Some(FilterBlockBuilder::new(opt.filter_policy.clone())),
+            hash_index: match (opt.index_type, &opt.prefix_extractor) {
+                (IndexType::HashSearch, Some(extractor)) => {
+                    Some(HashIndexBuilder::new(extractor.clone()))
+                }
+                _ => None,
+            },
+            table_filter: opt.table_filter_fp_rate.map(TableFilterBuilder::new),
+            block_keys: vec![],
+            block_key_offsets: vec![],
+            pending: None,
+            file_checksum: Some(FileChecksum::new()).filter(|_| opt.file_checksum),
+            partition_blocks: None,
             index_block: Some(BlockBuilder::new(opt)),
         }
     }
@@ -122,66 +409,535 @@ impl<Dst: Write> TableBuilder<Dst> {
         self.num_entries
     }
 
-    #[allow(unused)]
-    fn size_estimate(&self) -> usize {
-        let mut size = 0;
-        size += self
-            .data_block
-            .as_ref()
-            .map(|b| b.size_estimate())
-            .unwrap_or(0);
-        size += self
-            .index_block
-            .as_ref()
-            .map(|b| b.size_estimate())
-            .unwrap_or(0);
-        size += self
-            .filter_block
+    /// Returns the options the table is built with.
+    pub fn options(&self) -> &Options {
+        &self.opt
+    }
+
+    /// Returns a checkpoint from which building the table can be resumed using `resume()`, e.g.
+    /// after a crash during a long bulk load into a remote sink. The sink is flushed first. The
+    /// state of `TablePropertiesCollector`s and `BlockStatsAggregator`s can't be saved, so
+    /// builders using them return `Error::NotSupported`.
+    pub fn checkpoint(&mut self) -> Result<BuilderCheckpoint> {
+        self.check_resumable()?;
+        self.dst.flush()?;
+
+        let mut w = StateWriter::default();
+        w.put_u64(CHECKPOINT_VERSION);
+        w.put_bytes(self.opt.cmp.id().as_bytes());
+        w.put_bytes(
+            self.filter_block
+                .as_ref()
+                .unwrap()
+                .meta_block_name()
+                .as_bytes(),
+        );
+        w.put_u64(self.offset);
+        w.put_u64(self.num_entries as u64);
+        w.put_bytes(&self.first_key);
+        w.put_bytes(&self.prev_block_last_key);
+        w.put_bytes(&self.props.encode(&self.opt));
+        w.put_u64(self.all_entries_expire as u64);
+        w.put_u64(self.raw_data_size);
+        w.put_u64(self.compressed_data_size);
+        w.put_u64(self.restart_interval as u64);
+        w.put_u64(self.temperature as u64);
+        w.put_u64(self.block_temperature as u64);
+        self.data_block.as_ref().unwrap().save(&mut w);
+        self.index_block.as_ref().unwrap().save(&mut w);
+        self.filter_block.as_ref().unwrap().save(&mut w);
+        w.put_u64(self.hash_index.is_some() as u64);
+        if let Some(ref hash_index) = self.hash_index {
+            hash_index.save(&mut w);
+        }
+        w.put_u64(self.table_filter.is_some() as u64);
+        if let Some(ref table_filter) = self.table_filter {
+            table_filter.save(&mut w);
+        }
+        w.put_bytes(&self.block_keys);
+        w.put_usizes(&self.block_key_offsets);
+        w.put_u64(self.pending.is_some() as u64);
+        if let Some((ref k, ref v, e, t)) = self.pending {
+            w.put_bytes(k);
+            w.put_bytes(v);
+            w.put_u64(e.is_some() as u64);
+            w.put_u64(e.unwrap_or(0));
+            w.put_u64(t as u64);
+        }
+        w.put_u64(self.file_checksum.is_some() as u64);
+        w.put_u64(self.file_checksum.map_or(0, |c| c.value()));
+        Ok(BuilderCheckpoint {
+            offset: self.offset,
+            state: w.buf,
+        })
+    }
+
+    /// Resumes building a table from the `state` of a checkpoint (see `checkpoint()`). `dst` must
+    /// continue the sink right after the checkpoint's first `offset` bytes, e.g. a file truncated
+    /// to that length and opened for appending. `opt` must match the options the table was
+    /// started with.
+    pub fn resume(opt: Options, state: &[u8], dst: Dst) -> Result<TableBuilder<Dst>> {
+        let mut b = TableBuilder::new(opt, dst);
+        b.check_resumable()?;
+        let mut r = StateReader::new(state);
+        if r.get_u64()? != CHECKPOINT_VERSION {
+            return Err(Error::NotSupported(
+                "unknown table builder checkpoint version".to_string(),
+            ));
+        }
+        let cmp = r.get_bytes()?;
+        let filter = r.get_bytes()?;
+        if cmp != b.opt.cmp.id().as_bytes()
+            || filter
+                != b.filter_block
+                    .as_ref()
+                    .unwrap()
+                    .meta_block_name()
+                    .as_bytes()
+        {
+            return Err(Error::InvalidArgument(
+                "checkpoint was taken with another comparator or filter policy".to_string(),
+            ));
+        }
+
+        b.offset = r.get_u64()?;
+        b.num_entries = r.get_usize()?;
+        b.first_key = r.get_bytes()?.to_vec();
+        b.prev_block_last_key = r.get_bytes()?.to_vec();
+        let props = Block::new(meta_options(&b.opt), r.get_bytes()?.to_vec());
+        b.props = TableProperties::decode(&props)?;
+        b.all_entries_expire = r.get_bool()?;
+        b.raw_data_size = r.get_u64()?;
+        b.compressed_data_size = r.get_u64()?;
+        b.restart_interval = r.get_usize()?;
+        if b.restart_interval == 0 {
+            return Err(builder_state::malformed());
+        }
+        b.temperature = get_temperature(&mut r)?;
+        b.block_temperature = get_temperature(&mut r)?;
+        b.data_block = Some(BlockBuilder::restore(b.data_block_options(), &mut r)?);
+        b.index_block = Some(BlockBuilder::restore(b.opt.clone(), &mut r)?);
+        b.filter_block = Some(FilterBlockBuilder::restore(
+            b.opt.filter_policy.clone(),
+            &mut r,
+        )?);
+        if r.get_bool()? != b.hash_index.is_some() {
+            return Err(Error::InvalidArgument(
+                "checkpoint was taken with another index type".to_string(),
+            ));
+        }
+        if let Some(ref extractor) = b.opt.prefix_extractor {
+            if b.hash_index.is_some() {
+                b.hash_index = Some(HashIndexBuilder::restore(extractor.clone(), &mut r)?);
+            }
+        }
+        if r.get_bool()? != b.table_filter.is_some() {
+            return Err(Error::InvalidArgument(
+                "checkpoint was taken with another table filter setting".to_string(),
+            ));
+        }
+        if let Some(fp_rate) = b.opt.table_filter_fp_rate {
+            b.table_filter = Some(TableFilterBuilder::restore(fp_rate, &mut r)?);
+        }
+        b.block_keys = r.get_bytes()?.to_vec();
+        b.block_key_offsets = r.get_usizes()?;
+        if r.get_bool()? {
+            let (k, v) = (r.get_bytes()?.to_vec(), r.get_bytes()?.to_vec());
+            let (has_expiration, e) = (r.get_bool()?, r.get_u64()?);
+            let t = get_temperature(&mut r)?;
+            b.pending = Some((k, v, Some(e).filter(|_| has_expiration), t));
+        }
+        let (has_file_checksum, file_checksum) = (r.get_bool()?, r.get_u64()?);
+        if has_file_checksum != b.file_checksum.is_some() {
+            return Err(Error::InvalidArgument(
+                "checkpoint was taken with another file checksum setting".to_string(),
+            ));
+        }
+        if has_file_checksum {
+            b.file_checksum = Some(FileChecksum::from_value(file_checksum));
+        }
+        if !r.is_empty() {
+            return Err(builder_state::malformed());
+        }
+        Ok(b)
+    }
+
+    fn check_resumable(&self) -> Result<()> {
+        if !self.collectors.is_empty() || self.block_stats.is_some() {
+            return Err(Error::NotSupported(
+                "checkpoints of table builders with properties collectors or block stats"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the approximate size the table would have if it was finished now. Blocks not
+    /// written yet are assumed to compress as well as the data blocks written so far, and the
+    /// filters and the index are extrapolated to the pending entries. This allows rolling over to
+    /// a new table once a target file size is reached.
+    pub fn size_estimate(&self) -> u64 {
+        let ratio = if self.raw_data_size > 0 {
+            self.compressed_data_size as f64 / self.raw_data_size as f64
+        } else {
+            1.0
+        };
+        let trailer = (TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN) as u64;
+        let data_block = self.data_block.as_ref().unwrap();
+        let pending_entry = self
+            .pending
             .as_ref()
-            .map(|b| b.size_estimate())
-            .unwrap_or(0);
-        size += self.offset;
-        size += FULL_FOOTER_LENGTH;
-        size
+            .map_or(0, |(k, v, _, _)| k.len() + v.len());
+        let pending_keys = self.block_key_offsets.len() + self.pending.is_some() as usize;
+
+        let mut size = self.offset;
+        let mut index = self.index_block.as_ref().unwrap().size_estimate() as u64;
+        if data_block.entries() > 0 || self.pending.is_some() {
+            let block = ((data_block.size_estimate() + pending_entry) as f64 * ratio) as u64;
+            size += block + trailer;
+            // The index entry of the pending block.
+            let mut handle_enc = [0_u8; 16];
+            let enc_len = BlockHandle::new(self.offset, block).encode_to(&mut handle_enc);
+            index += (data_block.last_key().len().max(1) + enc_len + 3) as u64;
+        }
+        size += index + trailer;
+        if let Some(ref fblock) = self.filter_block {
+            size += fblock.size_estimate(pending_keys) as u64 + trailer;
+        }
+        if let Some(ref table_filter) = self.table_filter {
+            size += table_filter.size_estimate() as u64 + trailer;
+        }
+        // The properties block, and the metaindex block with an entry of at most 40 bytes per
+        // meta block.
+        size += self.props.encode(&self.opt).len() as u64 + trailer;
+        let meta_blocks = 1
+            + self.filter_block.is_some() as u64
+            + self.block_stats.is_some() as u64
+            + self.hash_index.is_some() as u64
+            + self.table_filter.is_some() as u64;
+        size += 40 * meta_blocks + 4 + trailer;
+        size + FULL_FOOTER_LENGTH as u64
     }
 
-    /// Add a key to the table. The key must be lexically greater or equal to the one that was
-    /// previously added.
+    /// Sets an expiration timestamp for the whole table. Readers treat all entries as expired at
+    /// and after this time (see `ReadOptions::read_time`).
+    pub fn set_expiration(&mut self, expiration: u64) {
+        self.props.table_expiration = Some(expiration);
+    }
+
+    /// Sets the temperature of the entries added from now on; entries are cold until it's set.
+    /// Entries of different temperatures are never stored in the same data block: a change of
+    /// temperature ends the current block. Runs of consecutive hot blocks are recorded in
+    /// `TableProperties::hot_ranges`, so that readers can load them ahead of reads (see
+    /// `Table::prefetch_hot()`) or place them in faster storage than the rest of the file.
+    pub fn set_temperature(&mut self, temperature: Temperature) {
+        self.temperature = temperature;
+    }
+
+    /// Add a key to the table. The key must be greater than the one that was previously added;
+    /// duplicate keys are handled according to `Options::dedup_policy`. `Options::key_order`
+    /// relaxes these checks.
     pub fn add(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
-        assert!(self.data_block.is_some());
+        let expiration = if self.opt.entry_expiration {
+            Some(ttl::NO_EXPIRATION)
+        } else {
+            None
+        };
+        self.add_value(key, val, expiration)
+    }
+
+    /// Add a key to the table that expires at `expiration`; 0 means that it doesn't expire. This
+    /// requires `Options::entry_expiration` to be set.
+    pub fn add_with_expiration(&mut self, key: &[u8], val: &[u8], expiration: u64) -> Result<()> {
+        if !self.opt.entry_expiration {
+            return Err(Error::InvalidArgument(
+                "entry expiration is not enabled in options".to_string(),
+            ));
+        }
+        self.add_value(key, val, Some(expiration))
+    }
 
-        if !self.prev_block_last_key.is_empty() {
-            assert!(self.opt.cmp.cmp(&self.prev_block_last_key, key) == Ordering::Less);
+    /// Encodes the key (see `Options::key_codec`), checks the order of keys as configured by
+    /// `Options::key_order` and handles duplicates as configured by `Options::dedup_policy`.
+    fn add_value(&mut self, key: &[u8], val: &[u8], expiration: Option<u64>) -> Result<()> {
+        self.check_key_range(key)?;
+        let stored = key_codec::encode(self.opt.key_codec.as_deref(), key)?;
+        let key = &stored[..];
+        if let Some(max) = self.opt.max_key_size.filter(|&max| key.len() > max) {
+            return Err(Error::InvalidArgument(format!(
+                "key of length {} exceeds max_key_size {}",
+                key.len(),
+                max
+            )));
+        }
+        if let Some(max) = self.opt.max_value_size.filter(|&max| val.len() > max) {
+            return Err(Error::InvalidArgument(format!(
+                "value of length {} (key of length {}) exceeds max_value_size {}",
+                val.len(),
+                key.len(),
+                max
+            )));
+        }
+        let order = if self.opt.key_order == KeyOrder::Unchecked {
+            Ordering::Less
+        } else {
+            match self.last_key() {
+                Some(last_key) => self.opt.cmp.cmp(last_key, key),
+                None => Ordering::Less,
+            }
+        };
+        match order {
+            Ordering::Less => {}
+            Ordering::Equal if self.opt.key_order == KeyOrder::AllowEqual => {}
+            Ordering::Equal => match self.opt.dedup_policy {
+                DedupPolicy::Error => {
+                    return Err(Error::InvalidArgument(format!(
+                        "duplicate key {:?}",
+                        String::from_utf8_lossy(key)
+                    )))
+                }
+                DedupPolicy::KeepFirst => return Ok(()),
+                DedupPolicy::KeepLast => {
+                    let t = self.temperature;
+                    self.pending = Some((key.to_vec(), val.to_vec(), expiration, t));
+                    return Ok(());
+                }
+            },
+            Ordering::Greater => {
+                return Err(Error::InvalidArgument(format!(
+                    "key {:?} added out of order",
+                    String::from_utf8_lossy(key)
+                )))
+            }
         }
 
-        if self.data_block.as_ref().unwrap().size_estimate() > self.opt.block_size {
-            self.write_data_block(key)?;
+        if self.opt.dedup_policy == DedupPolicy::KeepLast {
+            let next = Some((key.to_vec(), val.to_vec(), expiration, self.temperature));
+            if let Some((k, v, e, t)) = std::mem::replace(&mut self.pending, next) {
+                self.write_value(&k, &v, e, t)?;
+            }
+            Ok(())
+        } else {
+            self.write_value(key, val, expiration, self.temperature)
+        }
+    }
+
+    /// Checks that the logical `key` is in `Options::expected_key_range`, if set.
+    fn check_key_range(&self, key: &[u8]) -> Result<()> {
+        if let Some((ref start, ref end)) = self.opt.expected_key_range {
+            if self.opt.cmp.cmp(key, start) == Ordering::Less
+                || self.opt.cmp.cmp(key, end) != Ordering::Less
+            {
+                return Err(Error::InvalidArgument(format!(
+                    "key {:?} is outside of the expected key range",
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the key added last, if any.
+    fn last_key(&self) -> Option<&[u8]> {
+        match self.pending {
+            Some((ref k, _, _, _)) => Some(k.as_slice()),
+            None => self.last_written_key(),
+        }
+    }
+
+    /// Returns the key written to the table last, if any.
+    fn last_written_key(&self) -> Option<&[u8]> {
+        let dblock = self.data_block.as_ref().unwrap();
+        if self.num_entries == 0 {
+            None
+        } else if dblock.entries() > 0 {
+            Some(dblock.last_key())
+        } else {
+            Some(self.prev_block_last_key.as_slice())
+        }
+    }
+
+    /// Encodes a value as configured in the options (see `ttl`, `blob`, `value_compression` and
+    /// `value_codec` modules) and adds it to a data block of its temperature.
+    fn write_value(
+        &mut self,
+        key: &[u8],
+        val: &[u8],
+        expiration: Option<u64>,
+        temperature: Temperature,
+    ) -> Result<()> {
+        if temperature != self.block_temperature {
+            if self.data_block.as_ref().unwrap().entries() > 0 {
+                self.write_data_block(Some(key))?;
+            }
+            self.block_temperature = temperature;
+        }
+        if self.props.num_deletions.is_some() {
+            self.count_garbage(key);
+        }
+        self.props.raw_value_size += val.len() as u64;
+        if expiration.is_none()
+            && self.opt.blob_threshold.is_none()
+            && !self.props.value_compression
+            && self.opt.value_codec.is_none()
+            && !self.opt.value_checksums
+        {
+            self.add_entry(key, val)?;
+            self.collect(key, val);
+            return Ok(());
+        }
+
+        let mut buf = std::mem::take(&mut self.value_buf);
+        buf.clear();
+        if let Some(expiration) = expiration {
+            if expiration == ttl::NO_EXPIRATION {
+                self.all_entries_expire = false;
+            } else {
+                let max = self.props.max_entry_expiration.unwrap_or(0);
+                self.props.max_entry_expiration = Some(max.max(expiration));
+            }
+            ttl::encode_value(expiration, &[], &mut buf);
+        }
+        let prefix_len = buf.len();
+        let mut coded = vec![];
+        let val_coded = match self.opt.value_codec {
+            Some(ref codec) => {
+                codec.encode(val, &mut coded)?;
+                &coded[..]
+            }
+            None => val,
+        };
+        let mut compressed = vec![];
+        let encoded = if self.props.value_compression {
+            value_compression::compress(self.opt.value_compression, val_coded, &mut compressed)?;
+            &compressed[..]
+        } else {
+            val_coded
+        };
+        match self.opt.blob_threshold {
+            Some(threshold) if encoded.len() > threshold => {
+                let handle = self.write_block(encoded.to_vec(), self.opt.compression_type)?;
+                self.props.num_blobs += 1;
+                self.props.blob_size += handle.size();
+                blob::encode_pointer(&handle, &mut buf);
+            }
+            Some(_) => blob::encode_inline(encoded, &mut buf),
+            None => buf.extend_from_slice(encoded),
+        }
+        if self.opt.value_checksums {
+            let mut sum = Vec::with_capacity(checksum::VALUE_CHECKSUM_SIZE);
+            checksum::put_value_checksum(&buf[prefix_len..], &mut sum);
+            buf.splice(prefix_len..prefix_len, sum);
+        }
+
+        let result = self.add_entry(key, &buf);
+        self.value_buf = buf;
+        result?;
+        self.collect(key, val);
+        Ok(())
+    }
+
+    /// Updates the statistics of deletions and overwritten values with an internal key about to
+    /// be written.
+    fn count_garbage(&mut self, key: &[u8]) {
+        let (deletion, overwritten) = garbage(self.last_written_key(), key);
+        if deletion {
+            *self.props.num_deletions.as_mut().unwrap() += 1;
+        } else if overwritten {
+            *self.props.num_overwritten.as_mut().unwrap() += 1;
+        }
+    }
+
+    fn collect(&mut self, key: &[u8], val: &[u8]) {
+        for c in self.collectors.iter_mut() {
+            c.add(key, val);
+        }
+        if let Some(ref mut stats) = self.block_stats {
+            stats.add(key, val);
+        }
+    }
+
+    fn add_entry(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        assert!(self.data_block.is_some());
+
+        let dblock = self.data_block.as_ref().unwrap();
+        if dblock.entries() > 0 {
+            let state = BlockState {
+                size_estimate: dblock.size_estimate(),
+                entries: dblock.entries(),
+                last_key: dblock.last_key(),
+                block_size: self.opt.block_size,
+            };
+            if self.opt.flush_block_policy.should_flush(&state, key) {
+                self.write_data_block(Some(key))?;
+            }
         }
 
         let dblock = &mut self.data_block.as_mut().unwrap();
 
-        if let Some(ref mut fblock) = self.filter_block {
-            fblock.add_key(key);
+        if let Some(ref mut hash_index) = self.hash_index {
+            hash_index.add(key, self.props.num_data_blocks);
+        }
+        if let Some(ref mut table_filter) = self.table_filter {
+            table_filter.add(key);
+        }
+        if self.filter_block.is_some() || self.partition_blocks.is_some() {
+            self.block_key_offsets.push(self.block_keys.len());
+            self.block_keys.extend_from_slice(key);
         }
 
+        if self.num_entries == 0 {
+            self.first_key = key.to_vec();
+        }
         self.num_entries += 1;
+        self.props.raw_key_size += key.len() as u64;
         dblock.add(key, val);
         Ok(())
     }
 
     /// Writes an index entry for the current data_block where `next_key` is the first key of the
-    /// next block.
+    /// next block, or None for the last block of the table.
     /// Calls write_block() for writing the block to disk.
-    fn write_data_block(&mut self, next_key: &[u8]) -> Result<()> {
+    fn write_data_block(&mut self, next_key: Option<&[u8]>) -> Result<()> {
         assert!(self.data_block.is_some());
 
         let block = self.data_block.take().unwrap();
-        let sep = self.opt.cmp.find_shortest_sep(block.last_key(), next_key);
+        let last_key = block.last_key();
+        let sep = self.separator(last_key, next_key);
+        self.props.raw_index_key_size += last_key.len() as u64;
+        self.props.index_key_size += sep.len() as u64;
         self.prev_block_last_key = Vec::from(block.last_key());
+        if let Some(budget) = self.opt.auto_restart_interval {
+            *self
+                .props
+                .restart_intervals
+                .entry(self.restart_interval as u64)
+                .or_insert(0) += 1;
+            let entry_size = block.entries_size().div_ceil(block.entries()).max(1);
+            self.restart_interval = (budget / entry_size).clamp(1, MAX_AUTO_RESTART_INTERVAL);
+        }
         let contents = block.finish();
 
         let ctype = self.opt.compression_type;
-        let handle = self.write_block(contents, ctype)?;
+        self.raw_data_size += contents.len() as u64;
+        #[cfg(feature = "snappy")]
+        let framed = if ctype == CompressionType::CompressionSnappyFramed {
+            framed_block::compress(&contents, self.opt.compression_frame_size)?
+        } else {
+            None
+        };
+        #[cfg(not(feature = "snappy"))]
+        let framed = None;
+        let handle = match framed {
+            Some(framed) => self.write_encoded_block(framed, ctype)?,
+            None => self.write_block(contents, ctype)?,
+        };
+        self.compressed_data_size += handle.size();
+        self.props.num_data_blocks += 1;
+        self.record_temperature(&handle);
+        if let Some(ref mut stats) = self.block_stats {
+            stats.finish_block(handle.offset());
+        }
 
         let mut handle_enc = [0_u8; 16];
         let enc_len = handle.encode_to(&mut handle_enc);
@@ -190,84 +946,539 @@ impl<Dst: Write> TableBuilder<Dst> {
             .as_mut()
             .unwrap()
             .add(&sep, &handle_enc[0..enc_len]);
-        self.data_block = Some(BlockBuilder::new(self.opt.clone()));
+        self.data_block = Some(BlockBuilder::new(self.data_block_options()));
 
         if let Some(ref mut fblock) = self.filter_block {
-            fblock.start_block(self.offset);
+            fblock.start_block(handle.offset());
+            for (i, &start) in self.block_key_offsets.iter().enumerate() {
+                let end = self
+                    .block_key_offsets
+                    .get(i + 1)
+                    .copied()
+                    .unwrap_or(self.block_keys.len());
+                fblock.add_key(&self.block_keys[start..end]);
+            }
+        }
+        if let Some(ref mut blocks) = self.partition_blocks {
+            blocks.push(PartitionBlock {
+                handle,
+                separator: sep,
+                keys: std::mem::take(&mut self.block_keys),
+                key_offsets: std::mem::take(&mut self.block_key_offsets),
+            });
+        }
+        self.block_keys.clear();
+        self.block_key_offsets.clear();
+
+        Ok(())
+    }
+
+    /// Writes an already built data block, e.g. one read from another table using
+    /// `Table::read_encoded_block()`, as is: `contents` are the block's bytes as stored in a
+    /// table, compressed with `ctype`, without trailer. This makes rewriting or concatenating
+    /// tables cheap, as blocks aren't compressed again. Entries added before are written to a block
+    /// of their own first.
+    ///
+    /// The block must hold entries encoded with options like the table's (comparator, entry
+    /// expiration, value compression, key and value codecs and checksums), and `first_key` and
+    /// `last_key` are in the stored form of keys (see `Options::key_codec`). It's decoded to read
+    /// its keys, which fill the filters and the properties; they must start at `first_key`, end at
+    /// `last_key` and be greater than the keys added before. The block is indexed by `last_key`,
+    /// as the next key isn't known yet, and isn't counted in `TableProperties::restart_intervals`.
+    /// Tables with blobs, properties collectors or block stats don't support raw blocks.
+    pub fn add_raw_block(
+        &mut self,
+        contents: &[u8],
+        first_key: &[u8],
+        last_key: &[u8],
+        ctype: CompressionType,
+    ) -> Result<()> {
+        self.add_encoded_block(contents, Some((first_key, last_key)), ctype)
+    }
+
+    /// Like `add_raw_block()`, but takes the first and the last key from the block (checking them
+    /// against `keys` if given). Empty blocks are skipped if no `keys` are given.
+    pub(crate) fn add_encoded_block(
+        &mut self,
+        contents: &[u8],
+        keys: Option<(&[u8], &[u8])>,
+        ctype: CompressionType,
+    ) -> Result<()> {
+        if self.opt.blob_threshold.is_some()
+            || !self.collectors.is_empty()
+            || self.block_stats.is_some()
+        {
+            return Err(Error::NotSupported(
+                "raw blocks in tables with blobs, properties collectors or block stats".to_string(),
+            ));
+        }
+
+        let location = BlockHandle::new(self.offset, contents.len() as u64);
+        let raw = RawBlock {
+            compression: ctype as u8,
+            data: Arc::new(contents.to_vec()),
+        };
+        let block = table_block::decode_table_block(self.opt.clone(), &location, raw)?;
+        let mut iter = block.iter();
+        let (mut keys_buf, mut key_offsets) = (vec![], vec![]);
+        let (mut key, mut val) = (vec![], vec![]);
+        let mut value_size = 0;
+        let mut max_expiration = None;
+        let mut all_entries_expire = true;
+        while iter.advance() {
+            iter.current(&mut key, &mut val);
+            key_offsets.push(keys_buf.len());
+            keys_buf.extend_from_slice(&key);
+            let mut stored = &val[..];
+            if self.opt.entry_expiration {
+                let (expiration, v) = ttl::decode_value(stored);
+                stored = v;
+                max_expiration = max_expiration.max(Some(expiration));
+                all_entries_expire &= expiration != ttl::NO_EXPIRATION;
+            }
+            if self.opt.value_checksums {
+                stored = checksum::check_value_checksum(stored)
+                    .map_err(|kind| Error::corrupt_block(&location, kind))?;
+            }
+            value_size += stored.len() as u64;
+        }
+        let block_keys = PartitionBlock {
+            handle: location,
+            separator: key,
+            keys: keys_buf,
+            key_offsets,
+        };
+        let (first_key, last_key) = match (block_keys.key_offsets.is_empty(), keys) {
+            (true, None) => return Ok(()),
+            (false, Some((first, last)))
+                if block_keys.key(0) == first && block_keys.separator == last =>
+            {
+                (first, last)
+            }
+            (false, None) => (block_keys.key(0), &block_keys.separator[..]),
+            _ => {
+                return Err(Error::InvalidArgument(
+                    "raw block doesn't start at first_key and end at last_key".to_string(),
+                ))
+            }
+        };
+        if let Some(prev) = self.last_key() {
+            let order = self.opt.cmp.cmp(prev, first_key);
+            let in_order = match self.opt.key_order {
+                KeyOrder::Strict => order == Ordering::Less,
+                KeyOrder::AllowEqual => order != Ordering::Greater,
+                KeyOrder::Unchecked => true,
+            };
+            if !in_order {
+                return Err(Error::InvalidArgument(format!(
+                    "raw block starting at {:?} added out of order",
+                    String::from_utf8_lossy(first_key)
+                )));
+            }
+        }
+        for key in [first_key, last_key] {
+            self.check_key_range(&key_codec::decode(self.opt.key_codec.as_deref(), key)?)?;
+        }
+        self.all_entries_expire &= all_entries_expire;
+
+        if let Some((k, v, e, t)) = self.pending.take() {
+            self.write_value(&k, &v, e, t)?;
+        }
+        if self.data_block.as_ref().unwrap().entries() > 0 {
+            self.write_data_block(Some(first_key))?;
         }
+        self.block_temperature = self.temperature;
 
+        let prev_key = self.last_written_key().map(|k| k.to_vec());
+        if let (Some(deletions), Some(overwritten)) = (
+            self.props.num_deletions.as_mut(),
+            self.props.num_overwritten.as_mut(),
+        ) {
+            let mut prev = prev_key.as_deref();
+            for key in block_keys.keys() {
+                let (deletion, overwrite) = garbage(prev, key);
+                if deletion {
+                    *deletions += 1;
+                } else if overwrite {
+                    *overwritten += 1;
+                }
+                prev = Some(key);
+            }
+        }
+        if self.num_entries == 0 {
+            self.first_key = first_key.to_vec();
+        }
+        self.num_entries += block_keys.key_offsets.len();
+        self.props.raw_key_size += block_keys.keys.len() as u64;
+        self.props.raw_value_size += value_size;
+        if let Some(t) = max_expiration.filter(|&t| t != ttl::NO_EXPIRATION) {
+            let max = self.props.max_entry_expiration.unwrap_or(0);
+            self.props.max_entry_expiration = Some(max.max(t));
+        }
+        self.props.raw_index_key_size += last_key.len() as u64;
+        self.prev_block_last_key = last_key.to_vec();
+
+        let handle = self.write_encoded_block(contents.to_vec(), ctype)?;
+        self.raw_data_size += block.contents().len() as u64;
+        self.compressed_data_size += handle.size();
+        self.record_temperature(&handle);
+        self.index_data_block(&block_keys, handle, last_key);
         Ok(())
     }
 
+    /// Adds the data block just written at `handle` to `TableProperties::hot_ranges` if it's hot.
+    fn record_temperature(&mut self, handle: &BlockHandle) {
+        if self.block_temperature != Temperature::Hot {
+            return;
+        }
+        let size = handle.size() + (TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN) as u64;
+        match self.props.hot_ranges.last_mut() {
+            Some((offset, len)) if *offset + *len == handle.offset() => *len += size,
+            _ => self.props.hot_ranges.push((handle.offset(), size)),
+        }
+    }
+
+    /// Adds a data block written at `handle` to the index under `sep`, and its keys to the
+    /// filters and the hash index.
+    fn index_data_block(&mut self, block: &PartitionBlock, handle: BlockHandle, sep: &[u8]) {
+        self.props.index_key_size += sep.len() as u64;
+        let mut handle_enc = [0_u8; 16];
+        let enc_len = handle.encode_to(&mut handle_enc);
+        self.index_block
+            .as_mut()
+            .unwrap()
+            .add(sep, &handle_enc[0..enc_len]);
+
+        if let Some(ref mut fblock) = self.filter_block {
+            fblock.start_block(handle.offset());
+        }
+        for key in block.keys() {
+            if let Some(ref mut fblock) = self.filter_block {
+                fblock.add_key(key);
+            }
+            if let Some(ref mut hash_index) = self.hash_index {
+                hash_index.add(key, self.props.num_data_blocks);
+            }
+            if let Some(ref mut table_filter) = self.table_filter {
+                table_filter.add(key);
+            }
+        }
+        self.props.num_data_blocks += 1;
+    }
+
+    /// Appends the data blocks of a partition, whose keys must be greater than all keys added so
+    /// far, and indexes all but the last one, whose separator depends on the next partition.
+    /// Returns the last block and its location, to be passed to `index_partition_block()`.
+    pub(crate) fn add_partition(
+        &mut self,
+        part: BuiltPartition,
+    ) -> Result<Option<(PartitionBlock, BlockHandle)>> {
+        assert_eq!(self.data_block.as_ref().unwrap().entries(), 0);
+        if part.num_entries == 0 {
+            return Ok(None);
+        }
+        if self.num_entries == 0 {
+            self.first_key = part.first_key().unwrap().to_vec();
+        }
+        let base = self.offset;
+        self.append(&part.data)?;
+        let mut blocks = part.blocks;
+        let last = blocks.pop().unwrap();
+        for block in blocks {
+            let handle = BlockHandle::new(base + block.handle.offset(), block.handle.size());
+            self.index_data_block(&block, handle, &block.separator);
+        }
+        let last_handle = BlockHandle::new(base + last.handle.offset(), last.handle.size());
+
+        let props = part.props;
+        self.props.raw_key_size += props.raw_key_size;
+        self.props.raw_value_size += props.raw_value_size;
+        self.props.raw_index_key_size += props.raw_index_key_size;
+        if let (Some(n), Some(m)) = (self.props.num_deletions.as_mut(), props.num_deletions) {
+            *n += m;
+        }
+        if let (Some(n), Some(m)) = (self.props.num_overwritten.as_mut(), props.num_overwritten) {
+            *n += m;
+        }
+        if let Some(t) = props.max_entry_expiration {
+            let max = self.props.max_entry_expiration.unwrap_or(0);
+            self.props.max_entry_expiration = Some(max.max(t));
+        }
+        for (interval, blocks) in props.restart_intervals {
+            *self.props.restart_intervals.entry(interval).or_insert(0) += blocks;
+        }
+        self.all_entries_expire &= part.all_entries_expire;
+        self.raw_data_size += part.raw_data_size;
+        self.compressed_data_size += part.compressed_data_size;
+        self.num_entries += part.num_entries;
+        self.prev_block_last_key = part.last_key;
+        Ok(Some((last, last_handle)))
+    }
+
+    /// Indexes the last data block of the partition added last (see `add_partition()`).
+    /// `next_key` is the first key of the next partition, if any.
+    pub(crate) fn index_partition_block(
+        &mut self,
+        (block, handle): (PartitionBlock, BlockHandle),
+        next_key: Option<&[u8]>,
+    ) {
+        let sep = self.separator(&self.prev_block_last_key, next_key);
+        self.index_data_block(&block, handle, &sep);
+    }
+
+    /// Returns the separator between `last_key` and `next_key`, the first key of the next block,
+    /// or None for the last block, as configured by `Options::index_shortening`.
+    fn separator(&self, last_key: &[u8], next_key: Option<&[u8]>) -> Vec<u8> {
+        match (self.opt.index_shortening, next_key) {
+            (IndexShortening::NoShortening, _) => last_key.to_vec(),
+            (_, Some(next_key)) => self.opt.cmp.find_shortest_sep(last_key, next_key),
+            (IndexShortening::ShortenSeparators, None) => last_key.to_vec(),
+            (IndexShortening::ShortenSeparatorsAndSuccessor, None) => {
+                self.opt.cmp.find_short_succ(last_key)
+            }
+        }
+    }
+
+    /// Returns the options for the current data block, i.e. with its restart interval.
+    fn data_block_options(&self) -> Options {
+        let mut opt = self.opt.clone();
+        opt.block_restart_interval = self.restart_interval;
+        opt
+    }
+
+    /// Calculates the checksum, writes the block to disk and updates the offset.
+    #[cfg(not(feature = "snappy"))]
+    fn write_block(&mut self, block: BlockContents, ctype: CompressionType) -> Result<BlockHandle> {
+        if ctype != CompressionType::CompressionNone {
+            return Err(table_block::compression_not_supported(ctype));
+        }
+        self.write_encoded_block(block, ctype)
+    }
+
     /// Calculates the checksum, writes the block to disk and updates the offset.
+    #[cfg(feature = "snappy")]
     fn write_block(&mut self, block: BlockContents, ctype: CompressionType) -> Result<BlockHandle> {
         let mut data = block;
+        let mut ctype = ctype;
+        if ctype == CompressionType::CompressionSnappyFramed {
+            ctype = CompressionType::CompressionSnappy;
+        }
         if ctype == CompressionType::CompressionSnappy {
             let mut encoder = Encoder::new();
-            data = encoder.compress_vec(&data)?;
+            let compressed = encoder.compress_vec(&data)?;
+            event!(
+                trace,
+                offset = self.offset,
+                raw = data.len(),
+                compressed = compressed.len(),
+                "compressed block with snappy"
+            );
+            data = compressed;
         }
+        self.write_encoded_block(data, ctype)
+    }
 
-        let mut digest = CASTAGNOLI.digest();
-        digest.update(&data);
-        digest.update(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN]);
-
-        self.dst.write_all(&data)?;
-        self.dst
-            .write_all(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN])?;
-        self.dst.write_fixedint(mask_crc(digest.finalize()))?;
+    /// Writes a block already compressed with `ctype`, followed by its trailer.
+    fn write_encoded_block(
+        &mut self,
+        data: BlockContents,
+        ctype: CompressionType,
+    ) -> Result<BlockHandle> {
+        let cs = self.opt.checksummer.as_ref();
+        let mut trailer = [0; TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN];
+        trailer[0] = ctype as u8;
+        cs.mask(checksum::block_checksum(cs, &data, ctype as u8))
+            .encode_fixed(&mut trailer[TABLE_BLOCK_COMPRESS_LEN..]);
 
-        let handle = BlockHandle::new(self.offset, data.len());
-        self.offset += data.len() + TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN;
+        let handle = BlockHandle::new(self.offset, data.len() as u64);
+        self.append(&data)?;
+        self.append(&trailer)?;
+        event!(
+            debug,
+            offset = handle.offset(),
+            size = handle.size(),
+            "wrote table block"
+        );
 
         Ok(handle)
     }
 
-    pub fn finish(mut self) -> Result<usize> {
+    /// Writes all of `data` to the sink. All writes go through here, so that `self.offset` is
+    /// exactly the number of bytes the sink has accepted.
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.dst.write_all(data)?;
+        self.offset += data.len() as u64;
+        if let Some(ref mut checksum) = self.file_checksum {
+            checksum.update(data);
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining blocks and the footer, and flushes the sink. Returns the size of the
+    /// table, i.e. the number of bytes written to the sink.
+    ///
+    /// A builder without entries writes a valid empty table: it has no data blocks, an empty
+    /// index and properties recording 0 entries. Readers open it like any other table; lookups
+    /// find nothing and iterators are exhausted right away (see `Table::is_empty()`).
+    pub fn finish(self) -> Result<u64> {
+        Ok(self.finish_with_meta()?.size)
+    }
+
+    /// Like `finish()`, but also returns the range of keys in the table and the checksum of the
+    /// whole file if it's built with `Options::file_checksum`, e.g. to be recorded in a manifest.
+    pub fn finish_with_meta(mut self) -> Result<TableFileMeta> {
         assert!(self.data_block.is_some());
         let ctype = self.opt.compression_type;
 
+        if let Some((k, v, e, t)) = self.pending.take() {
+            self.write_value(&k, &v, e, t)?;
+        }
+
         // If there's a pending data block, write it
         if self.data_block.as_ref().unwrap().entries() > 0 {
-            // Find a key reliably past the last key
-            let key_past_last = self
-                .opt
-                .cmp
-                .find_short_succ(self.data_block.as_ref().unwrap().last_key());
-            self.write_data_block(&key_past_last)?;
+            self.write_data_block(None)?;
         }
+        let key_range = match self.last_written_key() {
+            Some(last) => {
+                let codec = self.opt.key_codec.as_deref();
+                let first = key_codec::decode(codec, &self.first_key)?.into_owned();
+                Some((first, key_codec::decode(codec, last)?.into_owned()))
+            }
+            None => None,
+        };
 
-        // Create metaindex block
-        let mut meta_ix_block = BlockBuilder::new(self.opt.clone());
+        for c in self.collectors.iter_mut() {
+            for (name, val) in c.finish() {
+                if name.starts_with(RESERVED_PREFIX) {
+                    return Err(Error::InvalidArgument(format!(
+                        "table property name {} uses reserved prefix {}",
+                        name, RESERVED_PREFIX
+                    )));
+                }
+                self.props.user_properties.insert(name, val);
+            }
+        }
+        self.props.num_entries = self.num_entries as u64;
+        self.props.data_size = self.offset;
+        if !self.all_entries_expire || self.num_entries == 0 {
+            self.props.max_entry_expiration = None;
+        }
+
+        // Collect the metaindex entries; they are added to the metaindex block once all meta
+        // blocks are written, as the file checksum must be written last but doesn't sort last.
+        let mut meta_ix_entries = BTreeMap::new();
 
         if self.filter_block.is_some() {
             // if there's a filter block, write the filter block and add it to the metaindex block.
             let fblock = self.filter_block.take().unwrap();
-            let filter_key = format!("filter.{}", fblock.filter_name());
+            let filter_key = fblock.meta_block_name();
             let fblock_data = fblock.finish();
             let fblock_handle = self.write_block(fblock_data, CompressionType::CompressionNone)?;
+            self.props.filter_size = fblock_handle.size();
 
             let mut handle_enc = [0_u8; 16];
             let enc_len = fblock_handle.encode_to(&mut handle_enc);
 
-            meta_ix_block.add(filter_key.as_bytes(), &handle_enc[0..enc_len]);
+            meta_ix_entries.insert(filter_key, handle_enc[0..enc_len].to_vec());
+        }
+
+        // write block statistics, if any, and add them to the metaindex block.
+        if let Some(stats) = self.block_stats.take().and_then(|s| s.finish()) {
+            let stats_handle = self.write_block(stats, ctype)?;
+            let mut handle_enc = [0_u8; 16];
+            let enc_len = stats_handle.encode_to(&mut handle_enc);
+            meta_ix_entries.insert(
+                BLOCK_STATS_BLOCK_KEY.to_string(),
+                handle_enc[0..enc_len].to_vec(),
+            );
+        }
+
+        // write the hash index, if any, and add it to the metaindex block.
+        if let Some(hash_index) = self.hash_index.take() {
+            let hash_index_handle = self.write_block(hash_index.finish(), ctype)?;
+            let mut handle_enc = [0_u8; 16];
+            let enc_len = hash_index_handle.encode_to(&mut handle_enc);
+            meta_ix_entries.insert(
+                HASH_INDEX_BLOCK_KEY.to_string(),
+                handle_enc[0..enc_len].to_vec(),
+            );
+        }
+
+        // write properties block and add it to the metaindex block.
+        let index_cont = self.index_block.take().unwrap().finish();
+        self.props.index_size = index_cont.len() as u64;
+        let props_block = self.props.encode(&self.opt);
+        let props_handle = self.write_block(props_block, ctype)?;
+        let mut handle_enc = [0_u8; 16];
+        let enc_len = props_handle.encode_to(&mut handle_enc);
+        meta_ix_entries.insert(
+            PROPERTIES_BLOCK_KEY.to_string(),
+            handle_enc[0..enc_len].to_vec(),
+        );
+
+        // write the table filter, if any, and add it to the metaindex block.
+        if let Some(table_filter) = self.table_filter.take() {
+            let table_filter_handle =
+                self.write_block(table_filter.finish(), CompressionType::CompressionNone)?;
+            let enc_len = table_filter_handle.encode_to(&mut handle_enc);
+            meta_ix_entries.insert(
+                TABLE_FILTER_BLOCK_KEY.to_string(),
+                handle_enc[0..enc_len].to_vec(),
+            );
+        }
+
+        // write the checksum of everything written so far, if enabled, as the last meta block.
+        if let Some(checksum) = self.file_checksum {
+            let contents = checksum::encode_file_checksum(checksum.value());
+            let checksum_handle = self.write_block(contents, CompressionType::CompressionNone)?;
+            let enc_len = checksum_handle.encode_to(&mut handle_enc);
+            meta_ix_entries.insert(
+                FILE_CHECKSUM_BLOCK_KEY.to_string(),
+                handle_enc[0..enc_len].to_vec(),
+            );
         }
 
         // write metaindex block
+        // Meta blocks are always ordered bytewise.
+        let mut meta_ix_block = BlockBuilder::new(meta_options(&self.opt));
+        for (name, handle) in meta_ix_entries {
+            meta_ix_block.add(name.as_bytes(), &handle);
+        }
         let meta_ix = meta_ix_block.finish();
         let meta_ix_handle = self.write_block(meta_ix, ctype)?;
 
         // write index block
-        let index_cont = self.index_block.take().unwrap().finish();
         let ix_handle = self.write_block(index_cont, ctype)?;
 
         // write footer.
-        let footer = Footer::new(meta_ix_handle, ix_handle);
+        let mut footer = Footer::new(meta_ix_handle, ix_handle);
+        footer.magic_number = self.opt.table_magic_number;
+        if !checksum::is_default(self.opt.checksummer.as_ref()) {
+            footer.checksum_type = CHECKSUM_CUSTOM;
+        }
         let mut buf = [0; FULL_FOOTER_LENGTH];
         footer.encode(&mut buf);
 
-        self.offset += self.dst.write(&buf[..])?;
+        let footer_offset = self.offset;
+        self.append(&buf)?;
         self.dst.flush()?;
-        Ok(self.offset)
+
+        // The index block is written last, right before the footer, which ends the table.
+        let index_end = footer.index.offset()
+            + footer.index.size()
+            + (TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN) as u64;
+        if index_end != footer_offset || footer_offset + FULL_FOOTER_LENGTH as u64 != self.offset {
+            return Err(Error::malformed(
+                Some(footer_offset),
+                "table layout doesn't match the bytes written",
+            ));
+        }
+        Ok(TableFileMeta {
+            size: self.offset,
+            num_entries: self.props.num_entries,
+            file_checksum: self.file_checksum.map(|c| c.value()),
+            key_range,
+        })
     }
 }
 
@@ -282,13 +1493,67 @@ mod tests {
         let mut buf = [0; 48];
         f.encode(&mut buf[..]);
 
-        let f2 = Footer::decode(&buf);
+        let f2 = Footer::decode(&buf, LEVELDB_MAGIC_NUMBER).unwrap();
         assert_eq!(f2.meta_index.offset(), 44);
         assert_eq!(f2.meta_index.size(), 4);
         assert_eq!(f2.index.offset(), 55);
         assert_eq!(f2.index.size(), 5);
+        assert_eq!(f2.format_version, FORMAT_VERSION);
+        assert_eq!(f2.checksum_type, CHECKSUM_CRC32C);
+    }
+
+    #[test]
+    fn test_footer_versions() {
+        let mut f = Footer::new(BlockHandle::new(44, 4), BlockHandle::new(55, 5));
+        let mut buf = [0; FULL_FOOTER_LENGTH];
+
+        // LevelDB footers.
+        f.format_version = 0;
+        f.checksum_type = 0;
+        f.encode(&mut buf);
+        assert!(buf[20..FOOTER_LENGTH].iter().all(|&b| b == 0));
+        assert_eq!(
+            buf[FOOTER_LENGTH..],
+            [0x57, 0xfb, 0x80, 0x8b, 0x24, 0x75, 0x47, 0xdb]
+        );
+        let f2 = Footer::decode(&buf, LEVELDB_MAGIC_NUMBER).unwrap();
+        assert_eq!(f2.format_version, 0);
+        assert_eq!(f2.checksum_type, CHECKSUM_CRC32C);
+
+        f.format_version = FORMAT_VERSION + 1;
+        f.checksum_type = CHECKSUM_CRC32C;
+        f.encode(&mut buf);
+        assert!(matches!(
+            Footer::decode(&buf, LEVELDB_MAGIC_NUMBER),
+            Err(Error::NotSupported(_))
+        ));
+
+        f.format_version = FORMAT_VERSION;
+        f.checksum_type = 2;
+        f.encode(&mut buf);
+        assert!(matches!(
+            Footer::decode(&buf, LEVELDB_MAGIC_NUMBER),
+            Err(Error::NotSupported(_))
+        ));
+
+        // Handles of files smaller than 2^56 bytes leave room for the version fields.
+        let max = (1 << 56) - 1;
+        let big = Footer::new(BlockHandle::new(max, max), BlockHandle::new(max, max));
+        big.encode(&mut buf);
+        let f2 = Footer::decode(&buf, LEVELDB_MAGIC_NUMBER).unwrap();
+        assert_eq!((f2.index.offset(), f2.index.size()), (max, max));
+        assert_eq!(f2.format_version, FORMAT_VERSION);
+
+        f.checksum_type = CHECKSUM_CRC32C;
+        f.magic_number = 0x1234;
+        f.encode(&mut buf);
+        assert!(Footer::decode(&buf, 0x1234).is_ok());
+        assert!(Footer::decode(&buf, LEVELDB_MAGIC_NUMBER)
+            .unwrap_err()
+            .is_corruption());
     }
 
+    #[cfg(feature = "snappy")]
     #[test]
     fn test_table_builder() {
         let mut d = Vec::with_capacity(512);
@@ -297,13 +1562,13 @@ mod tests {
         opt.compression_type = CompressionType::CompressionSnappy;
         let mut b = TableBuilder::new(opt, &mut d);
 
-        let data = vec![
+        let data = [
             ("abc", "def"),
             ("abe", "dee"),
             ("bcd", "asa"),
             ("dcc", "a00"),
         ];
-        let data2 = vec![
+        let data2 = [
             ("abd", "def"),
             ("abf", "dee"),
             ("ccd", "asa"),
@@ -316,15 +1581,48 @@ mod tests {
         }
 
         let estimate = b.size_estimate();
-
-        assert_eq!(143, estimate);
         assert!(b.filter_block.is_some());
 
         let actual = b.finish().unwrap();
+        assert!(estimate.abs_diff(actual) < 200, "{} {}", estimate, actual);
         // Ensure that something has been written
         assert!(!d.is_empty());
         // The returned length should be the same as the data size
-        assert_eq!(d.len(), actual);
+        assert_eq!(d.len() as u64, actual);
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_builder_size_estimate() {
+        for ctype in &[
+            CompressionType::CompressionNone,
+            CompressionType::CompressionSnappy,
+        ] {
+            let mut opt = Options::default();
+            opt.compression_type = *ctype;
+            opt.table_filter_fp_rate = Some(0.01);
+            let mut d = vec![];
+            let mut b = TableBuilder::new(opt, &mut d);
+            let mut raw = 0;
+            for i in 0..20000 {
+                let k = format!("key{:08}", i);
+                let v = format!("value {} {}", i % 7, "x".repeat(50));
+                raw += k.len() + v.len();
+                b.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+            let estimate = b.size_estimate();
+            let actual = b.finish().unwrap();
+            assert!(
+                estimate.abs_diff(actual) < actual / 100,
+                "{:?}: {} {}",
+                ctype,
+                estimate,
+                actual
+            );
+            if *ctype == CompressionType::CompressionSnappy {
+                assert!(actual < raw as u64 / 2);
+            }
+        }
     }
 
     #[test]
@@ -336,7 +1634,7 @@ mod tests {
         let mut b = TableBuilder::new(opt, &mut d);
 
         // Test two equal consecutive keys
-        let data = vec![
+        let data = [
             ("abc", "def"),
             ("abc", "dee"),
             ("bcd", "asa"),
@@ -348,4 +1646,475 @@ mod tests {
         }
         b.finish().unwrap();
     }
+
+    fn build_with_policy(policy: DedupPolicy, data: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut d = vec![];
+        let mut opt = Options::default();
+        opt.dedup_policy = policy;
+        let mut b = TableBuilder::new(opt, &mut d);
+        for &(k, v) in data.iter() {
+            b.add(k.as_bytes(), v.as_bytes())?;
+        }
+        b.finish()?;
+        Ok(d)
+    }
+
+    #[test]
+    fn test_table_builder_dedup_policy() {
+        use crate::table_reader::Table;
+
+        let data = [
+            ("abc", "1"),
+            ("abc", "2"),
+            ("abd", "3"),
+            ("abd", "4"),
+            ("abd", "5"),
+        ];
+        match build_with_policy(DedupPolicy::Error, &data) {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("abc")),
+            _ => panic!("expected duplicate key error"),
+        }
+        assert!(matches!(
+            build_with_policy(DedupPolicy::KeepLast, &[("b", "1"), ("a", "2")]),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        for &(policy, expected) in &[
+            (DedupPolicy::KeepFirst, [b"1", b"3"]),
+            (DedupPolicy::KeepLast, [b"2", b"5"]),
+        ] {
+            let d = build_with_policy(policy, &data).unwrap();
+            let size = d.len() as u64;
+            let table = Table::new(Options::default(), Box::new(d), size).unwrap();
+            assert_eq!(table.properties().unwrap().num_entries, 2);
+            assert_eq!(table.get(b"abc").unwrap().unwrap(), expected[0]);
+            assert_eq!(table.get(b"abd").unwrap().unwrap(), expected[1]);
+        }
+    }
+
+    #[test]
+    fn test_table_builder_key_order() {
+        use crate::table_reader::Table;
+        use crate::test_util::SSIteratorIter;
+
+        let data = [("abc", "1"), ("abc", "2"), ("abd", "3"), ("abc", "4")];
+        let build = |order: KeyOrder, data: &[(&str, &str)]| -> Result<Table> {
+            let mut d = vec![];
+            let mut opt = Options::default();
+            opt.block_size = 16;
+            opt.key_order = order;
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for &(k, v) in data.iter() {
+                b.add(k.as_bytes(), v.as_bytes())?;
+            }
+            b.finish()?;
+            let size = d.len() as u64;
+            Table::new(opt, Box::new(d), size)
+        };
+
+        match build(KeyOrder::Strict, &data) {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("duplicate key")),
+            _ => panic!("expected duplicate key error"),
+        }
+        match build(KeyOrder::AllowEqual, &data) {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("out of order")),
+            _ => panic!("expected out of order error"),
+        }
+
+        let table = build(KeyOrder::AllowEqual, &data[..3]).unwrap();
+        let values: Vec<Vec<u8>> = SSIteratorIter::wrap(&mut table.iter())
+            .map(|(_, v)| v)
+            .collect();
+        assert_eq!(values, vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]);
+        assert_eq!(table.get(b"abc").unwrap().unwrap(), b"1");
+
+        // Unchecked input isn't validated, even if it is out of order.
+        assert!(build(KeyOrder::Unchecked, &data).is_ok());
+        let table = build(KeyOrder::Unchecked, &[("a", "1"), ("b", "2")]).unwrap();
+        assert_eq!(table.get(b"b").unwrap().unwrap(), b"2");
+    }
+
+    #[test]
+    fn test_table_builder_size_limits() {
+        let mut opt = Options::default();
+        opt.max_key_size = Some(4);
+        opt.max_value_size = Some(8);
+        let mut b = TableBuilder::new(opt, vec![]);
+
+        b.add(b"abcd", b"12345678").unwrap();
+        match b.add(b"abcde", b"") {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("key of length 5")),
+            _ => panic!("expected error for oversized key"),
+        }
+        match b.add(b"abce", b"123456789") {
+            Err(Error::InvalidArgument(msg)) => {
+                assert!(msg.contains("value of length 9 (key of length 4)"))
+            }
+            _ => panic!("expected error for oversized value"),
+        }
+        // Rejected entries don't affect the table.
+        b.add(b"abce", b"").unwrap();
+        assert_eq!(b.entries(), 2);
+    }
+
+    #[test]
+    fn test_table_builder_expected_key_range() {
+        let opt = Options::default().expected_key_range(b"b", b"d");
+        let meta = TableBuilder::new(opt.clone(), vec![])
+            .finish_with_meta()
+            .unwrap();
+        assert_eq!(meta.key_range, None);
+
+        let mut b = TableBuilder::new(opt, vec![]);
+        for key in [&b"a"[..], b"b", b"bcd", b"c", b"d", b"e"] {
+            let r = b.add(key, b"v");
+            match key {
+                b"a" | b"d" | b"e" => match r {
+                    Err(Error::InvalidArgument(msg)) => {
+                        assert!(msg.contains("outside of the expected key range"))
+                    }
+                    _ => panic!("expected error for key outside of the range"),
+                },
+                _ => r.unwrap(),
+            }
+        }
+        let meta = b.finish_with_meta().unwrap();
+        assert_eq!(meta.num_entries, 3);
+        assert_eq!(meta.key_range, Some((b"b".to_vec(), b"c".to_vec())));
+    }
+
+    #[test]
+    fn test_table_builder_checkpoint_resume() {
+        let mut opts = vec![];
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        opts.push(opt.clone());
+        opt.entry_expiration = true;
+        opt.dedup_policy = DedupPolicy::KeepLast;
+        opt.index_type = IndexType::HashSearch;
+        opt.prefix_extractor = Some(Arc::new(crate::hash_index::FixedPrefix(4)));
+        opt.table_filter_fp_rate = Some(0.01);
+        opt.blob_threshold = Some(20);
+        opt.file_checksum = true;
+        opt.auto_restart_interval = Some(64);
+        opts.push(opt);
+
+        for opt in opts {
+            let entries: Vec<(String, String)> = (0..200)
+                .map(|i| {
+                    (
+                        format!("k{:03}", i / 2),
+                        format!("value {}", i).repeat(i % 4),
+                    )
+                })
+                .collect();
+            let build = |b: &mut TableBuilder<&mut Vec<u8>>, range: std::ops::Range<usize>| {
+                for i in range {
+                    let (k, v) = &entries[i];
+                    b.set_temperature(if i % 30 < 7 {
+                        Temperature::Hot
+                    } else {
+                        Temperature::Cold
+                    });
+                    let r = if opt.entry_expiration {
+                        b.add_with_expiration(k.as_bytes(), v.as_bytes(), i as u64)
+                    } else {
+                        b.add(k.as_bytes(), v.as_bytes())
+                    };
+                    r.or_else(|e| match e {
+                        Error::InvalidArgument(_) => Ok(()),
+                        e => Err(e),
+                    })
+                    .unwrap();
+                }
+            };
+
+            let mut expected = vec![];
+            let mut b = TableBuilder::new(opt.clone(), &mut expected);
+            build(&mut b, 0..entries.len());
+            b.finish().unwrap();
+
+            for &split in &[0, 1, 77, 200] {
+                let mut dst = vec![];
+                let mut b = TableBuilder::new(opt.clone(), &mut dst);
+                build(&mut b, 0..split);
+                let checkpoint = b.checkpoint().unwrap();
+                // Bytes written after the checkpoint are lost.
+                b.add(b"zzz", b"lost").unwrap();
+                b.finish().unwrap();
+                assert!(checkpoint.offset as usize <= dst.len());
+                dst.truncate(checkpoint.offset as usize);
+
+                let mut b = TableBuilder::resume(opt.clone(), &checkpoint.state, &mut dst).unwrap();
+                build(&mut b, split..entries.len());
+                let meta = b.finish_with_meta().unwrap();
+                assert_eq!(meta.key_range, Some((b"k000".to_vec(), b"k099".to_vec())));
+                assert_eq!(dst, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_builder_auto_restart_interval() {
+        use crate::table_reader::Table;
+
+        let mut opt = Options::default();
+        opt.block_size = 1024;
+        opt.auto_restart_interval = Some(200);
+        let build = |opt: &Options, value_size: usize| {
+            let mut d = vec![];
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for i in 0..1000 {
+                let k = format!("some/common/prefix/{:06}", i);
+                b.add(k.as_bytes(), &vec![b'x'; value_size]).unwrap();
+            }
+            b.finish().unwrap();
+            let size = d.len() as u64;
+            Table::new(opt.clone(), Box::new(d), size).unwrap()
+        };
+
+        // Prefix compression leaves entries of about 10 bytes: a header of 3, a key suffix of 2 or
+        // 3 and the value.
+        let table = build(&opt, 4);
+        let props = table.properties().unwrap();
+        assert_eq!(
+            props.restart_intervals.values().sum::<u64>(),
+            props.num_data_blocks
+        );
+        assert_eq!(props.restart_intervals.get(&16), Some(&1));
+        let last = *props.restart_intervals.keys().last().unwrap();
+        assert!((18..=25).contains(&last), "{}", last);
+        assert_eq!(
+            table.get(b"some/common/prefix/000765").unwrap(),
+            Some(b"xxxx".to_vec())
+        );
+
+        // Large entries get a restart point each.
+        let table = build(&opt, 300);
+        let props = table.properties().unwrap();
+        assert_eq!(
+            props.restart_intervals.get(&1),
+            Some(&(props.num_data_blocks - 1))
+        );
+        assert!(table.get(b"some/common/prefix/000999").unwrap().is_some());
+
+        opt.auto_restart_interval = None;
+        assert!(build(&opt, 4)
+            .properties()
+            .unwrap()
+            .restart_intervals
+            .is_empty());
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_builder_add_raw_block() {
+        use crate::table_reader::Table;
+
+        let mut opt = Options::default();
+        opt.block_size = 128;
+        opt.compression_type = CompressionType::CompressionSnappy;
+        opt.table_filter_fp_rate = Some(0.01);
+        let key = |i: usize| format!("key{:04}", i).into_bytes();
+        let mut src = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut src);
+        for i in 100..200 {
+            b.add(&key(i), b"value").unwrap();
+        }
+        b.finish().unwrap();
+        let size = src.len() as u64;
+        let src = Table::new(opt.clone(), Box::new(src), size).unwrap();
+
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d);
+        for i in 0..10 {
+            b.add(&key(i), b"before").unwrap();
+        }
+        for handle in src.index_handles() {
+            let mut iter = src.read_block(&handle).unwrap().iter();
+            iter.advance();
+            let first = iter.current_key().unwrap().to_vec();
+            iter.seek_to_last();
+            let last = iter.current_key().unwrap().to_vec();
+            let (contents, ctype) = src.read_encoded_block(&handle).unwrap();
+            assert_eq!(ctype, CompressionType::CompressionSnappy);
+            // Out of order: the block was added already, or its keys are wrong.
+            if first != key(100) {
+                assert!(b.add_raw_block(&contents, &key(100), &last, ctype).is_err());
+            } else {
+                assert!(b.add_raw_block(&contents, &first, &key(0), ctype).is_err());
+            }
+            b.add_raw_block(&contents, &first, &last, ctype).unwrap();
+        }
+        assert!(b.add(&key(150), b"after").is_err());
+        b.add(&key(200), b"after").unwrap();
+        let size = b.finish().unwrap();
+
+        // Taking the keys from the blocks yields the same table.
+        let mut d2 = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d2);
+        for i in 0..10 {
+            b.add(&key(i), b"before").unwrap();
+        }
+        for handle in src.index_handles() {
+            let (contents, ctype) = src.read_encoded_block(&handle).unwrap();
+            b.add_encoded_block(&contents, None, ctype).unwrap();
+        }
+        b.add(&key(200), b"after").unwrap();
+        b.finish().unwrap();
+        assert_eq!(d2, d);
+
+        let table = Table::new(opt.clone(), Box::new(d), size).unwrap();
+        let props = table.properties().unwrap();
+        assert_eq!(props.num_entries, 111);
+        assert_eq!(props.raw_value_size, 10 * 6 + 100 * 5 + 5);
+        for (i, val) in [
+            (5, "before"),
+            (100, "value"),
+            (199, "value"),
+            (200, "after"),
+        ] {
+            assert_eq!(table.get(&key(i)).unwrap(), Some(val.as_bytes().to_vec()));
+        }
+        assert_eq!(table.get(&key(50)).unwrap(), None);
+        assert_eq!(table.iter().entries().count(), 111);
+
+        opt.blob_threshold = Some(10);
+        let mut b = TableBuilder::new(opt, vec![]);
+        assert!(matches!(
+            b.add_raw_block(&[], b"a", b"b", CompressionType::CompressionNone),
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_builder_deterministic() {
+        use crate::memtable::MemTable;
+        use crate::table_reader::Table;
+        use crate::types::SSIterator;
+
+        // Fresh options every time, so that nothing is shared between builds.
+        let options = || {
+            let mut opt = Options::default();
+            opt.block_size = 64;
+            opt.compression_type = CompressionType::CompressionSnappy;
+            opt.index_type = IndexType::HashSearch;
+            opt.prefix_extractor = Some(Arc::new(crate::hash_index::FixedPrefix(2)));
+            opt.table_filter_fp_rate = Some(0.01);
+            opt.blob_threshold = Some(30);
+            opt
+        };
+        let entries: Vec<(String, String)> = (0..100)
+            .map(|i| (format!("k{:03}", i), format!("value {}", i).repeat(i % 5)))
+            .collect();
+        let build = |entries: &mut dyn Iterator<Item = (Vec<u8>, Vec<u8>)>| {
+            let mut d = vec![];
+            let mut b = TableBuilder::new(options(), &mut d);
+            for (k, v) in entries {
+                b.add(&k, &v).unwrap();
+            }
+            b.finish().unwrap();
+            d
+        };
+        let bytes = |e: &[(String, String)]| {
+            e.iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        let expected = build(&mut bytes(&entries).into_iter());
+        assert_eq!(build(&mut bytes(&entries).into_iter()), expected);
+
+        // Entries arriving in another order, sorted by a MemTable.
+        let mut mt = MemTable::new(options());
+        for (k, v) in entries.iter().rev() {
+            mt.add(k.as_bytes(), v.as_bytes());
+        }
+        let mut d = vec![];
+        mt.flush_into(TableBuilder::new(options(), &mut d)).unwrap();
+        assert_eq!(d, expected);
+
+        // Rewriting a table reproduces it.
+        let size = expected.len() as u64;
+        let table = Table::new(options(), Box::new(expected.clone()), size).unwrap();
+        assert_eq!(build(&mut table.iter().entries()), expected);
+    }
+
+    #[test]
+    fn test_table_builder_checkpoint_errors() {
+        let mut b = TableBuilder::new(Options::default(), vec![]);
+        b.add(b"abc", b"def").unwrap();
+        let checkpoint = b.checkpoint().unwrap();
+        let state = &checkpoint.state;
+        assert!(
+            TableBuilder::resume(Options::default(), &state[..state.len() - 1], vec![])
+                .err()
+                .unwrap()
+                .is_corruption()
+        );
+
+        #[cfg(feature = "filters")]
+        {
+            let mut opt = Options::default();
+            opt.filter_policy = Arc::new(Box::new(NoFilterPolicy::new()));
+            assert!(matches!(
+                TableBuilder::resume(opt, state, vec![]),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+
+        struct NoopCollector;
+        impl TablePropertiesCollector for NoopCollector {
+            fn add(&mut self, _: &[u8], _: &[u8]) {}
+            fn finish(&mut self) -> Vec<(String, Vec<u8>)> {
+                vec![]
+            }
+        }
+        let mut opt = Options::default();
+        opt.properties_collectors
+            .push(Arc::new(|| Box::new(NoopCollector)));
+        assert!(matches!(
+            TableBuilder::new(opt, vec![]).checkpoint(),
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_table_builder_short_writes() {
+        // A sink accepting at most 7 bytes per write() call.
+        struct ShortWriter(Vec<u8>);
+        impl Write for ShortWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(7);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let mut dst = ShortWriter(vec![]);
+        let mut b = TableBuilder::new(opt.clone(), &mut dst);
+        for i in 0..50 {
+            b.add(format!("key{:02}", i).as_bytes(), b"value").unwrap();
+        }
+        let size = b.finish().unwrap();
+        assert_eq!(size, dst.0.len() as u64);
+
+        let footer = Footer::decode(
+            &dst.0[dst.0.len() - FULL_FOOTER_LENGTH..],
+            opt.table_magic_number,
+        )
+        .unwrap();
+        assert_eq!(
+            footer.index.offset() + footer.index.size() + 5,
+            size - FULL_FOOTER_LENGTH as u64
+        );
+        let table = crate::table_reader::Table::new(opt, Box::new(dst.0), size).unwrap();
+        assert_eq!(table.get(b"key42").unwrap(), Some(b"value".to_vec()));
+    }
 }
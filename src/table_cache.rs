@@ -0,0 +1,225 @@
+//! A cache of open tables, addressed by file number. Applications keeping many tables (e.g. the
+//! levels of an LSM tree) use it to bound the number of simultaneously open files while avoiding
+//! re-reading footers, index and filter blocks on every access.
+
+use crate::cache::{Cache, CacheKey};
+use crate::error::Result;
+use crate::options::Options;
+use crate::table_reader::{Table, TableIterator};
+
+use std::sync::{Mutex, MutexGuard};
+
+use integer_encoding::FixedIntWriter;
+
+/// Opens the table with the given file number using the given options.
+pub type TableOpener = dyn Fn(u64, Options) -> Result<Table> + Send + Sync;
+
+/// TableCache keeps up to a fixed number of tables open; when the capacity is exceeded, the least
+/// recently used table is closed (its file is closed once no iterator refers to it anymore). With
+/// `Options::memory_budget`, tables are also closed while the memory pinned by open tables exceeds
+/// the budget, except for the most recently used one.
+///
+/// Tables are opened on demand by an opener function, which maps a file number to a table (for
+/// example by calling `Table::new_from_file()` with a path derived from the number).
+pub struct TableCache {
+    opt: Options,
+    cache: Mutex<Cache<Table>>,
+    open: Box<TableOpener>,
+}
+
+impl TableCache {
+    /// Returns a cache holding up to `capacity` open tables, which are opened by `open` with the
+    /// given options. The minimal allowed capacity is 1. If `opt.memory_budget` is set, the
+    /// tables must be opened with it to be accounted for.
+    pub fn new<F>(opt: Options, capacity: usize, open: F) -> TableCache
+    where
+        F: Fn(u64, Options) -> Result<Table> + Send + Sync + 'static,
+    {
+        TableCache {
+            opt,
+            cache: Mutex::new(Cache::new(capacity)),
+            open: Box::new(open),
+        }
+    }
+
+    /// Returns the table with file number `file_no`, opening it if it isn't open yet.
+    pub fn get_table(&self, file_no: u64) -> Result<Table> {
+        let key = table_cache_key(file_no);
+        if let Some(t) = self.cache().get(&key) {
+            return Ok(t.clone());
+        }
+        event!(debug, file_no, "opening table");
+
+        // Don't hold the lock while opening the table; if another thread opened the same table in
+        // the meantime, the cache keeps the last one inserted.
+        let table = (self.open)(file_no, self.opt.clone())?;
+        let mut cache = self.cache();
+        cache.insert_charged(&key, table.clone(), table.pinned_memory());
+        if let Some(ref budget) = self.opt.memory_budget {
+            // Tables held by iterators release their memory only once these are dropped, so
+            // this may close more tables than necessary; the new table is closed last.
+            let excess = budget.pinned().saturating_sub(budget.limit());
+            if excess > 0 {
+                let usage = cache.usage().saturating_sub(excess);
+                cache.shrink_to(usage.max(table.pinned_memory()));
+            }
+        }
+        Ok(table)
+    }
+
+    /// Looks up `key` in the table with file number `file_no`. See `Table::get()`.
+    pub fn get(&self, file_no: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_table(file_no)?.get(key)
+    }
+
+    /// Returns an iterator over the table with file number `file_no`. The iterator keeps the table
+    /// open even if it is evicted from the cache.
+    pub fn iter(&self, file_no: u64) -> Result<TableIterator> {
+        Ok(self.get_table(file_no)?.iter())
+    }
+
+    /// Closes the table with file number `file_no`, e.g. after the file has been deleted.
+    pub fn evict(&self, file_no: u64) {
+        self.cache().remove(&table_cache_key(file_no));
+    }
+
+    /// Returns the number of open tables.
+    pub fn count(&self) -> usize {
+        self.cache().count()
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache<Table>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn table_cache_key(file_no: u64) -> CacheKey {
+    let mut dst = [0; 16];
+    (&mut dst[..8])
+        .write_fixedint(file_no)
+        .expect("error writing to vec");
+    dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryBudget;
+    use crate::error::Error;
+    use crate::table_builder::TableBuilder;
+    use crate::types::SSIterator;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn build_table(file_no: u64) -> Vec<u8> {
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(Options::default(), &mut d);
+            b.add(b"key", format!("val{}", file_no).as_bytes()).unwrap();
+            b.add(b"other", b"x").unwrap();
+            b.finish().unwrap();
+        }
+        d
+    }
+
+    fn make_cache(capacity: usize) -> (TableCache, Arc<AtomicUsize>) {
+        let opens = Arc::new(AtomicUsize::new(0));
+        let counter = opens.clone();
+        let cache = TableCache::new(Options::default(), capacity, move |file_no, opt| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if file_no >= 100 {
+                return Err(Error::InvalidArgument(format!("no table {}", file_no)));
+            }
+            let data = build_table(file_no);
+            let size = data.len() as u64;
+            Table::new(opt, Box::new(data), size)
+        });
+        (cache, opens)
+    }
+
+    #[test]
+    fn test_table_cache_get() {
+        let (cache, opens) = make_cache(2);
+
+        assert_eq!(cache.get(1, b"key").unwrap(), Some(b"val1".to_vec()));
+        assert_eq!(cache.get(1, b"nokey").unwrap(), None);
+        assert_eq!(cache.get(2, b"key").unwrap(), Some(b"val2".to_vec()));
+        assert_eq!(opens.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.count(), 2);
+
+        // Table 1 is the least recently used one and gets closed.
+        assert_eq!(cache.get(3, b"key").unwrap(), Some(b"val3".to_vec()));
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.get(2, b"key").unwrap(), Some(b"val2".to_vec()));
+        assert_eq!(opens.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.get(1, b"key").unwrap(), Some(b"val1".to_vec()));
+        assert_eq!(opens.load(Ordering::SeqCst), 4);
+
+        cache.evict(1);
+        assert_eq!(cache.count(), 1);
+        assert_eq!(cache.get(1, b"key").unwrap(), Some(b"val1".to_vec()));
+        assert_eq!(opens.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_table_cache_iter_outlives_eviction() {
+        let (cache, _) = make_cache(1);
+
+        let mut iter = cache.iter(7).unwrap();
+        cache.get(8, b"key").unwrap();
+        assert_eq!(cache.count(), 1);
+
+        assert!(iter.advance());
+        assert_eq!(iter.current_key(), Some(&b"key"[..]));
+        assert!(iter.advance());
+        assert!(!iter.advance());
+    }
+
+    #[test]
+    fn test_table_cache_memory_budget() {
+        let pinned = {
+            let data = build_table(0);
+            let size = data.len() as u64;
+            Table::new(Options::default(), Box::new(data), size)
+                .unwrap()
+                .pinned_memory()
+        };
+        let budget = Arc::new(MemoryBudget::new(3 * pinned));
+        let mut opt = Options::default();
+        opt.memory_budget = Some(budget.clone());
+        let cache = TableCache::new(opt, 10, |file_no, opt| {
+            let data = build_table(file_no);
+            let size = data.len() as u64;
+            Table::new(opt, Box::new(data), size)
+        });
+
+        for file_no in 0..3 {
+            cache.get(file_no, b"key").unwrap();
+        }
+        assert_eq!(cache.count(), 3);
+        assert_eq!(budget.pinned(), 3 * pinned);
+
+        // Table 0 is the least recently used one and gets closed.
+        cache.get(1, b"key").unwrap();
+        assert_eq!(cache.get(3, b"key").unwrap(), Some(b"val3".to_vec()));
+        assert_eq!(cache.count(), 3);
+        assert_eq!(budget.pinned(), 3 * pinned);
+        assert!(!cache.cache().contains(&table_cache_key(0)));
+
+        // An iterator keeps its table's memory pinned, so one more table is closed.
+        let iter = cache.iter(1).unwrap();
+        cache.evict(1);
+        cache.get(4, b"key").unwrap();
+        assert_eq!(cache.count(), 2);
+        drop(iter);
+        assert_eq!(budget.pinned(), 2 * pinned);
+    }
+
+    #[test]
+    fn test_table_cache_open_error() {
+        let (cache, _) = make_cache(1);
+        assert!(cache.get(100, b"key").is_err());
+        assert_eq!(cache.count(), 0);
+    }
+}
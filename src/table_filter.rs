@@ -0,0 +1,91 @@
+//! Whole-table filters (see `Options::table_filter_fp_rate`). Unlike the filter block, whose
+//! filters each cover the keys of a range of data blocks and are only checked once the index has
+//! been searched, a table filter is a single bloom filter over all keys of a table. Checking it
+//! takes a few memory accesses, making existence checks across many tables cheap (see
+//! `Table::key_may_exist()`).
+//!
+//! The meta block holds the filter in the format of `filter::BloomPolicy`.
+
+use crate::builder_state::{StateReader, StateWriter};
+use crate::error::Result;
+use crate::filter;
+
+/// Key of the table filter block in the metaindex block.
+pub const TABLE_FILTER_BLOCK_KEY: &str = "sstable.table_filter";
+
+/// Collects the hashes of all keys of a table while it's built.
+pub struct TableFilterBuilder {
+    bits_per_key: u32,
+    hashes: Vec<u32>,
+}
+
+impl TableFilterBuilder {
+    /// Returns a builder for a filter with a false positive rate of about `fp_rate`.
+    pub fn new(fp_rate: f64) -> TableFilterBuilder {
+        TableFilterBuilder {
+            bits_per_key: filter::bloom_bits_per_key(fp_rate),
+            hashes: vec![],
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8]) {
+        self.hashes.push(filter::bloom_hash(key));
+    }
+
+    /// Returns the approximate size of the finished filter.
+    pub fn size_estimate(&self) -> usize {
+        (self.hashes.len() * self.bits_per_key as usize).max(64) / 8 + 1
+    }
+
+    /// Serializes the state of the builder (see `TableBuilder::checkpoint()`).
+    pub fn save(&self, w: &mut StateWriter) {
+        w.put_u64(self.hashes.len() as u64);
+        for &h in &self.hashes {
+            w.put_u64(h as u64);
+        }
+    }
+
+    /// Restores a builder saved using `save()`.
+    pub fn restore(fp_rate: f64, r: &mut StateReader) -> Result<TableFilterBuilder> {
+        let mut b = TableFilterBuilder::new(fp_rate);
+        for _ in 0..r.get_u64()? {
+            b.hashes.push(r.get_u64()? as u32);
+        }
+        Ok(b)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        filter::bloom_filter_from_hashes(&self.hashes, self.bits_per_key)
+    }
+}
+
+/// Checks whether `key` may be among the keys of the table filter `filter`.
+pub fn key_may_match(filter: &[u8], key: &[u8]) -> bool {
+    filter::bloom_key_may_match(key, filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_filter_fp_rate() {
+        for &fp_rate in &[0.1, 0.01] {
+            let mut b = TableFilterBuilder::new(fp_rate);
+            for i in 0..10000 {
+                b.add(format!("key{}", i).as_bytes());
+            }
+            let f = b.finish();
+            assert!((0..10000).all(|i| key_may_match(&f, format!("key{}", i).as_bytes())));
+            let fps = (0..10000)
+                .filter(|i| key_may_match(&f, format!("other{}", i).as_bytes()))
+                .count();
+            assert!((fps as f64) < 10000.0 * fp_rate * 1.5, "{}", fps);
+        }
+        // An empty table filter matches no key.
+        assert!(!key_may_match(
+            &TableFilterBuilder::new(0.01).finish(),
+            b"abc"
+        ));
+    }
+}
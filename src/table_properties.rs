@@ -0,0 +1,355 @@
+//! Table properties: statistics and metadata about a table, collected by the `TableBuilder` and
+//! stored in the properties meta block.
+
+use crate::block::{Block, BlockContents};
+use crate::block_builder::BlockBuilder;
+use crate::cmp::DefaultCmp;
+use crate::coding;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::types::SSIterator;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use integer_encoding::VarInt;
+
+/// Key of the properties block in the metaindex block.
+pub const PROPERTIES_BLOCK_KEY: &str = "sstable.properties";
+/// Prefix of the properties written by this crate; user properties must not use it.
+pub const RESERVED_PREFIX: &str = "sstable.";
+
+const PROP_NUM_ENTRIES: &str = "sstable.num.entries";
+const PROP_NUM_DATA_BLOCKS: &str = "sstable.num.data.blocks";
+const PROP_RAW_KEY_SIZE: &str = "sstable.raw.key.size";
+const PROP_RAW_VALUE_SIZE: &str = "sstable.raw.value.size";
+const PROP_DATA_SIZE: &str = "sstable.data.size";
+const PROP_INDEX_SIZE: &str = "sstable.index.size";
+const PROP_INDEX_KEY_SIZE: &str = "sstable.index.key.size";
+const PROP_RAW_INDEX_KEY_SIZE: &str = "sstable.index.raw.key.size";
+const PROP_FILTER_SIZE: &str = "sstable.filter.size";
+const PROP_ENTRY_EXPIRATION: &str = "sstable.expiration.entries";
+const PROP_MAX_ENTRY_EXPIRATION: &str = "sstable.expiration.max";
+const PROP_TABLE_EXPIRATION: &str = "sstable.expiration.table";
+const PROP_BLOB_VALUES: &str = "sstable.blob.values";
+const PROP_NUM_BLOBS: &str = "sstable.blob.count";
+const PROP_BLOB_SIZE: &str = "sstable.blob.size";
+const PROP_VALUE_COMPRESSION: &str = "sstable.value.compression";
+const PROP_VALUE_CODEC: &str = "sstable.value.codec";
+const PROP_KEY_CODEC: &str = "sstable.key.codec";
+const PROP_VALUE_CHECKSUMS: &str = "sstable.value.checksums";
+const PROP_CHECKSUMMER: &str = "sstable.checksummer";
+const PROP_NUM_DELETIONS: &str = "sstable.num.deletions";
+const PROP_NUM_OVERWRITTEN: &str = "sstable.num.overwritten";
+const PROP_RESTART_INTERVALS: &str = "sstable.restart.intervals";
+const PROP_HOT_RANGES: &str = "sstable.hot.ranges";
+
+/// TableProperties describes a table. They are written by every `TableBuilder`; tables written by
+/// older versions of this crate don't have properties.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableProperties {
+    pub num_entries: u64,
+    pub num_data_blocks: u64,
+    /// Sum of the sizes of all keys added to the table.
+    pub raw_key_size: u64,
+    /// Sum of the sizes of all values added to the table.
+    pub raw_value_size: u64,
+    /// Size of all data blocks as stored in the file.
+    pub data_size: u64,
+    /// Size of the index block before compression.
+    pub index_size: u64,
+    /// Sum of the sizes of the index block's keys (see `Options::index_shortening`).
+    pub index_key_size: u64,
+    /// Sum of the sizes of the data blocks' last keys, i.e. of the index keys had they not been
+    /// shortened. The difference to `index_key_size` is what shortening saved.
+    pub raw_index_key_size: u64,
+    pub filter_size: u64,
+    /// Whether entries carry an expiration timestamp (see `Options::entry_expiration`).
+    pub entry_expiration: bool,
+    /// The latest expiration timestamp of all entries, if every entry has one.
+    pub max_entry_expiration: Option<u64>,
+    /// The expiration timestamp of the whole table, if set using `TableBuilder::set_expiration()`.
+    pub table_expiration: Option<u64>,
+    /// Whether large values are stored separately from the data blocks (see
+    /// `Options::blob_threshold`).
+    pub blob_values: bool,
+    pub num_blobs: u64,
+    /// Size of all blobs as stored in the file.
+    pub blob_size: u64,
+    /// Whether values are compressed individually (see `Options::value_compression`).
+    pub value_compression: bool,
+    /// The name of the `ValueCodec` values are encoded with (see `Options::value_codec`).
+    pub value_codec: Option<String>,
+    /// The name of the `KeyCodec` keys are encoded with (see `Options::key_codec`).
+    pub key_codec: Option<String>,
+    /// Whether values are stored with a checksum (see `Options::value_checksums`).
+    pub value_checksums: bool,
+    /// The name of the `Checksummer` of tables with custom block checksums (see
+    /// `Options::checksummer`).
+    pub checksummer: Option<String>,
+    /// The number of deletions (tombstones) in tables of internal keys (see
+    /// `Cmp::internal_keys()`).
+    pub num_deletions: Option<u64>,
+    /// The number of values in tables of internal keys that are shadowed by a newer version (or
+    /// deletion) of their key in the same table.
+    pub num_overwritten: Option<u64>,
+    /// The number of data blocks per restart interval, in tables built with
+    /// `Options::auto_restart_interval`; empty otherwise.
+    pub restart_intervals: BTreeMap<u64, u64>,
+    /// The byte ranges (offset and length) of the runs of consecutive hot data blocks, including
+    /// their trailers, in file order (see `TableBuilder::set_temperature()`).
+    pub hot_ranges: Vec<(u64, u64)>,
+    /// Properties added by `TablePropertiesCollector`s.
+    pub user_properties: BTreeMap<String, Vec<u8>>,
+}
+
+/// A TablePropertiesCollector gathers custom properties while a table is built. It is notified
+/// of every entry added to the table, and the properties returned by `finish()` are stored in the
+/// table's properties block, from where they can be read using `Table::user_properties()`.
+pub trait TablePropertiesCollector: Send {
+    /// Called for every entry added to the table, in order.
+    fn add(&mut self, key: &[u8], value: &[u8]);
+    /// Called once all entries have been added; returns the properties to store. Property names
+    /// must not start with "sstable.".
+    fn finish(&mut self) -> Vec<(String, Vec<u8>)>;
+}
+
+/// Creates a new `TablePropertiesCollector` for every table that is built. Factories are
+/// registered in `Options::properties_collectors`.
+pub type TablePropertiesCollectorFactory =
+    Arc<dyn Fn() -> Box<dyn TablePropertiesCollector> + Send + Sync>;
+
+impl TableProperties {
+    /// Returns true if all entries of the table have expired at time `now`, i.e. the table can be
+    /// dropped.
+    pub fn is_fully_expired(&self, now: u64) -> bool {
+        self.table_expiration.is_some_and(|t| t <= now)
+            || self.max_entry_expiration.is_some_and(|t| t <= now)
+    }
+
+    /// Returns the fraction of entries that are deletions or overwritten values, i.e. that a
+    /// compaction could drop, or None if the table doesn't hold internal keys.
+    pub fn garbage_ratio(&self) -> Option<f64> {
+        let garbage = self.num_deletions? + self.num_overwritten?;
+        if self.num_entries == 0 {
+            return Some(0.0);
+        }
+        Some(garbage as f64 / self.num_entries as f64)
+    }
+
+    /// Encodes the properties as block.
+    pub fn encode(&self, opt: &Options) -> BlockContents {
+        let mut props: BTreeMap<&str, Vec<u8>> = self
+            .user_properties
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect();
+        props.insert(PROP_NUM_ENTRIES, self.num_entries.encode_var_vec());
+        props.insert(PROP_NUM_DATA_BLOCKS, self.num_data_blocks.encode_var_vec());
+        props.insert(PROP_RAW_KEY_SIZE, self.raw_key_size.encode_var_vec());
+        props.insert(PROP_RAW_VALUE_SIZE, self.raw_value_size.encode_var_vec());
+        props.insert(PROP_DATA_SIZE, self.data_size.encode_var_vec());
+        props.insert(PROP_INDEX_SIZE, self.index_size.encode_var_vec());
+        props.insert(PROP_INDEX_KEY_SIZE, self.index_key_size.encode_var_vec());
+        props.insert(
+            PROP_RAW_INDEX_KEY_SIZE,
+            self.raw_index_key_size.encode_var_vec(),
+        );
+        props.insert(PROP_FILTER_SIZE, self.filter_size.encode_var_vec());
+        if self.entry_expiration {
+            props.insert(PROP_ENTRY_EXPIRATION, 1_u64.encode_var_vec());
+        }
+        if let Some(t) = self.max_entry_expiration {
+            props.insert(PROP_MAX_ENTRY_EXPIRATION, t.encode_var_vec());
+        }
+        if let Some(t) = self.table_expiration {
+            props.insert(PROP_TABLE_EXPIRATION, t.encode_var_vec());
+        }
+        if self.value_compression {
+            props.insert(PROP_VALUE_COMPRESSION, 1_u64.encode_var_vec());
+        }
+        if let Some(ref name) = self.value_codec {
+            props.insert(PROP_VALUE_CODEC, name.as_bytes().to_vec());
+        }
+        if let Some(ref name) = self.key_codec {
+            props.insert(PROP_KEY_CODEC, name.as_bytes().to_vec());
+        }
+        if self.value_checksums {
+            props.insert(PROP_VALUE_CHECKSUMS, 1_u64.encode_var_vec());
+        }
+        if let Some(ref name) = self.checksummer {
+            props.insert(PROP_CHECKSUMMER, name.as_bytes().to_vec());
+        }
+        if let Some(n) = self.num_deletions {
+            props.insert(PROP_NUM_DELETIONS, n.encode_var_vec());
+        }
+        if let Some(n) = self.num_overwritten {
+            props.insert(PROP_NUM_OVERWRITTEN, n.encode_var_vec());
+        }
+        if !self.restart_intervals.is_empty() {
+            let mut v = vec![];
+            for (&interval, &blocks) in self.restart_intervals.iter() {
+                coding::put_varint64(&mut v, interval);
+                coding::put_varint64(&mut v, blocks);
+            }
+            props.insert(PROP_RESTART_INTERVALS, v);
+        }
+        if !self.hot_ranges.is_empty() {
+            let mut v = vec![];
+            for &(offset, len) in self.hot_ranges.iter() {
+                coding::put_varint64(&mut v, offset);
+                coding::put_varint64(&mut v, len);
+            }
+            props.insert(PROP_HOT_RANGES, v);
+        }
+        if self.blob_values {
+            props.insert(PROP_BLOB_VALUES, 1_u64.encode_var_vec());
+            props.insert(PROP_NUM_BLOBS, self.num_blobs.encode_var_vec());
+            props.insert(PROP_BLOB_SIZE, self.blob_size.encode_var_vec());
+        }
+
+        let mut block = BlockBuilder::new(meta_options(opt));
+        for (k, v) in props.iter() {
+            block.add(k.as_bytes(), v);
+        }
+        block.finish()
+    }
+
+    /// Decodes properties from a block written by `encode()`. Unknown properties are ignored.
+    pub fn decode(block: &Block) -> Result<TableProperties> {
+        let mut props = TableProperties::default();
+        let mut iter = block.iter();
+        let (mut key, mut val) = (vec![], vec![]);
+        while iter.advance() {
+            iter.current(&mut key, &mut val);
+            let num = || coding::get_varint64(&val).map(|(n, _)| n);
+            match std::str::from_utf8(&key) {
+                Ok(PROP_NUM_ENTRIES) => props.num_entries = num()?,
+                Ok(PROP_NUM_DATA_BLOCKS) => props.num_data_blocks = num()?,
+                Ok(PROP_RAW_KEY_SIZE) => props.raw_key_size = num()?,
+                Ok(PROP_RAW_VALUE_SIZE) => props.raw_value_size = num()?,
+                Ok(PROP_DATA_SIZE) => props.data_size = num()?,
+                Ok(PROP_INDEX_SIZE) => props.index_size = num()?,
+                Ok(PROP_INDEX_KEY_SIZE) => props.index_key_size = num()?,
+                Ok(PROP_RAW_INDEX_KEY_SIZE) => props.raw_index_key_size = num()?,
+                Ok(PROP_FILTER_SIZE) => props.filter_size = num()?,
+                Ok(PROP_ENTRY_EXPIRATION) => props.entry_expiration = num()? != 0,
+                Ok(PROP_MAX_ENTRY_EXPIRATION) => props.max_entry_expiration = Some(num()?),
+                Ok(PROP_TABLE_EXPIRATION) => props.table_expiration = Some(num()?),
+                Ok(PROP_BLOB_VALUES) => props.blob_values = num()? != 0,
+                Ok(PROP_NUM_BLOBS) => props.num_blobs = num()?,
+                Ok(PROP_BLOB_SIZE) => props.blob_size = num()?,
+                Ok(PROP_NUM_DELETIONS) => props.num_deletions = Some(num()?),
+                Ok(PROP_NUM_OVERWRITTEN) => props.num_overwritten = Some(num()?),
+                Ok(PROP_VALUE_COMPRESSION) => props.value_compression = num()? != 0,
+                Ok(PROP_VALUE_CHECKSUMS) => props.value_checksums = num()? != 0,
+                Ok(PROP_RESTART_INTERVALS) => {
+                    let mut src = &val[..];
+                    while !src.is_empty() {
+                        let (interval, n) = coding::get_varint64(src)?;
+                        let (blocks, m) = coding::get_varint64(&src[n..])?;
+                        props.restart_intervals.insert(interval, blocks);
+                        src = &src[n + m..];
+                    }
+                }
+                Ok(PROP_HOT_RANGES) => {
+                    let mut src = &val[..];
+                    while !src.is_empty() {
+                        let (offset, n) = coding::get_varint64(src)?;
+                        let (len, m) = coding::get_varint64(&src[n..])?;
+                        props.hot_ranges.push((offset, len));
+                        src = &src[n + m..];
+                    }
+                }
+                Ok(PROP_VALUE_CODEC) => {
+                    props.value_codec = Some(
+                        String::from_utf8(val.clone())
+                            .map_err(|_| Error::malformed(None, "bad value codec name"))?,
+                    )
+                }
+                Ok(PROP_KEY_CODEC) => {
+                    props.key_codec = Some(
+                        String::from_utf8(val.clone())
+                            .map_err(|_| Error::malformed(None, "bad key codec name"))?,
+                    )
+                }
+                Ok(PROP_CHECKSUMMER) => {
+                    props.checksummer = Some(
+                        String::from_utf8(val.clone())
+                            .map_err(|_| Error::malformed(None, "bad checksummer name"))?,
+                    )
+                }
+                Ok(name) if !name.starts_with(RESERVED_PREFIX) => {
+                    props.user_properties.insert(name.to_string(), val.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok(props)
+    }
+}
+
+/// Returns the options used for meta blocks (the metaindex and properties blocks), which are
+/// always ordered bytewise, independent of the comparator used for the table's keys.
+pub fn meta_options(opt: &Options) -> Options {
+    let mut opt = opt.clone();
+    opt.cmp = Arc::new(Box::new(DefaultCmp));
+    opt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_properties_encode_decode() {
+        let props = TableProperties {
+            num_entries: 12,
+            num_data_blocks: 3,
+            raw_key_size: 120,
+            raw_value_size: 1 << 40,
+            data_size: 4096,
+            index_size: 55,
+            index_key_size: 30,
+            raw_index_key_size: 42,
+            filter_size: 0,
+            entry_expiration: true,
+            max_entry_expiration: Some(1_600_000_000),
+            table_expiration: None,
+            blob_values: true,
+            num_blobs: 2,
+            blob_size: 1 << 21,
+            value_compression: true,
+            value_codec: Some("fixed".to_string()),
+            key_codec: Some("escaped".to_string()),
+            value_checksums: true,
+            checksummer: Some("crc64".to_string()),
+            num_deletions: Some(3),
+            num_overwritten: Some(0),
+            restart_intervals: vec![(4, 10), (16, 1)].into_iter().collect(),
+            hot_ranges: vec![(0, 4101), (8200, 100)],
+            user_properties: vec![
+                ("a.min".to_string(), b"abc".to_vec()),
+                ("z".to_string(), vec![]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let opt = Options::default();
+        let block = Block::new(opt.clone(), props.encode(&opt));
+        assert_eq!(TableProperties::decode(&block).unwrap(), props);
+    }
+
+    #[test]
+    fn test_table_properties_expired() {
+        let mut props = TableProperties::default();
+        assert!(!props.is_fully_expired(u64::MAX));
+        props.max_entry_expiration = Some(100);
+        assert!(!props.is_fully_expired(99));
+        assert!(props.is_fully_expired(100));
+        props.max_entry_expiration = None;
+        props.table_expiration = Some(50);
+        assert!(props.is_fully_expired(50));
+        assert!(!props.is_fully_expired(49));
+    }
+}
@@ -1,667 +1,5343 @@
-use crate::block::{Block, BlockIter};
+use crate::blob::{self, StoredValue};
+use crate::block::{Block, BlockContents, BlockIter};
+use crate::block_stats::{decode_block_stats, BlockPruner, BLOCK_STATS_BLOCK_KEY};
 use crate::blockhandle::BlockHandle;
 use crate::cache;
-use crate::error::Result;
+use crate::checksum::{
+    self, Crc32c, FileChecksum, LevelDbCrc32c, CRC32C_NAME, FILE_CHECKSUM_BLOCK_KEY,
+};
+use crate::cmp::Cmp;
+use crate::error::{CorruptionKind, Error, Result};
+use crate::filter::{self, BoxedFilterPolicy, FilterPolicy, NoFilterPolicy};
 use crate::filter_block::FilterBlockReader;
-use crate::options::Options;
+use crate::hash_index::{HashIndex, HashLookup, HASH_INDEX_BLOCK_KEY};
+use crate::key_codec::{self, BoxedKeyCodec};
+use crate::key_types::{self, SequenceNumber, ValueType};
+use crate::manifest::BlockChecksum;
+use crate::options::{self, CompressionType, Options, ReadOptions};
 use crate::table_block;
 use crate::table_builder::{self, Footer};
+use crate::table_filter::{self, TABLE_FILTER_BLOCK_KEY};
+use crate::table_properties::{meta_options, TableProperties, PROPERTIES_BLOCK_KEY};
+use crate::ttl;
+use crate::two_level::{BlockFunction, TwoLevelIterator};
 use crate::types::{current_key_val, RandomAccess, SSIterator};
+use crate::value_codec;
+use crate::value_compression;
+use crate::value_reader::ValueReader;
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+#[cfg(any(unix, windows))]
 use std::fs;
+use std::ops::Deref;
+#[cfg(any(unix, windows))]
 use std::path;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::thread;
 
 use integer_encoding::FixedIntWriter;
 
+/// The size of the reads of `Table::verify_file_checksum()`.
+const FILE_CHECKSUM_READ_SIZE: usize = 64 * 1024;
+
+/// Decodes a block handle stored in the index or metaindex block of a table, which `Table::new()`
+/// has validated.
+fn index_handle(encoded: &[u8]) -> BlockHandle {
+    BlockHandle::decode(encoded)
+        .expect("block handles are validated when opening a table")
+        .0
+}
+
+/// Fails if any value of `block` isn't a valid block handle of a block (including its trailer)
+/// within a file of `file_size` bytes.
+fn validate_handles(block: &Block, file_size: u64) -> Result<()> {
+    let mut iter = block.iter();
+    while iter.advance() {
+        let (handle, _) = BlockHandle::decode(iter.current_value().unwrap())?;
+        table_block::check_block_in_file(&handle, file_size)?;
+    }
+    Ok(())
+}
+
 /// Reads the table footer.
-fn read_footer(f: &dyn RandomAccess, size: usize) -> Result<Footer> {
+fn read_footer(f: &dyn RandomAccess, size: u64, magic_number: u64) -> Result<Footer> {
+    if size < table_builder::FULL_FOOTER_LENGTH as u64 {
+        return Err(Error::malformed(None, "file too short to contain a footer"));
+    }
+    let footer_offset = size - table_builder::FULL_FOOTER_LENGTH as u64;
     let mut buf = vec![0; table_builder::FULL_FOOTER_LENGTH];
-    f.read_at(size - table_builder::FULL_FOOTER_LENGTH, &mut buf)?;
-    Ok(Footer::decode(&buf))
+    f.read_at(footer_offset, &mut buf)?;
+    let footer = Footer::decode(&buf, magic_number)?;
+    // The blocks referenced by the footer precede it.
+    table_block::check_block_in_file(&footer.index, footer_offset)?;
+    table_block::check_block_in_file(&footer.meta_index, footer_offset)?;
+    Ok(footer)
 }
 
 /// `Table` is used for accessing SSTables.
+///
+/// A table holds its file open for as long as it, one of its clones or one of its iterators
+/// exists, and reads only through that handle. Unlinking or replacing the file at its path, e.g.
+/// when compaction rewrites it, doesn't affect them: they keep reading the contents the table was
+/// opened with (files opened by the standard library allow this on Windows, too). `Table::pin()`
+/// keeps the file open without the rest of the table.
 #[derive(Clone)]
 pub struct Table {
     file: Arc<Box<dyn RandomAccess>>,
-    file_size: usize,
+    file_size: u64,
     cache_id: cache::CacheID,
+    compressed_cache_id: Option<cache::CacheID>,
 
     opt: Options,
 
     footer: Footer,
     index_block: Block,
+    metaindex_block: Block,
     filters: Option<FilterBlockReader>,
+    properties: Option<Arc<TableProperties>>,
+    block_stats: Option<Arc<HashMap<u64, Vec<u8>>>>,
+    hash_index: Option<Arc<HashIndex>>,
+    table_filter: Option<Arc<Vec<u8>>>,
+    // The table's pinned memory in `opt.memory_budget`, released with the last clone.
+    memory_reservation: Option<Arc<cache::MemoryReservation>>,
 }
 
 impl Table {
     /// Creates a new table reader from a file at `path`.
+    #[cfg(any(unix, windows))]
     pub fn new_from_file(opt: Options, path: &path::Path) -> Result<Table> {
         let f = fs::OpenOptions::new().read(true).open(path)?;
-        let size = f.metadata()?.len() as usize;
+        let size = f.metadata()?.len();
         Table::new(opt, Box::new(f), size)
     }
 
     /// Creates a new table reader.
-    pub fn new(opt: Options, file: Box<dyn RandomAccess>, size: usize) -> Result<Table> {
-        let footer = read_footer(file.as_ref(), size)?;
-        let index_block = table_block::read_table_block(opt.clone(), file.as_ref(), &footer.index)?;
-        let metaindex_block =
-            table_block::read_table_block(opt.clone(), file.as_ref(), &footer.meta_index)?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", target = "sstable", skip(opt, file))
+    )]
+    pub fn new(mut opt: Options, file: Box<dyn RandomAccess>, size: u64) -> Result<Table> {
+        let footer = read_footer(file.as_ref(), size, opt.table_magic_number)?;
+        // From here on, the options carry the checksummer of the table.
+        if footer.checksum_type == table_builder::CHECKSUM_CRC32C {
+            // LevelDB masks its checksums, which this crate doesn't.
+            let leveldb = footer.format_version == 0
+                && checksum::is_leveldb_masked(&table_block::read_block_with_trailer(
+                    file.as_ref(),
+                    size,
+                    &footer.index,
+                )?)?;
+            opt.checksummer = if leveldb {
+                Arc::new(LevelDbCrc32c)
+            } else {
+                Arc::new(Crc32c)
+            };
+        } else if checksum::is_default(opt.checksummer.as_ref()) {
+            return Err(Error::InvalidArgument(
+                "table has custom checksums, but no checksummer is configured".to_string(),
+            ));
+        }
+        let index_block =
+            table_block::read_table_block(opt.clone(), file.as_ref(), size, &footer.index)?;
+        let metaindex_block = table_block::read_table_block(
+            meta_options(&opt),
+            file.as_ref(),
+            size,
+            &footer.meta_index,
+        )?;
+        validate_handles(&index_block, size)?;
+        validate_handles(&metaindex_block, size)?;
 
-        let filter_block_reader = Table::read_filter_block(&metaindex_block, file.as_ref(), &opt)?;
-        let cache_id = {
-            let mut block_cache = opt.block_cache.write()?;
-            block_cache.new_cache_id()
+        let filter_block_reader =
+            Table::read_filter_block(&metaindex_block, file.as_ref(), size, &opt)?;
+        let properties = Table::read_properties(&metaindex_block, file.as_ref(), size, &opt)?;
+        if let Some(name) = properties.as_ref().and_then(|p| p.value_codec.as_ref()) {
+            if opt.value_codec.as_ref().map(|c| c.name()) != Some(name.as_str()) {
+                return Err(Error::InvalidArgument(format!(
+                    "table values are encoded with value codec {}, which isn't configured",
+                    name
+                )));
+            }
+        }
+        // From here on, the options carry a key codec only if the table's keys are encoded.
+        match properties.as_ref().and_then(|p| p.key_codec.as_ref()) {
+            Some(name) if opt.key_codec.as_ref().map(|c| c.name()) != Some(name.as_str()) => {
+                return Err(Error::InvalidArgument(format!(
+                    "table keys are encoded with key codec {}, which isn't configured",
+                    name
+                )));
+            }
+            Some(_) => {}
+            None => opt.key_codec = None,
+        }
+        // Version 0 footers predate custom checksummers.
+        if let Some(props) = properties.as_ref().filter(|_| footer.format_version > 0) {
+            let name = props.checksummer.as_deref().unwrap_or(CRC32C_NAME);
+            if opt.checksummer.name() != name {
+                return Err(Error::InvalidArgument(format!(
+                    "table is checksummed with {}, but {} is configured",
+                    name,
+                    opt.checksummer.name()
+                )));
+            }
+        }
+        let block_stats = match Table::find_meta_block(&metaindex_block, BLOCK_STATS_BLOCK_KEY) {
+            Some(location) => {
+                let block = table_block::read_table_block(
+                    meta_options(&opt),
+                    file.as_ref(),
+                    size,
+                    &location,
+                )?;
+                Some(Arc::new(decode_block_stats(&block)?))
+            }
+            None => None,
+        };
+        let hash_index = match (
+            &opt.prefix_extractor,
+            Table::find_meta_block(&metaindex_block, HASH_INDEX_BLOCK_KEY),
+        ) {
+            (Some(extractor), Some(location)) => {
+                let contents = table_block::read_blob(file.as_ref(), size, &opt, &location)?;
+                HashIndex::decode(&contents, &index_block, extractor)?.map(Arc::new)
+            }
+            _ => None,
+        };
+        let table_filter = match Table::find_meta_block(&metaindex_block, TABLE_FILTER_BLOCK_KEY) {
+            Some(location) => Some(Arc::new(table_block::read_blob(
+                file.as_ref(),
+                size,
+                &opt,
+                &location,
+            )?)),
+            None => None,
+        };
+        let file_id = file.file_id();
+        let cache_id = match file_id {
+            Some(id) => cache::stable_cache_id(id),
+            None => opt.block_cache.new_cache_id(),
+        };
+        let compressed_cache_id = match (&opt.compressed_block_cache, file_id) {
+            (Some(_), Some(id)) => Some(cache::stable_cache_id(id)),
+            (Some(c), None) => Some(c.write()?.new_cache_id()),
+            (None, _) => None,
         };
 
-        Ok(Table {
+        let mut table = Table {
             file: Arc::new(file),
             file_size: size,
-            cache_id: cache_id,
-            opt: opt,
-            footer: footer,
+            cache_id,
+            compressed_cache_id,
+            opt,
+            footer,
             filters: filter_block_reader,
-            index_block: index_block,
-        })
+            index_block,
+            metaindex_block,
+            properties: properties.map(Arc::new),
+            block_stats,
+            hash_index,
+            table_filter,
+            memory_reservation: None,
+        };
+        if let Some(ref budget) = table.opt.memory_budget {
+            table.memory_reservation = Some(Arc::new(budget.reserve(table.pinned_memory())));
+        }
+        Ok(table)
+    }
+
+    /// Returns the approximate number of bytes of memory used by the table: its index and
+    /// metaindex blocks, filters, block statistics and hash index, which are held for as long as
+    /// the table is open, plus its blocks in the block caches. Blocks held only by iterators (or
+    /// pinned values) aren't included. Counting the cached blocks walks the caches, so this
+    /// isn't meant to be called on hot paths.
+    pub fn approximate_memory_usage(&self) -> usize {
+        let mut usage = self.pinned_memory() + self.opt.block_cache.charge_of(self.cache_id);
+        if let (Some(cache), Some(id)) =
+            (&self.opt.compressed_block_cache, self.compressed_cache_id)
+        {
+            if let Ok(cache) = cache.read() {
+                usage += cache.usage_of(id);
+            }
+        }
+        usage
+    }
+
+    /// Returns the memory held by the table itself, excluding cached blocks.
+    pub(crate) fn pinned_memory(&self) -> usize {
+        let mut size = self.index_block.contents().len() + self.metaindex_block.contents().len();
+        if let Some(ref filters) = self.filters {
+            size += filters.size();
+        }
+        if let Some(ref table_filter) = self.table_filter {
+            size += table_filter.len();
+        }
+        if let Some(ref stats) = self.block_stats {
+            size += stats.values().map(|s| s.len() + 8).sum::<usize>();
+        }
+        if let Some(ref hash_index) = self.hash_index {
+            size += hash_index.approximate_memory_usage();
+        }
+        size
+    }
+
+    /// Inserts a data block into the block cache, shrinking the cache if it exceeds the memory
+    /// budget.
+    fn cache_block(&self, location: &BlockHandle, block: &Block) {
+        let charge = block.contents().len();
+        self.opt.block_cache.insert(
+            &self.block_cache_handle(location.offset()),
+            block.clone(),
+            charge,
+        );
+        if let Some(ref budget) = self.opt.memory_budget {
+            budget.enforce(self.opt.block_cache.as_ref());
+        }
+    }
+
+    /// Returns the options the table was opened with, carrying the table's checksummer.
+    pub fn options(&self) -> &Options {
+        &self.opt
+    }
+
+    /// Returns the size of the table's file.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Returns a guard keeping the table's file open until it's dropped, from which the table can
+    /// be opened again (see `TablePin::open()`) even after its path refers to another file. Unlike
+    /// a clone of the table, a pin doesn't hold the index, filters and other blocks in memory.
+    pub fn pin(&self) -> TablePin {
+        TablePin {
+            file: self.file.clone(),
+            size: self.file_size,
+        }
+    }
+
+    /// Returns the file the table is read from.
+    pub(crate) fn file(&self) -> &dyn RandomAccess {
+        self.file.as_ref().as_ref()
+    }
+
+    /// Identifies the table by `id` in the block caches instead of by its file's ID (see
+    /// `RandomAccess::file_id()`) or, if it has none, by an ID unique to this `Table` instance.
+    /// Tables with the same ID share cached blocks, so `id` must identify the table's contents,
+    /// e.g. a file number that is never reused. This way, a table reopened later finds the blocks
+    /// cached by earlier instances.
+    pub fn with_cache_id(mut self, id: u64) -> Table {
+        self.cache_id = cache::stable_cache_id(id);
+        if self.compressed_cache_id.is_some() {
+            self.compressed_cache_id = Some(self.cache_id);
+        }
+        self
     }
 
     fn read_filter_block(
         metaix: &Block,
         file: &dyn RandomAccess,
+        file_size: u64,
         options: &Options,
     ) -> Result<Option<FilterBlockReader>> {
         // Open filter block for reading
-        let filter_name = format!("filter.{}", options.filter_policy.name())
-            .as_bytes()
-            .to_vec();
-
-        let mut metaindexiter = metaix.iter();
-        metaindexiter.seek(&filter_name);
-
-        if let Some((_key, val)) = current_key_val(&metaindexiter) {
-            let filter_block_location = BlockHandle::decode(&val).0;
-            if filter_block_location.size() > 0 {
-                return Ok(Some(table_block::read_filter_block(
-                    file,
-                    &filter_block_location,
-                    options.filter_policy.clone(),
-                )?));
-            }
+        if let Some((location, policy)) = Table::find_filter_block(metaix, options) {
+            return Ok(Some(table_block::read_filter_block(
+                file, file_size, &location, policy,
+            )?));
         }
         Ok(None)
     }
 
-    /// block_cache_handle creates a CacheKey for a block with a given offset to be used in the
-    /// block cache.
-    fn block_cache_handle(&self, block_off: usize) -> cache::CacheKey {
-        let mut dst = [0; 2 * 8];
-        (&mut dst[..8])
-            .write_fixedint(self.cache_id)
-            .expect("error writing to vec");
-        (&mut dst[8..])
-            .write_fixedint(block_off as u64)
-            .expect("error writing to vec");
-        dst
+    /// Returns the location of the table's filter block and the policy to read it with: the
+    /// options' filter policy if the table has a filter block written by it, otherwise one of the
+    /// builtin policies of LevelDB or RocksDB (see `filter::builtin_policy()`).
+    fn find_filter_block(
+        metaix: &Block,
+        options: &Options,
+    ) -> Option<(BlockHandle, BoxedFilterPolicy)> {
+        // A NoFilterPolicy writes empty filters, which would rule out every key.
+        let policy = options.filter_policy.as_ref().as_ref();
+        let configured = Some(filter::meta_block_name(policy))
+            .filter(|_| policy.name() != NoFilterPolicy.name())
+            .and_then(|name| Table::find_meta_block(metaix, &name))
+            .map(|location| (location, options.filter_policy.clone()));
+        configured
+            .or_else(|| {
+                filter::BUILTIN_FILTER_META_BLOCKS.iter().find_map(|name| {
+                    let location = Table::find_meta_block(metaix, name)?;
+                    event!(debug, filter = *name, "using builtin filter policy");
+                    Some((location, filter::builtin_policy(name)?))
+                })
+            })
+            .filter(|(location, _)| location.size() > 0)
     }
 
-    /// Read a block from the current table at `location`, and cache it in the options' block
-    /// cache.
-    fn read_block(&self, location: &BlockHandle) -> Result<Block> {
-        let cachekey = self.block_cache_handle(location.offset());
-        let mut block_cache = self.opt.block_cache.write()?;
-        if let Some(block) = block_cache.get(&cachekey) {
-            return Ok(block.clone());
-        }
-
-        // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
-        let b =
-            table_block::read_table_block(self.opt.clone(), self.file.as_ref().as_ref(), location)?;
+    /// Returns the location of the table's filter block and the policy it was written with, if
+    /// the table has one. Together with `FilterBlockReader::read()`, this allows keeping only the
+    /// filters of a table in memory.
+    pub fn filter_block_handle(&self) -> Option<(BlockHandle, BoxedFilterPolicy)> {
+        Table::find_filter_block(&self.metaindex_block, &self.opt)
+    }
 
-        // insert a cheap copy (Arc).
-        block_cache.insert(&cachekey, b.clone());
+    /// Returns the location of the meta block stored under `name` in the metaindex block.
+    fn find_meta_block(metaix: &Block, name: &str) -> Option<BlockHandle> {
+        let mut metaindexiter = metaix.iter();
+        metaindexiter.seek(name.as_bytes());
 
-        Ok(b)
+        match current_key_val(&metaindexiter) {
+            Some((key, val)) if key == name.as_bytes() => Some(index_handle(&val)),
+            _ => None,
+        }
     }
 
-    /// Returns the offset of the block that contains `key`.
-    pub fn approx_offset_of(&self, key: &[u8]) -> usize {
-        let mut iter = self.index_block.iter();
-
-        iter.seek(key);
+    /// Returns an iterator over the metaindex block. Its keys are the names of the table's meta
+    /// blocks, its values their encoded `BlockHandle`s.
+    pub fn metaindex_iter(&self) -> BlockIter {
+        self.metaindex_block.iter()
+    }
 
-        if let Some((_, val)) = current_key_val(&iter) {
-            let location = BlockHandle::decode(&val).0;
-            return location.offset();
+    /// Returns the names of all meta blocks, including ones this crate doesn't know about (e.g.
+    /// written by other implementations).
+    pub fn meta_block_names(&self) -> Vec<String> {
+        let mut iter = self.metaindex_iter();
+        let mut names = vec![];
+        while iter.advance() {
+            if let Some(key) = iter.current_key() {
+                names.push(String::from_utf8_lossy(key).into_owned());
+            }
         }
-
-        self.footer.meta_index.offset()
+        names
     }
 
-    /// Returns an iterator over an SSTable. Iterators hold internal references to the table, so
-    /// make sure to let them expire when not needed anymore.
-    pub fn iter(&self) -> TableIterator {
-        TableIterator {
-            current_block: None,
-            current_block_off: 0,
-            index_block: self.index_block.iter(),
-            table: self.clone(),
+    /// Reads the meta block stored under `name`, returning its uncompressed contents after
+    /// verifying its checksum.
+    pub fn read_meta_block(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match Table::find_meta_block(&self.metaindex_block, name) {
+            Some(location) => Ok(Some(table_block::read_blob(
+                self.file.as_ref().as_ref(),
+                self.file_size,
+                &self.opt,
+                &location,
+            )?)),
+            None => Ok(None),
         }
     }
 
-    /// Retrieve an entry for a key from the table. This function uses the attached filters, so
-    /// is better suited if you frequently look for non-existing values (as it will detect the
-    /// non-existence of an entry in a block without having to load the block).
-    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let mut index_iter = self.index_block.iter();
-        index_iter.seek(key);
+    fn read_properties(
+        metaix: &Block,
+        file: &dyn RandomAccess,
+        file_size: u64,
+        options: &Options,
+    ) -> Result<Option<TableProperties>> {
+        match Table::find_meta_block(metaix, PROPERTIES_BLOCK_KEY) {
+            Some(location) => {
+                let block = table_block::read_table_block(
+                    meta_options(options),
+                    file,
+                    file_size,
+                    &location,
+                )?;
+                Ok(Some(TableProperties::decode(&block)?))
+            }
+            None => Ok(None),
+        }
+    }
 
-        let handle;
-        if let Some((last_in_block, h)) = current_key_val(&index_iter) {
-            if self.opt.cmp.cmp(key, &last_in_block) == Ordering::Less {
-                handle = BlockHandle::decode(&h).0;
-            } else {
-                return Ok(None);
+    /// Returns the location and checksum of every block of the table: the index and metaindex
+    /// blocks, all meta blocks, all data blocks and, for tables written with
+    /// `Options::blob_threshold`, all blobs. The entries are ordered by offset. Every block is
+    /// read from the file (bypassing the block cache), so this takes as long as reading the whole
+    /// table.
+    pub fn checksum_manifest(&self) -> Result<Vec<BlockChecksum>> {
+        let mut handles = vec![self.footer.index.clone(), self.footer.meta_index.clone()];
+        let mut metaindex = self.metaindex_iter();
+        while let Some((_, val)) = metaindex.next() {
+            handles.push(index_handle(&val));
+        }
+        for handle in self.index_handles() {
+            if self.blob_values() {
+                self.blob_handles(&handle, &mut handles)?;
             }
-        } else {
-            return Ok(None);
+            handles.push(handle);
         }
+        handles.sort_by_key(|h| h.offset());
+        handles.dedup_by_key(|h| h.offset());
 
-        // found correct block.
+        handles
+            .into_iter()
+            .map(|handle| {
+                let crc = self.block_crc(&handle)?;
+                Ok(BlockChecksum { handle, crc })
+            })
+            .collect()
+    }
 
-        // Check bloom (or whatever) filter
-        if let Some(ref filters) = self.filters {
-            if !filters.key_may_match(handle.offset(), key) {
-                return Ok(None);
+    /// Checks the blocks listed in `manifest` (see `checksum_manifest()`) against the file and
+    /// returns those whose contents changed since. I/O errors are returned as errors.
+    pub fn verify_against_manifest(&self, manifest: &[BlockChecksum]) -> Result<Vec<BlockHandle>> {
+        let mut changed = vec![];
+        for entry in manifest {
+            let actual = self.block_crc(&entry.handle)?;
+            if actual != entry.crc {
+                event!(
+                    error,
+                    offset = entry.handle.offset(),
+                    size = entry.handle.size(),
+                    "block doesn't match manifest"
+                );
+                changed.push(entry.handle.clone());
             }
         }
+        Ok(changed)
+    }
 
-        // Read block (potentially from cache)
-        let tb = self.read_block(&handle)?;
-        let mut iter = tb.iter();
+    /// Verifies the file checksum of tables built with `Options::file_checksum`, which covers all
+    /// bytes before the meta block storing it; the remaining blocks are covered by their own
+    /// checksums. Returns `Error::Corruption` if the file doesn't match and `Error::NotSupported`
+    /// if the table has no file checksum. Reads the whole file, bypassing the block cache.
+    pub fn verify_file_checksum(&self) -> Result<()> {
+        let location = match Table::find_meta_block(&self.metaindex_block, FILE_CHECKSUM_BLOCK_KEY)
+        {
+            Some(location) => location,
+            None => {
+                return Err(Error::NotSupported(
+                    "table has no file checksum".to_string(),
+                ))
+            }
+        };
+        let stored = checksum::decode_file_checksum(&table_block::read_blob(
+            self.file.as_ref().as_ref(),
+            self.file_size,
+            &self.opt,
+            &location,
+        )?)?;
 
-        // Go to entry and check if it's the wanted entry.
-        iter.seek(key);
-        if let Some((k, v)) = current_key_val(&iter) {
-            if self.opt.cmp.cmp(&k, key) == Ordering::Equal {
-                return Ok(Some(v));
+        let mut actual = FileChecksum::new();
+        let mut buf = vec![0; FILE_CHECKSUM_READ_SIZE];
+        let mut offset = 0;
+        while offset < location.offset() {
+            let len = (location.offset() - offset).min(buf.len() as u64) as usize;
+            if self.file.read_at(offset, &mut buf[..len])? < len {
+                return Err(Error::malformed(Some(offset), "table file is truncated"));
             }
+            actual.update(&buf[..len]);
+            offset += len as u64;
         }
-        Ok(None)
+        if actual.value() != stored {
+            event!(
+                error,
+                stored = stored,
+                actual = actual.value(),
+                "file checksum mismatch"
+            );
+            return Err(Error::malformed(None, "file checksum mismatch"));
+        }
+        Ok(())
     }
-}
 
-/// This iterator is a "TwoLevelIterator"; it uses an index block in order to get an offset hint
-/// into the data blocks.
-pub struct TableIterator {
-    // A TableIterator is independent of its table (on the syntax level -- it doesn't know its
-    // Table's lifetime). This is mainly required by the dynamic iterators used everywhere, where a
-    // lifetime makes things like returning an iterator from a function neigh-impossible.
-    //
-    // Instead, reference-counted pointers and locks inside the Table ensure that all
-    // TableIterators still share a table.
-    table: Table,
-    current_block: Option<BlockIter>,
-    current_block_off: usize,
-    index_block: BlockIter,
-}
+    /// Returns the CRC32C of the block at `location` including its trailer.
+    fn block_crc(&self, location: &BlockHandle) -> Result<u32> {
+        let buf = table_block::read_block_with_trailer(
+            self.file.as_ref().as_ref(),
+            self.file_size,
+            location,
+        )?;
+        Ok(checksum::crc32c(&buf))
+    }
 
-impl TableIterator {
-    // Skips to the entry referenced by the next entry in the index block.
-    // This is called once a block has run out of entries.
-    // Err means corruption or I/O error; Ok(true) means a new block was loaded; Ok(false) means
-    // tht there's no more entries.
-    fn skip_to_next_entry(&mut self) -> Result<bool> {
-        if let Some((_key, val)) = self.index_block.next() {
-            self.load_block(&val).map(|_| true)
-        } else {
-            Ok(false)
+    /// Appends the locations of the blobs referenced by the data block at `location` to `dst`.
+    fn blob_handles(&self, location: &BlockHandle, dst: &mut Vec<BlockHandle>) -> Result<()> {
+        let mut iter = self.read_block_with(location, false)?.iter();
+        while let Some((_, val)) = iter.next() {
+            let stored = self.stored_value(location, &val, true)?;
+            if let StoredValue::Blob(handle) = blob::decode(stored)? {
+                dst.push(handle);
+            }
         }
+        Ok(())
     }
 
-    // Load the block at `handle` into `self.current_block`
-    fn load_block(&mut self, handle: &[u8]) -> Result<()> {
-        let (new_block_handle, _) = BlockHandle::decode(handle);
-        let block = self.table.read_block(&new_block_handle)?;
+    /// Returns the table's properties. Tables written by older versions of this crate don't have
+    /// properties.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.properties.as_deref()
+    }
 
-        self.current_block = Some(block.iter());
-        self.current_block_off = new_block_handle.offset();
+    /// Returns the properties added by `TablePropertiesCollector`s when the table was built.
+    pub fn user_properties(&self) -> Option<&BTreeMap<String, Vec<u8>>> {
+        self.properties.as_ref().map(|p| &p.user_properties)
+    }
 
-        Ok(())
+    /// Returns the statistics of the data block at `block_offset`, if the table was written with
+    /// `Options::block_stats`.
+    pub fn block_stats(&self, block_offset: u64) -> Option<&[u8]> {
+        self.block_stats
+            .as_ref()
+            .and_then(|s| s.get(&block_offset))
+            .map(|s| s.as_slice())
     }
-}
 
-impl SSIterator for TableIterator {
-    fn advance(&mut self) -> bool {
-        // Uninitialized case.
-        if self.current_block.is_none() {
-            match self.skip_to_next_entry() {
-                Ok(true) => return self.advance(),
-                Ok(false) => {
-                    self.reset();
-                    return false;
-                }
-                // try next block from index, this might be corruption
-                Err(_) => return self.advance(),
-            }
-        }
+    /// Returns true if the table has no entries, i.e. its builder was finished without any (see
+    /// `TableBuilder::finish()`).
+    pub fn is_empty(&self) -> bool {
+        !self.index_block.iter().advance()
+    }
 
-        // Initialized case -- does the current block have more entries?
-        if let Some(ref mut cb) = self.current_block {
-            if cb.advance() {
-                return true;
-            }
-        }
+    /// Returns true if all entries of this table have expired at time `now`, meaning that the
+    /// table can be dropped without reading it.
+    pub fn is_fully_expired(&self, now: u64) -> bool {
+        self.properties
+            .as_ref()
+            .is_some_and(|p| p.is_fully_expired(now))
+    }
 
-        // If the current block is exhausted, try loading the next block.
-        self.current_block = None;
-        match self.skip_to_next_entry() {
-            Ok(true) => self.advance(),
-            Ok(false) => {
-                self.reset();
-                false
-            }
-            // try next block, this might be corruption
-            Err(_) => self.advance(),
+    /// Returns the fraction of entries that are deletions or values overwritten by a newer
+    /// version in this table, as recorded in its properties. This is None for tables not built
+    /// with internal keys (see `Cmp::internal_keys()`), including tables without properties.
+    pub fn garbage_ratio(&self) -> Option<f64> {
+        self.properties.as_ref().and_then(|p| p.garbage_ratio())
+    }
+
+    fn entry_expiration(&self) -> bool {
+        self.properties.as_ref().is_some_and(|p| p.entry_expiration)
+    }
+
+    fn value_checksums(&self) -> bool {
+        self.properties.as_ref().is_some_and(|p| p.value_checksums)
+    }
+
+    /// Returns the part of `val`, a value as stored in the data block at `location`, following
+    /// its expiration timestamp and checksum, verifying the checksum if `verify` is set.
+    fn stored_value<'a>(
+        &self,
+        location: &BlockHandle,
+        val: &'a [u8],
+        verify: bool,
+    ) -> Result<&'a [u8]> {
+        let stored = if self.entry_expiration() {
+            ttl::decode_value(val).1
+        } else {
+            val
+        };
+        if !self.value_checksums() {
+            return Ok(stored);
         }
+        if verify {
+            return checksum::check_value_checksum(stored)
+                .map_err(|kind| Error::corrupt_block(location, kind));
+        }
+        stored
+            .get(checksum::VALUE_CHECKSUM_SIZE..)
+            .ok_or_else(|| Error::malformed(Some(location.offset()), "missing value checksum"))
     }
 
-    // A call to valid() after seeking is necessary to ensure that the seek worked (e.g., no error
-    // while reading from disk)
-    fn seek(&mut self, to: &[u8]) {
-        // first seek in index block, rewind by one entry (so we get the next smaller index entry),
-        // then set current_block and seek there
-        self.index_block.seek(to);
+    fn blob_values(&self) -> bool {
+        self.properties.as_ref().is_some_and(|p| p.blob_values)
+    }
 
-        // It's possible that this is a seek past-last; reset in that case.
-        if let Some((past_block, handle)) = current_key_val(&self.index_block) {
-            if self.table.opt.cmp.cmp(to, &past_block) <= Ordering::Equal {
-                // ok, found right block: continue
-                if let Ok(()) = self.load_block(&handle) {
-                    // current_block is always set if load_block() returned Ok.
-                    self.current_block.as_mut().unwrap().seek(to);
-                    return;
+    fn value_compression(&self) -> bool {
+        self.properties
+            .as_ref()
+            .is_some_and(|p| p.value_compression)
+    }
+
+    fn value_codec(&self) -> bool {
+        self.properties
+            .as_ref()
+            .is_some_and(|p| p.value_codec.is_some())
+    }
+
+    /// Returns true if values stored in data blocks need to be passed through resolve_value().
+    fn encoded_values(&self) -> bool {
+        self.blob_values() || self.value_compression() || self.value_codec()
+    }
+
+    /// Returns the value for a value stored in a data block (without expiration prefix), reading
+    /// it from the file if it is stored separately, and decoding it (see `decode_value()`).
+    fn resolve_value(&self, mut stored: Vec<u8>, projection: Option<&[usize]>) -> Result<Vec<u8>> {
+        if self.blob_values() {
+            stored = match blob::decode(&stored)? {
+                StoredValue::Inline(_) => {
+                    stored.remove(0);
+                    stored
                 }
-            }
+                StoredValue::Blob(handle) => self.read_blob(&handle)?,
+            };
         }
-        // Reached in case of failure.
-        self.reset();
+        self.decode_value(stored, projection)
     }
 
-    fn prev(&mut self) -> bool {
-        // happy path: current block contains previous entry
-        if let Some(ref mut cb) = self.current_block {
-            if cb.prev() {
-                return true;
+    /// Decompresses a value if it's compressed and decodes it using the value codec, projecting it
+    /// to `projection` if given.
+    fn decode_value(&self, mut val: Vec<u8>, projection: Option<&[usize]>) -> Result<Vec<u8>> {
+        if self.value_compression() {
+            val = value_compression::decompress(val, self.opt.max_decompressed_block_size)?;
+        }
+        match self.opt.value_codec {
+            Some(ref codec) if self.value_codec() => {
+                value_codec::decode(codec.as_ref(), &val, projection)
             }
+            _ => Ok(val),
         }
+    }
 
-        // Go back one block and look for the last entry in the previous block
-        if self.index_block.prev() {
-            if let Some((_, handle)) = current_key_val(&self.index_block) {
-                if self.load_block(&handle).is_ok() {
-                    self.current_block.as_mut().unwrap().seek_to_last();
-                    self.current_block.as_ref().unwrap().valid()
-                } else {
-                    self.reset();
-                    false
+    /// Returns the form in which the logical key `key` is stored (see `Options::key_codec`).
+    fn stored_key<'a>(&self, key: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        key_codec::encode(self.opt.key_codec.as_deref(), key)
+    }
+
+    /// Returns the logical key stored as `stored` (see `Options::key_codec`).
+    pub(crate) fn logical_key<'a>(&self, stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        key_codec::decode(self.opt.key_codec.as_deref(), stored)
+    }
+
+    fn lazy_value(&self, stored: Vec<u8>) -> Result<LazyValue> {
+        let value = if self.blob_values() {
+            match blob::decode(&stored)? {
+                StoredValue::Inline(v) => {
+                    LazyValueKind::Inline(self.decode_value(v.to_vec(), None)?)
                 }
-            } else {
-                false
+                StoredValue::Blob(handle) => LazyValueKind::Blob(handle),
             }
         } else {
-            false
-        }
+            LazyValueKind::Inline(self.decode_value(stored, None)?)
+        };
+        Ok(LazyValue {
+            table: self.clone(),
+            value,
+        })
     }
 
-    fn reset(&mut self) {
-        self.index_block.reset();
-        self.current_block = None;
+    /// Reads a separately stored value. Blobs bypass the block caches.
+    fn read_blob(&self, location: &BlockHandle) -> Result<Vec<u8>> {
+        event!(
+            trace,
+            offset = location.offset(),
+            size = location.size(),
+            "reading blob"
+        );
+        table_block::read_blob(
+            self.file.as_ref().as_ref(),
+            self.file_size,
+            &self.opt,
+            location,
+        )
     }
 
-    // This iterator is special in that it's valid even before the first call to advance(). It
-    // behaves correctly, though.
-    fn valid(&self) -> bool {
-        self.current_block.is_some() && (self.current_block.as_ref().unwrap().valid())
+    /// block_cache_handle creates a CacheKey for a block with a given offset to be used in the
+    /// block cache.
+    fn block_cache_handle(&self, block_off: u64) -> cache::CacheKey {
+        Table::cache_key(self.cache_id, block_off)
     }
 
-    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
-        if let Some(ref cb) = self.current_block {
-            cb.current(key, val)
-        } else {
-            false
-        }
+    fn cache_key(cache_id: cache::CacheID, block_off: u64) -> cache::CacheKey {
+        let mut dst = [0; 2 * 8];
+        (&mut dst[..8])
+            .write_fixedint(cache_id)
+            .expect("error writing to vec");
+        (&mut dst[8..])
+            .write_fixedint(block_off)
+            .expect("error writing to vec");
+        dst
     }
 
-    fn current_key(&self) -> Option<&[u8]> {
-        if let Some(ref cb) = self.current_block {
-            cb.current_key()
-        } else {
-            None
-        }
+    /// Reads the block at `location` as stored in the file, verifying its checksum but not
+    /// decompressing it. Returns its contents and compression type, e.g. to be written to another
+    /// table using `TableBuilder::add_raw_block()`.
+    pub fn read_encoded_block(&self, location: &BlockHandle) -> Result<(Vec<u8>, CompressionType)> {
+        let raw = self.read_raw_block(location)?;
+        let ctype = options::int_to_compressiontype(raw.compression as u32).ok_or_else(|| {
+            Error::corrupt_block(
+                location,
+                CorruptionKind::UnknownCompressionType(raw.compression),
+            )
+        })?;
+        Ok((
+            Arc::try_unwrap(raw.data).unwrap_or_else(|shared| (*shared).clone()),
+            ctype,
+        ))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::options::CompressionType;
-    use crate::table_builder::TableBuilder;
-    use crate::test_util::{test_iterator_properties, SSIteratorIter};
-    use crate::types::{current_key_val, SSIterator};
+    /// Reads the data block at `location` (see `index_handles()`), verifying its checksum and
+    /// decompressing it, and caches it in the options' block cache. Values are returned as stored
+    /// in the block, i.e. including expiration timestamps and blob pointers if the table was
+    /// written with `Options::entry_expiration`, `Options::blob_threshold` or
+    /// `Options::value_compression`.
+    pub fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+        self.read_block_with(location, true)
+    }
+
+    /// Like `read_block()`, but blocks read from the file are only inserted into the block cache
+    /// if `fill_cache` is set (see `ReadOptions::fill_cache`).
+    fn read_block_with(&self, location: &BlockHandle, fill_cache: bool) -> Result<Block> {
+        let cachekey = self.block_cache_handle(location.offset());
+        if let Some(block) = self.opt.block_cache.lookup(&cachekey) {
+            event!(trace, offset = location.offset(), "block cache hit");
+            return Ok(block);
+        }
+        event!(trace, offset = location.offset(), "block cache miss");
+
+        let b = table_block::decode_table_block(
+            self.opt.clone(),
+            location,
+            self.read_raw_block(location)?,
+        )?;
+
+        if fill_cache {
+            // insert a cheap copy (Arc).
+            self.cache_block(location, &b);
+        }
+
+        Ok(b)
+    }
+
+    /// Reads the data blocks at `locations` like `read_block()`. The blocks not in the block
+    /// cache are read using a single `RandomAccess::read_batch()` call.
+    pub fn read_blocks(&self, locations: &[BlockHandle]) -> Result<Vec<Block>> {
+        self.read_blocks_with(locations, true)
+    }
+
+    fn read_blocks_with(&self, locations: &[BlockHandle], fill_cache: bool) -> Result<Vec<Block>> {
+        let mut blocks: Vec<Option<Block>> = locations
+            .iter()
+            .map(|l| {
+                self.opt
+                    .block_cache
+                    .lookup(&self.block_cache_handle(l.offset()))
+            })
+            .collect();
+
+        // Blocks missing from the block cache may still be in the compressed block cache.
+        let mut raw: Vec<Option<table_block::RawBlock>> = vec![None; locations.len()];
+        if let (Some(cache), Some(cache_id)) =
+            (&self.opt.compressed_block_cache, self.compressed_cache_id)
+        {
+            let mut cache = cache.write()?;
+            for (i, location) in locations.iter().enumerate() {
+                if blocks[i].is_none() {
+                    raw[i] = cache
+                        .get(&Table::cache_key(cache_id, location.offset()))
+                        .cloned();
+                }
+            }
+        }
+
+        let missing: Vec<usize> = (0..locations.len())
+            .filter(|&i| blocks[i].is_none() && raw[i].is_none())
+            .collect();
+        event!(
+            trace,
+            blocks = locations.len(),
+            missing = missing.len(),
+            "reading blocks"
+        );
+        if !missing.is_empty() {
+            let missing_locations: Vec<BlockHandle> =
+                missing.iter().map(|&i| locations[i].clone()).collect();
+            let read = table_block::read_raw_table_blocks(
+                self.file.as_ref().as_ref(),
+                self.file_size,
+                self.opt.checksummer.as_ref(),
+                &missing_locations,
+            )?;
+            let mut compressed_cache =
+                match (&self.opt.compressed_block_cache, self.compressed_cache_id) {
+                    (Some(cache), Some(cache_id)) => Some((cache.write()?, cache_id)),
+                    _ => None,
+                };
+            for (i, r) in missing.into_iter().zip(read) {
+                if let Some((ref mut cache, cache_id)) = compressed_cache {
+                    cache.insert_charged(
+                        &Table::cache_key(cache_id, locations[i].offset()),
+                        r.clone(),
+                        r.data.len(),
+                    );
+                }
+                raw[i] = Some(r);
+            }
+        }
+
+        let mut result = Vec::with_capacity(locations.len());
+        for (i, location) in locations.iter().enumerate() {
+            let block = match (blocks[i].take(), raw[i].take()) {
+                (Some(block), _) => block,
+                (None, Some(r)) => {
+                    let b = table_block::decode_table_block(self.opt.clone(), location, r)?;
+                    if fill_cache {
+                        self.cache_block(location, &b);
+                    }
+                    b
+                }
+                (None, None) => unreachable!(),
+            };
+            result.push(block);
+        }
+        Ok(result)
+    }
+
+    /// Returns true if the data block at `location` is in the block cache.
+    fn is_cached(&self, location: &BlockHandle) -> bool {
+        self.opt
+            .block_cache
+            .contains(&self.block_cache_handle(location.offset()))
+    }
+
+    /// Decodes a data block read without decompressing it, inserting it into the block cache if
+    /// `fill_cache` is set.
+    fn decode_block_with(
+        &self,
+        location: &BlockHandle,
+        raw: table_block::RawBlock,
+        fill_cache: bool,
+    ) -> Result<Block> {
+        let b = table_block::decode_table_block(self.opt.clone(), location, raw)?;
+        if fill_cache {
+            self.cache_block(location, &b);
+        }
+        Ok(b)
+    }
+
+    /// Reads the adjacent data blocks at `locations` using a single
+    /// `RandomAccess::read_vectored()` call. The first one is decoded like by `read_block_with()`,
+    /// the others are returned without decompressing them.
+    fn read_blocks_ahead(
+        &self,
+        locations: &[BlockHandle],
+        fill_cache: bool,
+    ) -> Result<(Block, Vec<table_block::RawBlock>)> {
+        event!(trace, blocks = locations.len(), "reading blocks ahead");
+        let mut raw = table_block::read_raw_table_blocks(
+            self.file.as_ref().as_ref(),
+            self.file_size,
+            self.opt.checksummer.as_ref(),
+            locations,
+        )?
+        .into_iter();
+        let first = self.decode_block_with(&locations[0], raw.next().unwrap(), fill_cache)?;
+        Ok((first, raw.collect()))
+    }
+
+    /// Read a block without decompressing it, using the compressed block cache if configured.
+    fn read_raw_block(&self, location: &BlockHandle) -> Result<table_block::RawBlock> {
+        // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
+        let file = self.file.as_ref().as_ref();
+        let cs = self.opt.checksummer.as_ref();
+        let (cache, cache_id) = match (&self.opt.compressed_block_cache, self.compressed_cache_id) {
+            (Some(cache), Some(id)) => (cache, id),
+            _ => return table_block::read_raw_table_block(file, self.file_size, cs, location),
+        };
+
+        let cachekey = Table::cache_key(cache_id, location.offset());
+        let mut cache = cache.write()?;
+        if let Some(raw) = cache.get(&cachekey) {
+            event!(
+                trace,
+                offset = location.offset(),
+                "compressed block cache hit"
+            );
+            return Ok(raw.clone());
+        }
+        event!(
+            trace,
+            offset = location.offset(),
+            "compressed block cache miss"
+        );
+
+        let raw = table_block::read_raw_table_block(file, self.file_size, cs, location)?;
+        cache.insert_charged(&cachekey, raw.clone(), raw.data.len());
+        Ok(raw)
+    }
+
+    /// Returns the locations of all data blocks, in order.
+    pub fn index_handles(&self) -> Vec<BlockHandle> {
+        self.index_iter().map(|(_, handle)| handle).collect()
+    }
+
+    /// Returns an iterator over the entries of the index block: the separator key and location of
+    /// every data block, in order. A block holds keys greater than the previous block's separator
+    /// and not greater than its own. This allows mapping key ranges to byte ranges of the file,
+    /// e.g. to download only the parts of a remote table a query needs.
+    pub fn index_iter(&self) -> IndexIterator {
+        IndexIterator {
+            iter: self.index_block.iter(),
+            seeked: false,
+        }
+    }
+
+    /// Returns the offset of the block that contains `key`. Keys the table's key codec can't
+    /// encode (see `Options::key_codec`) are treated as following the last key.
+    pub fn approx_offset_of(&self, key: &[u8]) -> u64 {
+        let key = match self.stored_key(key) {
+            Ok(key) => key,
+            Err(_) => return self.footer.meta_index.offset(),
+        };
+        let mut iter = self.index_block.iter();
+
+        iter.seek(&key);
+
+        if let Some((_, val)) = current_key_val(&iter) {
+            let location = index_handle(&val);
+            return location.offset();
+        }
+
+        self.footer.meta_index.offset()
+    }
+
+    /// Estimates the number of entries with keys in `[start, end)`. The blocks at the boundaries
+    /// of the range are scanned and counted exactly; the blocks in between are assumed to hold the
+    /// average number of entries per block recorded in the table's properties (or scanned as well
+    /// if the table has no properties).
+    pub fn estimate_keys_in_range(&self, start: &[u8], end: &[u8]) -> Result<u64> {
+        let cmp = self.opt.cmp.clone();
+        if cmp.cmp(start, end) != Ordering::Less {
+            return Ok(0);
+        }
+        let (start, end) = (self.stored_key(start)?, self.stored_key(end)?);
+        let avg_entries = self
+            .properties
+            .as_ref()
+            .filter(|p| p.num_data_blocks > 0)
+            .map(|p| (p.num_entries + p.num_data_blocks / 2) / p.num_data_blocks);
+
+        let blocks = self.blocks_in_range(&start, &end);
+        let mut count = 0;
+        for (i, handle) in blocks.iter().enumerate() {
+            match avg_entries {
+                Some(n) if i > 0 && i < blocks.len() - 1 => count += n,
+                _ => count += self.count_keys_in_block(handle, &start, &end)?,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns the locations of the data blocks that may contain keys in `[start, end)`, given in
+    /// their stored form.
+    fn blocks_in_range(&self, start: &[u8], end: &[u8]) -> Vec<BlockHandle> {
+        let mut index = self.index_block.iter();
+        index.seek(start);
+        let mut blocks = vec![];
+        while let Some((sep, handle)) = current_key_val(&index) {
+            blocks.push(index_handle(&handle));
+            // All keys of later blocks are greater than this block's separator.
+            if self.opt.cmp.cmp(&sep, end) != Ordering::Less {
+                break;
+            }
+            index.advance();
+        }
+        blocks
+    }
+
+    /// Loads the data blocks that may contain keys in `[start, end)` into the block cache ahead
+    /// of reads of the range. The blocks not cached yet are read using a single
+    /// `RandomAccess::read_batch()` call. Returns the number of blocks in the range. Note that
+    /// the blocks may be evicted before they're read if the cache is too small, and that caches
+    /// with an admission policy (`CachePolicy::TinyLFU`) may not admit them.
+    pub fn prefetch_range(&self, start: &[u8], end: &[u8]) -> Result<usize> {
+        if self.opt.cmp.cmp(start, end) != Ordering::Less {
+            return Ok(0);
+        }
+        let blocks = self.blocks_in_range(&self.stored_key(start)?, &self.stored_key(end)?);
+        self.read_blocks(&blocks)?;
+        Ok(blocks.len())
+    }
+
+    /// Like `prefetch_range()`, but loads the blocks on a new thread and returns immediately.
+    /// Joining the returned handle waits for the blocks to be loaded.
+    pub fn prefetch_range_async(
+        &self,
+        start: &[u8],
+        end: &[u8],
+    ) -> thread::JoinHandle<Result<usize>> {
+        let (table, start, end) = (self.clone(), start.to_vec(), end.to_vec());
+        thread::spawn(move || table.prefetch_range(&start, &end))
+    }
+
+    /// Loads the hot data blocks (see `TableBuilder::set_temperature()`) into the block cache, like
+    /// `prefetch_range()`. Returns the number of hot blocks.
+    pub fn prefetch_hot(&self) -> Result<usize> {
+        let ranges = match self.properties() {
+            Some(props) if !props.hot_ranges.is_empty() => &props.hot_ranges,
+            _ => return Ok(0),
+        };
+        let blocks: Vec<BlockHandle> = self
+            .index_handles()
+            .into_iter()
+            .filter(|h| {
+                let i = ranges.partition_point(|&(offset, _)| offset <= h.offset());
+                i > 0 && h.offset() < ranges[i - 1].0 + ranges[i - 1].1
+            })
+            .collect();
+        self.read_blocks(&blocks)?;
+        Ok(blocks.len())
+    }
+
+    /// Returns the locations of the data blocks currently in the block cache, in order. Saved
+    /// across process restarts (e.g. encoded using `BlockHandle::encode_to()`), they allow
+    /// restoring the cache of the reopened table with `warm()`.
+    pub fn dump_hot_blocks(&self) -> Vec<BlockHandle> {
+        self.index_handles()
+            .into_iter()
+            .filter(|h| {
+                self.opt
+                    .block_cache
+                    .contains(&self.block_cache_handle(h.offset()))
+            })
+            .collect()
+    }
+
+    /// Loads the data blocks at `handles`, as returned by `dump_hot_blocks()`, into the block
+    /// cache. The blocks not cached yet are read using a single `RandomAccess::read_batch()`
+    /// call. Handles that aren't locations of this table's data blocks (e.g. saved for another
+    /// version of the file) are ignored. Returns the number of blocks loaded or already cached.
+    pub fn warm(&self, handles: &[BlockHandle]) -> Result<usize> {
+        let data_blocks = self.index_handles();
+        let mut blocks: Vec<BlockHandle> = handles
+            .iter()
+            .filter(|h| {
+                data_blocks
+                    .binary_search_by_key(&h.offset(), |d| d.offset())
+                    .is_ok_and(|i| data_blocks[i] == **h)
+            })
+            .cloned()
+            .collect();
+        blocks.sort_by_key(|h| h.offset());
+        blocks.dedup_by_key(|h| h.offset());
+        self.read_blocks(&blocks)?;
+        Ok(blocks.len())
+    }
+
+    /// Counts the entries of the block at `location` with keys in `[start, end)`, given in their
+    /// stored form.
+    fn count_keys_in_block(&self, location: &BlockHandle, start: &[u8], end: &[u8]) -> Result<u64> {
+        let block = self.read_block(location)?;
+        let mut iter = block.iter();
+        iter.seek(start);
+        let mut count = 0;
+        while let Some(key) = iter.current_key() {
+            if self.opt.cmp.cmp(key, end) != Ordering::Less {
+                break;
+            }
+            count += 1;
+            iter.advance();
+        }
+        Ok(count)
+    }
+
+    /// Returns an iterator over an SSTable. Iterators hold internal references to the table, so
+    /// make sure to let them expire when not needed anymore.
+    pub fn iter(&self) -> TableIterator {
+        self.iter_opt(&ReadOptions::default())
+    }
+
+    /// Returns an iterator over the entries as stored in the data blocks: values include
+    /// expiration timestamps and blob pointers like those returned by `read_block()`, and expired
+    /// entries aren't skipped. Meant for tools inspecting the contents of tables; data blocks are
+    /// read through the block cache.
+    pub fn raw_iter(&self) -> TwoLevelIterator<BlockIter, DataBlocks> {
+        TwoLevelIterator::new(self.index_block.iter(), DataBlocks(self.clone()))
+    }
+
+    /// Returns an iterator over an SSTable using the given read options.
+    pub fn iter_opt(&self, ropt: &ReadOptions) -> TableIterator {
+        TableIterator {
+            current_block: None,
+            current_block_handle: BlockHandle::new(0, 0),
+            index_block: self.index_block.iter(),
+            entry_expiration: self.entry_expiration(),
+            value_checksums: self.value_checksums(),
+            verify_value_checksums: ropt.verify_value_checksums,
+            encoded_values: self.encoded_values(),
+            projection: ropt.projection.clone(),
+            read_time: ropt.read_time,
+            table_expired: ropt.read_time.is_some_and(|t| {
+                self.properties
+                    .as_ref()
+                    .and_then(|p| p.table_expiration)
+                    .is_some_and(|e| ttl::is_expired(e, t))
+            }),
+            pruner: None,
+            skip_corrupt_blocks: ropt.skip_corrupt_blocks,
+            fill_cache: ropt.fill_cache,
+            max_scan_cached_blocks: ropt.max_scan_cached_blocks,
+            scan_blocks: 0,
+            pin_data: ropt.pin_data,
+            pinned_block: None,
+            readahead_blocks: ropt.readahead_blocks,
+            readahead: VecDeque::new(),
+            key_codec: self.opt.key_codec.clone(),
+            key: vec![],
+            lazy_values: ropt.lazy_values,
+            value: vec![],
+            err: None,
+            value_err: OnceLock::new(),
+            table: self.clone(),
+        }
+    }
+
+    /// Returns an iterator over the entries whose keys start with `prefix`. It starts at the first
+    /// such key and becomes invalid as soon as a key without the prefix is reached. This assumes
+    /// that the table's comparator orders keys sharing a prefix contiguously, as bytewise
+    /// comparators do. With a key codec, `prefix` is matched against the logical keys, which
+    /// must then be ordered contiguously as well.
+    pub fn prefix_iter(&self, prefix: &[u8]) -> PrefixIterator {
+        PrefixIterator {
+            iter: self.iter(),
+            prefix: prefix.to_vec(),
+            started: false,
+        }
+    }
+
+    /// Returns the newest version of `user_key` visible at sequence number `snapshot`, or None if
+    /// there's none or it has been deleted. The table must store internal keys (see `key_types`)
+    /// and use `InternalKeyCmp`.
+    pub fn get_at(&self, user_key: &[u8], snapshot: SequenceNumber) -> Result<Option<Vec<u8>>> {
+        let mut iter = self.iter();
+        iter.seek(&key_types::build_internal_key(
+            user_key,
+            snapshot,
+            ValueType::TypeValue,
+        ));
+        let (mut key, mut val) = (vec![], vec![]);
+        if !iter.current(&mut key, &mut val) {
+            return Ok(None);
+        }
+        match key_types::parse_internal_key(&key) {
+            Some((ValueType::TypeValue, _, k)) if k == user_key => Ok(Some(val)),
+            Some(_) => Ok(None),
+            None => Err(Error::malformed(None, "bad internal key")),
+        }
+    }
+
+    /// Returns an iterator over the user keys of a table storing internal keys (see `get_at()`),
+    /// which yields the newest version of every key visible at sequence number `snapshot` and
+    /// skips deleted keys.
+    pub fn snapshot_iter(&self, snapshot: SequenceNumber) -> SnapshotIterator {
+        SnapshotIterator {
+            iter: self.iter(),
+            snapshot,
+            key: vec![],
+            val: vec![],
+            valid: false,
+            reverse: false,
+        }
+    }
+
+    /// Retrieve an entry for a key from the table. This function uses the attached filters, so
+    /// is better suited if you frequently look for non-existing values (as it will detect the
+    /// non-existence of an entry in a block without having to load the block).
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_opt(&ReadOptions::default(), key)
+    }
+
+    /// Retrieve an entry for a key from the table using the given read options. Expired entries
+    /// are not returned if `ropt.read_time` is set.
+    pub fn get_opt(&self, ropt: &ReadOptions, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.get_stored(ropt, key)? {
+            Some(stored) => Ok(Some(
+                self.resolve_value(stored, ropt.projection.as_deref())?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get_opt()`, but values stored separately from the data blocks (see
+    /// `Options::blob_threshold`) are only read once `LazyValue::fetch()` is called.
+    pub fn get_lazy(&self, ropt: &ReadOptions, key: &[u8]) -> Result<Option<LazyValue>> {
+        match self.get_stored(ropt, key)? {
+            Some(stored) => Ok(Some(self.lazy_value(stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a reader streaming the value of `key`. This avoids holding large values in memory
+    /// completely if they are stored separately (see `Options::blob_threshold`) and neither
+    /// compressed by block nor value compression; see `ValueReader`.
+    pub fn get_reader(&self, key: &[u8]) -> Result<Option<ValueReader>> {
+        let stored = match self.get_stored(&ReadOptions::default(), key)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        if self.blob_values() && !self.value_compression() && !self.value_codec() {
+            if let StoredValue::Blob(handle) = blob::decode(&stored)? {
+                return ValueReader::blob(self.file.clone(), self.file_size, &self.opt, &handle)
+                    .map(Some);
+            }
+        }
+        Ok(Some(ValueReader::buffered(
+            self.resolve_value(stored, None)?,
+        )))
+    }
+
+    /// Like `get()`, but uses `hint` to find the data block: if `key` falls into the block of the
+    /// previous lookup with the same hint, neither the index nor the block cache are searched,
+    /// and if it falls into the following block, the index is only advanced by one entry. This
+    /// speeds up many lookups of (nearly) sorted keys.
+    pub fn get_with_hint(&self, key: &[u8], hint: &mut SeekHint) -> Result<Option<Vec<u8>>> {
+        let ropt = ReadOptions::default();
+        let stored_key = self.stored_key(key)?;
+        let key = &stored_key[..];
+        if self.expired_at(&ropt) || !self.table_filter_may_match(key) {
+            return Ok(None);
+        }
+        if !self.position_hint(key, hint)? {
+            return Ok(None);
+        }
+        let (handle, block) = hint.block.as_ref().unwrap();
+        if let Some(ref filters) = self.filters {
+            if !filters.key_may_match(handle.offset(), key) {
+                return Ok(None);
+            }
+        }
+        match self.get_in_block(&ropt, handle, block, key)? {
+            Some(stored) => Ok(Some(self.resolve_value(stored, None)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Makes `hint` refer to the data block that may contain `key`. Returns false if there is
+    /// none, i.e. `key` is greater than all keys of the table.
+    fn position_hint(&self, key: &[u8], hint: &mut SeekHint) -> Result<bool> {
+        if hint.cache_id != self.cache_id || hint.index.is_none() {
+            *hint = SeekHint {
+                cache_id: self.cache_id,
+                index: Some(self.index_block.iter()),
+                ..Default::default()
+            };
+        }
+        let cmp = self.opt.cmp.as_ref().as_ref();
+        if hint.block.is_some() && cmp.cmp(key, &hint.upper) != Ordering::Greater {
+            match cmp.cmp(key, &hint.lower) {
+                Ordering::Greater => return Ok(true),
+                Ordering::Equal if hint.lower_inclusive => return Ok(true),
+                _ => {}
+            }
+        }
+
+        let index = hint.index.as_mut().unwrap();
+        let next_block = hint.block.is_some()
+            && cmp.cmp(key, &hint.upper) == Ordering::Greater
+            && index.advance()
+            && index
+                .current_key()
+                .is_some_and(|sep| cmp.cmp(key, sep) != Ordering::Greater);
+        hint.block = None;
+        if next_block {
+            // The next block holds the keys after the previous block's index key.
+            hint.lower = std::mem::take(&mut hint.upper);
+            hint.lower_inclusive = false;
+        } else {
+            index.seek(key);
+            hint.lower = key.to_vec();
+            hint.lower_inclusive = true;
+        }
+        let (sep, h) = match current_key_val(index) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let handle = index_handle(&h);
+        hint.upper = sep;
+        hint.block = Some((handle.clone(), self.read_block(&handle)?));
+        Ok(true)
+    }
+
+    /// Returns the value of `key` as stored in its data block, minus the expiration prefix and
+    /// value checksum.
+    fn get_stored(&self, ropt: &ReadOptions, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.expired_at(ropt) {
+            return Ok(None);
+        }
+        let stored_key = self.stored_key(key)?;
+        let key = &stored_key[..];
+        let handle = match self.locate(key) {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        // Read block (potentially from cache)
+        let tb = self.read_block_for(&handle, key, ropt.fill_cache)?;
+        self.get_in_block(ropt, &handle, &tb, key)
+    }
+
+    /// Like `read_block_with()`, but of a framed block (see
+    /// `CompressionType::CompressionSnappyFramed`) that isn't in the block cache, only the frame
+    /// `key` falls into is decompressed. Such partial blocks aren't inserted into the block cache.
+    fn read_block_for(
+        &self,
+        location: &BlockHandle,
+        key: &[u8],
+        fill_cache: bool,
+    ) -> Result<Block> {
+        let cachekey = self.block_cache_handle(location.offset());
+        if let Some(block) = self.opt.block_cache.lookup(&cachekey) {
+            event!(trace, offset = location.offset(), "block cache hit");
+            return Ok(block);
+        }
+        event!(trace, offset = location.offset(), "block cache miss");
+
+        let raw = self.read_raw_block(location)?;
+        let framed = raw.compression == CompressionType::CompressionSnappyFramed as u8;
+        let b = table_block::decode_table_block_for(self.opt.clone(), location, raw, key)?;
+        if fill_cache && !framed {
+            self.cache_block(location, &b);
+        }
+        Ok(b)
+    }
+
+    /// Looks up several keys at once, returning their values in the order of `keys`. The data
+    /// blocks not in the block cache are read using a single `RandomAccess::read_batch()` call.
+    pub fn multi_get(&self, ropt: &ReadOptions, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        if self.expired_at(ropt) {
+            return Ok(vec![None; keys.len()]);
+        }
+        let keys = keys
+            .iter()
+            .map(|k| self.stored_key(k))
+            .collect::<Result<Vec<_>>>()?;
+        let handles: Vec<Option<BlockHandle>> = keys.iter().map(|k| self.locate(k)).collect();
+        let mut distinct: Vec<BlockHandle> = handles.iter().flatten().cloned().collect();
+        distinct.sort_by_key(|h| h.offset());
+        distinct.dedup_by_key(|h| h.offset());
+        let blocks = self.read_blocks_with(&distinct, ropt.fill_cache)?;
+
+        let mut values = Vec::with_capacity(keys.len());
+        for (key, handle) in keys.iter().zip(handles) {
+            let stored = match handle {
+                Some(h) => {
+                    let i = distinct
+                        .binary_search_by_key(&h.offset(), |d| d.offset())
+                        .unwrap();
+                    self.get_in_block(ropt, &h, &blocks[i], key)?
+                }
+                None => None,
+            };
+            values.push(match stored {
+                Some(stored) => Some(self.resolve_value(stored, ropt.projection.as_deref())?),
+                None => None,
+            });
+        }
+        Ok(values)
+    }
+
+    /// Returns true if the whole table has expired at `ropt.read_time`.
+    fn expired_at(&self, ropt: &ReadOptions) -> bool {
+        match (
+            ropt.read_time,
+            self.properties.as_ref().and_then(|p| p.table_expiration),
+        ) {
+            (Some(t), Some(e)) => ttl::is_expired(e, t),
+            _ => false,
+        }
+    }
+
+    /// Returns false if `key` is certainly not in the table. This only consults the table filter
+    /// (see `Options::table_filter_fp_rate`), the index and the filter block, all of which are
+    /// held in memory; no data block is read. Keys for which it returns true may still be absent.
+    pub fn key_may_exist(&self, key: &[u8]) -> bool {
+        match self.stored_key(key) {
+            Ok(key) => self.locate(&key).is_some(),
+            Err(_) => true,
+        }
+    }
+
+    fn table_filter_may_match(&self, key: &[u8]) -> bool {
+        match self.table_filter {
+            Some(ref filter) => table_filter::key_may_match(filter, key),
+            None => true,
+        }
+    }
+
+    /// Returns the location of the data block that may contain `key`, or None if the table filter,
+    /// the index or the filter block rule it out.
+    fn locate(&self, key: &[u8]) -> Option<BlockHandle> {
+        if !self.table_filter_may_match(key) {
+            return None;
+        }
+        let hashed = match self.hash_index {
+            Some(ref hash_index) => hash_index.lookup(self.opt.cmp.as_ref().as_ref(), key),
+            None => HashLookup::NotIndexed,
+        };
+        let handle = match hashed {
+            HashLookup::Block(handle) => handle,
+            HashLookup::Absent => return None,
+            HashLookup::NotIndexed => {
+                let mut index_iter = self.index_block.iter();
+                index_iter.seek(key);
+
+                let (last_in_block, h) = current_key_val(&index_iter)?;
+                if self.opt.cmp.cmp(key, &last_in_block) == Ordering::Greater {
+                    return None;
+                }
+                index_handle(&h)
+            }
+        };
+
+        // Check bloom (or whatever) filter
+        if let Some(ref filters) = self.filters {
+            if !filters.key_may_match(handle.offset(), key) {
+                return None;
+            }
+        }
+        Some(handle)
+    }
+
+    /// Returns the value of `key` as stored in `block`, the data block at `location`, minus the
+    /// expiration prefix and value checksum.
+    fn get_in_block(
+        &self,
+        ropt: &ReadOptions,
+        location: &BlockHandle,
+        block: &Block,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut iter = block.iter();
+
+        // Go to entry and check if it's the wanted entry.
+        iter.seek(key);
+        let (k, v) = match current_key_val(&iter) {
+            Some(kv) => kv,
+            None => return Ok(None),
+        };
+        if self.opt.cmp.cmp(&k, key) != Ordering::Equal {
+            return Ok(None);
+        }
+        if !self.entry_expiration() && !self.value_checksums() {
+            return Ok(Some(v));
+        }
+        let expiration = ttl::decode_value(&v).0;
+        if self.entry_expiration()
+            && ropt
+                .read_time
+                .is_some_and(|t| ttl::is_expired(expiration, t))
+        {
+            return Ok(None);
+        }
+        let stored = self.stored_value(location, &v, ropt.verify_value_checksums)?;
+        Ok(Some(stored.to_vec()))
+    }
+}
+
+/// Remembers the data block of the previous lookup done by `Table::get_with_hint()`, and its
+/// position in the table's index. A hint may be used with several tables, but it only helps
+/// consecutive lookups in the same table.
+#[derive(Default)]
+pub struct SeekHint {
+    cache_id: cache::CacheID,
+    index: Option<BlockIter>,
+    block: Option<(BlockHandle, Block)>,
+    // Keys greater than (or, if lower_inclusive, equal to) lower and not greater than upper, the
+    // block's index key, can only be in the block.
+    lower: Vec<u8>,
+    lower_inclusive: bool,
+    upper: Vec<u8>,
+}
+
+impl SeekHint {
+    pub fn new() -> SeekHint {
+        SeekHint::default()
+    }
+}
+
+/// Where the value of an entry is stored, as returned by `TableIterator::current_entry_meta()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryMeta {
+    /// The location of the data block containing the entry.
+    pub block: BlockHandle,
+    /// The offset of the value within the block's (uncompressed) contents, see
+    /// `Table::read_block()` and `Block::contents()`.
+    pub value_offset: usize,
+    /// The length of the value.
+    pub value_len: usize,
+}
+
+/// The location and the keys of a data block, as returned by
+/// `TableIterator::current_block_bounds()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockBounds {
+    /// The location of the data block.
+    pub block: BlockHandle,
+    /// The first key in the block.
+    pub first_key: Vec<u8>,
+    /// The last key in the block.
+    pub last_key: Vec<u8>,
+}
+
+/// The file of a table, kept open until the pin is dropped (see `Table::pin()`).
+#[derive(Clone)]
+pub struct TablePin {
+    file: Arc<Box<dyn RandomAccess>>,
+    size: u64,
+}
+
+impl TablePin {
+    /// Opens the pinned table again with `opt`, reading the file as it was when it was pinned.
+    /// Tables whose file has an ID (see `RandomAccess::file_id()`) share cached blocks with
+    /// earlier instances.
+    pub fn open(&self, opt: Options) -> Result<Table> {
+        Table::new(opt, Box::new(self.file.clone()), self.size)
+    }
+}
+
+/// A value referencing the data block it's stored in, as returned by
+/// `TableIterator::current_pinned_value()`. Dereferences to the value's bytes; the block stays in
+/// memory as long as the slice exists.
+#[derive(Clone)]
+pub struct PinnedSlice {
+    block: Arc<BlockContents>,
+    start: usize,
+    end: usize,
+}
+
+impl Deref for PinnedSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.block[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for PinnedSlice {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// A value that is read from the file only when needed. See `Table::get_lazy()` and
+/// `TableIterator::current_lazy()`.
+pub struct LazyValue {
+    table: Table,
+    value: LazyValueKind,
+}
+
+enum LazyValueKind {
+    Inline(Vec<u8>),
+    Blob(BlockHandle),
+}
+
+impl LazyValue {
+    /// Returns true if the value is stored separately and fetching it requires reading from the
+    /// file.
+    pub fn is_blob(&self) -> bool {
+        matches!(self.value, LazyValueKind::Blob(_))
+    }
+
+    /// Returns the value, reading it from the file if necessary.
+    pub fn fetch(&self) -> Result<Vec<u8>> {
+        match self.value {
+            LazyValueKind::Inline(ref v) => Ok(v.clone()),
+            LazyValueKind::Blob(ref handle) => {
+                self.table.decode_value(self.table.read_blob(handle)?, None)
+            }
+        }
+    }
+}
+
+/// This iterator is a "TwoLevelIterator"; it uses an index block in order to get an offset hint
+/// into the data blocks.
+pub struct TableIterator {
+    // A TableIterator is independent of its table (on the syntax level -- it doesn't know its
+    // Table's lifetime). This is mainly required by the dynamic iterators used everywhere, where a
+    // lifetime makes things like returning an iterator from a function neigh-impossible.
+    //
+    // Instead, reference-counted pointers and locks inside the Table ensure that all
+    // TableIterators still share a table.
+    table: Table,
+    current_block: Option<BlockIter>,
+    current_block_handle: BlockHandle,
+    index_block: BlockIter,
+    // Whether values are prefixed by an expiration timestamp.
+    entry_expiration: bool,
+    // Whether values are prefixed by a checksum, and whether it's verified.
+    value_checksums: bool,
+    verify_value_checksums: bool,
+    // Whether values are separated or encoded and need to be passed to Table::resolve_value().
+    encoded_values: bool,
+    projection: Option<Vec<usize>>,
+    read_time: Option<u64>,
+    table_expired: bool,
+    pruner: Option<BlockPruner>,
+    skip_corrupt_blocks: bool,
+    fill_cache: bool,
+    max_scan_cached_blocks: Option<usize>,
+    // The number of blocks read since the iterator was last seeked or reset.
+    scan_blocks: usize,
+    pin_data: bool,
+    // The block at `current_block_handle` after the iterator has left it (by running past its
+    // end or being reset). Seeks landing in it again reuse it instead of going through the block
+    // cache.
+    pinned_block: Option<BlockIter>,
+    readahead_blocks: usize,
+    // Blocks read ahead of the iterator, in order, and not decompressed yet.
+    readahead: VecDeque<(BlockHandle, table_block::RawBlock)>,
+    key_codec: Option<BoxedKeyCodec>,
+    // The logical key of the current entry if the table's keys are encoded.
+    key: Vec<u8>,
+    lazy_values: bool,
+    // The value of the current entry if values are stored with a prefix or encoded (see
+    // `load_value()`).
+    value: Vec<u8>,
+    // The first error encountered since the iterator was last reset or seeked.
+    err: Option<Error>,
+    // An error reading a value in `current()` with `ReadOptions::lazy_values`, which stops the
+    // iterator like `err`.
+    value_err: OnceLock<Error>,
+}
+
+impl TableIterator {
+    // Skips to the entry referenced by the next entry in the index block.
+    // This is called once a block has run out of entries.
+    // Err means corruption or I/O error; Ok(true) means a new block was loaded; Ok(false) means
+    // tht there's no more entries.
+    fn skip_to_next_entry(&mut self) -> Result<bool> {
+        while let Some((_key, val)) = self.index_block.next() {
+            if !self.is_pruned(&val) {
+                return self.load_block(&val).map(|_| true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns where the current entry's value is stored, without copying it. The value is
+    /// located as stored in the block, minus the expiration timestamp (see
+    /// `Options::entry_expiration`) and checksum (see `Options::value_checksums`), which isn't
+    /// verified; for tables with blob values, value compression or a value codec, it is in the
+    /// encoded form written by the `TableBuilder`.
+    pub fn current_entry_meta(&self) -> Option<EntryMeta> {
+        let cb = self.current_block.as_ref()?;
+        let (mut offset, mut len) = cb.current_value_range()?;
+        if self.entry_expiration || self.value_checksums {
+            let val = cb.current_value()?;
+            let stored = self
+                .table
+                .stored_value(&self.current_block_handle, val, false)
+                .ok()?;
+            let prefix_len = val.len() - stored.len();
+            offset += prefix_len;
+            len -= prefix_len;
+        }
+        Some(EntryMeta {
+            block: self.current_block_handle.clone(),
+            value_offset: offset,
+            value_len: len,
+        })
+    }
+
+    /// Returns the location of the current entry's data block and the first and last key in it,
+    /// read from the block in memory. Keys are returned in their logical form, like `current()`
+    /// does; None is returned if they fail to decode.
+    pub fn current_block_bounds(&self) -> Option<BlockBounds> {
+        if !self.valid() {
+            return None;
+        }
+        let mut block = self.current_block.clone()?;
+        block.reset();
+        block.advance();
+        let first_key = block.current_key()?.to_vec();
+        block.seek_to_last();
+        let last_key = block.current_key()?;
+        let codec = self.key_codec.as_deref();
+        Some(BlockBounds {
+            block: self.current_block_handle.clone(),
+            first_key: key_codec::decode(codec, &first_key).ok()?.into_owned(),
+            last_key: key_codec::decode(codec, last_key).ok()?.into_owned(),
+        })
+    }
+
+    /// Moves the iterator to the first entry of the next data block, without going through the
+    /// remaining entries of the current one, e.g. to sample a table block by block. Blocks skipped
+    /// by `prune_blocks()` and expired entries are passed over like by `advance()`, which this
+    /// behaves like if the iterator isn't positioned. Returns whether the iterator is valid.
+    pub fn skip_current_block(&mut self) -> bool {
+        if self.table_expired || self.stopped() {
+            return false;
+        }
+        // Without a current block, the next entry is the first one of the next block.
+        self.release_block();
+        self.advance_entry() && self.skip_expired_forward() && self.load_entry(Self::next_entry)
+    }
+
+    /// Returns the current value as a slice of its data block, without copying it, if the
+    /// iterator was created with `ReadOptions::pin_data` set. Values of tables with blob values,
+    /// value compression or a value codec have to be decoded, so None is returned for them, as
+    /// well as for values failing checksum verification.
+    pub fn current_pinned_value(&self) -> Option<PinnedSlice> {
+        if !self.pin_data || self.encoded_values {
+            return None;
+        }
+        if self.value_checksums && self.verify_value_checksums {
+            let val = self.current_block.as_ref()?.current_value()?;
+            self.table
+                .stored_value(&self.current_block_handle, val, true)
+                .ok()?;
+        }
+        let meta = self.current_entry_meta()?;
+        Some(PinnedSlice {
+            block: self.current_block.as_ref()?.contents(),
+            start: meta.value_offset,
+            end: meta.value_offset + meta.value_len,
+        })
+    }
+
+    /// Like `current()`, but values stored separately from the data blocks (see
+    /// `Options::blob_threshold`) are only read once `LazyValue::fetch()` is called. Iterators
+    /// created with `ReadOptions::lazy_values` don't read them before either.
+    pub fn current_lazy(&self, key: &mut Vec<u8>) -> Option<LazyValue> {
+        let cb = self.current_block.as_ref()?;
+        let mut val = vec![];
+        if !cb.current(key, &mut val) {
+            return None;
+        }
+        if self.key_codec.is_some() {
+            key.clear();
+            key.extend_from_slice(&self.key);
+        }
+        self.strip_prefix(&mut val).ok()?;
+        self.table.lazy_value(val).ok()
+    }
+
+    /// Removes and returns the error recorded by the iterator (see `status()`).
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.err.take().or_else(|| self.value_err.take())
+    }
+
+    /// Returns the expiration timestamp of the current entry (see `Options::entry_expiration`), or
+    /// `ttl::NO_EXPIRATION` if it has none.
+    pub(crate) fn current_expiration(&self) -> u64 {
+        match self
+            .current_block
+            .as_ref()
+            .and_then(|cb| cb.current_value())
+        {
+            Some(val) if self.entry_expiration => ttl::decode_value(val).0,
+            _ => ttl::NO_EXPIRATION,
+        }
+    }
+
+    /// Returns the comparator of the iterator's table.
+    pub(crate) fn cmp(&self) -> Arc<Box<dyn Cmp>> {
+        self.table.opt.cmp.clone()
+    }
+
+    /// Makes the iterator skip all data blocks for whose statistics (see `Options::block_stats`)
+    /// `pruner` returns true. Blocks without statistics are never skipped. This should be called
+    /// before positioning the iterator.
+    pub fn prune_blocks(&mut self, pruner: BlockPruner) {
+        self.pruner = Some(pruner);
+    }
+
+    // Returns true if the block referenced by the encoded `handle` is skipped.
+    fn is_pruned(&self, handle: &[u8]) -> bool {
+        match self.pruner {
+            Some(ref pruner) => {
+                let handle = index_handle(handle);
+                self.table
+                    .block_stats(handle.offset())
+                    .is_some_and(|stats| pruner(stats))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if the iterator has stopped at an error.
+    fn stopped(&self) -> bool {
+        self.status().is_some() && !self.skip_corrupt_blocks
+    }
+
+    /// Records an error encountered while moving the iterator. Returns true if the iterator should
+    /// carry on with the next block.
+    fn fail(&mut self, e: Error) -> bool {
+        event!(error, error = %e, "iterator failed to read block");
+        if self.err.is_none() {
+            self.err = Some(e);
+        }
+        self.skip_corrupt_blocks
+    }
+
+    /// Decodes the key of the current entry into `key` if the table's keys are encoded (see
+    /// `Options::key_codec`). Returns whether the iterator is valid; keys that fail to decode
+    /// stop it.
+    fn decode_key(&mut self) -> bool {
+        let codec = match self.key_codec {
+            Some(ref codec) => codec,
+            None => return self.valid(),
+        };
+        let stored = match self.current_block.as_ref().and_then(|cb| cb.current_key()) {
+            Some(stored) => stored,
+            None => return false,
+        };
+        self.key.clear();
+        if let Err(e) = codec.decode(stored, &mut self.key) {
+            self.fail(e);
+            self.invalidate();
+            return false;
+        }
+        true
+    }
+
+    /// Returns whether values are copied from `value` instead of the current block.
+    fn loads_values(&self) -> bool {
+        self.entry_expiration || self.value_checksums || self.encoded_values
+    }
+
+    /// Decodes the value of the current entry into `value`, verifying its checksum and reading
+    /// it if it's stored separately (unless `ReadOptions::lazy_values` is set).
+    fn load_value(&mut self) -> Result<()> {
+        if !self.loads_values() {
+            return Ok(());
+        }
+        let mut val = std::mem::take(&mut self.value);
+        val.clear();
+        if let Some(stored) = self
+            .current_block
+            .as_ref()
+            .and_then(|cb| cb.current_value())
+        {
+            val.extend_from_slice(stored);
+        }
+        self.strip_prefix(&mut val)?;
+        if self.encoded_values && !self.lazy_values {
+            val = self.table.resolve_value(val, self.projection.as_deref())?;
+        }
+        self.value = val;
+        Ok(())
+    }
+
+    /// Decodes the key and value of the entry the iterator has moved to. If the value can't be
+    /// read, the iterator stops, or moves on using `next` with `ReadOptions::skip_corrupt_blocks`.
+    /// Returns whether the iterator is valid.
+    fn load_entry(&mut self, next: fn(&mut TableIterator) -> bool) -> bool {
+        loop {
+            if !self.decode_key() {
+                return false;
+            }
+            match self.load_value() {
+                Ok(()) => return true,
+                Err(e) => {
+                    if !self.fail(e) {
+                        self.invalidate();
+                        return false;
+                    }
+                }
+            }
+            if !next(self) {
+                return false;
+            }
+        }
+    }
+
+    /// Moves to the next entry that hasn't expired. Returns whether the iterator is valid.
+    fn next_entry(&mut self) -> bool {
+        self.advance_entry() && self.skip_expired_forward()
+    }
+
+    /// Moves to the previous entry that hasn't expired. Returns whether the iterator is valid.
+    fn prev_unexpired(&mut self) -> bool {
+        while self.prev_entry() {
+            if !self.current_expired() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Makes the iterator invalid without clearing its error.
+    fn invalidate(&mut self) {
+        self.index_block.reset();
+        self.release_block();
+    }
+
+    // Leaves the current block, keeping it as `pinned_block`.
+    fn release_block(&mut self) {
+        if let Some(block) = self.current_block.take() {
+            self.pinned_block = Some(block);
+        }
+    }
+
+    // Load the block at `handle` into `self.current_block`
+    fn load_block(&mut self, handle: &[u8]) -> Result<()> {
+        let new_block_handle = index_handle(handle);
+        self.release_block();
+        let block = match self.pinned_block.take() {
+            Some(mut block) if new_block_handle == self.current_block_handle => {
+                block.reset();
+                block
+            }
+            _ => self.read_block(&new_block_handle)?.iter(),
+        };
+
+        self.current_block = Some(block);
+        self.current_block_handle = new_block_handle;
+
+        Ok(())
+    }
+
+    // Reads the block at `location`, taking it from the blocks read ahead if possible. Otherwise,
+    // the blocks following it in the index are read along with it, up to the first one that is
+    // cached, skipped, or not adjacent to the previous one (see `ReadOptions::readahead_blocks`).
+    fn read_block(&mut self, location: &BlockHandle) -> Result<Block> {
+        self.scan_blocks += 1;
+        let fill_cache = self.fill_cache
+            && self
+                .max_scan_cached_blocks
+                .is_none_or(|max| self.scan_blocks <= max);
+        while self
+            .readahead
+            .front()
+            .is_some_and(|(h, _)| h.offset() < location.offset())
+        {
+            self.readahead.pop_front();
+        }
+        if self.readahead.front().is_some_and(|(h, _)| h == location) {
+            let (_, raw) = self.readahead.pop_front().unwrap();
+            return self.table.decode_block_with(location, raw, fill_cache);
+        }
+        if self.readahead_blocks == 0 || self.table.is_cached(location) {
+            return self.table.read_block_with(location, fill_cache);
+        }
+
+        let trailer_len =
+            (table_builder::TABLE_BLOCK_COMPRESS_LEN + table_builder::TABLE_BLOCK_CKSUM_LEN) as u64;
+        let mut locations = vec![location.clone()];
+        let mut end = location.offset() + location.size() + trailer_len;
+        // The index iterator is positioned at the entry of `location`.
+        let mut index = self.index_block.clone();
+        while locations.len() <= self.readahead_blocks && index.advance() {
+            let handle = match index.current_value() {
+                Some(val) if !self.is_pruned(val) => index_handle(val),
+                _ => break,
+            };
+            if handle.offset() != end || self.table.is_cached(&handle) {
+                break;
+            }
+            end = handle.offset() + handle.size() + trailer_len;
+            locations.push(handle);
+        }
+        if locations.len() == 1 {
+            return self.table.read_block_with(location, fill_cache);
+        }
+        match self.table.read_blocks_ahead(&locations, fill_cache) {
+            Ok((block, raw)) => {
+                self.readahead.clear();
+                self.readahead
+                    .extend(locations.into_iter().skip(1).zip(raw));
+                Ok(block)
+            }
+            // A corrupt block read ahead must not fail the current one.
+            Err(_) => self.table.read_block_with(location, fill_cache),
+        }
+    }
+
+    // Removes the expiration prefix and checksum from a stored value, verifying the checksum.
+    fn strip_prefix(&self, val: &mut Vec<u8>) -> Result<()> {
+        if self.entry_expiration || self.value_checksums {
+            let stored = self.table.stored_value(
+                &self.current_block_handle,
+                val,
+                self.verify_value_checksums,
+            )?;
+            let prefix_len = val.len() - stored.len();
+            val.drain(..prefix_len);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the current entry has expired at the iterator's read time.
+    fn current_expired(&self) -> bool {
+        match (self.read_time, self.current_block.as_ref()) {
+            (Some(t), Some(cb)) if self.entry_expiration => cb
+                .current_value()
+                .is_some_and(|v| ttl::is_expired(ttl::decode_value(v).0, t)),
+            _ => false,
+        }
+    }
+
+    /// Advances past expired entries. Returns whether the iterator is valid.
+    fn skip_expired_forward(&mut self) -> bool {
+        while self.current_expired() {
+            if !self.advance_entry() {
+                return false;
+            }
+        }
+        self.valid()
+    }
+
+    fn advance_entry(&mut self) -> bool {
+        // Uninitialized case.
+        if self.current_block.is_none() {
+            return match self.skip_to_next_entry() {
+                Ok(true) => self.advance_entry(),
+                Ok(false) => {
+                    self.invalidate();
+                    false
+                }
+                Err(e) => self.skip_failed_block(e),
+            };
+        }
+
+        // Initialized case -- does the current block have more entries?
+        if let Some(ref mut cb) = self.current_block {
+            if cb.advance() {
+                return true;
+            }
+        }
+
+        // If the current block is exhausted, try loading the next block.
+        self.release_block();
+        match self.skip_to_next_entry() {
+            Ok(true) => self.advance_entry(),
+            Ok(false) => {
+                self.invalidate();
+                false
+            }
+            Err(e) => self.skip_failed_block(e),
+        }
+    }
+
+    /// Handles a block that couldn't be loaded while advancing: either moves on to the next block
+    /// or stops.
+    fn skip_failed_block(&mut self, e: Error) -> bool {
+        self.release_block();
+        // When stopping, the iterator stays exhausted: advance() doesn't move on while the error
+        // is set.
+        self.fail(e) && self.advance_entry()
+    }
+
+    /// Positions the iterator at the first entry of the next block that isn't skipped.
+    fn seek_next_block(&mut self) {
+        match self.skip_to_next_entry() {
+            Ok(true) => {
+                self.current_block.as_mut().unwrap().advance();
+                return;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                self.fail(e);
+            }
+        }
+        self.invalidate();
+    }
+
+    fn seek_entry(&mut self, to: &[u8]) {
+        // first seek in index block, rewind by one entry (so we get the next smaller index entry),
+        // then set current_block and seek there
+        self.index_block.seek(to);
+
+        // The index iterator is only valid at the first index key not less than `to`, so there is
+        // no need to compare them again; it's invalid if this is a seek past the last block.
+        if let Some((_, handle)) = current_key_val(&self.index_block) {
+            // If the block is skipped, the first entry of the next block that isn't is the
+            // first one at or after `to`.
+            if self.is_pruned(&handle) {
+                self.release_block();
+                self.seek_next_block();
+                return;
+            }
+            // ok, found right block: continue
+            match self.load_block(&handle) {
+                Ok(()) => {
+                    // current_block is always set if load_block() returned Ok.
+                    let block = self.current_block.as_mut().unwrap();
+                    block.seek(to);
+                    // `to` may lie between the block's last key and its shortened index key,
+                    // in which case the next block starts with the first entry after it.
+                    if !block.valid() {
+                        self.seek_next_block();
+                    }
+                    return;
+                }
+                Err(e) => {
+                    self.fail(e);
+                }
+            }
+        }
+        // Reached in case of failure.
+        self.invalidate();
+    }
+
+    fn prev_entry(&mut self) -> bool {
+        // happy path: current block contains previous entry
+        if let Some(ref mut cb) = self.current_block {
+            if cb.prev() {
+                return true;
+            }
+        }
+
+        // Go back one block (skipping pruned blocks) and look for the last entry in the previous
+        // block
+        while self.index_block.prev() {
+            if let Some((_, handle)) = current_key_val(&self.index_block) {
+                if self.is_pruned(&handle) {
+                    continue;
+                }
+                match self.load_block(&handle) {
+                    Ok(()) => {
+                        self.current_block.as_mut().unwrap().seek_to_last();
+                        return self.current_block.as_ref().unwrap().valid();
+                    }
+                    Err(e) => {
+                        self.fail(e);
+                        self.invalidate();
+                        return false;
+                    }
+                }
+            } else {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+impl SSIterator for TableIterator {
+    fn advance(&mut self) -> bool {
+        if self.table_expired || self.stopped() {
+            return false;
+        }
+        self.advance_entry() && self.skip_expired_forward() && self.load_entry(Self::next_entry)
+    }
+
+    // A call to valid() after seeking is necessary to ensure that the seek worked (e.g., no error
+    // while reading from disk)
+    fn seek(&mut self, to: &[u8]) {
+        self.err = None;
+        self.value_err = OnceLock::new();
+        self.scan_blocks = 0;
+        if self.table_expired {
+            return;
+        }
+        let to = match key_codec::encode(self.key_codec.as_deref(), to) {
+            Ok(to) => to,
+            Err(e) => {
+                self.fail(e);
+                self.invalidate();
+                return;
+            }
+        };
+        self.seek_entry(&to);
+        if self.skip_expired_forward() {
+            self.load_entry(Self::next_entry);
+        }
+    }
+
+    fn prev(&mut self) -> bool {
+        if self.table_expired || self.stopped() {
+            return false;
+        }
+        self.prev_unexpired() && self.load_entry(Self::prev_unexpired)
+    }
+
+    fn reset(&mut self) {
+        self.err = None;
+        self.value_err = OnceLock::new();
+        self.scan_blocks = 0;
+        self.invalidate();
+    }
+
+    // This iterator is special in that it's valid even before the first call to advance(). It
+    // behaves correctly, though.
+    fn valid(&self) -> bool {
+        self.current_block.is_some() && (self.current_block.as_ref().unwrap().valid())
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if let Some(ref cb) = self.current_block {
+            if !cb.current(key, val) {
+                return false;
+            }
+            if self.key_codec.is_some() {
+                key.clear();
+                key.extend_from_slice(&self.key);
+            }
+            if self.encoded_values && self.lazy_values {
+                match self
+                    .table
+                    .resolve_value(self.value.clone(), self.projection.as_deref())
+                {
+                    Ok(v) => *val = v,
+                    Err(e) => {
+                        event!(error, error = %e, "couldn't read value");
+                        if self.err.is_none() {
+                            let _ = self.value_err.set(e);
+                        }
+                        return false;
+                    }
+                }
+            } else if self.loads_values() {
+                val.clear();
+                val.extend_from_slice(&self.value);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        match self.current_block {
+            Some(ref cb) if self.key_codec.is_some() => cb.current_key().map(|_| &self.key[..]),
+            Some(ref cb) => cb.current_key(),
+            None => None,
+        }
+    }
+    fn status(&self) -> Option<&Error> {
+        self.err.as_ref().or_else(|| self.value_err.get())
+    }
+}
+
+/// Opens the data blocks of a table for the iterator returned by `Table::raw_iter()`.
+pub struct DataBlocks(Table);
+
+impl BlockFunction for DataBlocks {
+    type Iter = BlockIter;
+
+    fn open(&mut self, index_value: &[u8]) -> Result<BlockIter> {
+        Ok(self.0.read_block(&index_handle(index_value))?.iter())
+    }
+}
+
+/// An iterator over the separator keys and locations of a table's data blocks. See
+/// `Table::index_iter()`.
+pub struct IndexIterator {
+    iter: BlockIter,
+    // Whether the block iterator has been positioned at the next entry by seek().
+    seeked: bool,
+}
+
+impl IndexIterator {
+    /// Positions the iterator so that the next entry is that of the first block that may contain
+    /// keys at or after `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(key);
+        self.seeked = true;
+    }
+}
+
+impl Iterator for IndexIterator {
+    type Item = (Vec<u8>, BlockHandle);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !std::mem::replace(&mut self.seeked, false) && !self.iter.advance() {
+            return None;
+        }
+        let key = self.iter.current_key()?.to_vec();
+        let handle = index_handle(self.iter.current_value()?);
+        Some((key, handle))
+    }
+}
+
+/// An iterator over the entries of a table whose keys start with a given prefix. See
+/// `Table::prefix_iter()`.
+pub struct PrefixIterator {
+    iter: TableIterator,
+    prefix: Vec<u8>,
+    started: bool,
+}
+
+impl SSIterator for PrefixIterator {
+    fn advance(&mut self) -> bool {
+        if !self.started {
+            self.started = true;
+            self.iter.seek(&self.prefix);
+        } else if self.valid() {
+            self.iter.advance();
+        }
+        self.valid()
+    }
+
+    fn seek(&mut self, to: &[u8]) {
+        self.started = true;
+        if self.iter.table.opt.cmp.cmp(to, &self.prefix) == Ordering::Less {
+            self.iter.seek(&self.prefix);
+        } else {
+            self.iter.seek(to);
+        }
+    }
+
+    fn prev(&mut self) -> bool {
+        self.valid() && self.iter.prev() && self.valid()
+    }
+
+    fn reset(&mut self) {
+        self.started = false;
+        self.iter.reset();
+    }
+
+    fn valid(&self) -> bool {
+        self.started
+            && self.iter.valid()
+            && self
+                .iter
+                .current_key()
+                .is_some_and(|k| k.starts_with(&self.prefix))
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.valid() && self.iter.current(key, val)
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.iter.status()
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        if self.valid() {
+            self.iter.current_key()
+        } else {
+            None
+        }
+    }
+}
+
+/// An iterator over the user keys of a table with internal keys, as of a snapshot. See
+/// `Table::snapshot_iter()`.
+pub struct SnapshotIterator {
+    iter: TableIterator,
+    snapshot: SequenceNumber,
+    // The current user key and its value.
+    key: Vec<u8>,
+    val: Vec<u8>,
+    valid: bool,
+    // After prev(), `iter` is positioned before the entries of the current key instead of at the
+    // visible one.
+    reverse: bool,
+}
+
+impl SnapshotIterator {
+    /// Parses the current internal key of `iter`.
+    fn parse_current(&self) -> Option<(ValueType, SequenceNumber, Vec<u8>)> {
+        let (t, seq, ukey) = key_types::parse_internal_key(self.iter.current_key()?)?;
+        Some((t, seq, ukey.to_vec()))
+    }
+
+    /// Starting at the current entry of `iter`, finds the first visible, non-deleted key. If
+    /// `skip` is set, older versions of the current key are skipped.
+    fn find_next_user_entry(&mut self, mut skip: bool) -> bool {
+        while self.iter.valid() {
+            if let Some((t, seq, ukey)) = self.parse_current() {
+                if seq <= self.snapshot && !(skip && ukey == self.key) {
+                    self.key = ukey;
+                    match t {
+                        ValueType::TypeDeletion => skip = true,
+                        ValueType::TypeValue => {
+                            let mut ikey = vec![];
+                            self.valid = self.iter.current(&mut ikey, &mut self.val);
+                            return self.valid;
+                        }
+                    }
+                }
+            }
+            self.iter.advance();
+        }
+        self.valid = false;
+        false
+    }
+
+    /// Starting at the current entry of `iter` and moving backwards, finds the newest visible
+    /// version of the previous non-deleted key.
+    fn find_prev_user_entry(&mut self) -> bool {
+        let mut found = None;
+        while self.iter.valid() {
+            if let Some((t, seq, ukey)) = self.parse_current() {
+                if seq <= self.snapshot {
+                    if found == Some(ValueType::TypeValue) && ukey != self.key {
+                        break;
+                    }
+                    found = Some(t);
+                    self.key = ukey;
+                    if t == ValueType::TypeValue {
+                        let mut ikey = vec![];
+                        if !self.iter.current(&mut ikey, &mut self.val) {
+                            found = None;
+                            break;
+                        }
+                    }
+                }
+            }
+            if !self.iter.prev() {
+                break;
+            }
+        }
+        self.valid = found == Some(ValueType::TypeValue);
+        self.valid
+    }
+}
+
+impl SSIterator for SnapshotIterator {
+    fn advance(&mut self) -> bool {
+        if self.reverse {
+            self.reverse = false;
+            if self.valid {
+                // Position `iter` at the current entry again.
+                self.iter.seek(&key_types::build_internal_key(
+                    &self.key,
+                    self.snapshot,
+                    ValueType::TypeValue,
+                ));
+            }
+        } else {
+            self.iter.advance();
+        }
+        let skip = self.valid;
+        self.find_next_user_entry(skip)
+    }
+
+    fn seek(&mut self, to: &[u8]) {
+        self.reverse = false;
+        self.iter.seek(&key_types::build_internal_key(
+            to,
+            self.snapshot,
+            ValueType::TypeValue,
+        ));
+        self.find_next_user_entry(false);
+    }
+
+    fn prev(&mut self) -> bool {
+        if !self.valid {
+            return false;
+        }
+        if !self.reverse {
+            // Move before all versions of the current key.
+            loop {
+                if !self.iter.prev() {
+                    self.valid = false;
+                    return false;
+                }
+                match self.parse_current() {
+                    Some((_, _, ukey)) if ukey == self.key => continue,
+                    _ => break,
+                }
+            }
+            self.reverse = true;
+        }
+        self.find_prev_user_entry()
+    }
+
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.valid = false;
+        self.reverse = false;
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.iter.status()
+    }
+
+    fn valid(&self) -> bool {
+        self.valid
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if self.valid {
+            key.clone_from(&self.key);
+            val.clone_from(&self.val);
+        }
+        self.valid
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        if self.valid {
+            Some(&self.key)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_stats::BlockStatsAggregator;
+    use crate::cache::{BlockCache, CacheKey, CacheStats, LRUBlockCache, MemoryBudget};
+    use crate::cmp::InternalKeyCmp;
+    #[cfg(feature = "snappy")]
+    use crate::error::CorruptionKind;
+    use crate::hash_index::{FixedPrefix, PrefixExtractor};
+    use crate::key_codec::KeyCodec;
+    use crate::manifest;
+    #[cfg(feature = "snappy")]
+    use crate::options::CompressionType;
+    use crate::options::{IndexShortening, IndexType, Temperature};
+    use crate::table_builder::TableBuilder;
+    use crate::table_properties::TablePropertiesCollector;
+    use crate::test_util::{test_iterator_properties, SSIteratorIter};
+    use crate::types::{current_key_val, SSIterator};
+    use crate::value_codec::ValueCodec;
+    use crate::version::LevelFile;
 
     use super::*;
 
-    const LOCK_POISONED: &str = "Lock poisoned";
+    use std::collections::HashMap;
+    use std::sync::atomic::{self, AtomicUsize};
+    use std::sync::Mutex;
+
+    const LOCK_POISONED: &str = "Lock poisoned";
+
+    fn build_data() -> Vec<(&'static str, &'static str)> {
+        vec![
+            // block 1
+            ("abc", "def"),
+            ("abd", "dee"),
+            ("bcd", "asa"),
+            // block 2
+            ("bsr", "a00"),
+            ("xyz", "xxx"),
+            ("xzz", "yyy"),
+            // block 3
+            ("zzz", "111"),
+        ]
+    }
+
+    // Build a table containing raw keys (no format). It returns (vector, length) for convenience
+    // reason, a call f(v, v.len()) doesn't work for borrowing reasons.
+    fn build_table(data: Vec<(&'static str, &'static str)>) -> (Vec<u8>, u64) {
+        let mut d = Vec::with_capacity(512);
+        let mut opt = Options::default();
+        opt.block_restart_interval = 2;
+        opt.block_size = 32;
+        #[cfg(feature = "snappy")]
+        {
+            opt.compression_type = CompressionType::CompressionSnappy;
+        }
+
+        {
+            // Uses the standard comparator in opt.
+            let mut b = TableBuilder::new(opt, &mut d);
+
+            for &(k, v) in data.iter() {
+                b.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+
+            b.finish().unwrap();
+        }
+
+        let size = d.len() as u64;
+        (d, size)
+    }
+
+    fn wrap_buffer(src: Vec<u8>) -> Box<dyn RandomAccess> {
+        Box::new(src)
+    }
+
+    /// A RandomAccess counting how often it has been read from.
+    struct CountingFile {
+        data: Vec<u8>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl RandomAccess for CountingFile {
+        fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+            self.reads.fetch_add(1, atomic::Ordering::SeqCst);
+            self.data.read_at(off, dst)
+        }
+
+        fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+            self.reads.fetch_add(1, atomic::Ordering::SeqCst);
+            self.data.read_vectored(off, bufs)
+        }
+    }
+
+    #[cfg(all(feature = "snappy", feature = "filters"))]
+    #[test]
+    fn test_table_approximate_offset() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+
+        let expected_offsets = [0, 0, 0, 44, 44, 44, 89];
+        for (i, (k, _)) in SSIteratorIter::wrap(&mut iter).enumerate() {
+            assert_eq!(expected_offsets[i], table.approx_offset_of(&k));
+        }
+
+        // Key-past-last returns offset of metaindex block (following the data, filter and
+        // properties blocks).
+        assert_eq!(294, table.approx_offset_of("{aa".as_bytes()));
+
+        // With a key codec, offsets are those of the blocks holding the logical keys.
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        opt.key_codec = Some(Arc::new(PrefixKeyCodec));
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d);
+        for (k, v) in build_data() {
+            b.add(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        let size = b.finish().unwrap();
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        assert!(table.index_handles().len() > 1);
+        for handle in table.index_handles() {
+            let mut block = table.read_block(&handle).unwrap().iter();
+            while block.advance() {
+                let key = table.logical_key(block.current_key().unwrap()).unwrap();
+                assert_eq!(table.approx_offset_of(&key), handle.offset());
+            }
+        }
+    }
+
+    /// Stores keys with a `~` prepended, so that they sort after all logical keys.
+    struct PrefixKeyCodec;
+
+    impl KeyCodec for PrefixKeyCodec {
+        fn name(&self) -> &str {
+            "prefix"
+        }
+        fn encode(&self, key: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            dst.push(b'~');
+            dst.extend_from_slice(key);
+            Ok(())
+        }
+        fn decode(&self, stored: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            match stored.split_first() {
+                Some((b'~', key)) => {
+                    dst.extend_from_slice(key);
+                    Ok(())
+                }
+                _ => Err(Error::malformed(None, "bad prefixed key")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_index_shortening() {
+        for &(shortening, index_key_size) in &[
+            (IndexShortening::ShortenSeparatorsAndSuccessor, 4),
+            (IndexShortening::ShortenSeparators, 6),
+            (IndexShortening::NoShortening, 9),
+        ] {
+            let mut opt = Options::default();
+            opt.block_size = 32;
+            opt.index_shortening = shortening;
+            let mut d = vec![];
+            {
+                let mut b = TableBuilder::new(opt.clone(), &mut d);
+                for &(k, v) in build_data().iter() {
+                    b.add(k.as_bytes(), v.as_bytes()).unwrap();
+                }
+                b.finish().unwrap();
+            }
+            let size = d.len() as u64;
+            let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+
+            let props = table.properties().unwrap();
+            assert_eq!(props.num_data_blocks, 3);
+            assert_eq!(props.raw_index_key_size, 9);
+            assert_eq!(props.index_key_size, index_key_size);
+
+            for &(k, v) in build_data().iter() {
+                assert_eq!(
+                    table.get(k.as_bytes()).unwrap(),
+                    Some(v.as_bytes().to_vec())
+                );
+            }
+            assert_eq!(table.get(b"bd").unwrap(), None);
+            assert_eq!(table.get(b"zzzz").unwrap(), None);
+
+            let mut iter = table.iter();
+            iter.seek(b"xzz");
+            assert_eq!(current_key_val(&iter).unwrap().0, b"xzz".to_vec());
+            iter.seek(b"xzza");
+            assert_eq!(current_key_val(&iter).unwrap().0, b"zzz".to_vec());
+            iter.seek(b"zzza");
+            assert!(!iter.valid());
+        }
+    }
+
+    #[test]
+    fn test_table_iterator_entry_meta() {
+        for &entry_expiration in &[false, true] {
+            let mut opt = Options::default();
+            opt.block_size = 32;
+            opt.entry_expiration = entry_expiration;
+            let mut d = vec![];
+            {
+                let mut b = TableBuilder::new(opt.clone(), &mut d);
+                for &(k, v) in build_data().iter() {
+                    b.add(k.as_bytes(), v.as_bytes()).unwrap();
+                }
+                b.finish().unwrap();
+            }
+            let size = d.len() as u64;
+            let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+
+            let mut iter = table.iter();
+            assert!(iter.current_entry_meta().is_none());
+            let mut blocks = vec![];
+            for &(k, v) in build_data().iter() {
+                assert!(iter.advance());
+                assert_eq!(iter.current_key().unwrap(), k.as_bytes());
+                let meta = iter.current_entry_meta().unwrap();
+                assert_eq!(meta.value_len, v.len());
+                let block = table.read_block(&meta.block).unwrap();
+                let contents = block.contents();
+                assert_eq!(
+                    &contents[meta.value_offset..meta.value_offset + meta.value_len],
+                    v.as_bytes()
+                );
+                if !blocks.contains(&meta.block) {
+                    blocks.push(meta.block);
+                }
+            }
+            assert_eq!(blocks, table.index_handles());
+        }
+    }
+
+    #[test]
+    fn test_table_get_with_hint() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+
+        let mut hint = SeekHint::new();
+        for &(k, v) in build_data().iter() {
+            assert_eq!(
+                table.get_with_hint(k.as_bytes(), &mut hint).unwrap(),
+                Some(v.as_bytes().to_vec())
+            );
+        }
+        // The last block was reached by advancing the index, not by seeking.
+        assert!(!hint.lower_inclusive);
+        assert_eq!(hint.lower, b"y".to_vec());
+
+        for k in &["zzz", "abc", "xzz", "abd", "bsr", "bcd"] {
+            assert_eq!(
+                table.get_with_hint(k.as_bytes(), &mut hint).unwrap(),
+                table.get(k.as_bytes()).unwrap()
+            );
+        }
+        for k in &["aaa", "abe", "bd", "xzza", "zzzz", "abc"] {
+            assert_eq!(
+                table.get_with_hint(k.as_bytes(), &mut hint).unwrap(),
+                table.get(k.as_bytes()).unwrap()
+            );
+        }
+
+        // Using the hint with another table resets it.
+        let (src2, size2) = build_table(vec![("abc", "other")]);
+        let table2 = Table::new(opt, wrap_buffer(src2), size2).unwrap();
+        assert_eq!(
+            table2.get_with_hint(b"abc", &mut hint).unwrap(),
+            Some(b"other".to_vec())
+        );
+        assert_eq!(table2.get_with_hint(b"abd", &mut hint).unwrap(), None);
+        assert_eq!(
+            table.get_with_hint(b"abd", &mut hint).unwrap(),
+            Some(b"dee".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_table_dump_hot_blocks_and_warm() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        assert!(table.dump_hot_blocks().is_empty());
+
+        assert!(table.get(b"abc").unwrap().is_some());
+        assert!(table.get(b"zzz").unwrap().is_some());
+        let handles = table.index_handles();
+        let hot = table.dump_hot_blocks();
+        assert_eq!(hot, vec![handles[0].clone(), handles[2].clone()]);
+
+        // Restore the hot blocks into a new cache, as after a restart.
+        opt.block_cache = Arc::new(LRUBlockCache::new(100));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt, Box::new(file), size).unwrap();
+        let mut saved = hot.clone();
+        saved.push(BlockHandle::new(1, 2));
+        saved.push(hot[0].clone());
+        assert_eq!(table.warm(&saved).unwrap(), 2);
+        assert_eq!(table.dump_hot_blocks(), hot);
+
+        let before = reads.load(atomic::Ordering::SeqCst);
+        assert!(table.get(b"abd").unwrap().is_some());
+        assert!(table.get(b"zzz").unwrap().is_some());
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), before);
+        assert!(table.get(b"xyz").unwrap().is_some());
+        assert!(reads.load(atomic::Ordering::SeqCst) > before);
+    }
+
+    #[test]
+    fn test_table_raw_iter() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.raw_iter();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = iter.entries().collect();
+        assert_eq!(entries, table.iter().entries().collect::<Vec<_>>());
+        assert_eq!(entries.len(), build_data().len());
+
+        iter.seek(b"bsr");
+        assert_eq!(iter.current_key(), Some(&b"bsr"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+    }
+
+    #[test]
+    fn test_table_index_iter() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+
+        let entries: Vec<(Vec<u8>, BlockHandle)> = table.index_iter().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries.iter().map(|(_, h)| h.clone()).collect::<Vec<_>>(),
+            table.index_handles()
+        );
+        // Every block's last key is not greater than its separator.
+        for ((sep, handle), last) in entries.iter().zip(&["bcd", "xzz", "zzz"]) {
+            assert!(sep.as_slice() >= last.as_bytes());
+            let mut block = table.read_block(handle).unwrap().iter();
+            block.seek_to_last();
+            assert_eq!(block.current_key(), Some(last.as_bytes()));
+        }
+
+        let mut iter = table.index_iter();
+        iter.seek(b"bsr");
+        assert_eq!(iter.next().unwrap().1, entries[1].1);
+        assert_eq!(iter.next().unwrap().1, entries[2].1);
+        assert!(iter.next().is_none());
+        iter.seek(b"a");
+        assert_eq!(iter.next().unwrap().1, entries[0].1);
+        iter.seek(b"{{");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_table_raw_block_access() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+
+        let handles = table.index_handles();
+        assert_eq!(handles.len(), 3);
+        assert_eq!(handles[0].offset(), 0);
+
+        let mut keys = vec![];
+        for h in handles.iter() {
+            let block = table.read_block(h).unwrap();
+            let mut iter = block.iter();
+            while iter.advance() {
+                keys.push(iter.current_key().unwrap().to_vec());
+            }
+        }
+        let expected: Vec<_> = build_data()
+            .iter()
+            .map(|(k, _)| k.as_bytes().to_vec())
+            .collect();
+        assert_eq!(keys, expected);
+
+        // Corrupt blocks are reported.
+        let (mut src, size) = build_table(build_data());
+        src[10] += 1;
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        match table.read_block(&table.index_handles()[0]) {
+            Err(e) => assert!(e.is_corruption()),
+            Ok(_) => panic!("expected corruption"),
+        }
+    }
+
+    #[cfg(feature = "filters")]
+    #[test]
+    fn test_table_meta_blocks() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        assert_eq!(
+            table.meta_block_names(),
+            vec!["filter.leveldb.BuiltinBloomFilter2", PROPERTIES_BLOCK_KEY]
+        );
+        let props = table
+            .read_meta_block(PROPERTIES_BLOCK_KEY)
+            .unwrap()
+            .unwrap();
+        let block = Block::new(Options::default(), props);
+        assert_eq!(TableProperties::decode(&block).unwrap().num_entries, 7);
+        assert!(table
+            .read_meta_block("rocksdb.properties")
+            .unwrap()
+            .is_none());
+
+        let mut iter = table.metaindex_iter();
+        assert!(iter.advance());
+        let handle = BlockHandle::decode(iter.current_value().unwrap())
+            .unwrap()
+            .0;
+        assert!(handle.size() > 0);
+    }
+
+    #[test]
+    fn test_table_iterator_skip_current_block() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+
+        // The blocks and their keys as seen by advancing entry by entry.
+        let mut expected: Vec<BlockBounds> = vec![];
+        let mut iter = table.iter();
+        while iter.advance() {
+            let key = iter.current_key().unwrap().to_vec();
+            let block = iter.current_entry_meta().unwrap().block;
+            match expected.last_mut() {
+                Some(b) if b.block == block => b.last_key = key,
+                _ => expected.push(BlockBounds {
+                    block,
+                    first_key: key.clone(),
+                    last_key: key,
+                }),
+            }
+        }
+        assert!(expected.len() > 2);
+
+        let mut iter = table.iter();
+        assert!(iter.current_block_bounds().is_none());
+        let mut bounds = vec![];
+        while iter.skip_current_block() {
+            let b = iter.current_block_bounds().unwrap();
+            assert_eq!(iter.current_key().unwrap(), &b.first_key[..]);
+            bounds.push(b);
+        }
+        assert_eq!(bounds, expected);
+        assert!(!iter.valid());
+        assert!(iter.current_block_bounds().is_none());
+
+        // Skipping from the middle of a block moves on to the next one.
+        iter.seek(&expected[1].last_key);
+        assert_eq!(iter.current_block_bounds().as_ref(), Some(&expected[1]));
+        assert!(iter.skip_current_block());
+        assert_eq!(iter.current_key().unwrap(), &expected[2].first_key[..]);
+        // Past the last block, the iterator is exhausted.
+        iter.seek(&expected.last().unwrap().first_key);
+        assert!(!iter.skip_current_block());
+    }
+
+    #[cfg(feature = "filters")]
+    #[test]
+    fn test_table_filter_block_handle() {
+        let (src, size) = build_table(build_data());
+        let opt = Options::default();
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        let (handle, policy) = table.filter_block_handle().unwrap();
+        assert_eq!(policy.name(), opt.filter_policy.name());
+
+        // Only the filter block is read.
+        let filters = FilterBlockReader::read(&src, size, &handle, policy).unwrap();
+        for (k, _) in build_data() {
+            let block = table.approx_offset_of(k.as_bytes());
+            assert!(filters.key_may_match(block, k.as_bytes()));
+        }
+        assert!(!filters.key_may_match(0, b"abe"));
+
+        let mut d = vec![];
+        TableBuilder::new_no_filter(opt.clone(), &mut d)
+            .finish()
+            .unwrap();
+        let size = d.len() as u64;
+        let table = Table::new(opt.clone(), wrap_buffer(d), size).unwrap();
+        assert!(table.filter_block_handle().is_none());
+
+        // Tables written without filters are readable with a NoFilterPolicy configured.
+        let mut d = vec![];
+        let mut b = TableBuilder::new_no_filter(opt.clone(), &mut d);
+        for (k, v) in build_data() {
+            b.add(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        b.finish().unwrap();
+        let size = d.len() as u64;
+        let mut opt = opt;
+        opt.filter_policy = Arc::new(Box::new(filter::NoFilterPolicy::new()));
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        assert!(table.filter_block_handle().is_none());
+        for (k, v) in build_data() {
+            assert_eq!(table.get(k.as_bytes()).unwrap().unwrap(), v.as_bytes());
+        }
+    }
+
+    #[cfg(feature = "filters")]
+    #[test]
+    fn test_table_builtin_filters() {
+        use crate::filter::{FilterPolicy, RocksDBBloomPolicy, RocksDBFullFilterPolicy};
+
+        let policies: Vec<Box<dyn FilterPolicy>> = vec![
+            Box::new(RocksDBBloomPolicy::new(10)),
+            Box::new(RocksDBFullFilterPolicy::new(10)),
+        ];
+        for policy in policies {
+            let name = filter::meta_block_name(policy.as_ref());
+            let mut opt = Options::default();
+            opt.block_size = 32;
+            opt.filter_policy = Arc::new(policy);
+            let mut d = vec![];
+            {
+                let mut b = TableBuilder::new(opt, &mut d);
+                for (k, v) in build_data() {
+                    b.add(k.as_bytes(), v.as_bytes()).unwrap();
+                }
+                b.finish().unwrap();
+            }
+            let size = d.len() as u64;
+
+            // Read with the default (LevelDB) policy configured.
+            let table = Table::new(Options::default(), wrap_buffer(d), size).unwrap();
+            assert_eq!(table.meta_block_names()[0], name);
+            let (_, policy) = table.filter_block_handle().unwrap();
+            assert_eq!(filter::meta_block_name(policy.as_ref().as_ref()), name);
+
+            let filters = table.filters.as_ref().unwrap();
+            for (k, v) in build_data() {
+                assert!(filters.key_may_match(table.approx_offset_of(k.as_bytes()), k.as_bytes()));
+                assert_eq!(
+                    table.get(k.as_bytes()).unwrap(),
+                    Some(v.as_bytes().to_vec())
+                );
+            }
+            let misses = (0..100)
+                .filter(|i| {
+                    let key = format!("abc{}", i);
+                    !filters.key_may_match(table.approx_offset_of(key.as_bytes()), key.as_bytes())
+                })
+                .count();
+            assert!(misses > 50, "{} {}", name, misses);
+        }
+    }
+
+    #[test]
+    fn test_table_prefix_iter() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+
+        let keys = |prefix: &[u8]| {
+            let mut iter = table.prefix_iter(prefix);
+            SSIteratorIter::wrap(&mut iter)
+                .map(|(k, _)| String::from_utf8(k).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(b"ab"), vec!["abc", "abd"]);
+        assert_eq!(keys(b"b"), vec!["bcd", "bsr"]);
+        // Spanning a block boundary.
+        assert_eq!(keys(b"x"), vec!["xyz", "xzz"]);
+        assert_eq!(keys(b"zzz"), vec!["zzz"]);
+        assert_eq!(keys(b"abe"), Vec::<String>::new());
+        assert_eq!(keys(b"\xff"), Vec::<String>::new());
+        assert_eq!(keys(b"").len(), 7);
+
+        let data = vec![
+            ("aa", "1"),
+            ("ba", "2"),
+            ("bb", "3"),
+            ("bc", "4"),
+            ("bd", "5"),
+            ("ca", "6"),
+        ];
+        let (src, size) = build_table(data);
+        let four = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        test_iterator_properties(four.prefix_iter(b"b"));
+
+        let mut iter = table.prefix_iter(b"b");
+        assert!(!iter.valid());
+        iter.seek(b"a");
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+        iter.seek(b"bs");
+        assert_eq!(iter.current_key(), Some(&b"bsr"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+        assert!(!iter.prev());
+        assert!(!iter.advance());
+
+        iter.reset();
+        assert!(iter.advance());
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+    }
+
+    fn build_versioned_table() -> (Vec<u8>, Options) {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        let entries: &[(&str, SequenceNumber, ValueType, &str)] = &[
+            ("a", 3, ValueType::TypeValue, "a3"),
+            ("b", 9, ValueType::TypeDeletion, ""),
+            ("b", 5, ValueType::TypeValue, "b5"),
+            ("b", 2, ValueType::TypeValue, "b2"),
+            ("c", 7, ValueType::TypeValue, "c7"),
+            ("d", 4, ValueType::TypeDeletion, ""),
+            ("d", 1, ValueType::TypeValue, "d1"),
+            ("e", 6, ValueType::TypeValue, "e6"),
+            ("e", 5, ValueType::TypeValue, "e5"),
+        ];
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d);
+        for &(k, seq, t, v) in entries {
+            b.add(
+                &key_types::build_internal_key(k.as_bytes(), seq, t),
+                v.as_bytes(),
+            )
+            .unwrap();
+        }
+        b.finish().unwrap();
+        (d, opt)
+    }
+
+    #[test]
+    fn test_table_garbage_ratio() {
+        let (src, opt) = build_versioned_table();
+        let size = src.len() as u64;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        let props = table.properties().unwrap();
+        assert_eq!(props.num_deletions, Some(2));
+        // b5, b2, d1 and e5.
+        assert_eq!(props.num_overwritten, Some(4));
+        assert_eq!(table.garbage_ratio(), Some(6.0 / 9.0));
+
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        assert_eq!(table.properties().unwrap().num_deletions, None);
+        assert_eq!(table.garbage_ratio(), None);
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_max_decompressed_block_size() {
+        let mut opt = Options::default().with_cache_capacity(16);
+        opt.compression_type = CompressionType::CompressionSnappy;
+        opt.block_size = 1024;
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for i in 0..100 {
+                b.add(format!("key{:03}", i).as_bytes(), &[b'x'; 100])
+                    .unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+
+        opt.max_decompressed_block_size = Some(64 << 10);
+        let table = Table::new(opt.clone(), wrap_buffer(d.clone()), size).unwrap();
+        assert!(table.get(b"key050").unwrap().is_some());
+
+        // The index block is small enough, data blocks aren't.
+        opt.max_decompressed_block_size = Some(512);
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        match table.get(b"key050") {
+            Err(Error::Corruption {
+                kind: CorruptionKind::DecompressedSizeExceeded { size, limit: 512 },
+                block_handle: Some(_),
+                ..
+            }) => assert!(size > 1024),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_table_get_at() {
+        let (src, opt) = build_versioned_table();
+        let size = src.len() as u64;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+
+        let get = |k: &str, seq| {
+            table
+                .get_at(k.as_bytes(), seq)
+                .unwrap()
+                .map(|v| String::from_utf8(v).unwrap())
+        };
+        assert_eq!(get("a", 2), None);
+        assert_eq!(get("a", 3).as_deref(), Some("a3"));
+        assert_eq!(get("b", 1), None);
+        assert_eq!(get("b", 4).as_deref(), Some("b2"));
+        assert_eq!(get("b", 8).as_deref(), Some("b5"));
+        assert_eq!(get("b", 9), None);
+        assert_eq!(get("d", 3).as_deref(), Some("d1"));
+        assert_eq!(get("d", 100), None);
+        assert_eq!(get("e", 100).as_deref(), Some("e6"));
+        assert_eq!(get("f", 100), None);
+    }
+
+    #[test]
+    fn test_table_snapshot_iter() {
+        let (src, opt) = build_versioned_table();
+        let size = src.len() as u64;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+
+        let scan = |seq| {
+            let mut iter = table.snapshot_iter(seq);
+            SSIteratorIter::wrap(&mut iter)
+                .map(|(k, v)| {
+                    format!(
+                        "{}={}",
+                        String::from_utf8(k).unwrap(),
+                        String::from_utf8(v).unwrap()
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(scan(100), vec!["a=a3", "c=c7", "e=e6"]);
+        assert_eq!(scan(5), vec!["a=a3", "b=b5", "e=e5"]);
+        assert_eq!(scan(3), vec!["a=a3", "b=b2", "d=d1"]);
+        assert_eq!(scan(0), Vec::<String>::new());
+        assert_eq!(scan(8), vec!["a=a3", "b=b5", "c=c7", "e=e6"]);
+        test_iterator_properties(table.snapshot_iter(8));
+
+        let mut iter = table.snapshot_iter(5);
+        iter.seek(b"b");
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"b".to_vec(), b"b5".to_vec()))
+        );
+        iter.seek(b"bb");
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"e".to_vec(), b"e5".to_vec()))
+        );
+        assert!(iter.prev());
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"b".to_vec(), b"b5".to_vec()))
+        );
+        assert!(iter.prev());
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"a".to_vec(), b"a3".to_vec()))
+        );
+        assert!(!iter.prev());
+
+        iter.seek(b"e");
+        assert!(iter.prev());
+        assert!(iter.advance());
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"e".to_vec(), b"e5".to_vec()))
+        );
+        assert!(!iter.advance());
+    }
+
+    #[test]
+    fn test_table_estimate_keys_in_range() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let estimate = |start: &[u8], end: &[u8]| table.estimate_keys_in_range(start, end).unwrap();
+
+        // Boundary blocks are counted exactly.
+        assert_eq!(estimate(b"abd", b"xz"), 4);
+        assert_eq!(estimate(b"abc", b"abd"), 1);
+        assert_eq!(estimate(b"bsr", b"zzz"), 3);
+        // The middle block is assumed to hold round(7 / 3) entries.
+        assert_eq!(estimate(b"a", b"zzzz"), 6);
+        assert_eq!(estimate(b"", b"\xff"), 6);
+
+        assert_eq!(estimate(b"b", b"b"), 0);
+        assert_eq!(estimate(b"x", b"b"), 0);
+        assert_eq!(estimate(b"zzzz", b"\xff"), 0);
+    }
+
+    #[test]
+    fn test_table_bad_handles() {
+        assert!(Table::new(Options::default(), wrap_buffer(vec![0; 10]), 10)
+            .err()
+            .unwrap()
+            .is_corruption());
+
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        for h in &[
+            BlockHandle::new(u64::MAX - 2, 1),
+            BlockHandle::new(1, u64::MAX - 2),
+            // Past the end of the file, but without overflowing.
+            BlockHandle::new(0, size),
+            BlockHandle::new(size - 4, 1),
+            BlockHandle::new(1 << 40, 1 << 20),
+        ] {
+            assert!(table.read_block(h).err().unwrap().is_corruption());
+            assert!(table.read_blob(h).err().unwrap().is_corruption());
+        }
+    }
+
+    #[test]
+    fn test_table_leveldb_checksums() {
+        // Written by C++ LevelDB, which masks its checksums.
+        let src = include_bytes!("../tests/golden/leveldb_uncompressed.ldb").to_vec();
+        let size = src.len() as u64;
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        assert_eq!(
+            table.options().checksummer.name(),
+            checksum::LEVELDB_CRC32C_NAME
+        );
+        let mut iter = table.iter();
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), 258);
+        assert!(iter.status().is_none());
+
+        // Tables written with version 0 footers by this crate store their checksums unmasked.
+        let (mut src, size) = build_table(build_data());
+        let footer_offset = src.len() - table_builder::FULL_FOOTER_LENGTH;
+        let mut footer =
+            Footer::decode(&src[footer_offset..], table_builder::LEVELDB_MAGIC_NUMBER).unwrap();
+        footer.format_version = 0;
+        footer.encode(&mut src[footer_offset..]);
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        assert_eq!(table.options().checksummer.name(), CRC32C_NAME);
+        let mut iter = table.iter();
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), build_data().len());
+    }
+
+    #[test]
+    fn test_table_handles_past_eof() {
+        let (mut src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src.clone()), size).unwrap();
+        // Rejected before allocating a buffer for the block.
+        match table.read_block(&BlockHandle::new(0, 1 << 28)) {
+            Err(Error::Corruption {
+                kind: CorruptionKind::Malformed(_),
+                ..
+            }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let footer_offset = src.len() - table_builder::FULL_FOOTER_LENGTH;
+        let mut footer =
+            Footer::decode(&src[footer_offset..], table_builder::LEVELDB_MAGIC_NUMBER).unwrap();
+        footer.index = BlockHandle::new(footer.index.offset(), 1 << 46);
+        footer.encode(&mut src[footer_offset..]);
+        match Table::new(Options::default(), wrap_buffer(src), size) {
+            Err(Error::Corruption {
+                kind: CorruptionKind::Malformed(_),
+                ..
+            }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_table_magic_number() {
+        let mut opt = Options::default();
+        opt.table_magic_number = 0x0123_4567_89ab_cdef;
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut d);
+        b.add(b"abc", b"def").unwrap();
+        b.finish().unwrap();
+        let size = d.len() as u64;
+
+        assert_eq!(
+            &d[d.len() - 8..],
+            &0x0123_4567_89ab_cdef_u64.to_le_bytes()[..]
+        );
+        let table = Table::new(opt, wrap_buffer(d.clone()), size).unwrap();
+        assert_eq!(table.get(b"abc").unwrap().unwrap(), b"def");
+        assert!(Table::new(Options::default(), wrap_buffer(d), size)
+            .err()
+            .unwrap()
+            .is_corruption());
+    }
+
+    #[test]
+    fn test_table_block_cache_use() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+
+        // index/metaindex blocks are not cached. That'd be a waste of memory.
+        assert_eq!(opt.block_cache.count(), 0);
+
+        iter.next();
+        assert_eq!(opt.block_cache.count(), 1);
+
+        // This may fail if block parameters or data change. In that case, adapt it.
+        iter.next();
+        iter.next();
+        iter.next();
+        iter.next();
+        assert_eq!(opt.block_cache.count(), 2);
+    }
+
+    #[test]
+    fn test_table_block_tiny_cache() {
+        let (src, size) = build_table(build_data());
+        // Create a table with no block cache
+        let mut opt = Options::default().with_cache_capacity(1);
+        opt.block_size = 32;
+
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+
+        // index/metaindex blocks are not cached. That'd be a waste of memory.
+        assert_eq!(opt.block_cache.count(), 0);
+
+        // We should have at most one item in the cache
+        iter.next();
+        assert_eq!(opt.block_cache.count(), 1);
+        iter.next();
+        assert_eq!(opt.block_cache.count(), 1);
+    }
+
+    #[cfg(feature = "clock_cache")]
+    #[test]
+    fn test_table_fill_cache() {
+        use crate::options::CachePolicy;
+
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default().with_cache_policy(CachePolicy::TinyLFU, 8);
+        opt.block_size = 32;
+
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        let ropt = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), build_data().len());
+        assert!(table.get_opt(&ropt, b"abc").unwrap().is_some());
+        assert_eq!(opt.block_cache.count(), 0);
+
+        // Cached blocks are still used.
+        assert!(table.get(b"abc").unwrap().is_some());
+        assert_eq!(opt.block_cache.count(), 1);
+        let mut iter = table.iter_opt(&ropt);
+        for _ in SSIteratorIter::wrap(&mut iter) {}
+        assert_eq!(opt.block_cache.count(), 1);
+    }
+
+    #[test]
+    fn test_table_scan_detection() {
+        let key = |i: usize| format!("key{:04}", i).into_bytes();
+        let mut opt = Options::default().with_cache_capacity(10);
+        opt.block_size = 128;
+        let mut src = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut src);
+        for i in 0..1000 {
+            b.add(&key(i), b"some value").unwrap();
+        }
+        let size = b.finish().unwrap();
+
+        // Looks up a working set of keys in different blocks after a full scan, returning the hit
+        // rate of the lookups.
+        let hit_rate_after_scan = |ropt: &ReadOptions| {
+            let opt = opt.clone().with_cache_capacity(10);
+            let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+            assert!(table.index_handles().len() > 50);
+            for i in (0..500).step_by(100) {
+                assert!(table.get(&key(i)).unwrap().is_some());
+            }
+            let mut iter = table.iter_opt(ropt);
+            assert_eq!(SSIteratorIter::wrap(&mut iter).count(), 1000);
+
+            let before = opt.block_cache.stats();
+            for i in (0..500).step_by(100) {
+                assert!(table.get(&key(i)).unwrap().is_some());
+            }
+            let after = opt.block_cache.stats();
+            CacheStats {
+                hits: after.hits - before.hits,
+                misses: after.misses - before.misses,
+                ..Default::default()
+            }
+            .hit_rate()
+        };
+
+        // The scan flushes the working set from the cache...
+        assert_eq!(hit_rate_after_scan(&ReadOptions::default()), 0.0);
+        // ...unless it's detected and stops filling the cache after a few blocks.
+        let ropt = ReadOptions {
+            max_scan_cached_blocks: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(hit_rate_after_scan(&ropt), 1.0);
+
+        // Seeks start counting anew.
+        let opt = opt.clone().with_cache_capacity(100);
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter_opt(&ropt);
+        for i in (0..1000).step_by(200) {
+            iter.seek(&key(i));
+            for _ in 0..50 {
+                iter.advance();
+            }
+        }
+        assert_eq!(opt.block_cache.count(), 5 * 4);
+        assert_eq!(opt.block_cache.stats().inserts, 20);
+    }
+
+    /// An unbounded block cache recording its total charge.
+    #[derive(Default)]
+    struct MapCache {
+        blocks: Mutex<HashMap<CacheKey, (Block, usize)>>,
+        lookups: AtomicUsize,
+    }
+
+    impl BlockCache for MapCache {
+        fn insert(&self, key: &CacheKey, block: Block, charge: usize) {
+            self.blocks.lock().unwrap().insert(*key, (block, charge));
+        }
+        fn lookup(&self, key: &CacheKey) -> Option<Block> {
+            self.lookups.fetch_add(1, atomic::Ordering::SeqCst);
+            self.blocks.lock().unwrap().get(key).map(|(b, _)| b.clone())
+        }
+        fn erase(&self, key: &CacheKey) {
+            self.blocks.lock().unwrap().remove(key);
+        }
+        fn count(&self) -> usize {
+            self.blocks.lock().unwrap().len()
+        }
+        fn total_charge(&self) -> usize {
+            self.blocks.lock().unwrap().values().map(|(_, c)| c).sum()
+        }
+    }
+
+    #[test]
+    fn test_table_iterator_pinning() {
+        let (src, size) = build_table(build_data());
+        let cache = Arc::new(MapCache::default());
+        let mut opt = Options::default().with_block_cache(cache.clone());
+        opt.block_size = 32;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        let lookups = || cache.lookups.load(atomic::Ordering::SeqCst);
+
+        let mut ropt = ReadOptions::default();
+        ropt.pin_data = true;
+        let mut iter = table.iter_opt(&ropt);
+
+        // Seeks within the current block don't look it up in the cache.
+        for k in &["abd", "abc", "bcd", "abc"] {
+            iter.seek(k.as_bytes());
+            assert_eq!(iter.current_key(), Some(k.as_bytes()));
+        }
+        assert_eq!(lookups(), 1);
+        let pinned = iter.current_pinned_value().unwrap();
+        assert_eq!(&*pinned, b"def");
+
+        // Neither do seeks back into a block after leaving it past its end, or after a reset.
+        iter.seek(b"zzzz");
+        assert!(!iter.valid());
+        assert_eq!(lookups(), 2);
+        iter.seek(b"zzz");
+        assert_eq!(iter.current_key(), Some(&b"zzz"[..]));
+        iter.reset();
+        iter.seek(b"zz");
+        assert_eq!(iter.current_key(), Some(&b"zzz"[..]));
+        assert_eq!(lookups(), 2);
+        iter.seek(b"bsr");
+        assert_eq!(lookups(), 3);
+
+        // Pinned values outlive the iterator's visit of their block.
+        assert_eq!(pinned.as_ref(), b"def");
+        assert!(table.iter().current_pinned_value().is_none());
+    }
+
+    #[test]
+    fn test_table_custom_block_cache() {
+        let (src, size) = build_table(build_data());
+        let cache = Arc::new(MapCache::default());
+        let mut opt = Options::default().with_block_cache(cache.clone());
+        opt.block_size = 32;
+
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        let data = build_data();
+        for (k, v) in data.iter() {
+            assert_eq!(
+                Some(v.as_bytes().to_vec()),
+                table.get(k.as_bytes()).unwrap()
+            );
+        }
+        assert_eq!(cache.count(), 3);
+        assert_eq!(cache.lookups.load(atomic::Ordering::SeqCst), data.len());
+        assert!(cache.total_charge() > 0);
+
+        // A second table sharing the cache uses distinct keys.
+        let (src, size) = build_table(build_data());
+        let table2 = Table::new(table.opt.clone(), wrap_buffer(src), size).unwrap();
+        assert!(table2.get(b"abc").unwrap().is_some());
+        assert_eq!(cache.count(), 4);
+    }
+
+    #[test]
+    fn test_table_stable_cache_id() {
+        let (src, size) = build_table(build_data());
+        let mut opt = Options::default();
+        opt.block_size = 32;
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let open = |src: &Vec<u8>| {
+            let file = CountingFile {
+                data: src.clone(),
+                reads: reads.clone(),
+            };
+            Table::new(opt.clone(), Box::new(file), size).unwrap()
+        };
+
+        // Without a stable ID, every instance caches its own blocks.
+        assert!(open(&src).get(b"abc").unwrap().is_some());
+        assert!(open(&src).get(b"abc").unwrap().is_some());
+        assert_eq!(opt.block_cache.count(), 2);
+
+        // With one, a reopened table finds the blocks cached before.
+        assert!(open(&src).with_cache_id(7).get(b"abc").unwrap().is_some());
+        let table = open(&src).with_cache_id(7);
+        let before = reads.load(atomic::Ordering::SeqCst);
+        assert!(table.get(b"abc").unwrap().is_some());
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), before);
+        assert_eq!(opt.block_cache.count(), 3);
+    }
+
+    #[test]
+    fn test_table_hash_index() {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        opt.index_type = IndexType::HashSearch;
+        opt.prefix_extractor = Some(Arc::new(FixedPrefix(3)));
+
+        let mut keys = vec![];
+        for p in &["aaa", "abc", "bcd", "xyz"] {
+            for i in 0..6 {
+                keys.push(format!("{}{}", p, i));
+            }
+        }
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            b.add(b"a", b"no prefix").unwrap();
+            for k in &keys {
+                b.add(k.as_bytes(), k.as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+
+        let table = Table::new(opt.clone(), wrap_buffer(d.clone()), size).unwrap();
+        assert!(table.hash_index.is_some());
+        assert!(table.properties().unwrap().num_data_blocks > 4);
+        for k in &keys {
+            assert_eq!(
+                table.get(k.as_bytes()).unwrap(),
+                Some(k.as_bytes().to_vec())
+            );
+        }
+        assert_eq!(table.get(b"a").unwrap(), Some(b"no prefix".to_vec()));
+        assert_eq!(table.get(b"abc9").unwrap(), None);
+        assert_eq!(table.get(b"abd0").unwrap(), None);
+        assert_eq!(table.get(b"zzz0").unwrap(), None);
+
+        // Readers without (the same) prefix extractor use the index block.
+        let mut plain = Options::default();
+        plain.block_size = 32;
+        let table = Table::new(plain.clone(), wrap_buffer(d.clone()), size).unwrap();
+        assert!(table.hash_index.is_none());
+        assert_eq!(table.get(b"bcd3").unwrap(), Some(b"bcd3".to_vec()));
+
+        struct OtherPrefix;
+        impl PrefixExtractor for OtherPrefix {
+            fn name(&self) -> &str {
+                "other"
+            }
+            fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+                key.get(..1)
+            }
+        }
+        plain.prefix_extractor = Some(Arc::new(OtherPrefix));
+        let table = Table::new(plain, wrap_buffer(d), size).unwrap();
+        assert!(table.hash_index.is_none());
+        assert_eq!(table.get(b"xyz5").unwrap(), Some(b"xyz5".to_vec()));
+    }
+
+    #[test]
+    fn test_table_filter() {
+        let mut opt = Options::default();
+        opt.block_size = 128;
+        opt.table_filter_fp_rate = Some(0.01);
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for i in 0..1000 {
+                b.add(format!("key{:04}", i * 2).as_bytes(), b"val")
+                    .unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: d,
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+        assert!(table.table_filter.is_some());
+        assert!(table
+            .meta_block_names()
+            .contains(&TABLE_FILTER_BLOCK_KEY.to_string()));
+        assert!(table.key_may_exist(b"key0042"));
+        assert_eq!(table.get(b"key0042").unwrap(), Some(b"val".to_vec()));
+
+        // Missing keys are (nearly always) ruled out without reading data blocks.
+        let before = reads.load(atomic::Ordering::SeqCst);
+        let mut maybe = 0;
+        for i in 0..1000 {
+            let k = format!("key{:04}", i * 2 + 1);
+            if table.key_may_exist(k.as_bytes()) {
+                maybe += 1;
+            }
+            assert_eq!(table.get(k.as_bytes()).unwrap(), None);
+        }
+        assert!(maybe < 30, "{}", maybe);
+        assert!(reads.load(atomic::Ordering::SeqCst) - before <= maybe);
+
+        let mut hint = SeekHint::new();
+        assert_eq!(
+            table.get_with_hint(b"key0100", &mut hint).unwrap(),
+            Some(b"val".to_vec())
+        );
+        assert!(!table.key_may_exist(b"zzz"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_table_file_cache_id() {
+        let (src, _) = build_table(build_data());
+        let path = std::env::temp_dir().join(format!("sstable_cache_id_{}", std::process::id()));
+        fs::write(&path, &src).unwrap();
+        let mut opt = Options::default();
+        opt.block_size = 32;
+
+        for _ in 0..2 {
+            let table = Table::new_from_file(opt.clone(), &path).unwrap();
+            assert!(table.get(b"abc").unwrap().is_some());
+        }
+        assert_eq!(opt.block_cache.count(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_table_file_replaced() {
+        let dir = std::env::temp_dir().join(format!("sstable_replace_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, tmp) = (dir.join("1.sst"), dir.join("1.sst.tmp"));
+        let (src, _) = build_table(build_data());
+        fs::write(&path, &src).unwrap();
+        let mut opt = Options::default();
+        opt.block_size = 32;
+
+        let table = Table::new_from_file(opt.clone(), &path).unwrap();
+        assert!(table.index_handles().len() > 2);
+        let pin = table.pin();
+        let mut iter = table.iter();
+        assert!(iter.advance());
+        drop(table);
+
+        // Replace the file by one with other contents, then unlink it.
+        let (other, _) = build_table(vec![("abc", "new")]);
+        fs::write(&tmp, &other).unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        let replacement = Table::new_from_file(opt.clone(), &path).unwrap();
+        assert_eq!(
+            replacement.get(b"abc").unwrap().as_deref(),
+            Some(&b"new"[..])
+        );
+        fs::remove_file(&path).unwrap();
+
+        // The iterator reads the remaining blocks of the original file.
+        let mut n = 1;
+        while iter.advance() {
+            n += 1;
+        }
+        assert!(iter.status().is_none());
+        assert_eq!(n, build_data().len());
+        drop(iter);
+
+        // The pin opens the original table again.
+        let table = pin.open(opt).unwrap();
+        assert_eq!(table.get(b"abc").unwrap().as_deref(), Some(&b"def"[..]));
+        assert_eq!(
+            replacement.get(b"abc").unwrap().as_deref(),
+            Some(&b"new"[..])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_iterator_readahead() {
+        let mut d = vec![];
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        opt.compression_type = CompressionType::CompressionSnappy;
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for i in 0..200 {
+                b.add(format!("key{:04}", i).as_bytes(), b"value").unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: d.clone(),
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+        let num_blocks = table.index_handles().len();
+        assert!(num_blocks > 20);
+        let mut ropt = ReadOptions::default();
+        ropt.fill_cache = false;
+        let expected: Vec<_> = SSIteratorIter::wrap(&mut table.iter_opt(&ropt)).collect();
+        assert_eq!(expected.len(), 200);
+
+        ropt.readahead_blocks = 4;
+        let before = reads.load(atomic::Ordering::SeqCst);
+        let entries: Vec<_> = SSIteratorIter::wrap(&mut table.iter_opt(&ropt)).collect();
+        assert_eq!(entries, expected);
+        assert_eq!(
+            reads.load(atomic::Ordering::SeqCst) - before,
+            num_blocks.div_ceil(5)
+        );
+
+        // Blocks read ahead are only decompressed and cached once the iterator reaches them.
+        let file = CountingFile {
+            data: d,
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt.with_cache_capacity(100), Box::new(file), size).unwrap();
+        ropt.fill_cache = true;
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        assert_eq!(iter.readahead.len(), 4);
+        assert_eq!(table.dump_hot_blocks().len(), 1);
+        // Seeking past the blocks read ahead drops them; seeking back reads the blocks again.
+        iter.seek(b"key0150");
+        assert_eq!(current_key_val(&iter).unwrap().0, b"key0150");
+        iter.seek(b"key0001");
+        assert_eq!(current_key_val(&iter).unwrap().0, b"key0001");
+        assert!(iter.prev());
+        assert!(!iter.prev());
+
+        // Contiguous blocks requested together are read using one vectored read.
+        let handles = table.index_handles();
+        let before = reads.load(atomic::Ordering::SeqCst);
+        table.read_blocks(&handles[10..15]).unwrap();
+        assert_eq!(reads.load(atomic::Ordering::SeqCst) - before, 1);
+    }
+
+    #[test]
+    fn test_table_multi_get() {
+        let (src, size) = build_table(build_data());
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+
+        let keys: Vec<&[u8]> = vec![b"xyz", b"abc", b"abx", b"zzz", b"abd", b"{{{"];
+        let ropt = ReadOptions::default();
+        let before = reads.load(atomic::Ordering::SeqCst);
+        let values = table.multi_get(&ropt, &keys).unwrap();
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(&table.get(key).unwrap(), value);
+        }
+        assert_eq!(values[0], Some(b"xxx".to_vec()));
+        assert_eq!(values[2], None);
+        // Every data block is read once; as they are adjacent, using a single read.
+        assert_eq!(opt.block_cache.count(), 3);
+        assert_eq!(reads.load(atomic::Ordering::SeqCst) - before, 1);
+
+        let before = reads.load(atomic::Ordering::SeqCst);
+        let blocks = table.read_blocks(&table.index_handles()).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), before);
+    }
+
+    #[test]
+    fn test_table_prefetch_range() {
+        let (src, size) = build_table(build_data());
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+
+        assert_eq!(table.prefetch_range(b"b", b"a").unwrap(), 0);
+        assert_eq!(table.prefetch_range(b"abd", b"bsr").unwrap(), 2);
+        assert_eq!(opt.block_cache.count(), 2);
+        let handle = table.prefetch_range_async(b"x", b"zzzz");
+        assert_eq!(handle.join().unwrap().unwrap(), 2);
+        assert_eq!(opt.block_cache.count(), 3);
+
+        // Reads of the range are served from the cache.
+        let before = reads.load(atomic::Ordering::SeqCst);
+        let mut iter = table.iter();
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), build_data().len());
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), before);
+    }
+
+    #[test]
+    fn test_table_prefetch_hot() {
+        let key = |i: usize| format!("key{:02}", i).into_bytes();
+        let hot = |i: usize| (10..20).contains(&i) || (25..27).contains(&i);
+        let mut src = vec![];
+        let mut b = TableBuilder::new(Options::default(), &mut src);
+        for i in 0..30 {
+            b.set_temperature(if hot(i) {
+                Temperature::Hot
+            } else {
+                Temperature::Cold
+            });
+            b.add(&key(i), b"value").unwrap();
+        }
+        let size = b.finish().unwrap();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let opt = Options::default();
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+
+        // Blocks end where the temperature changes.
+        let props = table.properties().unwrap();
+        assert_eq!(props.num_data_blocks, 5);
+        let handles = table.index_handles();
+        let range = |h: &BlockHandle| (h.offset(), h.size() + 5);
+        assert_eq!(
+            props.hot_ranges,
+            vec![range(&handles[1]), range(&handles[3])]
+        );
+
+        assert_eq!(table.prefetch_hot().unwrap(), 2);
+        assert_eq!(opt.block_cache.count(), 2);
+        let before = reads.load(atomic::Ordering::SeqCst);
+        for i in (0..30).filter(|&i| hot(i)) {
+            assert_eq!(table.get(&key(i)).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), before);
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_framed_compression() {
+        let key = |i: usize| format!("key{:05}", i).into_bytes();
+        let mut opt = Options::default();
+        opt.compression_type = CompressionType::CompressionSnappyFramed;
+        opt.compression_frame_size = 1024;
+        opt.block_size = 64 * 1024;
+        let mut src = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut src);
+        for i in 0..6100 {
+            b.add(&key(i), format!("value of key {}", i).as_bytes())
+                .unwrap();
+        }
+        let size = b.finish().unwrap();
+
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        let handles = table.index_handles();
+        assert_eq!(handles.len(), 3);
+        let (_, ctype) = table.read_encoded_block(&handles[0]).unwrap();
+        assert_eq!(ctype, CompressionType::CompressionSnappyFramed);
+        // The last block fits into a single frame.
+        let (_, ctype) = table.read_encoded_block(&handles[2]).unwrap();
+        assert_eq!(ctype, CompressionType::CompressionSnappy);
+
+        // Point lookups decompress single frames, which aren't cached; only the last block is.
+        for i in (0..6100).step_by(7) {
+            assert_eq!(
+                table.get(&key(i)).unwrap(),
+                Some(format!("value of key {}", i).into_bytes())
+            );
+        }
+        assert_eq!(table.get(b"key06100").unwrap(), None);
+        assert_eq!(opt.block_cache.count(), 1);
+
+        // Iterators decompress and cache whole blocks.
+        let mut iter = table.iter();
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), 6100);
+        assert_eq!(opt.block_cache.count(), 3);
+        assert_eq!(
+            table.get(&key(1)).unwrap(),
+            Some(b"value of key 1".to_vec())
+        );
+
+        // Frames are checked against the decompression limit; whole blocks aren't read.
+        let mut limited = opt.clone();
+        limited.block_cache = Options::default().block_cache;
+        limited.max_decompressed_block_size = Some(16 * 1024);
+        let table = Table::new(limited, wrap_buffer(src), size).unwrap();
+        assert!(table.get(&key(1)).unwrap().is_some());
+        let mut iter = table.iter();
+        assert!(!iter.advance());
+        assert!(matches!(
+            iter.status(),
+            Some(Error::Corruption {
+                kind: CorruptionKind::DecompressedSizeExceeded { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_table_block_cache_charge() {
+        let (src, size) = build_table(build_data());
+        let opt =
+            Options::default().with_block_cache(Arc::new(LRUBlockCache::with_charge_capacity(1)));
+
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+        iter.next();
+        let first = opt.block_cache.total_charge();
+        assert!(first > 1);
+        // Every block exceeds the capacity, so only the most recent one is kept.
+        for _ in SSIteratorIter::wrap(&mut iter) {}
+        assert_eq!(opt.block_cache.count(), 1);
+    }
+
+    #[test]
+    fn test_table_compressed_block_cache() {
+        let (src, size) = build_table(build_data());
+        let opt = Options::default()
+            .with_cache_capacity(1)
+            .with_compressed_cache_capacity(8);
+        let compressed_cache = opt.compressed_block_cache.clone().unwrap();
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt.clone(), Box::new(file), size).unwrap();
+        let data = build_data();
+        for (k, v) in data.iter() {
+            assert_eq!(
+                Some(v.as_bytes().to_vec()),
+                table.get(k.as_bytes()).unwrap()
+            );
+        }
+        assert_eq!(opt.block_cache.count(), 1);
+        assert_eq!(compressed_cache.read().expect(LOCK_POISONED).count(), 3);
+
+        // Blocks evicted from the block cache are served from the compressed cache without
+        // touching the file.
+        let reads_before = reads.load(atomic::Ordering::SeqCst);
+        let mut iter = table.iter();
+        for (i, (k, v)) in SSIteratorIter::wrap(&mut iter).enumerate() {
+            assert_eq!(
+                (data[i].0.as_bytes(), data[i].1.as_bytes()),
+                (&k[..], &v[..])
+            );
+        }
+        assert_eq!(reads_before, reads.load(atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_table_memory_usage() {
+        let (src, size) = build_table(build_data());
+        let budget = Arc::new(MemoryBudget::new(usize::MAX));
+        let mut opt = Options::default()
+            .with_block_cache(Arc::new(LRUBlockCache::with_charge_capacity(1 << 20)));
+        opt.memory_budget = Some(budget.clone());
+
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        let pinned = table.approximate_memory_usage();
+        assert!(pinned > 0);
+        assert_eq!(budget.pinned(), pinned);
+        // Other tables' blocks aren't counted.
+        let other = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        other.read_blocks(&other.index_handles()).unwrap();
+        assert_eq!(table.approximate_memory_usage(), pinned);
+
+        let handles = table.index_handles();
+        let blocks = table.read_blocks(&handles).unwrap();
+        let cached: usize = blocks.iter().map(|b| b.contents().len()).sum();
+        assert_eq!(table.approximate_memory_usage(), pinned + cached);
+
+        drop(other);
+        let clone = table.clone();
+        drop(table);
+        assert_eq!(budget.pinned(), pinned);
+        drop(clone);
+        assert_eq!(budget.pinned(), 0);
+
+        // A budget leaving room for one block keeps the cache at one block.
+        let budget = Arc::new(MemoryBudget::new(pinned + cached / 2));
+        opt.memory_budget = Some(budget.clone());
+        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+        for (k, v) in build_data() {
+            assert_eq!(
+                table.get(k.as_bytes()).unwrap(),
+                Some(v.as_bytes().to_vec())
+            );
+        }
+        assert_eq!(opt.block_cache.count(), 1);
+        assert!(table.approximate_memory_usage() <= budget.limit());
+    }
+
+    #[test]
+    fn test_table_iterator_fwd_bwd() {
+        let (src, size) = build_table(build_data());
+        let data = build_data();
+
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+        let mut i = 0;
+
+        while let Some((k, v)) = iter.next() {
+            assert_eq!(
+                (data[i].0.as_bytes(), data[i].1.as_bytes()),
+                (k.as_ref(), v.as_ref())
+            );
+            i += 1;
+        }
+
+        assert_eq!(i, data.len());
+        assert!(!iter.valid());
+
+        // Go forward again, to last entry.
+        while let Some((key, _)) = iter.next() {
+            if key.as_slice() == b"zzz" {
+                break;
+            }
+        }
+
+        assert!(iter.valid());
+        // backwards count
+        let mut j = 0;
+
+        while iter.prev() {
+            if let Some((k, v)) = current_key_val(&iter) {
+                j += 1;
+                assert_eq!(
+                    (
+                        data[data.len() - 1 - j].0.as_bytes(),
+                        data[data.len() - 1 - j].1.as_bytes()
+                    ),
+                    (k.as_ref(), v.as_ref())
+                );
+            } else {
+                break;
+            }
+        }
+
+        // expecting 7 - 1, because the last entry that the iterator stopped on is the last entry
+        // in the table; that is, it needs to go back over 6 entries.
+        assert_eq!(j, 6);
+    }
+
+    #[cfg(feature = "filters")]
+    #[test]
+    fn test_table_iterator_filter() {
+        let (src, size) = build_table(build_data());
+
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        assert!(table.filters.is_some());
+        let filter_reader = table.filters.clone().unwrap();
+        let mut iter = table.iter();
+
+        while let Some((k, _)) = iter.next() {
+            assert!(filter_reader.key_may_match(iter.current_block_handle.offset(), &k));
+            assert!(
+                !filter_reader.key_may_match(iter.current_block_handle.offset(), b"somerandomkey")
+            );
+        }
+    }
+
+    #[test]
+    fn test_table_iterator_state_behavior() {
+        let (src, size) = build_table(build_data());
+
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+
+        // behavior test
+
+        // See comment on valid()
+        assert!(!iter.valid());
+        assert!(current_key_val(&iter).is_none());
+        assert!(!iter.prev());
+
+        assert!(iter.advance());
+        let first = current_key_val(&iter);
+        assert!(iter.valid());
+        assert!(current_key_val(&iter).is_some());
+
+        assert!(iter.advance());
+        assert!(iter.prev());
+        assert!(iter.valid());
+
+        iter.reset();
+        assert!(!iter.valid());
+        assert!(current_key_val(&iter).is_none());
+        assert_eq!(first, iter.next());
+    }
+
+    #[test]
+    fn test_table_iterator_behavior_standard() {
+        let mut data = build_data();
+        data.truncate(4);
+        let (src, size) = build_table(data);
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        test_iterator_properties(table.iter());
+    }
+
+    #[test]
+    fn test_table_iterator_values() {
+        let (src, size) = build_table(build_data());
+        let data = build_data();
 
-    fn build_data() -> Vec<(&'static str, &'static str)> {
-        vec![
-            // block 1
-            ("abc", "def"),
-            ("abd", "dee"),
-            ("bcd", "asa"),
-            // block 2
-            ("bsr", "a00"),
-            ("xyz", "xxx"),
-            ("xzz", "yyy"),
-            // block 3
-            ("zzz", "111"),
-        ]
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+        let mut i = 0;
+
+        iter.next();
+        iter.next();
+
+        // Go back to previous entry, check, go forward two entries, repeat
+        // Verifies that prev/next works well.
+        loop {
+            iter.prev();
+
+            if let Some((k, v)) = current_key_val(&iter) {
+                assert_eq!(
+                    (data[i].0.as_bytes(), data[i].1.as_bytes()),
+                    (k.as_ref(), v.as_ref())
+                );
+            } else {
+                break;
+            }
+
+            i += 1;
+            if iter.next().is_none() || iter.next().is_none() {
+                break;
+            }
+        }
+
+        // Skipping the last value because the second next() above will break the loop
+        assert_eq!(i, 6);
     }
 
-    // Build a table containing raw keys (no format). It returns (vector, length) for convenience
-    // reason, a call f(v, v.len()) doesn't work for borrowing reasons.
-    fn build_table(data: Vec<(&'static str, &'static str)>) -> (Vec<u8>, usize) {
+    #[test]
+    fn test_table_iterator_seek() {
+        let (src, size) = build_table(build_data());
+
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let mut iter = table.iter();
+
+        iter.seek(b"bcd");
+        assert!(iter.valid());
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"bcd".to_vec(), b"asa".to_vec()))
+        );
+        iter.seek(b"abc");
+        assert!(iter.valid());
+        assert_eq!(
+            current_key_val(&iter),
+            Some((b"abc".to_vec(), b"def".to_vec()))
+        );
+
+        // Seek-past-last invalidates.
+        iter.seek("{{{".as_bytes());
+        assert!(!iter.valid());
+        iter.seek(b"bbb");
+        assert!(iter.valid());
+    }
+
+    #[test]
+    fn test_table_get() {
+        let (src, size) = build_table(build_data());
+
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let table2 = table.clone();
+
+        let mut _iter = table.iter();
+        // Test that all of the table's entries are reachable via get()
+        for (k, v) in SSIteratorIter::wrap(&mut _iter) {
+            let r = table2.get(&k);
+            assert_eq!(Some(v), r.unwrap());
+        }
+
+        assert_eq!(table.opt.block_cache.count(), 3);
+
+        // test that filters work and don't return anything at all.
+        assert!(table.get(b"aaa").unwrap().is_none());
+        assert!(table.get(b"aaaa").unwrap().is_none());
+        assert!(table.get(b"aa").unwrap().is_none());
+        assert!(table.get(b"abcd").unwrap().is_none());
+        assert!(table.get(b"abb").unwrap().is_none());
+        assert!(table.get(b"xyy").unwrap().is_none());
+        assert!(table.get(b"zzy").unwrap().is_none());
+        assert!(table.get(b"zz1").unwrap().is_none());
+        assert!(table.get("zz{".as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_table_empty() {
+        let mut variants = vec![Options::default()];
+        let mut opt = Options::default();
+        #[cfg(feature = "snappy")]
+        {
+            opt.compression_type = CompressionType::CompressionSnappy;
+        }
+        opt.table_filter_fp_rate = Some(0.01);
+        opt.entry_expiration = true;
+        opt.value_checksums = true;
+        opt.file_checksum = true;
+        variants.push(opt);
+        let mut opt = Options::default();
+        opt.blob_threshold = Some(4);
+        opt.filter_policy = Arc::new(Box::new(filter::NoFilterPolicy::new()));
+        variants.push(opt);
+        let mut opt = Options::default();
+        opt.index_type = IndexType::HashSearch;
+        opt.prefix_extractor = Some(Arc::new(FixedPrefix(3)));
+        opt.block_stats = Some(Arc::new(|| {
+            Box::new(FirstByteRange::default()) as Box<dyn BlockStatsAggregator>
+        }));
+        variants.push(opt);
+
+        for opt in variants {
+            let mut src = vec![];
+            let meta = TableBuilder::new(opt.clone(), &mut src)
+                .finish_with_meta()
+                .unwrap();
+            assert_eq!(meta.num_entries, 0);
+            let size = meta.size;
+
+            let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+            assert!(table.is_empty());
+            let props = table.properties().unwrap();
+            assert_eq!((props.num_entries, props.num_data_blocks), (0, 0));
+            assert!(table.index_handles().is_empty());
+            assert_eq!(table.get(b"abc").unwrap(), None);
+            assert_eq!(table.get(b"").unwrap(), None);
+            assert_eq!(
+                table
+                    .multi_get(&ReadOptions::default(), &[b"a", b"b"])
+                    .unwrap(),
+                vec![None, None]
+            );
+            assert_eq!(table.estimate_keys_in_range(b"a", b"z").unwrap(), 0);
+            assert_eq!(table.prefetch_range(b"a", b"z").unwrap(), 0);
+            assert!(table.garbage_ratio().is_none_or(|r| r == 0.0));
+            assert!(!table.key_may_exist(b"abc"));
+            assert_eq!(
+                table
+                    .get_with_hint(b"abc", &mut SeekHint::default())
+                    .unwrap(),
+                None
+            );
+            assert!(table.dump_hot_blocks().is_empty());
+            assert!(!table.raw_iter().advance());
+
+            let mut iter = table.iter();
+            assert!(!iter.advance());
+            iter.seek(b"abc");
+            assert!(!iter.valid());
+            iter.seek_to_first();
+            assert!(!iter.valid());
+            assert!(!iter.prev());
+            assert!(iter.status().is_none());
+            assert!(!table.prefix_iter(b"a").advance());
+        }
+    }
+
+    #[test]
+    fn test_table_properties() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let props = table.properties().unwrap();
+
+        assert_eq!(props.num_entries, 7);
+        assert_eq!(props.num_data_blocks, 3);
+        assert_eq!(props.raw_key_size, 21);
+        assert_eq!(props.raw_value_size, 21);
+        assert!(props.filter_size > 0);
+        assert!(!props.entry_expiration);
+        assert!(!table.is_fully_expired(u64::MAX));
+    }
+
+    fn build_expiring_table(expirations: &[u64]) -> (Vec<u8>, u64) {
         let mut d = Vec::with_capacity(512);
         let mut opt = Options::default();
         opt.block_restart_interval = 2;
         opt.block_size = 32;
-        opt.compression_type = CompressionType::CompressionSnappy;
-
+        opt.entry_expiration = true;
         {
-            // Uses the standard comparator in opt.
             let mut b = TableBuilder::new(opt, &mut d);
+            for (&(k, v), &e) in build_data().iter().zip(expirations.iter()) {
+                b.add_with_expiration(k.as_bytes(), v.as_bytes(), e)
+                    .unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+        (d, size)
+    }
 
-            for &(k, v) in data.iter() {
-                b.add(k.as_bytes(), v.as_bytes()).unwrap();
+    /// Collects the smallest and largest value.
+    #[derive(Default)]
+    struct MinMaxCollector {
+        min: Option<Vec<u8>>,
+        max: Option<Vec<u8>>,
+    }
+
+    impl TablePropertiesCollector for MinMaxCollector {
+        fn add(&mut self, _key: &[u8], value: &[u8]) {
+            if self.min.as_ref().is_none_or(|m| value < &m[..]) {
+                self.min = Some(value.to_vec());
+            }
+            if self.max.as_ref().is_none_or(|m| value > &m[..]) {
+                self.max = Some(value.to_vec());
+            }
+        }
+        fn finish(&mut self) -> Vec<(String, Vec<u8>)> {
+            let mut props = vec![];
+            if let Some(min) = self.min.take() {
+                props.push(("value.min".to_string(), min));
+            }
+            if let Some(max) = self.max.take() {
+                props.push(("value.max".to_string(), max));
             }
+            props
+        }
+    }
 
+    #[test]
+    fn test_table_user_properties() {
+        let mut d = Vec::with_capacity(512);
+        let mut opt = Options::default().with_properties_collector(Arc::new(|| {
+            Box::new(MinMaxCollector::default()) as Box<dyn TablePropertiesCollector>
+        }));
+        opt.entry_expiration = true;
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for &(k, v) in build_data().iter() {
+                b.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
             b.finish().unwrap();
         }
+        let size = d.len() as u64;
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
 
-        let size = d.len();
-        (d, size)
+        let props = table.user_properties().unwrap();
+        assert_eq!(props.len(), 2);
+        assert_eq!(props["value.min"], b"111".to_vec());
+        assert_eq!(props["value.max"], b"yyy".to_vec());
     }
 
-    fn wrap_buffer(src: Vec<u8>) -> Box<dyn RandomAccess> {
-        Box::new(src)
+    #[test]
+    fn test_table_user_properties_reserved() {
+        struct BadCollector;
+        impl TablePropertiesCollector for BadCollector {
+            fn add(&mut self, _: &[u8], _: &[u8]) {}
+            fn finish(&mut self) -> Vec<(String, Vec<u8>)> {
+                vec![("sstable.num.entries".to_string(), vec![0])]
+            }
+        }
+
+        let opt = Options::default().with_properties_collector(Arc::new(|| {
+            Box::new(BadCollector) as Box<dyn TablePropertiesCollector>
+        }));
+        let mut d = vec![];
+        let mut b = TableBuilder::new(opt, &mut d);
+        b.add(b"abc", b"def").unwrap();
+        match b.finish() {
+            Err(Error::InvalidArgument(_)) => {}
+            _ => panic!("reserved property name accepted"),
+        }
+    }
+
+    /// Records the smallest and largest first byte of the values in a block.
+    #[derive(Default)]
+    struct FirstByteRange(Option<(u8, u8)>);
+
+    impl BlockStatsAggregator for FirstByteRange {
+        fn add(&mut self, _key: &[u8], value: &[u8]) {
+            let b = value[0];
+            self.0 = Some(match self.0 {
+                Some((min, max)) => (min.min(b), max.max(b)),
+                None => (b, b),
+            });
+        }
+        fn finish_block(&mut self) -> Vec<u8> {
+            let (min, max) = self.0.take().unwrap();
+            vec![min, max]
+        }
     }
 
     #[test]
-    fn test_table_approximate_offset() {
-        let (src, size) = build_table(build_data());
+    fn test_table_prune_blocks() {
+        let mut d = Vec::with_capacity(512);
         let mut opt = Options::default();
+        opt.block_restart_interval = 2;
         opt.block_size = 32;
-        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        opt.block_stats = Some(Arc::new(|| {
+            Box::new(FirstByteRange::default()) as Box<dyn BlockStatsAggregator>
+        }));
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for &(k, v) in build_data().iter() {
+                b.add(k.as_bytes(), v.as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+
+        // Blocks are [abc, abd, bcd], [bsr, xyz, xzz], [zzz].
+        assert_eq!(table.block_stats(0), Some(&b"ad"[..]));
+        assert_eq!(table.block_stats(1), None);
+
+        // Skip blocks that can't contain values starting with 'x'..'y'.
+        let pruner: BlockPruner = Arc::new(|stats| stats[1] < b'x' || stats[0] > b'y');
         let mut iter = table.iter();
+        iter.prune_blocks(pruner.clone());
+        let keys: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![b"bsr".to_vec(), b"xyz".to_vec(), b"xzz".to_vec()]
+        );
 
-        let expected_offsets = vec![0, 0, 0, 44, 44, 44, 89];
-        let mut i = 0;
-        for (k, _) in SSIteratorIter::wrap(&mut iter) {
-            assert_eq!(expected_offsets[i], table.approx_offset_of(&k));
-            i += 1;
+        let mut iter = table.iter();
+        iter.prune_blocks(pruner.clone());
+        iter.seek(b"abd");
+        assert_eq!(iter.current_key(), Some(&b"bsr"[..]));
+        iter.seek(b"zz");
+        assert!(!iter.valid());
+        iter.seek(b"xzz");
+        assert_eq!(iter.current_key(), Some(&b"xzz"[..]));
+        assert!(iter.prev());
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"bsr"[..]));
+        assert!(!iter.prev());
+
+        // Pruning everything yields nothing.
+        let mut iter = table.iter();
+        iter.prune_blocks(Arc::new(|_| true));
+        assert!(!iter.advance());
+    }
+
+    fn build_blob_table(opt: Options, n: usize) -> (Vec<u8>, u64) {
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt, &mut d);
+            for i in 0..n {
+                let key = format!("key{:04}", i);
+                // Every third value is large.
+                let val = if i % 3 == 0 {
+                    key.repeat(100)
+                } else {
+                    key.clone()
+                };
+                b.add(key.as_bytes(), val.as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
         }
+        let size = d.len() as u64;
+        (d, size)
+    }
+
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_blob_values() {
+        for &ctype in &[
+            CompressionType::CompressionNone,
+            CompressionType::CompressionSnappy,
+        ] {
+            let mut opt = Options::default();
+            opt.block_size = 64;
+            opt.compression_type = ctype;
+            opt.blob_threshold = Some(100);
+            opt.entry_expiration = true;
+            let (src, size) = build_blob_table(opt.clone(), 300);
+            let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+
+            let props = table.properties().unwrap();
+            assert!(props.blob_values);
+            assert_eq!(props.num_blobs, 100);
+            assert!(props.blob_size > 0);
+            assert_eq!(props.raw_value_size, 100 * 700 + 200 * 7);
 
-        // Key-past-last returns offset of metaindex block.
-        assert_eq!(137, table.approx_offset_of("{aa".as_bytes()));
+            // Blobs are written between data blocks; make sure that filters still match.
+            for i in 0..300 {
+                let key = format!("key{:04}", i);
+                let val = table.get(key.as_bytes()).unwrap().unwrap();
+                assert_eq!(val.len(), if i % 3 == 0 { 700 } else { 7 });
+                assert_eq!(&val[..7], key.as_bytes());
+            }
+            assert!(table.get(b"key").unwrap().is_none());
+
+            let mut iter = table.iter();
+            let mut n = 0;
+            for (i, (k, v)) in SSIteratorIter::wrap(&mut iter).enumerate() {
+                let key = format!("key{:04}", i);
+                assert_eq!(k, key.as_bytes());
+                assert_eq!(v.len(), if i % 3 == 0 { 700 } else { 7 });
+                n += 1;
+            }
+            assert_eq!(n, 300);
+        }
     }
 
     #[test]
-    fn test_table_block_cache_use() {
-        let (src, size) = build_table(build_data());
+    fn test_table_checksum_manifest() {
         let mut opt = Options::default();
-        opt.block_size = 32;
+        opt.block_size = 64;
+        opt.blob_threshold = Some(100);
+        opt.entry_expiration = true;
+        let (mut src, size) = build_blob_table(opt.clone(), 30);
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+
+        let manifest = table.checksum_manifest().unwrap();
+        let data_blocks = table.index_handles();
+        // Index, metaindex, filter and properties blocks, data blocks and blobs.
+        assert_eq!(manifest.len(), 4 + data_blocks.len() + 10);
+        assert!(manifest
+            .windows(2)
+            .all(|w| w[0].handle.offset() < w[1].handle.offset()));
+        assert!(table.verify_against_manifest(&manifest).unwrap().is_empty());
+        let decoded = manifest::decode_manifest(&manifest::encode_manifest(&manifest)).unwrap();
+        assert_eq!(decoded, manifest);
+
+        // Flip a bit in the trailer of the second data block.
+        let block = &data_blocks[1];
+        src[(block.offset() + block.size()) as usize + 2] ^= 1;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        assert_eq!(
+            table.verify_against_manifest(&manifest).unwrap(),
+            vec![block.clone()]
+        );
+    }
+
+    #[test]
+    fn test_table_iterator_corrupt_blob() {
+        let mut opt = Options::default();
+        opt.blob_threshold = Some(100);
+        let (mut src, size) = build_blob_table(opt.clone(), 10);
+        // The first value is large, and stored in a blob at the start of the file.
+        src[10] ^= 1;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        assert!(table.get(b"key0000").unwrap_err().is_corruption());
 
-        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
         let mut iter = table.iter();
+        assert!(!iter.advance());
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap().is_corruption());
 
-        // index/metaindex blocks are not cached. That'd be a waste of memory.
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 0);
+        // The other values are read with skip_corrupt_blocks...
+        let ropt = ReadOptions {
+            skip_corrupt_blocks: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        let keys: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(keys.len(), 9);
+        assert_eq!(keys[0], b"key0001".to_vec());
+        assert!(iter.status().unwrap().is_corruption());
 
-        iter.next();
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 1);
+        // ...and with lazy_values, the blob is only read when it's fetched.
+        let ropt = ReadOptions {
+            lazy_values: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        let mut key = vec![];
+        let val = iter.current_lazy(&mut key).unwrap();
+        assert!(val.fetch().unwrap_err().is_corruption());
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), 9);
+        assert!(iter.status().is_none());
 
-        // This may fail if block parameters or data change. In that case, adapt it.
-        iter.next();
-        iter.next();
-        iter.next();
-        iter.next();
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 2);
+        // Reading the blob through current() reports the error and stops the iterator...
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        let (mut key, mut val) = (vec![], vec![]);
+        assert!(!iter.current(&mut key, &mut val));
+        assert!(iter.status().unwrap().is_corruption());
+        assert!(!iter.advance());
+        iter.reset();
+        assert!(iter.status().is_none());
+
+        // ...or is skipped with skip_corrupt_blocks.
+        let ropt = ReadOptions {
+            lazy_values: true,
+            skip_corrupt_blocks: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        assert!(!iter.current(&mut key, &mut val));
+        assert!(iter.advance());
+        assert!(iter.current(&mut key, &mut val));
+        assert_eq!(key, b"key0001");
+        assert!(iter.status().unwrap().is_corruption());
     }
 
     #[test]
-    fn test_table_block_tiny_cache() {
-        let (src, size) = build_table(build_data());
-        // Create a table with no block cache
-        let mut opt = Options::default().with_cache_capacity(1);
-        opt.block_size = 32;
+    fn test_table_blob_values_lazy() {
+        let mut opt = Options::default();
+        opt.blob_threshold = Some(100);
+        let (src, size) = build_blob_table(opt.clone(), 10);
+        let reads = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            data: src,
+            reads: reads.clone(),
+        };
+        let table = Table::new(opt, Box::new(file), size).unwrap();
+        let ropt = ReadOptions::default();
+
+        let small = table.get_lazy(&ropt, b"key0001").unwrap().unwrap();
+        assert!(!small.is_blob());
+        assert_eq!(small.fetch().unwrap(), b"key0001".to_vec());
+
+        let large = table.get_lazy(&ropt, b"key0003").unwrap().unwrap();
+        assert!(large.is_blob());
+        let reads_before = reads.load(atomic::Ordering::SeqCst);
+        assert_eq!(large.fetch().unwrap(), b"key0003".repeat(100));
+        assert_eq!(reads.load(atomic::Ordering::SeqCst), reads_before + 1);
 
-        let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
         let mut iter = table.iter();
+        iter.seek(b"key0006");
+        let mut key = vec![];
+        let val = iter.current_lazy(&mut key).unwrap();
+        assert_eq!(key, b"key0006".to_vec());
+        assert!(val.is_blob());
+        assert_eq!(val.fetch().unwrap(), b"key0006".repeat(100));
+    }
 
-        // index/metaindex blocks are not cached. That'd be a waste of memory.
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 0);
+    #[cfg(feature = "snappy")]
+    #[test]
+    fn test_table_get_reader() {
+        use std::io::Read;
+
+        for &ctype in &[
+            CompressionType::CompressionNone,
+            CompressionType::CompressionSnappy,
+        ] {
+            let mut opt = Options::default();
+            opt.compression_type = ctype;
+            opt.blob_threshold = Some(100);
+            let (src, size) = build_blob_table(opt.clone(), 10);
+            let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+
+            let mut r = table.get_reader(b"key0003").unwrap().unwrap();
+            assert_eq!(r.remaining(), 700);
+            let mut buf = [0; 64];
+            assert_eq!(r.read(&mut buf).unwrap(), 64);
+            assert_eq!(&buf[..14], b"key0003key0003");
+            let mut rest = vec![];
+            r.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest.len(), 700 - 64);
+            assert_eq!(r.remaining(), 0);
+
+            let mut small = vec![];
+            table
+                .get_reader(b"key0004")
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut small)
+                .unwrap();
+            assert_eq!(small, b"key0004".to_vec());
+            assert!(table.get_reader(b"nokey").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_table_get_reader_corrupt() {
+        use std::io::Read;
+
+        let mut opt = Options::default();
+        opt.blob_threshold = Some(100);
+        let (mut src, size) = build_blob_table(opt.clone(), 1);
+        // The first blob is at the start of the file.
+        src[100] ^= 0xff;
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
 
-        // We should have at most one item in the cache
-        iter.next();
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 1);
-        iter.next();
-        assert_eq!(opt.block_cache.read().expect(LOCK_POISONED).count(), 1);
+        let mut r = table.get_reader(b"key0000").unwrap().unwrap();
+        let mut buf = vec![];
+        let err = r.read_to_end(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let inner = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(inner.is_corruption());
     }
 
+    #[cfg(feature = "snappy")]
     #[test]
-    fn test_table_iterator_fwd_bwd() {
-        let (src, size) = build_table(build_data());
-        let data = build_data();
+    fn test_table_value_compression() {
+        for &blob_threshold in &[None, Some(200)] {
+            let mut opt = Options::default();
+            opt.block_size = 64;
+            opt.value_compression = CompressionType::CompressionSnappy;
+            opt.blob_threshold = blob_threshold;
+            let (src, size) = build_blob_table(opt.clone(), 30);
+            let compressed_size = size;
+            let table = Table::new(opt.clone(), wrap_buffer(src), size).unwrap();
+            assert!(table.properties().unwrap().value_compression);
 
-        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        let mut iter = table.iter();
-        let mut i = 0;
+            for i in 0..30 {
+                let key = format!("key{:04}", i);
+                let val = table.get(key.as_bytes()).unwrap().unwrap();
+                assert_eq!(val.len(), if i % 3 == 0 { 700 } else { 7 });
+                let lazy = table
+                    .get_lazy(&ReadOptions::default(), key.as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(lazy.fetch().unwrap(), val);
 
-        while let Some((k, v)) = iter.next() {
-            assert_eq!(
-                (data[i].0.as_bytes(), data[i].1.as_bytes()),
-                (k.as_ref(), v.as_ref())
-            );
-            i += 1;
+                let mut streamed = vec![];
+                std::io::Read::read_to_end(
+                    &mut table.get_reader(key.as_bytes()).unwrap().unwrap(),
+                    &mut streamed,
+                )
+                .unwrap();
+                assert_eq!(streamed, val);
+            }
+
+            let mut iter = table.iter();
+            let vals: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(_, v)| v).collect();
+            assert_eq!(vals.len(), 30);
+            assert_eq!(vals[3], b"key0003".repeat(100));
+
+            opt.value_compression = CompressionType::CompressionNone;
+            let (_, size) = build_blob_table(opt, 30);
+            assert!(compressed_size < size);
         }
+    }
 
-        assert_eq!(i, data.len());
-        assert!(!iter.valid());
+    /// Stores records of 4-byte fields with every field's bytes reversed.
+    struct ReversedFieldsCodec;
 
-        // Go forward again, to last entry.
-        while let Some((key, _)) = iter.next() {
-            if key.as_slice() == b"zzz" {
-                break;
+    impl ValueCodec for ReversedFieldsCodec {
+        fn name(&self) -> &str {
+            "reversed-fields"
+        }
+        fn encode(&self, value: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            if !value.len().is_multiple_of(4) {
+                return Err(Error::InvalidArgument("bad record".to_string()));
+            }
+            for field in value.chunks(4) {
+                dst.extend(field.iter().rev());
+            }
+            Ok(())
+        }
+        fn decode(&self, encoded: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            self.encode(encoded, dst)
+        }
+        fn project(&self, encoded: &[u8], fields: &[usize], dst: &mut Vec<u8>) -> Result<()> {
+            for &f in fields {
+                match encoded.get(4 * f..4 * f + 4) {
+                    Some(field) => dst.extend(field.iter().rev()),
+                    None => return Err(Error::malformed(None, "no such field")),
+                }
             }
+            Ok(())
         }
+    }
 
-        assert!(iter.valid());
-        // backwards count
-        let mut j = 0;
+    #[test]
+    fn test_table_value_codec() {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        opt.value_codec = Some(Arc::new(ReversedFieldsCodec));
+        #[cfg(feature = "snappy")]
+        {
+            opt.value_compression = CompressionType::CompressionSnappy;
+        }
 
-        while iter.prev() {
-            if let Some((k, v)) = current_key_val(&iter) {
-                j += 1;
-                assert_eq!(
-                    (
-                        data[data.len() - 1 - j].0.as_bytes(),
-                        data[data.len() - 1 - j].1.as_bytes()
-                    ),
-                    (k.as_ref(), v.as_ref())
-                );
-            } else {
-                break;
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for i in 0..20_u32 {
+                let record: Vec<u8> = [i, 2 * i, 3 * i]
+                    .iter()
+                    .flat_map(|f| f.to_be_bytes().to_vec())
+                    .collect();
+                b.add(format!("key{:02}", i).as_bytes(), &record).unwrap();
             }
+            assert!(b.add(b"key99", b"odd").is_err());
+            b.finish().unwrap();
         }
+        let size = d.len() as u64;
 
-        // expecting 7 - 1, because the last entry that the iterator stopped on is the last entry
-        // in the table; that is, it needs to go back over 6 entries.
-        assert_eq!(j, 6);
-    }
+        let table = Table::new(opt.clone(), wrap_buffer(d.clone()), size).unwrap();
+        assert_eq!(
+            table.properties().unwrap().value_codec.as_deref(),
+            Some("reversed-fields")
+        );
+        assert_eq!(
+            table.get(b"key07").unwrap().unwrap(),
+            [0, 0, 0, 7, 0, 0, 0, 14, 0, 0, 0, 21]
+        );
 
-    #[test]
-    fn test_table_iterator_filter() {
-        let (src, size) = build_table(build_data());
+        let ropt = ReadOptions {
+            projection: Some(vec![2, 0]),
+            ..Default::default()
+        };
+        assert_eq!(
+            table.get_opt(&ropt, b"key07").unwrap().unwrap(),
+            [0, 0, 0, 21, 0, 0, 0, 7]
+        );
+        let mut iter = table.iter_opt(&ropt);
+        let vals: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(_, v)| v).collect();
+        assert_eq!(vals.len(), 20);
+        assert_eq!(vals[19], [0, 0, 0, 57, 0, 0, 0, 19]);
 
-        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        assert!(table.filters.is_some());
-        let filter_reader = table.filters.clone().unwrap();
-        let mut iter = table.iter();
+        // The codec is required to read the table.
+        opt.value_codec = None;
+        match Table::new(opt, wrap_buffer(d), size) {
+            Err(Error::InvalidArgument(_)) => {}
+            _ => panic!("expected missing value codec to be rejected"),
+        }
+    }
 
-        loop {
-            if let Some((k, _)) = iter.next() {
-                assert!(filter_reader.key_may_match(iter.current_block_off, &k));
-                assert!(!filter_reader.key_may_match(iter.current_block_off, b"somerandomkey"));
-            } else {
-                break;
+    /// Stores keys with zero bytes escaped and a terminator appended, which preserves their order.
+    struct EscapedKeyCodec;
+
+    impl KeyCodec for EscapedKeyCodec {
+        fn name(&self) -> &str {
+            "escaped"
+        }
+        fn encode(&self, key: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            for &b in key {
+                match b {
+                    0 => dst.extend_from_slice(&[0, 0xff]),
+                    b => dst.push(b),
+                }
+            }
+            dst.extend_from_slice(&[0, 1]);
+            Ok(())
+        }
+        fn decode(&self, stored: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+            let mut i = 0;
+            loop {
+                match stored.get(i..) {
+                    Some([0, 1]) => return Ok(()),
+                    Some([0, 0xff, ..]) => {
+                        dst.push(0);
+                        i += 2;
+                    }
+                    Some([b, ..]) if *b != 0 => {
+                        dst.push(*b);
+                        i += 1;
+                    }
+                    _ => return Err(Error::malformed(None, "bad escaped key")),
+                }
             }
         }
     }
 
     #[test]
-    fn test_table_iterator_state_behavior() {
-        let (src, size) = build_table(build_data());
-
-        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        let mut iter = table.iter();
+    fn test_table_key_codec() {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        opt.key_codec = Some(Arc::new(EscapedKeyCodec));
+        let mut keys: Vec<Vec<u8>> = (0..30_u8)
+            .map(|i| {
+                let mut key = format!("key{:02}", i / 2).into_bytes();
+                if i % 2 == 1 {
+                    key.extend_from_slice(&[0, i]);
+                }
+                key
+            })
+            .collect();
+        keys.sort();
 
-        // behavior test
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for key in &keys {
+                b.add(key, key).unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
 
-        // See comment on valid()
-        assert!(!iter.valid());
-        assert!(current_key_val(&iter).is_none());
-        assert!(!iter.prev());
+        let table = Table::new(opt.clone(), wrap_buffer(d.clone()), size).unwrap();
+        assert_eq!(
+            table.properties().unwrap().key_codec.as_deref(),
+            Some("escaped")
+        );
+        assert!(table.index_handles().len() > 3);
+        // Keys are stored escaped...
+        let mut raw = table.raw_iter();
+        assert!(raw.advance());
+        assert_eq!(raw.current_key().unwrap(), b"key00\0\x01");
+        // ...but looked up and iterated as they were added.
+        for key in &keys {
+            assert_eq!(table.get(key).unwrap().as_ref(), Some(key));
+            assert!(table.key_may_exist(key));
+        }
+        assert_eq!(table.get(b"key03\0").unwrap(), None);
+        assert_eq!(table.get(b"key99").unwrap(), None);
+        let lookups: Vec<&[u8]> = vec![&keys[3], b"key99", &keys[20]];
+        let vals = table.multi_get(&ReadOptions::default(), &lookups).unwrap();
+        assert_eq!(
+            vals,
+            vec![Some(keys[3].clone()), None, Some(keys[20].clone())]
+        );
+        let mut hint = SeekHint::new();
+        for key in &keys {
+            assert_eq!(
+                table.get_with_hint(key, &mut hint).unwrap().as_ref(),
+                Some(key)
+            );
+        }
 
+        let mut iter = table.iter();
+        let entries: Vec<_> = SSIteratorIter::wrap(&mut iter).collect();
+        assert_eq!(entries.len(), keys.len());
+        assert!(entries
+            .iter()
+            .zip(&keys)
+            .all(|((k, v), key)| k == key && v == key));
+        iter.seek(b"key05\0");
+        assert_eq!(iter.current_key(), Some(&keys[11][..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&keys[10][..]));
+        iter.reset();
+        assert!(iter.current_key().is_none());
         assert!(iter.advance());
-        let first = current_key_val(&iter);
-        assert!(iter.valid());
-        assert!(current_key_val(&iter).is_some());
+        assert_eq!(iter.current_key(), Some(&keys[0][..]));
+        assert_eq!(iter.current_block_bounds().unwrap().first_key, keys[0]);
+        assert!(iter.skip_current_block());
+        let bounds = iter.current_block_bounds().unwrap();
+        assert_eq!(iter.current_key(), Some(&bounds.first_key[..]));
+        assert!(keys.contains(&bounds.last_key));
 
-        assert!(iter.advance());
-        assert!(iter.prev());
-        assert!(iter.valid());
+        // Ranges and prefixes are given as logical keys, too. Only `key` lies in [key, key + "\0").
+        for key in &keys {
+            let mut end = key.clone();
+            end.push(0);
+            assert_eq!(table.estimate_keys_in_range(key, &end).unwrap(), 1);
 
-        iter.reset();
-        assert!(!iter.valid());
-        assert!(current_key_val(&iter).is_none());
-        assert_eq!(first, iter.next());
-    }
+            let mut iter = table.iter();
+            iter.seek(key);
+            let block = iter.current_block_bounds().unwrap().block;
+            // A new instance doesn't share the cached blocks of `table`.
+            let uncached = Table::new(opt.clone(), wrap_buffer(d.clone()), size).unwrap();
+            assert!(uncached.prefetch_range(key, &end).unwrap() >= 1);
+            assert!(uncached.dump_hot_blocks().contains(&block));
+        }
+        assert_eq!(
+            table.estimate_keys_in_range(&keys[0], b"key05\0").unwrap(),
+            11
+        );
+        let mut iter = table.prefix_iter(b"key05");
+        let entries: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(entries, vec![keys[10].clone(), keys[11].clone()]);
+        let mut iter = table.prefix_iter(b"key05\0");
+        let entries: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(entries, vec![keys[11].clone()]);
 
-    #[test]
-    fn test_table_iterator_behavior_standard() {
-        let mut data = build_data();
-        data.truncate(4);
-        let (src, size) = build_table(data);
-        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        test_iterator_properties(table.iter());
+        let file = LevelFile::new(table).unwrap();
+        assert_eq!(file.smallest, keys[0]);
+        assert_eq!(&file.largest, keys.last().unwrap());
+
+        // The codec is required to read the table.
+        opt.key_codec = None;
+        match Table::new(opt, wrap_buffer(d), size) {
+            Err(Error::InvalidArgument(_)) => {}
+            _ => panic!("expected missing key codec to be rejected"),
+        }
     }
 
     #[test]
-    fn test_table_iterator_values() {
-        let (src, size) = build_table(build_data());
+    fn test_table_value_checksums() {
+        let mut opt = Options::default();
+        opt.value_checksums = true;
+        opt.entry_expiration = true;
         let data = build_data();
+        let mut src = vec![];
+        let mut b = TableBuilder::new(opt.clone(), &mut src);
+        for (i, (k, v)) in data.iter().enumerate() {
+            b.add_with_expiration(k.as_bytes(), v.as_bytes(), i as u64 * 10)
+                .unwrap();
+        }
+        let size = b.finish().unwrap();
 
-        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        let mut iter = table.iter();
-        let mut i = 0;
-
-        iter.next();
-        iter.next();
-
-        // Go back to previous entry, check, go forward two entries, repeat
-        // Verifies that prev/next works well.
-        loop {
-            iter.prev();
+        let table = Table::new(opt.clone(), wrap_buffer(src.clone()), size).unwrap();
+        let props = table.properties().unwrap();
+        assert!(props.value_checksums);
+        assert_eq!(props.raw_value_size, 21);
+        for (k, v) in data.iter() {
+            assert_eq!(
+                table.get(k.as_bytes()).unwrap(),
+                Some(v.as_bytes().to_vec())
+            );
+        }
+        let ropt = ReadOptions {
+            pin_data: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        assert_eq!(&*iter.current_pinned_value().unwrap(), b"def");
+        assert_eq!(
+            SSIteratorIter::wrap(&mut iter)
+                .map(|(_, v)| String::from_utf8(v).unwrap())
+                .collect::<Vec<_>>(),
+            data[1..]
+                .iter()
+                .map(|(_, v)| v.to_string())
+                .collect::<Vec<_>>()
+        );
 
-            if let Some((k, v)) = current_key_val(&iter) {
-                assert_eq!(
-                    (data[i].0.as_bytes(), data[i].1.as_bytes()),
-                    (k.as_ref(), v.as_ref())
-                );
-            } else {
-                break;
-            }
+        // Corrupt the first value, keeping its block's checksum intact as if it had been
+        // corrupted in the block cache.
+        let mut iter = table.iter();
+        iter.advance();
+        let meta = iter.current_entry_meta().unwrap();
+        let (start, end) = (
+            meta.block.offset() as usize,
+            (meta.block.offset() + meta.block.size()) as usize,
+        );
+        src[start + meta.value_offset] ^= 1;
+        let crc = checksum::block_checksum(&Crc32c, &src[start..end], src[end]);
+        src[end + 1..end + 5].copy_from_slice(&crate::types::mask_crc(crc).to_le_bytes());
 
-            i += 1;
-            if iter.next().is_none() || iter.next().is_none() {
-                break;
-            }
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        assert!(table.get(b"abc").unwrap_err().is_corruption());
+        assert_eq!(table.get(b"abd").unwrap(), Some(b"dee".to_vec()));
+        // Iterators stop at the corrupt value, or skip it with skip_corrupt_blocks.
+        let mut iter = table.iter();
+        assert!(!iter.advance());
+        assert!(iter.value().is_none());
+        match iter.status() {
+            Some(Error::Corruption {
+                kind: CorruptionKind::ChecksumMismatch { .. },
+                ..
+            }) => {}
+            s => panic!("expected value checksum mismatch, got {:?}", s),
         }
+        iter.seek(b"abd");
+        assert_eq!(iter.value(), Some(b"dee".to_vec()));
+        assert!(iter.status().is_none());
+        let ropt = ReadOptions {
+            skip_corrupt_blocks: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), data.len() - 1);
+        assert!(iter.status().unwrap().is_corruption());
 
-        // Skipping the last value because the second next() above will break the loop
-        assert_eq!(i, 6);
+        let ropt = ReadOptions {
+            verify_value_checksums: false,
+            ..Default::default()
+        };
+        assert_eq!(table.get_opt(&ropt, b"abc").unwrap(), Some(b"eef".to_vec()));
+        let mut iter = table.iter_opt(&ropt);
+        assert!(iter.advance());
+        assert_eq!(iter.value(), Some(b"eef".to_vec()));
     }
 
     #[test]
-    fn test_table_iterator_seek() {
-        let (src, size) = build_table(build_data());
-
+    fn test_table_entry_expiration() {
+        let (src, size) = build_expiring_table(&[0, 10, 20, 10, 0, 30, 10]);
         let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        let mut iter = table.iter();
+        let data = build_data();
 
-        iter.seek(b"bcd");
-        assert!(iter.valid());
+        let props = table.properties().unwrap();
+        assert!(props.entry_expiration);
+        assert_eq!(props.max_entry_expiration, None);
+        assert_eq!(props.raw_value_size, 21);
+        assert!(!table.is_fully_expired(100));
+
+        // Without a read time, all entries are returned.
+        assert_eq!(table.get(b"abd").unwrap(), Some(b"dee".to_vec()));
+        let mut iter = table.iter();
         assert_eq!(
-            current_key_val(&iter),
-            Some((b"bcd".to_vec(), b"asa".to_vec()))
+            SSIteratorIter::wrap(&mut iter)
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>(),
+            data.iter()
+                .map(|(_, v)| v.as_bytes().to_vec())
+                .collect::<Vec<_>>()
         );
-        iter.seek(b"abc");
-        assert!(iter.valid());
+
+        let ropt = ReadOptions {
+            read_time: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(table.get_opt(&ropt, b"abc").unwrap(), Some(b"def".to_vec()));
+        assert_eq!(table.get_opt(&ropt, b"abd").unwrap(), None);
+        assert_eq!(table.get_opt(&ropt, b"bcd").unwrap(), Some(b"asa".to_vec()));
+
+        let mut iter = table.iter_opt(&ropt);
+        let keys: Vec<_> = SSIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
         assert_eq!(
-            current_key_val(&iter),
-            Some((b"abc".to_vec(), b"def".to_vec()))
+            keys,
+            vec![
+                b"abc".to_vec(),
+                b"bcd".to_vec(),
+                b"xyz".to_vec(),
+                b"xzz".to_vec()
+            ]
         );
 
-        // Seek-past-last invalidates.
-        iter.seek("{{{".as_bytes());
+        // seek() and prev() skip expired entries, too.
+        iter.seek(b"abd");
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+        iter.seek(b"bsr");
+        assert_eq!(iter.current_key(), Some(&b"xyz"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"bcd"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"abc"[..]));
+        assert!(!iter.prev());
+        iter.seek(b"zzz");
         assert!(!iter.valid());
-        iter.seek(b"bbb");
-        assert!(iter.valid());
     }
 
     #[test]
-    fn test_table_get() {
-        let (src, size) = build_table(build_data());
-
+    fn test_table_fully_expired() {
+        let (src, size) = build_expiring_table(&[5, 10, 20, 10, 1, 30, 10]);
         let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
-        let table2 = table.clone();
 
-        let mut _iter = table.iter();
-        // Test that all of the table's entries are reachable via get()
-        for (k, v) in SSIteratorIter::wrap(&mut _iter) {
-            let r = table2.get(&k);
-            assert_eq!(Ok(Some(v)), r);
-        }
+        assert_eq!(table.properties().unwrap().max_entry_expiration, Some(30));
+        assert!(!table.is_fully_expired(29));
+        assert!(table.is_fully_expired(30));
 
-        assert_eq!(
-            table.opt.block_cache.read().expect(LOCK_POISONED).count(),
-            3
-        );
+        let ropt = ReadOptions {
+            read_time: Some(30),
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert!(!iter.advance());
+    }
 
-        // test that filters work and don't return anything at all.
-        assert!(table.get(b"aaa").unwrap().is_none());
-        assert!(table.get(b"aaaa").unwrap().is_none());
-        assert!(table.get(b"aa").unwrap().is_none());
-        assert!(table.get(b"abcd").unwrap().is_none());
-        assert!(table.get(b"abb").unwrap().is_none());
-        assert!(table.get(b"xyy").unwrap().is_none());
-        assert!(table.get(b"zzy").unwrap().is_none());
-        assert!(table.get(b"zz1").unwrap().is_none());
-        assert!(table.get("zz{".as_bytes()).unwrap().is_none());
+    #[test]
+    fn test_table_expiration() {
+        let mut d = Vec::with_capacity(512);
+        {
+            let mut b = TableBuilder::new(Options::default(), &mut d);
+            assert!(b.add_with_expiration(b"abc", b"def", 1).is_err());
+            b.add(b"abc", b"def").unwrap();
+            b.set_expiration(100);
+            b.finish().unwrap();
+        }
+        let size = d.len() as u64;
+        let table = Table::new(Options::default(), wrap_buffer(d), size).unwrap();
+        assert!(table.is_fully_expired(100));
+        assert!(!table.is_fully_expired(99));
+
+        let ropt = ReadOptions {
+            read_time: Some(99),
+            ..Default::default()
+        };
+        assert_eq!(table.get_opt(&ropt, b"abc").unwrap(), Some(b"def".to_vec()));
+        assert_eq!(SSIteratorIter::wrap(&mut table.iter_opt(&ropt)).count(), 1);
+        let ropt = ReadOptions {
+            read_time: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(table.get_opt(&ropt, b"abc").unwrap(), None);
+        assert!(!table.iter_opt(&ropt).advance());
+        assert_eq!(table.get(b"abc").unwrap(), Some(b"def".to_vec()));
     }
 
+    #[cfg(feature = "filters")]
     #[test]
     fn test_table_reader_checksum() {
         let (mut src, size) = build_table(build_data());
@@ -673,15 +5349,54 @@ mod tests {
         assert!(table.filters.is_some());
         assert_eq!(table.filters.as_ref().unwrap().num(), 1);
 
+        match table.get(b"abc") {
+            Err(Error::Corruption {
+                file_offset: Some(0),
+                block_handle: Some(h),
+                kind: CorruptionKind::ChecksumMismatch { .. },
+            }) => assert_eq!(h.offset(), 0),
+            r => panic!("expected checksum mismatch, got {:?}", r),
+        }
+
+        {
+            // By default, iteration stops at the corrupt block and reports the error.
+            let mut iter = table.iter();
+            assert!(iter.status().is_none());
+            assert!(!iter.advance());
+            assert!(!iter.valid());
+            match iter.status() {
+                Some(Error::Corruption {
+                    kind: CorruptionKind::ChecksumMismatch { .. },
+                    ..
+                }) => {}
+                s => panic!("expected checksum mismatch, got {:?}", s),
+            }
+            assert!(!iter.advance());
+            assert!(iter.status().is_some());
+
+            // Seeking past the corrupt block clears the error.
+            iter.seek(build_data()[5].0.as_bytes());
+            assert!(iter.valid());
+            assert!(iter.status().is_none());
+            iter.reset();
+            assert!(!iter.advance());
+            assert!(iter.status().is_some());
+        }
+
+        let ropt = ReadOptions {
+            skip_corrupt_blocks: true,
+            ..Default::default()
+        };
         {
-            let mut _iter = table.iter();
+            let mut _iter = table.iter_opt(&ropt);
             let iter = SSIteratorIter::wrap(&mut _iter);
             // first block is skipped
             assert_eq!(iter.count(), 4);
+            assert!(_iter.status().is_some());
         }
 
         {
-            let mut _iter = table.iter();
+            let mut _iter = table.iter_opt(&ropt);
             let iter = SSIteratorIter::wrap(&mut _iter);
 
             for (k, _) in iter {
@@ -693,4 +5408,44 @@ mod tests {
             panic!("Should have hit 5th record in table!");
         }
     }
+
+    #[test]
+    fn test_table_iterator_error_mid_scan() {
+        let (mut src, size) = build_table(build_data());
+        let table = Table::new(Options::default(), wrap_buffer(src.clone()), size).unwrap();
+        let mut iter = table.iter();
+        let mut blocks = vec![];
+        while iter.advance() {
+            blocks.push(iter.current_entry_meta().unwrap().block);
+        }
+        // Running out of entries isn't an error.
+        assert!(!iter.valid());
+        assert!(iter.status().is_none());
+
+        let corrupt = table.index_handles()[1].clone();
+        src[corrupt.offset() as usize + 1] ^= 0xff;
+        let table = Table::new(Options::default(), wrap_buffer(src), size).unwrap();
+        let before = blocks.iter().take_while(|&b| *b != corrupt).count();
+        let intact = blocks.iter().filter(|&b| *b != corrupt).count();
+        assert!(before > 0 && intact > before);
+
+        // By default, the scan stops at the corrupt block, leaving the iterator invalid.
+        let mut iter = table.iter();
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), before);
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap().is_corruption());
+        assert!(!iter.advance());
+        assert!(iter.next().is_none());
+        assert!(iter.status().is_some());
+
+        // With skip_corrupt_blocks, it carries on after the corrupt block.
+        let ropt = ReadOptions {
+            skip_corrupt_blocks: true,
+            ..Default::default()
+        };
+        let mut iter = table.iter_opt(&ropt);
+        assert_eq!(SSIteratorIter::wrap(&mut iter).count(), intact);
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap().is_corruption());
+    }
 }
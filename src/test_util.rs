@@ -1,8 +1,32 @@
 use crate::cmp::{Cmp, DefaultCmp};
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::table_reader::Table;
 use crate::types::{current_key_val, SSIterator};
 
 use std::cmp::Ordering;
 
+/// Returns the contents of a table holding `entries`, which must be sorted by `opt.cmp`.
+pub fn build_table_data<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    opt: &Options,
+    entries: &[(K, V)],
+) -> Vec<u8> {
+    let mut d = vec![];
+    let mut b = TableBuilder::new(opt.clone(), &mut d);
+    for (k, v) in entries {
+        b.add(k.as_ref(), v.as_ref()).unwrap();
+    }
+    b.finish().unwrap();
+    d
+}
+
+/// Returns an in-memory table holding `entries`, see `build_table_data()`.
+pub fn build_table<K: AsRef<[u8]>, V: AsRef<[u8]>>(opt: &Options, entries: &[(K, V)]) -> Table {
+    let d = build_table_data(opt, entries);
+    let size = d.len() as u64;
+    Table::new(opt.clone(), Box::new(d), size).unwrap()
+}
+
 /// TestSSIter is an SSIterator over a vector, to be used for testing purposes.
 pub struct TestSSIter<'a> {
     v: Vec<(&'a [u8], &'a [u8])>,
@@ -12,11 +36,11 @@ pub struct TestSSIter<'a> {
 
 impl<'a> TestSSIter<'a> {
     pub fn new(c: Vec<(&'a [u8], &'a [u8])>) -> TestSSIter<'a> {
-        return TestSSIter {
+        TestSSIter {
             v: c,
             ix: 0,
             init: false,
-        };
+        }
     }
 }
 
@@ -105,6 +129,7 @@ pub fn test_iterator_properties<It: SSIterator>(mut it: It) {
     assert!(it.valid());
     let first = current_key_val(&it);
     assert_eq!(first.as_ref().unwrap().0, it.current_key().unwrap());
+    assert_eq!(first.as_ref().unwrap().1, it.value().unwrap());
     assert!(it.advance());
     let second = current_key_val(&it);
     assert_eq!(second.as_ref().unwrap().0, it.current_key().unwrap());
@@ -119,6 +144,14 @@ pub fn test_iterator_properties<It: SSIterator>(mut it: It) {
     // past end is invalid
     assert!(!it.advance());
     assert!(!it.valid());
+    assert!(it.current_key().is_none());
+    assert!(it.value().is_none());
+
+    // entries() starts at the entry seeked to.
+    it.seek(&third.as_ref().unwrap().0);
+    let rest: Vec<_> = it.entries().collect();
+    assert_eq!(rest, vec![third.clone().unwrap(), fourth.clone().unwrap()]);
+    assert!(!it.valid());
 
     it.reset();
     it.seek(&fourth.as_ref().unwrap().0);
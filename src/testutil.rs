@@ -0,0 +1,391 @@
+//! Utilities for testing code built on this crate, enabled by the `testutil` feature.
+//!
+//! `generate()` builds deterministic tables from a `TableSpec`: the key distribution, number and
+//! size of entries, and corruptions applied to the finished table. Compression, filters and all
+//! other details are taken from the `Options` passed along. The same spec and options always
+//! produce the same bytes.
+//!
+//! Golden fixtures are tables written by other implementations (e.g. C++ LevelDB or RocksDB),
+//! paired with a text file listing their entries. `check_golden_fixture()` reads such a table
+//! and compares its entries with the listing, so that interoperability regressions show up as
+//! test failures. Listings contain one entry per line, the key and the value hex-encoded and
+//! separated by whitespace; empty lines and lines starting with `#` are ignored.
+
+#![cfg(feature = "testutil")]
+
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::table_reader::Table;
+use crate::types::{mix64, SSIterator};
+
+use std::cmp::Ordering;
+#[cfg(any(unix, windows))]
+use std::fs;
+#[cfg(any(unix, windows))]
+use std::path::Path;
+
+/// The keys of a generated table.
+#[derive(Clone, Debug)]
+pub enum KeyDistribution {
+    /// Keys `key00000000`, `key00000001` and so on.
+    Sequential,
+    /// Random keys of `len` arbitrary bytes.
+    Random { len: usize },
+    /// Keys starting with one of `prefixes` random 4-byte prefixes, followed by a sequence
+    /// number, e.g. for testing prefix extractors.
+    Prefixed { prefixes: usize },
+}
+
+/// A corruption applied to a generated table after it has been written.
+#[derive(Clone, Debug)]
+pub enum Corruption {
+    /// Inverts the bits of the byte at this offset.
+    FlipByte(u64),
+    /// Inverts the bits of the byte in the middle of the data block with this number, so that
+    /// its checksum doesn't match anymore.
+    DataBlock(usize),
+    /// Overwrites the magic number at the end of the table.
+    Footer,
+    /// Cuts the table off after this many bytes.
+    Truncate(u64),
+}
+
+/// Describes a table to generate.
+#[derive(Clone, Debug)]
+pub struct TableSpec {
+    pub num_entries: usize,
+    pub keys: KeyDistribution,
+    /// The length of every value. Values repeat a short pattern, so they compress well.
+    pub value_len: usize,
+    /// Seed of the generator deciding on random keys.
+    pub seed: u64,
+    /// Applied in order after the table has been written.
+    pub corruptions: Vec<Corruption>,
+}
+
+impl Default for TableSpec {
+    fn default() -> TableSpec {
+        TableSpec {
+            num_entries: 1000,
+            keys: KeyDistribution::Sequential,
+            value_len: 16,
+            seed: 0,
+            corruptions: vec![],
+        }
+    }
+}
+
+/// A table built by `generate()`.
+pub struct GeneratedTable {
+    /// The table as written, with the spec's corruptions applied.
+    pub contents: Vec<u8>,
+    /// The entries added to the table, in order.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl GeneratedTable {
+    /// Opens the table from memory.
+    pub fn open(&self, opt: Options) -> Result<Table> {
+        let size = self.contents.len() as u64;
+        Table::new(opt, Box::new(self.contents.clone()), size)
+    }
+}
+
+/// Builds the table described by `spec` using `opt`.
+pub fn generate(opt: &Options, spec: &TableSpec) -> Result<GeneratedTable> {
+    let keys = generate_keys(opt, spec)?;
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = keys
+        .into_iter()
+        .enumerate()
+        .map(|(i, k)| (k, generate_value(i, spec.value_len)))
+        .collect();
+
+    let mut contents = vec![];
+    let mut b = TableBuilder::new(opt.clone(), &mut contents);
+    for (k, v) in &entries {
+        b.add(k, v)?;
+    }
+    b.finish()?;
+
+    for c in &spec.corruptions {
+        corrupt(opt, &mut contents, c)?;
+    }
+    Ok(GeneratedTable { contents, entries })
+}
+
+fn generate_keys(opt: &Options, spec: &TableSpec) -> Result<Vec<Vec<u8>>> {
+    let n = spec.num_entries;
+    let random = |i: u64| mix64(spec.seed ^ mix64(i));
+    let mut keys: Vec<Vec<u8>> = match spec.keys {
+        KeyDistribution::Sequential => (0..n)
+            .map(|i| format!("key{:08}", i).into_bytes())
+            .collect(),
+        KeyDistribution::Random { len } => {
+            if len < 8 && (n as u64) > 1 << (8 * len as u64) {
+                return Err(Error::InvalidArgument(format!(
+                    "there are less than {} distinct keys of {} bytes",
+                    n, len
+                )));
+            }
+            // Draw keys until there are `n` distinct ones.
+            let mut keys = vec![];
+            let mut i = 0;
+            while keys.len() < n {
+                let mut key = Vec::with_capacity(len);
+                while key.len() < len {
+                    key.extend_from_slice(&random(i).to_le_bytes());
+                    i += 1;
+                }
+                key.truncate(len);
+                keys.push(key);
+                if keys.len() == n {
+                    sort_dedup(opt, &mut keys);
+                }
+            }
+            keys
+        }
+        KeyDistribution::Prefixed { prefixes } => {
+            if prefixes == 0 && n > 0 {
+                return Err(Error::InvalidArgument("no key prefixes".to_string()));
+            }
+            (0..n)
+                .map(|i| {
+                    let prefix = (random((i % prefixes) as u64) as u32).to_be_bytes();
+                    let mut key = prefix.to_vec();
+                    key.extend_from_slice(format!("{:08}", i / prefixes).as_bytes());
+                    key
+                })
+                .collect()
+        }
+    };
+    sort_dedup(opt, &mut keys);
+    Ok(keys)
+}
+
+fn sort_dedup(opt: &Options, keys: &mut Vec<Vec<u8>>) {
+    keys.sort_by(|a, b| opt.cmp.cmp(a, b));
+    keys.dedup_by(|a, b| opt.cmp.cmp(a, b) == Ordering::Equal);
+}
+
+fn generate_value(i: usize, len: usize) -> Vec<u8> {
+    format!("value{:08}", i)
+        .into_bytes()
+        .into_iter()
+        .cycle()
+        .take(len)
+        .collect()
+}
+
+fn corrupt(opt: &Options, contents: &mut Vec<u8>, c: &Corruption) -> Result<()> {
+    let offset = match *c {
+        Corruption::FlipByte(offset) => offset,
+        Corruption::DataBlock(n) => {
+            let size = contents.len() as u64;
+            let table = Table::new(opt.clone(), Box::new(contents.clone()), size)?;
+            let handle = table.index_handles().into_iter().nth(n).ok_or_else(|| {
+                Error::InvalidArgument(format!("the table has no data block {}", n))
+            })?;
+            handle.offset() + handle.size() / 2
+        }
+        // The last byte belongs to the magic number.
+        Corruption::Footer => (contents.len() as u64).saturating_sub(1),
+        Corruption::Truncate(len) => {
+            contents.truncate(len as usize);
+            return Ok(());
+        }
+    };
+    match contents.get_mut(offset as usize) {
+        Some(b) => *b = !*b,
+        None => {
+            return Err(Error::InvalidArgument(format!(
+                "offset {} is past the end of the table",
+                offset
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Returns all entries of `table`, failing on the first error its iterator encounters.
+pub fn read_entries(table: &Table) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut iter = table.iter();
+    let mut entries = vec![];
+    while let Some(entry) = iter.next() {
+        entries.push(entry);
+    }
+    match iter.take_error() {
+        Some(e) => Err(e),
+        None => Ok(entries),
+    }
+}
+
+/// Checks that `table` contains exactly the entries `expected`. A mismatch is reported as
+/// corruption, naming the first entry that differs.
+pub fn check_entries(table: &Table, expected: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+    let entries = read_entries(table)?;
+    for i in 0..entries.len().max(expected.len()) {
+        if entries.get(i) != expected.get(i) {
+            return Err(Error::malformed(
+                None,
+                &format!(
+                    "entry {} is {}, expected {}",
+                    i,
+                    describe(entries.get(i)),
+                    describe(expected.get(i))
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn describe(entry: Option<&(Vec<u8>, Vec<u8>)>) -> String {
+    match entry {
+        Some((k, v)) => format!("{} => {}", hex(k), hex(v)),
+        None => "missing".to_string(),
+    }
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses a listing of entries in the format described in the module documentation.
+pub fn parse_entries(listing: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = vec![];
+    for (n, line) in listing.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let entry = match (fields.next(), fields.next(), fields.next()) {
+            (Some(k), v, None) => unhex(k).zip(unhex(v.unwrap_or(""))),
+            _ => None,
+        };
+        entries.push(entry.ok_or_else(|| {
+            Error::InvalidArgument(format!("bad entry on line {} of listing", n + 1))
+        })?);
+    }
+    Ok(entries)
+}
+
+/// Returns a listing of `entries` that `parse_entries()` reads back.
+pub fn format_entries(entries: &[(Vec<u8>, Vec<u8>)]) -> String {
+    entries
+        .iter()
+        .map(|(k, v)| format!("{} {}\n", hex(k), hex(v)))
+        .collect()
+}
+
+/// Reads the golden fixture at `table_path` using `opt` and checks that its entries are those
+/// listed in the file at `listing_path`.
+#[cfg(any(unix, windows))]
+pub fn check_golden_fixture(opt: Options, table_path: &Path, listing_path: &Path) -> Result<()> {
+    let expected = parse_entries(&fs::read_to_string(listing_path)?)?;
+    let table = Table::new_from_file(opt, table_path)?;
+    check_entries(&table, &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::CompressionType;
+
+    #[test]
+    fn test_testutil_generate() {
+        let mut opt = Options::default();
+        opt.block_size = 256;
+        for keys in &[
+            KeyDistribution::Sequential,
+            KeyDistribution::Random { len: 6 },
+            KeyDistribution::Prefixed { prefixes: 7 },
+        ] {
+            let spec = TableSpec {
+                keys: keys.clone(),
+                seed: 3,
+                ..Default::default()
+            };
+            let t = generate(&opt, &spec).unwrap();
+            assert_eq!(t.entries.len(), 1000);
+            assert_eq!(t.contents, generate(&opt, &spec).unwrap().contents);
+            check_entries(&t.open(opt.clone()).unwrap(), &t.entries).unwrap();
+        }
+
+        let spec = TableSpec {
+            keys: KeyDistribution::Random { len: 1 },
+            ..Default::default()
+        };
+        assert!(generate(&opt, &spec).is_err());
+
+        let spec = TableSpec {
+            value_len: 100,
+            ..Default::default()
+        };
+        let plain = generate(&opt, &spec).unwrap();
+        opt.compression_type = CompressionType::CompressionSnappy;
+        let compressed = generate(&opt, &spec).unwrap();
+        assert!(compressed.contents.len() < plain.contents.len());
+    }
+
+    #[test]
+    fn test_testutil_corruptions() {
+        let mut opt = Options::default();
+        opt.block_size = 256;
+        let mut spec = TableSpec::default();
+
+        spec.corruptions = vec![Corruption::DataBlock(2)];
+        let t = generate(&opt, &spec).unwrap();
+        let table = t.open(opt.clone()).unwrap();
+        assert!(read_entries(&table).err().unwrap().is_corruption());
+        assert!(check_entries(&table, &t.entries).is_err());
+
+        spec.corruptions = vec![Corruption::Footer];
+        assert!(generate(&opt, &spec).unwrap().open(opt.clone()).is_err());
+
+        spec.corruptions = vec![Corruption::Truncate(100)];
+        let t = generate(&opt, &spec).unwrap();
+        assert_eq!(t.contents.len(), 100);
+        assert!(t.open(opt.clone()).is_err());
+
+        spec.corruptions = vec![Corruption::FlipByte(1 << 40)];
+        assert!(generate(&opt, &spec).is_err());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_testutil_golden_fixture() {
+        let entries = vec![
+            (b"abc".to_vec(), b"\x00\xff".to_vec()),
+            (b"abd".to_vec(), vec![]),
+        ];
+        let listing = format!("# comment\n\n{}", format_entries(&entries));
+        assert_eq!(parse_entries(&listing).unwrap(), entries);
+        assert!(parse_entries("abc").is_err());
+        assert!(parse_entries("61 62 63").is_err());
+
+        let dir = std::env::temp_dir().join(format!("sstable_golden_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let opt = Options::default();
+        let t = generate(&opt, &TableSpec::default()).unwrap();
+        fs::write(dir.join("table.sst"), &t.contents).unwrap();
+        fs::write(dir.join("table.txt"), format_entries(&t.entries)).unwrap();
+        fs::write(dir.join("other.txt"), listing).unwrap();
+
+        check_golden_fixture(opt.clone(), &dir.join("table.sst"), &dir.join("table.txt")).unwrap();
+        let err = check_golden_fixture(opt, &dir.join("table.sst"), &dir.join("other.txt"));
+        assert!(err.err().unwrap().is_corruption());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,16 @@
+//! Internal instrumentation macros. With the `tracing` feature enabled, they forward to the
+//! corresponding `tracing` macros; without it, they expand to nothing, so that instrumentation
+//! points don't cost anything in builds that don't use it.
+
+/// Emits a `tracing` event at the given level (`trace`, `debug`, `info`, `warn`, `error`).
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($lvl:ident, $($arg:tt)+) => {
+        ::tracing::$lvl!(target: "sstable", $($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($lvl:ident, $($arg:tt)+) => {};
+}
@@ -0,0 +1,48 @@
+//! Encoding of per-entry expiration timestamps. In tables with entry expiration enabled, every
+//! value is prefixed with its expiration timestamp as varint; 0 means that the entry doesn't
+//! expire.
+
+use integer_encoding::VarInt;
+
+/// Timestamp of entries that never expire.
+pub const NO_EXPIRATION: u64 = 0;
+
+/// Appends `val`, prefixed by `expiration`, to `dst`.
+pub fn encode_value(expiration: u64, val: &[u8], dst: &mut Vec<u8>) {
+    let start = dst.len();
+    dst.resize(start + expiration.required_space(), 0);
+    expiration.encode_var(&mut dst[start..]);
+    dst.extend_from_slice(val);
+}
+
+/// Splits an encoded value into expiration timestamp and the original value.
+pub fn decode_value(encoded: &[u8]) -> (u64, &[u8]) {
+    let (expiration, n) = u64::decode_var(encoded).unwrap_or((NO_EXPIRATION, 0));
+    (expiration, &encoded[n..])
+}
+
+/// Returns true if an entry with `expiration` has expired at `read_time`.
+pub fn is_expired(expiration: u64, read_time: u64) -> bool {
+    expiration != NO_EXPIRATION && expiration <= read_time
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_encode_decode() {
+        let mut buf = vec![];
+        encode_value(NO_EXPIRATION, b"abc", &mut buf);
+        assert_eq!(buf.len(), 4);
+        assert_eq!(decode_value(&buf), (NO_EXPIRATION, &b"abc"[..]));
+
+        buf.clear();
+        encode_value(1_600_000_000, b"", &mut buf);
+        assert_eq!(decode_value(&buf), (1_600_000_000, &b""[..]));
+
+        assert!(!is_expired(NO_EXPIRATION, u64::MAX));
+        assert!(!is_expired(10, 9));
+        assert!(is_expired(10, 10));
+    }
+}
@@ -0,0 +1,258 @@
+//! Two-level iteration, as over the index and data blocks of a table: the values of an index
+//! iterator refer to blocks, which a `BlockFunction` opens, and a `TwoLevelIterator` runs over the
+//! entries of all blocks in index order. `Table::raw_iter()` is built this way; other structures
+//! split into blocks referenced by an index (e.g. in a file format of an application) can reuse
+//! it.
+
+use crate::error::{Error, Result};
+use crate::types::SSIterator;
+
+/// Opens the block an index entry refers to. Implemented for closures taking the index entry's
+/// value.
+pub trait BlockFunction {
+    type Iter: SSIterator;
+
+    /// Returns an iterator over the block referred to by `index_value`, positioned before its
+    /// first entry.
+    fn open(&mut self, index_value: &[u8]) -> Result<Self::Iter>;
+}
+
+impl<F, D> BlockFunction for F
+where
+    F: FnMut(&[u8]) -> Result<D>,
+    D: SSIterator,
+{
+    type Iter = D;
+
+    fn open(&mut self, index_value: &[u8]) -> Result<D> {
+        self(index_value)
+    }
+}
+
+/// An iterator over the entries of the blocks referred to by the entries of an index iterator.
+/// The keys of the index must be separators: every block's keys are greater than the previous
+/// index key and not greater than its own. Empty blocks are skipped. A block that can't be opened
+/// makes the iterator invalid, with the error available from `status()`.
+pub struct TwoLevelIterator<I, F: BlockFunction> {
+    index: I,
+    blocks: F,
+    data: Option<F::Iter>,
+    // The index value `data` was opened from.
+    data_handle: Vec<u8>,
+    err: Option<Error>,
+}
+
+impl<I: SSIterator, F: BlockFunction> TwoLevelIterator<I, F> {
+    /// Returns an iterator over the blocks referred to by `index`, which are opened by `blocks`.
+    pub fn new(mut index: I, blocks: F) -> TwoLevelIterator<I, F> {
+        index.reset();
+        TwoLevelIterator {
+            index,
+            blocks,
+            data: None,
+            data_handle: vec![],
+            err: None,
+        }
+    }
+
+    /// Returns the index iterator, positioned at the entry of the current block if the iterator
+    /// is valid.
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    /// Opens the block referred to by the current index entry, positioned before its first entry.
+    /// Returns false after an error.
+    fn open_block(&mut self) -> bool {
+        let handle = match self.index.value() {
+            Some(handle) => handle,
+            None => return false,
+        };
+        if let Some(ref mut data) = self.data {
+            if handle == self.data_handle {
+                data.reset();
+                return true;
+            }
+        }
+        match self.blocks.open(&handle) {
+            Ok(data) => {
+                self.data = Some(data);
+                self.data_handle = handle;
+                true
+            }
+            Err(e) => {
+                self.err = Some(e);
+                self.invalidate();
+                false
+            }
+        }
+    }
+
+    /// Moves to the first entry of the next non-empty block.
+    fn next_block(&mut self) -> bool {
+        while self.index.advance() {
+            if !self.open_block() {
+                return false;
+            }
+            if self.data.as_mut().unwrap().advance() {
+                return true;
+            }
+        }
+        self.invalidate();
+        false
+    }
+
+    fn invalidate(&mut self) {
+        self.index.reset();
+        if let Some(ref mut data) = self.data {
+            data.reset();
+        }
+    }
+}
+
+impl<I: SSIterator, F: BlockFunction> SSIterator for TwoLevelIterator<I, F> {
+    fn advance(&mut self) -> bool {
+        if self.valid() && self.data.as_mut().unwrap().advance() {
+            return true;
+        }
+        self.next_block()
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.valid() && self.data.as_ref().unwrap().current(key, val)
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        if self.valid() {
+            self.data.as_ref().unwrap().current_key()
+        } else {
+            None
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.err = None;
+        self.index.seek(key);
+        if !self.index.valid() {
+            self.invalidate();
+            return;
+        }
+        if !self.open_block() {
+            return;
+        }
+        let data = self.data.as_mut().unwrap();
+        data.seek(key);
+        if !data.valid() {
+            self.next_block();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.err = None;
+        self.invalidate();
+    }
+
+    fn valid(&self) -> bool {
+        self.index.valid() && self.data.as_ref().is_some_and(|d| d.valid())
+    }
+
+    /// Moves to the previous entry. Moving back into the previous block iterates over it once to
+    /// find its last entry.
+    fn prev(&mut self) -> bool {
+        if !self.valid() {
+            return false;
+        }
+        if self.data.as_mut().unwrap().prev() {
+            return true;
+        }
+        while self.index.prev() {
+            if !self.open_block() {
+                return false;
+            }
+            let data = self.data.as_mut().unwrap();
+            let mut last = None;
+            while data.advance() {
+                last = data.current_key().map(|k| k.to_vec());
+            }
+            if let Some(last) = last {
+                data.seek(&last);
+                return true;
+            }
+        }
+        self.invalidate();
+        false
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.err
+            .as_ref()
+            .or_else(|| self.index.status())
+            .or_else(|| self.data.as_ref().and_then(|d| d.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::block_builder::BlockBuilder;
+    use crate::options::Options;
+    use crate::test_util::test_iterator_properties;
+
+    fn block(entries: &[(&str, &str)]) -> Block {
+        let mut b = BlockBuilder::new(Options::default());
+        for (k, v) in entries {
+            b.add(k.as_bytes(), v.as_bytes());
+        }
+        Block::new(Options::default(), b.finish())
+    }
+
+    fn iter() -> TwoLevelIterator<crate::block::BlockIter, impl BlockFunction> {
+        let data = [
+            block(&[("a", "1"), ("b", "2")]),
+            block(&[]),
+            block(&[("c", "3")]),
+            block(&[("d", "4")]),
+        ];
+        let index = block(&[("b", "0"), ("bb", "1"), ("c", "2"), ("d", "3")]);
+        TwoLevelIterator::new(index.iter(), move |i: &[u8]| {
+            Ok(data[(i[0] - b'0') as usize].iter())
+        })
+    }
+
+    #[test]
+    fn test_two_level_iterator_properties() {
+        test_iterator_properties(iter());
+    }
+
+    #[test]
+    fn test_two_level_iterator_seek() {
+        let mut it = iter();
+        // Between the last key of a block and its separator; the empty block is skipped.
+        it.seek(b"ba");
+        assert_eq!(it.current_key(), Some(&b"c"[..]));
+        assert!(it.prev());
+        assert_eq!(it.current_key(), Some(&b"b"[..]));
+        it.seek(b"e");
+        assert!(!it.valid());
+        assert!(it.status().is_none());
+    }
+
+    #[test]
+    fn test_two_level_iterator_error() {
+        let index = block(&[("a", "0"), ("b", "1")]);
+        let mut it = TwoLevelIterator::new(index.iter(), |i: &[u8]| {
+            if i == b"0" {
+                Ok(block(&[("a", "1")]).iter())
+            } else {
+                Err(Error::malformed(None, "bad block"))
+            }
+        });
+        assert!(it.advance());
+        assert!(!it.advance());
+        assert!(!it.valid());
+        assert!(it.status().unwrap().is_corruption());
+        it.reset();
+        assert!(it.status().is_none());
+    }
+}
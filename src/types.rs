@@ -1,7 +1,8 @@
 //! A collection of fundamental and/or simple types used by other modules. A bit of a grab bag :-)
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
+#[cfg(any(unix, windows))]
 use std::fs::File;
 #[cfg(unix)]
 use std::os::unix::fs::FileExt;
@@ -10,44 +11,265 @@ use std::os::windows::fs::FileExt;
 use std::sync::Arc;
 use std::sync::RwLock;
 
+/// RandomAccess is the only interface through which tables are read. Implement it to read tables
+/// from storage other than local files (e.g. memory, flash, or remote storage).
 pub trait RandomAccess: Send + Sync {
-    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize>;
+
+    /// Reads several byte ranges, each given as an offset and the buffer to fill. Used when
+    /// several blocks are needed at once (`Table::read_blocks()`, `Table::multi_get()`);
+    /// implementations able to issue reads concurrently, like `UringFile`, can serve them in one
+    /// batch. The default implementation calls `read_at()` for every range.
+    fn read_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Result<()> {
+        for (off, dst) in reqs.iter_mut() {
+            self.read_at(*off, dst)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the byte range starting at `off` into `bufs`, filling them one after the other, and
+    /// returns the number of bytes read; fewer bytes than the buffers hold are read only at the
+    /// end of the file. Used when adjacent blocks are needed at once (e.g. the next blocks of an
+    /// iterator, see `ReadOptions::readahead_blocks`); files implement it using a single
+    /// `preadv()` call on Linux, Android and FreeBSD. The default implementation calls
+    /// `read_at()` for every buffer.
+    fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let n = self.read_at(off + total as u64, buf)?;
+            total += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Returns an ID identifying the file and its contents, if there is one: two files with the
+    /// same ID must have the same contents. Tables opened from files with an ID share the blocks
+    /// they cache, even across reopening (see `Table::with_cache_id()`). The default
+    /// implementation returns None.
+    fn file_id(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// BufferBackedFile is a simple type implementing RandomAccess on a Vec<u8>. Used for some tests.
 #[allow(unused)]
 pub type BufferBackedFile = Vec<u8>;
 
+/// Copies as many bytes as available from `src` at `off` into `dst`.
+fn read_at_slice(src: &[u8], off: u64, dst: &mut [u8]) -> usize {
+    if off > src.len() as u64 {
+        return 0;
+    }
+    let off = off as usize;
+    let remaining = src.len() - off;
+    let to_read = if dst.len() > remaining {
+        remaining
+    } else {
+        dst.len()
+    };
+    dst[0..to_read].copy_from_slice(&src[off..off + to_read]);
+    to_read
+}
+
 impl RandomAccess for BufferBackedFile {
-    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
-        if off > self.len() {
-            return Ok(0);
-        }
-        let remaining = self.len() - off;
-        let to_read = if dst.len() > remaining {
-            remaining
-        } else {
-            dst.len()
-        };
-        (&mut dst[0..to_read]).copy_from_slice(&self[off..off + to_read]);
-        Ok(to_read)
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        Ok(read_at_slice(self, off, dst))
+    }
+}
+
+/// Allows reading tables embedded into the binary, e.g. using `include_bytes!()`.
+impl RandomAccess for &'static [u8] {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        Ok(read_at_slice(self, off, dst))
+    }
+}
+
+/// FnRandomAccess implements RandomAccess on top of a function reading a byte range, such as a
+/// callback issuing HTTP range requests. This is the way to read tables on platforms without
+/// file APIs, like `wasm32-unknown-unknown`.
+///
+/// The function is called as `f(offset, dst)` and returns the number of bytes read into `dst`.
+pub struct FnRandomAccess<F> {
+    f: F,
+}
+
+impl<F> FnRandomAccess<F>
+where
+    F: Fn(u64, &mut [u8]) -> Result<usize>,
+{
+    pub fn new(f: F) -> FnRandomAccess<F> {
+        FnRandomAccess { f }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+impl<F> RandomAccess for FnRandomAccess<F>
+where
+    F: Fn(u64, &mut [u8]) -> Result<usize> + Send + Sync,
+{
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        (self.f)(off, dst)
+    }
+}
+
+// Functions wrapping JavaScript callbacks are neither Send nor Sync. As wasm32 without the atomics
+// feature has no threads, requiring them would only make those functions unusable.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+impl<F> RandomAccess for FnRandomAccess<F>
+where
+    F: Fn(u64, &mut [u8]) -> Result<usize>,
+{
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        (self.f)(off, dst)
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<F> Send for FnRandomAccess<F> {}
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<F> Sync for FnRandomAccess<F> {}
+
+/// Shared files, e.g. handed out by a `FileRegistry`, can back several tables at once.
+impl<R: RandomAccess + ?Sized> RandomAccess for Arc<R> {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        (**self).read_at(off, dst)
+    }
+
+    fn read_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Result<()> {
+        (**self).read_batch(reqs)
+    }
+
+    fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+        (**self).read_vectored(off, bufs)
+    }
+
+    fn file_id(&self) -> Option<u64> {
+        (**self).file_id()
+    }
+}
+
+/// Boxed files, like those of tables, can be shared through an `Arc` as well (see `Table::pin()`).
+impl<R: RandomAccess + ?Sized> RandomAccess for Box<R> {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        (**self).read_at(off, dst)
+    }
+
+    fn read_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Result<()> {
+        (**self).read_batch(reqs)
+    }
+
+    fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+        (**self).read_vectored(off, bufs)
+    }
+
+    fn file_id(&self) -> Option<u64> {
+        (**self).file_id()
     }
 }
 
 #[cfg(unix)]
 impl RandomAccess for File {
-    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
-        Ok((self as &dyn FileExt).read_at(dst, off as u64)?)
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        Ok((self as &dyn FileExt).read_at(dst, off)?)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+        preadv(self, off, bufs)
+    }
+
+    /// Derived from the device and inode numbers, the size and the modification time, so that a
+    /// file that is replaced or modified gets a new ID.
+    fn file_id(&self) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+
+        let m = self.metadata().ok()?;
+        let parts = [
+            m.dev(),
+            m.ino(),
+            m.size(),
+            m.mtime() as u64,
+            m.mtime_nsec() as u64,
+        ];
+        Some(parts.iter().fold(0, |h, p| mix64(h ^ p)))
     }
 }
 
+/// Reads the byte range starting at `off` into `bufs` using `preadv()`, repeating the call after
+/// short reads.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn preadv(f: &File, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+    use std::convert::TryFrom;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    // The maximum number of buffers of one call (IOV_MAX) is at least 1024 on all supported
+    // platforms.
+    const MAX_IOVECS: usize = 1024;
+
+    let mut iovecs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .filter(|b| !b.is_empty())
+        .map(|b| libc::iovec {
+            iov_base: b.as_mut_ptr() as *mut libc::c_void,
+            iov_len: b.len(),
+        })
+        .collect();
+    let mut first = 0;
+    let mut total = 0;
+    while first < iovecs.len() {
+        let pos = libc::off_t::try_from(off + total as u64)
+            .map_err(|_| Error::InvalidArgument(format!("offset {} out of range", off)))?;
+        let batch = &iovecs[first..iovecs.len().min(first + MAX_IOVECS)];
+        // Safe because the iovecs point into `bufs`, which outlive the call.
+        let n = unsafe { libc::preadv(f.as_raw_fd(), batch.as_ptr(), batch.len() as _, pos) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err.into());
+        }
+        if n == 0 {
+            break;
+        }
+        let mut n = n as usize;
+        total += n;
+        // Skip the buffers that have been filled, and the filled part of the last one.
+        while n > 0 {
+            let iov = &mut iovecs[first];
+            if n < iov.iov_len {
+                iov.iov_base = unsafe { (iov.iov_base as *mut u8).add(n) } as *mut libc::c_void;
+                iov.iov_len -= n;
+                break;
+            }
+            n -= iov.iov_len;
+            first += 1;
+        }
+    }
+    Ok(total)
+}
+
 #[cfg(windows)]
 impl RandomAccess for File {
-    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
-        Ok((self as &dyn FileExt).seek_read(dst, off as u64)?)
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        Ok((self as &dyn FileExt).seek_read(dst, off)?)
     }
 }
 
+/// Scrambles the bits of `x` (the finalizer of MurmurHash3); used to derive hash values from
+/// integers.
+pub fn mix64(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
 /// A shared thingy with guarded by a lock.
 pub type Shared<T> = Arc<RwLock<T>>;
 
@@ -57,19 +279,32 @@ pub fn share<T>(t: T) -> Arc<RwLock<T>> {
 
 /// An extension of the standard `Iterator` trait that supporting some additional functionality.
 ///
-/// Note: Implementing types are expected to hold `!valid()` before the first call to `advance()`,
-/// and after `advance()` has returned `false` for the first time.
+/// An SSIterator is either positioned at an entry (`valid()`), or it isn't, which is the case
+/// before the first call to `advance()`, after `reset()`, and after a positioning call ran past
+/// either end of the data or failed (see `status()`). The entry is accessed through
+/// `current_key()`, `value()` and `current()`, which return None respectively false unless the
+/// iterator is valid. Positioning works as follows:
 ///
-/// test_util::test_iterator_properties() verifies that all properties hold for a given
-/// implementation.
+/// - `advance()` moves to the next entry, or to the first one if the iterator isn't valid.
+/// - `prev()` moves to the previous entry.
+/// - `seek()` and `seek_to_first()` move directly to an entry: the iterator is positioned at
+///   it afterwards, *without* calling `advance()`. In particular, `next()` after `seek()`
+///   returns the entry following the one seeked to; use `entries()` to iterate from the current
+///   entry on.
+///
+/// All iterators of this crate follow these rules: block and table iterators, the two-level,
+/// concatenating (`ConcatIterator`) and merging (`VersionIterator`) iterators, and the adapters in
+/// `iter_adapters`. test_util::test_iterator_properties() verifies that all properties hold for a
+/// given implementation.
 pub trait SSIterator {
     /// Advances the position of the iterator by one element (which can be retrieved using
     /// current(). If no more elements are available, advance() returns false, and the iterator
     /// becomes invalid (i.e. as if reset() had been called).
     fn advance(&mut self) -> bool;
-    /// Return the current item (i.e. the item most recently returned by `next()`).
+    /// Copies the current entry into `key` and `val`. Returns false, leaving them untouched, if
+    /// the iterator isn't valid.
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool;
-    /// Return a reference to the key of the current item (i.e. the item most recently returned by `next()`).
+    /// Returns the key of the current entry, or None if the iterator isn't valid.
     fn current_key(&self) -> Option<&[u8]>;
     /// Seek the iterator to `key` or the next bigger key. If the seek is invalid (past last
     /// element, or before first element), the iterator is `reset()` and not valid.
@@ -86,6 +321,13 @@ pub trait SSIterator {
 
     // default implementations.
 
+    /// Returns the error that made the iterator invalid, if any. This distinguishes an iterator
+    /// that ran into corruption or an I/O error from one that reached the end of its data. The
+    /// error is cleared by `reset()` and `seek()`.
+    fn status(&self) -> Option<&Error> {
+        None
+    }
+
     /// next is like Iterator::next(). It's implemented here because Rust disallows implementing a
     /// foreign trait for any type, thus we can't do `impl<T: SSIterator> Iterator<Item=Vec<u8>>
     /// for T {}`.
@@ -106,6 +348,48 @@ pub trait SSIterator {
         self.reset();
         self.advance();
     }
+
+    /// Returns the value of the current entry, or None if the iterator isn't valid.
+    fn value(&self) -> Option<Vec<u8>> {
+        let (mut key, mut val) = (vec![], vec![]);
+        if self.current(&mut key, &mut val) {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a standard iterator over the entries starting at the current one, or at the first
+    /// one if the iterator isn't valid. Unlike calling `next()` repeatedly, this doesn't skip the
+    /// entry found by `seek()`.
+    fn entries(&mut self) -> Entries<'_, Self>
+    where
+        Self: Sized,
+    {
+        Entries {
+            iter: self,
+            started: false,
+        }
+    }
+}
+
+/// Iterator over the entries of an SSIterator, returned by `SSIterator::entries()`.
+pub struct Entries<'a, I> {
+    iter: &'a mut I,
+    started: bool,
+}
+
+impl<I: SSIterator> Iterator for Entries<'_, I> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let started = std::mem::replace(&mut self.started, true);
+        if (!started && self.iter.valid()) || self.iter.advance() {
+            current_key_val(self.iter)
+        } else {
+            None
+        }
+    }
 }
 
 /// current_key_val is a helper allocating two vectors and filling them with the current key/value
@@ -141,6 +425,12 @@ impl SSIterator for Box<dyn SSIterator> {
     fn prev(&mut self) -> bool {
         self.as_mut().prev()
     }
+    fn status(&self) -> Option<&Error> {
+        self.as_ref().status()
+    }
+    fn value(&self) -> Option<Vec<u8>> {
+        self.as_ref().value()
+    }
 }
 
 // Allow interface to iterator.
@@ -152,6 +442,7 @@ impl Iterator for dyn SSIterator {
     }
 }
 
+#[allow(dead_code)]
 const MASK_DELTA: u32 = 0xa282ead8;
 
 pub fn mask_crc(c: u32) -> u32 {
@@ -166,4 +457,53 @@ pub fn unmask_crc(mc: u32) -> u32 {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types_static_slice_random_access() {
+        static DATA: &[u8] = b"0123456789";
+        let ra: Box<dyn RandomAccess> = Box::new(DATA);
+        let mut buf = [0; 4];
+        assert_eq!(ra.read_at(8, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"89");
+        assert_eq!(ra.read_at(11, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_types_fn_random_access() {
+        let data: Vec<u8> = (0..100).collect();
+        let ra = FnRandomAccess::new(move |off, dst: &mut [u8]| data.read_at(off, dst));
+        let mut buf = [0; 3];
+        assert_eq!(ra.read_at(97, &mut buf).unwrap(), 3);
+        assert_eq!(buf, [97, 98, 99]);
+    }
+
+    #[test]
+    fn test_types_read_vectored() {
+        let data: Vec<u8> = (0..100).collect();
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut sources: Vec<Box<dyn RandomAccess>> = vec![Box::new(data.clone())];
+        #[cfg(unix)]
+        let path = std::env::temp_dir().join(format!("sstable_vectored_{}", std::process::id()));
+        #[cfg(unix)]
+        {
+            std::fs::write(&path, &data).unwrap();
+            sources.push(Box::new(File::open(&path).unwrap()));
+        }
+
+        for ra in sources.iter() {
+            let (mut a, mut b, mut c) = ([0; 3], [0; 0], [0; 5]);
+            let n = ra.read_vectored(10, &mut [&mut a, &mut b, &mut c]).unwrap();
+            assert_eq!(n, 8);
+            assert_eq!(a, [10, 11, 12]);
+            assert_eq!(c, [13, 14, 15, 16, 17]);
+            // Reads stop at the end of the file.
+            let (mut a, mut b) = ([0; 4], [0; 4]);
+            assert_eq!(ra.read_vectored(95, &mut [&mut a, &mut b]).unwrap(), 5);
+            assert_eq!((a, b[0]), ([95, 96, 97, 98], 99));
+        }
+        #[cfg(unix)]
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,442 @@
+//! A `RandomAccess` implementation for files on Linux that serves batched reads
+//! (`RandomAccess::read_batch()`) using io_uring: all reads of a batch are submitted with one
+//! system call and complete concurrently, instead of one `pread` call per block. Single reads
+//! still use `pread`.
+//!
+//! The ring is set up using the raw system calls (there's no dependency on liburing); it needs
+//! Linux 5.6 or newer. Where io_uring isn't available (older kernels, or disabled by seccomp
+//! policies), `UringFile::new()` fails and a plain `File` can be used instead.
+
+use crate::error::Result;
+use crate::types::RandomAccess;
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// The number of submission queue entries of rings created by `UringFile::new()`.
+pub const DEFAULT_RING_ENTRIES: u32 = 64;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_OP_READ: u8 = 22;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+// The structures below mirror those of <linux/io_uring.h>.
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared memory region mapped from the ring's file descriptor.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Mmap> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { (self.ptr as *mut u8).add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+struct Ring {
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+    params: Params,
+    // Declared last so that the rings are unmapped before the ring is closed.
+    fd: File,
+}
+
+// The mapped memory is only accessed while the ring is locked (see `UringFile`).
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { File::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+        Ok(Ring {
+            sq: Mmap::new(raw, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mmap::new(raw, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mmap::new(raw, sqes_len, IORING_OFF_SQES)?,
+            params,
+            fd,
+        })
+    }
+
+    fn sq_head(&self) -> &AtomicU32 {
+        unsafe { &*self.sq.at(self.params.sq_off.head) }
+    }
+
+    fn sq_tail(&self) -> &AtomicU32 {
+        unsafe { &*self.sq.at(self.params.sq_off.tail) }
+    }
+
+    fn cq_head(&self) -> &AtomicU32 {
+        unsafe { &*self.cq.at(self.params.cq_off.head) }
+    }
+
+    fn cq_tail(&self) -> &AtomicU32 {
+        unsafe { &*self.cq.at(self.params.cq_off.tail) }
+    }
+
+    /// Queues a read of `dst` at `off` from `fd`; `user_data` is passed back on completion. The
+    /// caller must make sure that the submission queue has room.
+    fn push_read(&mut self, fd: RawFd, off: u64, dst: &mut [u8], user_data: u64) {
+        let mask = unsafe { *self.sq.at::<u32>(self.params.sq_off.ring_mask) };
+        // We're the only producer, so nobody else modifies the tail.
+        let tail = self.sq_tail().load(Ordering::Relaxed);
+        let idx = tail & mask;
+        let sqe = Sqe {
+            opcode: IORING_OP_READ,
+            fd,
+            off,
+            addr: dst.as_mut_ptr() as u64,
+            // Larger buffers are completed by `pread` like short reads.
+            len: dst.len().min(u32::MAX as usize) as u32,
+            user_data,
+            ..Sqe::default()
+        };
+        unsafe {
+            ptr::write(self.sqes.at::<Sqe>(0).add(idx as usize), sqe);
+            ptr::write(
+                self.sq
+                    .at::<u32>(self.params.sq_off.array)
+                    .add(idx as usize),
+                idx,
+            );
+        }
+        self.sq_tail()
+            .store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Submits up to `to_submit` queued entries and waits for at least one completion. Returns
+    /// how many entries were submitted.
+    fn enter(&self, to_submit: u32) -> io::Result<u32> {
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                to_submit,
+                1u32,
+                IORING_ENTER_GETEVENTS,
+                ptr::null::<libc::c_void>(),
+                0usize,
+            )
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(r as u32)
+    }
+
+    /// Drops queued entries that haven't been submitted yet.
+    fn discard_unsubmitted(&self) {
+        let head = self.sq_head().load(Ordering::Acquire);
+        self.sq_tail().store(head, Ordering::Release);
+    }
+
+    /// Calls `f` with the user data and result of every available completion.
+    fn reap(&self, mut f: impl FnMut(u64, i32)) -> u32 {
+        let mask = unsafe { *self.cq.at::<u32>(self.params.cq_off.ring_mask) };
+        let cqes = self.cq.at::<Cqe>(self.params.cq_off.cqes);
+        let mut head = self.cq_head().load(Ordering::Relaxed);
+        let tail = self.cq_tail().load(Ordering::Acquire);
+        let mut n = 0;
+        while head != tail {
+            let cqe = unsafe { ptr::read(cqes.add((head & mask) as usize)) };
+            f(cqe.user_data, cqe.res);
+            head = head.wrapping_add(1);
+            n += 1;
+        }
+        self.cq_head().store(head, Ordering::Release);
+        n
+    }
+
+    /// Reads every range of `reqs` from `fd`. Returns the ranges that haven't been read (or only
+    /// partially) as indexes into `reqs` and the number of bytes read.
+    fn read_batch(&mut self, fd: RawFd, reqs: &mut [(u64, &mut [u8])]) -> Vec<(usize, usize)> {
+        let entries = self.params.sq_entries as usize;
+        let mut incomplete = vec![];
+        let (mut next, mut queued, mut in_flight) = (0, 0, 0);
+
+        while next < reqs.len() || queued > 0 || in_flight > 0 {
+            // The completion queue holds at least as many entries as the submission queue, so
+            // it can't overflow as long as not more reads are in flight.
+            while next < reqs.len() && queued + in_flight < entries {
+                let (off, ref mut dst) = reqs[next];
+                self.push_read(fd, off, dst, next as u64);
+                next += 1;
+                queued += 1;
+            }
+
+            match self.enter(queued as u32) {
+                Ok(submitted) => {
+                    queued -= submitted as usize;
+                    in_flight += submitted as usize;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) if in_flight > 0 => {
+                    // Completions may still arrive; they are reaped below before retrying.
+                }
+                Err(_) => {
+                    // Nothing is in flight, so the remaining ranges can be read without the
+                    // ring.
+                    self.discard_unsubmitted();
+                    incomplete.extend((next - queued..reqs.len()).map(|i| (i, 0)));
+                    return incomplete;
+                }
+            }
+
+            in_flight -= self.reap(|user_data, res| {
+                let i = user_data as usize;
+                if res < 0 || (res as usize) < reqs[i].1.len() {
+                    incomplete.push((i, res.max(0) as usize));
+                }
+            }) as usize;
+        }
+        incomplete
+    }
+}
+
+/// A file read using io_uring for batched reads (see the module documentation).
+pub struct UringFile {
+    file: File,
+    ring: Mutex<Ring>,
+}
+
+impl UringFile {
+    /// Opens the file at `path` for reading.
+    pub fn open(path: &Path) -> Result<UringFile> {
+        UringFile::new(File::open(path)?)
+    }
+
+    /// Wraps `file`, using a ring of `DEFAULT_RING_ENTRIES` entries.
+    pub fn new(file: File) -> Result<UringFile> {
+        UringFile::with_entries(file, DEFAULT_RING_ENTRIES)
+    }
+
+    /// Wraps `file`, using a ring of `entries` entries; at most that many reads are in flight at
+    /// once.
+    pub fn with_entries(file: File, entries: u32) -> Result<UringFile> {
+        Ok(UringFile {
+            file,
+            ring: Mutex::new(Ring::new(entries)?),
+        })
+    }
+
+    fn ring(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl RandomAccess for UringFile {
+    fn read_at(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        Ok(FileExt::read_at(&self.file, dst, off)?)
+    }
+
+    fn read_batch(&self, reqs: &mut [(u64, &mut [u8])]) -> Result<()> {
+        let incomplete = self.ring().read_batch(self.file.as_raw_fd(), reqs);
+        // Failed and short reads are retried (or their errors reported) using pread.
+        for (i, done) in incomplete {
+            let (off, ref mut dst) = reqs[i];
+            FileExt::read_at(&self.file, &mut dst[done..], off + done as u64)?;
+        }
+        Ok(())
+    }
+
+    fn read_vectored(&self, off: u64, bufs: &mut [&mut [u8]]) -> Result<usize> {
+        RandomAccess::read_vectored(&self.file, off, bufs)
+    }
+
+    fn file_id(&self) -> Option<u64> {
+        self.file.file_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{Options, ReadOptions};
+    use crate::table_builder::TableBuilder;
+    use crate::table_reader::Table;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_uring_read_batch() {
+        let path = env::temp_dir().join(format!("sstable_uring_{}", std::process::id()));
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let file = match UringFile::with_entries(File::open(&path).unwrap(), 4) {
+            Ok(file) => file,
+            // io_uring isn't available here.
+            Err(_) => return fs::remove_file(&path).unwrap(),
+        };
+
+        // More ranges than ring entries, including one reaching past the end of the file.
+        let ranges: Vec<(u64, usize)> = (0..10).map(|i| (i * 997, 100 + i as usize)).collect();
+        let mut bufs: Vec<Vec<u8>> = ranges.iter().map(|&(_, n)| vec![0; n]).collect();
+        let mut tail = vec![0xff; 20];
+        {
+            let mut reqs: Vec<(u64, &mut [u8])> = ranges
+                .iter()
+                .map(|&(off, _)| off)
+                .zip(bufs.iter_mut().map(|b| b.as_mut_slice()))
+                .collect();
+            reqs.push((9_990, &mut tail));
+            file.read_batch(&mut reqs).unwrap();
+        }
+        for (&(off, n), buf) in ranges.iter().zip(bufs.iter()) {
+            assert_eq!(&data[off as usize..off as usize + n], buf.as_slice());
+        }
+        assert_eq!(&data[9_990..], &tail[..10]);
+        assert_eq!(file.file_id(), File::open(&path).unwrap().file_id());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_uring_table_multi_get() {
+        let path = env::temp_dir().join(format!("sstable_uring_table_{}", std::process::id()));
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i)).collect();
+        {
+            let mut b = TableBuilder::new(opt.clone(), File::create(&path).unwrap());
+            for k in keys.iter() {
+                b.add(k.as_bytes(), k.as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
+        }
+
+        let file = match UringFile::with_entries(File::open(&path).unwrap(), 8) {
+            Ok(file) => file,
+            Err(_) => return fs::remove_file(&path).unwrap(),
+        };
+        let size = fs::metadata(&path).unwrap().len();
+        let table = Table::new(opt, Box::new(file), size).unwrap();
+        let lookup: Vec<&[u8]> = keys.iter().step_by(3).map(|k| k.as_bytes()).collect();
+        let values = table.multi_get(&ReadOptions::default(), &lookup).unwrap();
+        for (k, v) in lookup.iter().zip(values) {
+            assert_eq!(Some(k.to_vec()), v);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
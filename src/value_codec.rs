@@ -0,0 +1,39 @@
+//! Application-defined encoding of values (see `Options::value_codec`). A codec encodes every value
+//! added to a table and decodes it again on reads. Codecs that understand the layout of their
+//! values can also project them, i.e. decode only some fields of a structured value, which makes
+//! scans over a few fields of large records cheaper (see `ReadOptions::projection`).
+
+use crate::error::Result;
+
+use std::sync::Arc;
+
+/// A ValueCodec transforms values on their way into and out of a table. The codec's name is stored
+/// in the table's properties; tables written with a codec can only be read with options carrying a
+/// codec of the same name.
+pub trait ValueCodec: Send + Sync {
+    /// The name of the codec. It should change whenever the encoding changes incompatibly.
+    fn name(&self) -> &str;
+    /// Appends the encoded form of `value` to `dst`.
+    fn encode(&self, value: &[u8], dst: &mut Vec<u8>) -> Result<()>;
+    /// Appends the value encoded by `encode()` as `encoded` to `dst`.
+    fn decode(&self, encoded: &[u8], dst: &mut Vec<u8>) -> Result<()>;
+    /// Appends the fields selected by `fields` of the value encoded as `encoded` to `dst`. What a
+    /// field is depends on the codec. The default implementation ignores `fields` and decodes the
+    /// whole value.
+    fn project(&self, encoded: &[u8], fields: &[usize], dst: &mut Vec<u8>) -> Result<()> {
+        let _ = fields;
+        self.decode(encoded, dst)
+    }
+}
+
+pub type BoxedValueCodec = Arc<dyn ValueCodec>;
+
+/// Decodes `encoded` using `codec`, projecting it to `fields` if given.
+pub fn decode(codec: &dyn ValueCodec, encoded: &[u8], fields: Option<&[usize]>) -> Result<Vec<u8>> {
+    let mut dst = vec![];
+    match fields {
+        Some(fields) => codec.project(encoded, fields, &mut dst)?,
+        None => codec.decode(encoded, &mut dst)?,
+    }
+    Ok(dst)
+}
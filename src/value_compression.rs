@@ -0,0 +1,105 @@
+//! Compression of individual values, independent of block compression (see
+//! `Options::value_compression`). In tables written with value compression, every value starts
+//! with a byte denoting its `CompressionType`. Values that don't compress well are stored
+//! uncompressed.
+
+use crate::error::{CorruptionKind, Error, Result};
+use crate::options::{self, CompressionType};
+use crate::table_block;
+
+#[cfg(feature = "snappy")]
+use snap::{Decoder, Encoder};
+
+/// Appends `val`, compressed using `ctype` if that saves at least 12.5%, to `dst`.
+pub fn compress(ctype: CompressionType, val: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+    #[cfg(not(feature = "snappy"))]
+    if ctype != CompressionType::CompressionNone {
+        return Err(table_block::compression_not_supported(ctype));
+    }
+    #[cfg(feature = "snappy")]
+    if ctype != CompressionType::CompressionNone {
+        let compressed = Encoder::new().compress_vec(val)?;
+        if compressed.len() < val.len() - val.len() / 8 {
+            dst.push(CompressionType::CompressionSnappy as u8);
+            dst.extend_from_slice(&compressed);
+            return Ok(());
+        }
+    }
+    dst.push(CompressionType::CompressionNone as u8);
+    dst.extend_from_slice(val);
+    Ok(())
+}
+
+/// Returns the original value of a value written by `compress()`, failing if it would be larger
+/// than `limit` (see `Options::max_decompressed_block_size`).
+pub fn decompress(mut stored: Vec<u8>, limit: Option<usize>) -> Result<Vec<u8>> {
+    if stored.is_empty() {
+        return Err(Error::malformed(None, "missing value compression tag"));
+    }
+    match options::int_to_compressiontype(stored[0] as u32) {
+        Some(CompressionType::CompressionNone) => {
+            stored.remove(0);
+            Ok(stored)
+        }
+        #[cfg(feature = "snappy")]
+        Some(CompressionType::CompressionSnappy) => {
+            table_block::check_decompressed_len(&stored[1..], limit)
+                .and_then(|_| {
+                    Decoder::new()
+                        .decompress_vec(&stored[1..])
+                        .map_err(CorruptionKind::Decompression)
+                })
+                .map_err(|kind| Error::Corruption {
+                    file_offset: None,
+                    block_handle: None,
+                    kind,
+                })
+        }
+        #[cfg(not(feature = "snappy"))]
+        Some(CompressionType::CompressionSnappy) => {
+            let _ = limit;
+            Err(table_block::compression_not_supported(
+                CompressionType::CompressionSnappy,
+            ))
+        }
+        Some(CompressionType::CompressionSnappyFramed) | None => Err(Error::Corruption {
+            file_offset: None,
+            block_handle: None,
+            kind: CorruptionKind::UnknownCompressionType(stored[0]),
+        }),
+    }
+}
+
+#[cfg(all(test, feature = "snappy"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_compression() {
+        let compressible = b"abcabcabc".repeat(20);
+        let mut buf = vec![];
+        compress(CompressionType::CompressionSnappy, &compressible, &mut buf).unwrap();
+        assert_eq!(buf[0], CompressionType::CompressionSnappy as u8);
+        assert!(buf.len() < compressible.len());
+        assert!(matches!(
+            decompress(buf.clone(), Some(179)),
+            Err(Error::Corruption {
+                kind: CorruptionKind::DecompressedSizeExceeded {
+                    size: 180,
+                    limit: 179
+                },
+                ..
+            })
+        ));
+        assert_eq!(decompress(buf, Some(180)).unwrap(), compressible);
+
+        // Incompressible values are stored as they are.
+        let mut buf = vec![];
+        compress(CompressionType::CompressionSnappy, b"abc", &mut buf).unwrap();
+        assert_eq!(buf, b"\x00abc".to_vec());
+        assert_eq!(decompress(buf, None).unwrap(), b"abc".to_vec());
+
+        assert!(decompress(vec![], None).is_err());
+        assert!(decompress(vec![9, 1, 2], None).unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::blockhandle::BlockHandle;
+use crate::checksum::ChecksumDigest;
+use crate::error::{CorruptionKind, Error, Result};
+use crate::options::{CompressionType, Options};
+use crate::table_block;
+use crate::table_builder::{TABLE_BLOCK_CKSUM_LEN, TABLE_BLOCK_COMPRESS_LEN};
+use crate::types::RandomAccess;
+
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
+
+use integer_encoding::FixedInt;
+
+/// ValueReader streams a value out of a table (see `Table::get_reader()`). Uncompressed values
+/// stored separately from the data blocks (see `Options::blob_threshold`) are read from the file
+/// piece by piece, so that they never have to be held in memory completely; their checksum is
+/// verified once the end of the value is reached, and a mismatch is reported as
+/// `io::ErrorKind::InvalidData` error wrapping an `Error::Corruption`. Other values are read into
+/// memory first.
+pub struct ValueReader {
+    inner: Inner,
+}
+
+enum Inner {
+    Buffered(Cursor<Vec<u8>>),
+    Blob {
+        file: Arc<Box<dyn RandomAccess>>,
+        location: BlockHandle,
+        pos: u64,
+        // Taken once the end of the value is reached.
+        digest: Option<Box<dyn ChecksumDigest>>,
+        compression: u8,
+        expected: u32,
+    },
+}
+
+impl ValueReader {
+    pub(crate) fn buffered(value: Vec<u8>) -> ValueReader {
+        ValueReader {
+            inner: Inner::Buffered(Cursor::new(value)),
+        }
+    }
+
+    /// Returns a reader for the blob at `location` in a file of `file_size` bytes, whose checksum
+    /// is verified using the checksummer of `opt`.
+    pub(crate) fn blob(
+        file: Arc<Box<dyn RandomAccess>>,
+        file_size: u64,
+        opt: &Options,
+        location: &BlockHandle,
+    ) -> Result<ValueReader> {
+        let cs = opt.checksummer.as_ref();
+        let mut trailer = [0; TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN];
+        table_block::check_block_in_file(location, file_size)?;
+        file.read_at(location.offset() + location.size(), &mut trailer)?;
+        let compression = trailer[0];
+
+        // Compressed blobs can't be decompressed incrementally.
+        if compression != CompressionType::CompressionNone as u8 {
+            let value = table_block::read_blob(file.as_ref().as_ref(), file_size, opt, location)?;
+            return Ok(ValueReader::buffered(value));
+        }
+
+        Ok(ValueReader {
+            inner: Inner::Blob {
+                file,
+                location: location.clone(),
+                pos: 0,
+                digest: Some(cs.digest()),
+                compression,
+                expected: cs.unmask(u32::decode_fixed(&trailer[TABLE_BLOCK_COMPRESS_LEN..])),
+            },
+        })
+    }
+
+    /// Returns the number of bytes not read yet.
+    pub fn remaining(&self) -> u64 {
+        match self.inner {
+            Inner::Buffered(ref c) => c.get_ref().len() as u64 - c.position(),
+            Inner::Blob {
+                ref location, pos, ..
+            } => location.size() - pos,
+        }
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Buffered(ref mut c) => c.read(buf),
+            Inner::Blob {
+                ref file,
+                ref location,
+                ref mut pos,
+                ref mut digest,
+                compression,
+                expected,
+            } => {
+                let n = (buf.len() as u64).min(location.size() - *pos) as usize;
+                if n == 0 {
+                    return Ok(0);
+                }
+                let n = file
+                    .read_at(location.offset() + *pos, &mut buf[..n])
+                    .map_err(|e| match e {
+                        Error::Io(e) => e,
+                        e => io::Error::other(e),
+                    })?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if let Some(d) = digest.as_mut() {
+                    d.update(&buf[..n]);
+                }
+                *pos += n as u64;
+
+                if *pos == location.size() {
+                    let mut d = digest.take().unwrap();
+                    d.update(&[compression; TABLE_BLOCK_COMPRESS_LEN]);
+                    let actual = d.finalize();
+                    if actual != expected {
+                        event!(
+                            error,
+                            offset = location.offset(),
+                            size = location.size(),
+                            "checksum verification of streamed value failed"
+                        );
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            Error::corrupt_block(
+                                location,
+                                CorruptionKind::ChecksumMismatch { expected, actual },
+                            ),
+                        ));
+                    }
+                }
+                Ok(n)
+            }
+        }
+    }
+}
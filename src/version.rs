@@ -0,0 +1,621 @@
+//! The read side of an LSM tree: a `Version` holds tables arranged in levels and answers lookups
+//! and scans over all of them, newer tables shadowing older ones. Writing tables and compacting
+//! levels is left to the application, which creates a new `Version` whenever its set of tables
+//! changes.
+
+use crate::cmp::Cmp;
+use crate::concat::ConcatIterator;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_reader::Table;
+use crate::types::SSIterator;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// A table in a `Version`, with the smallest and largest key it contains.
+#[derive(Clone)]
+pub struct LevelFile {
+    pub table: Table,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+}
+
+impl LevelFile {
+    /// Returns a LevelFile for `table`, reading its first and last key. Fails for empty tables.
+    pub fn new(table: Table) -> Result<LevelFile> {
+        let mut iter = table.iter();
+        let smallest = match iter.next() {
+            Some((k, _)) => k,
+            None => {
+                return Err(iter.take_error().unwrap_or_else(|| {
+                    Error::InvalidArgument("empty tables can't be added to a level".to_string())
+                }))
+            }
+        };
+        let last_block = table.index_handles().pop().unwrap();
+        let mut block = table.read_block(&last_block)?.iter();
+        block.seek_to_last();
+        let largest = block
+            .current_key()
+            .ok_or_else(|| Error::malformed(Some(last_block.offset()), "empty data block"))?;
+        let largest = table.logical_key(largest)?.into_owned();
+        Ok(LevelFile::with_range(table, smallest, largest))
+    }
+
+    /// Returns a LevelFile for `table` with a known key range, e.g. from the application's own
+    /// metadata, avoiding reading the table.
+    pub fn with_range(table: Table, smallest: Vec<u8>, largest: Vec<u8>) -> LevelFile {
+        LevelFile {
+            table,
+            smallest,
+            largest,
+        }
+    }
+}
+
+/// A Version arranges tables in levels. Tables in level 0 may overlap; the newer a table, the
+/// earlier it comes. In all other levels, tables are sorted by key and don't overlap, and every
+/// level is older than the one before. When several tables contain a key, the value in the newest
+/// one wins.
+#[derive(Clone)]
+pub struct Version {
+    opt: Options,
+    levels: Vec<Vec<LevelFile>>,
+}
+
+impl Version {
+    /// Returns an empty Version ordering keys by `opt.cmp`, the comparator of its tables.
+    pub fn new(opt: Options) -> Version {
+        Version {
+            opt,
+            levels: vec![],
+        }
+    }
+
+    /// Adds `table` to `level`, reading its key range (see `LevelFile::new()`). Empty tables, e.g.
+    /// from empty partitions of a job, hold no keys and are skipped.
+    pub fn add_table(&mut self, level: usize, table: Table) -> Result<()> {
+        if table.is_empty() {
+            return Ok(());
+        }
+        self.add_file(level, LevelFile::new(table)?)
+    }
+
+    /// Adds `file` to `level`. In level 0, the file becomes the newest one. In other levels, it's
+    /// inserted in key order; it must not overlap with the level's other files.
+    pub fn add_file(&mut self, level: usize, file: LevelFile) -> Result<()> {
+        if self.levels.len() <= level {
+            self.levels.resize(level + 1, vec![]);
+        }
+        let cmp = self.opt.cmp.clone();
+        let files = &mut self.levels[level];
+        if level == 0 {
+            files.insert(0, file);
+            return Ok(());
+        }
+        let i = files.partition_point(|f| cmp.cmp(&f.largest, &file.smallest) == Ordering::Less);
+        if files
+            .get(i)
+            .is_some_and(|next| cmp.cmp(&next.smallest, &file.largest) != Ordering::Greater)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "file overlaps with another file of level {}",
+                level
+            )));
+        }
+        files.insert(i, file);
+        Ok(())
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns the files of `level`, in the order described for `Version`.
+    pub fn files(&self, level: usize) -> &[LevelFile] {
+        self.levels.get(level).map_or(&[], |files| files.as_slice())
+    }
+
+    /// Returns the files whose key range contains `key`, newest first. There's at most one per
+    /// level, except for level 0.
+    fn files_for<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = &'a LevelFile> + 'a {
+        let cmp = self.opt.cmp.clone();
+        let contains = move |f: &LevelFile| {
+            cmp.cmp(&f.smallest, key) != Ordering::Greater
+                && cmp.cmp(key, &f.largest) != Ordering::Greater
+        };
+        self.levels
+            .iter()
+            .enumerate()
+            .flat_map(move |(level, files)| {
+                let candidates = if level == 0 {
+                    files.as_slice()
+                } else {
+                    let i = files
+                        .partition_point(|f| self.opt.cmp.cmp(&f.largest, key) == Ordering::Less);
+                    &files[i..files.len().min(i + 1)]
+                };
+                let contains = contains.clone();
+                candidates.iter().filter(move |f| contains(f))
+            })
+    }
+
+    /// Looks up `key` in the tables that may contain it, newest first, and returns the first
+    /// value found.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        for file in self.files_for(key) {
+            if let Some(val) = file.table.get(key)? {
+                return Ok(Some(val));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns an iterator over the entries of all tables. Keys present in several tables are
+    /// returned once, with the value from the newest table. Each file of level 0 and each of the
+    /// other levels is read by an iterator of its own, which are merged; a level's iterator only
+    /// opens the file it is positioned in. If reading any file fails, the iterator stops and
+    /// reports the error from `status()`, as the failed file may hold newer values.
+    ///
+    /// Versions don't know about deletions: newer tables can overwrite a key, but not remove it.
+    /// To delete keys, store internal keys (see `key_types`) ordered by `InternalKeyCmp`, under
+    /// which every version of a key is a key of its own, and wrap the iterator in
+    /// `iter_adapters::DedupUserKeys` and `iter_adapters::DropTombstones`.
+    pub fn iter(&self) -> VersionIterator {
+        let level0 = self.files(0).iter().map(|f| vec![f.clone()]);
+        let levels = self.levels.iter().skip(1).cloned();
+        let iters: Vec<(ConcatIterator, Vec<u8>)> = level0
+            .chain(levels)
+            .filter_map(|files| {
+                let largest = files.last()?.largest.clone();
+                Some((ConcatIterator::with_files(files), largest))
+            })
+            .collect();
+        VersionIterator {
+            cmp: self.opt.cmp.clone(),
+            heap: Vec::with_capacity(iters.len()),
+            iters,
+        }
+    }
+}
+
+/// A merging iterator over the tables of a `Version`, see `Version::iter()`.
+pub struct VersionIterator {
+    cmp: Arc<Box<dyn Cmp>>,
+    // Iterators over the files of level 0, newest first, and over the other levels, with the
+    // largest key of each. All valid iterators are positioned at or after the current key.
+    iters: Vec<(ConcatIterator, Vec<u8>)>,
+    // The valid iterators as a min-heap, ordered by their current keys and, for equal keys, by
+    // their index (newer first). The first one is at the current entry.
+    heap: Vec<usize>,
+}
+
+impl VersionIterator {
+    fn key(&self, i: usize) -> &[u8] {
+        self.iters[i].0.current_key().unwrap()
+    }
+
+    /// Returns true if iterator `a` comes before iterator `b` in the heap.
+    fn before(&self, a: usize, b: usize) -> bool {
+        match self.cmp.cmp(self.key(a), self.key(b)) {
+            Ordering::Less => true,
+            Ordering::Equal => a < b,
+            Ordering::Greater => false,
+        }
+    }
+
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let mut first = pos;
+            for child in [2 * pos + 1, 2 * pos + 2] {
+                if child < self.heap.len() && self.before(self.heap[child], self.heap[first]) {
+                    first = child;
+                }
+            }
+            if first == pos {
+                return;
+            }
+            self.heap.swap(pos, first);
+            pos = first;
+        }
+    }
+
+    /// Rebuilds the heap from the valid iterators. If an iterator failed, the heap stays empty:
+    /// merging the remaining levels would return keys the failed one may shadow.
+    fn build_heap(&mut self) -> bool {
+        if self.status().is_some() {
+            self.heap.clear();
+            return false;
+        }
+        self.heap = (0..self.iters.len())
+            .filter(|&i| self.iters[i].0.valid())
+            .collect();
+        for pos in (0..self.heap.len() / 2).rev() {
+            self.sift_down(pos);
+        }
+        !self.heap.is_empty()
+    }
+}
+
+impl SSIterator for VersionIterator {
+    fn advance(&mut self) -> bool {
+        let current = match self.heap.first() {
+            Some(&i) => self.key(i).to_vec(),
+            None if self.status().is_some() => return false,
+            None => {
+                for (iter, _) in self.iters.iter_mut() {
+                    iter.reset();
+                    iter.advance();
+                }
+                return self.build_heap();
+            }
+        };
+        // Older versions of the current key are skipped along with it.
+        while let Some(&top) = self.heap.first() {
+            if self.cmp.cmp(self.key(top), &current) != Ordering::Equal {
+                break;
+            }
+            if !self.iters[top].0.advance() {
+                if self.iters[top].0.status().is_some() {
+                    // See build_heap().
+                    self.heap.clear();
+                    return false;
+                }
+                self.heap.swap_remove(0);
+            }
+            self.sift_down(0);
+        }
+        !self.heap.is_empty()
+    }
+
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        match self.heap.first() {
+            Some(&i) => self.iters[i].0.current(key, val),
+            None => false,
+        }
+    }
+
+    fn current_key(&self) -> Option<&[u8]> {
+        self.iters[*self.heap.first()?].0.current_key()
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        for (iter, _) in self.iters.iter_mut() {
+            iter.seek(key);
+        }
+        self.build_heap();
+    }
+
+    fn reset(&mut self) {
+        for (iter, _) in self.iters.iter_mut() {
+            iter.reset();
+        }
+        self.heap.clear();
+    }
+
+    fn valid(&self) -> bool {
+        !self.heap.is_empty()
+    }
+
+    /// Moves to the largest key before the current one. This repositions every iterator (twice),
+    /// so it's much more expensive than `advance()`.
+    fn prev(&mut self) -> bool {
+        let current = match self.current_key() {
+            Some(key) => key.to_vec(),
+            None => return false,
+        };
+        let cmp = self.cmp.clone();
+        let mut prev: Option<Vec<u8>> = None;
+        for (iter, largest) in self.iters.iter_mut() {
+            iter.seek(&current);
+            let found = if iter.valid() {
+                iter.prev()
+            } else {
+                // All keys of the files are before the current one.
+                iter.seek(largest);
+                iter.valid()
+            };
+            if let Some(key) = iter.current_key().filter(|_| found) {
+                if prev
+                    .as_ref()
+                    .is_none_or(|p| cmp.cmp(key, p) == Ordering::Greater)
+                {
+                    prev = Some(key.to_vec());
+                }
+            }
+        }
+        match prev {
+            Some(key) => {
+                self.seek(&key);
+                self.valid()
+            }
+            None => {
+                self.reset();
+                false
+            }
+        }
+    }
+
+    fn status(&self) -> Option<&Error> {
+        self.iters.iter().find_map(|(iter, _)| iter.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        build_table, build_table_data, test_iterator_properties, SSIteratorIter,
+    };
+
+    fn file(opt: &Options, entries: &[(&str, &str)]) -> LevelFile {
+        LevelFile::new(build_table(opt, entries)).unwrap()
+    }
+
+    fn build_version() -> Version {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let mut v = Version::new(opt.clone());
+        v.add_file(2, file(&opt, &[("a", "2a"), ("c", "2c"), ("x", "2x")]))
+            .unwrap();
+        v.add_file(1, file(&opt, &[("m", "1m"), ("n", "1n")]))
+            .unwrap();
+        v.add_file(1, file(&opt, &[("b", "1b"), ("c", "1c")]))
+            .unwrap();
+        v.add_file(0, file(&opt, &[("b", "0b-old"), ("z", "0z")]))
+            .unwrap();
+        v.add_file(0, file(&opt, &[("b", "0b"), ("d", "0d")]))
+            .unwrap();
+        v
+    }
+
+    #[test]
+    fn test_version_files() {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let f = file(
+            &opt,
+            &[("abc", "1"), ("abd", "2"), ("bcd", "3"), ("xyz", "4")],
+        );
+        assert_eq!(f.smallest, b"abc");
+        assert_eq!(f.largest, b"xyz");
+        assert!(LevelFile::new(build_table::<&str, &str>(&opt, &[])).is_err());
+
+        let mut v = build_version();
+        assert_eq!(v.num_levels(), 3);
+        assert_eq!(v.files(1)[0].smallest, b"b");
+        assert!(v.files(7).is_empty());
+        assert!(matches!(
+            v.add_file(1, file(&opt, &[("c", ""), ("d", "")])),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            v.add_file(1, file(&opt, &[("a", ""), ("b", "")])),
+            Err(Error::InvalidArgument(_))
+        ));
+        v.add_file(1, file(&opt, &[("e", ""), ("f", "")])).unwrap();
+        assert_eq!(v.files(1).len(), 3);
+        v.add_table(1, build_table::<&str, &str>(&opt, &[]))
+            .unwrap();
+        v.add_table(1, build_table(&opt, &[("g", "")])).unwrap();
+        assert_eq!(v.files(1).len(), 4);
+    }
+
+    #[test]
+    fn test_version_get() {
+        let v = build_version();
+        for (k, expected) in &[
+            ("a", Some("2a")),
+            ("b", Some("0b")),
+            ("c", Some("1c")),
+            ("d", Some("0d")),
+            ("n", Some("1n")),
+            ("x", Some("2x")),
+            ("z", Some("0z")),
+            ("e", None),
+            ("o", None),
+            ("zz", None),
+        ] {
+            assert_eq!(
+                v.get(k.as_bytes()).unwrap(),
+                expected.map(|e| e.as_bytes().to_vec()),
+                "{}",
+                k
+            );
+        }
+        assert_eq!(Version::new(Options::default()).get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_version_iterator() {
+        let v = build_version();
+        let entries: Vec<(String, String)> = SSIteratorIter::wrap(&mut v.iter())
+            .map(|(k, v)| (String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap()))
+            .collect();
+        let expected = [
+            ("a", "2a"),
+            ("b", "0b"),
+            ("c", "1c"),
+            ("d", "0d"),
+            ("m", "1m"),
+            ("n", "1n"),
+            ("x", "2x"),
+            ("z", "0z"),
+        ];
+        assert_eq!(
+            entries,
+            expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        );
+
+        let mut iter = v.iter();
+        iter.seek(b"ca");
+        assert_eq!(iter.current_key(), Some(&b"d"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"c"[..]));
+        assert!(iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"b"[..]));
+        let (mut k, mut val) = (vec![], vec![]);
+        assert!(iter.current(&mut k, &mut val));
+        assert_eq!(val, b"0b");
+        assert!(iter.prev());
+        assert!(!iter.prev());
+        assert!(!iter.valid());
+        iter.seek(b"zz");
+        assert!(!iter.valid());
+
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let mut v = Version::new(opt.clone());
+        v.add_file(0, file(&opt, &[("abc", "1"), ("xyz", "4")]))
+            .unwrap();
+        v.add_file(1, file(&opt, &[("abd", "2"), ("bcd", "3")]))
+            .unwrap();
+        v.add_file(1, file(&opt, &[("xyz", "5")])).unwrap();
+        test_iterator_properties(v.iter());
+    }
+
+    #[test]
+    fn test_version_iterator_levels() {
+        let mut opt = Options::default();
+        opt.block_size = 64;
+        let mut v = Version::new(opt.clone());
+        let mut expected = std::collections::BTreeMap::new();
+        // Oldest first: level 3 is overwritten by level 2 and so on. Every level has several files.
+        for (level, step) in [(3, 2), (2, 3), (1, 5)] {
+            let keys: Vec<String> = (0..300)
+                .step_by(step)
+                .map(|i| format!("key{:03}", i))
+                .collect();
+            for chunk in keys.chunks(20) {
+                let entries: Vec<(String, String)> = chunk
+                    .iter()
+                    .map(|k| (k.clone(), format!("{}{}", level, k)))
+                    .collect();
+                let refs: Vec<(&str, &str)> = entries
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect();
+                v.add_file(level, file(&opt, &refs)).unwrap();
+                expected.extend(entries);
+            }
+        }
+        for start in [7, 100] {
+            let entries: Vec<(String, String)> = (start..start + 50)
+                .map(|i| (format!("key{:03}", i), format!("0{}", start)))
+                .collect();
+            let refs: Vec<(&str, &str)> = entries
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            v.add_file(0, file(&opt, &refs)).unwrap();
+            expected.extend(entries);
+        }
+        assert!(v.files(1).len() > 1 && v.files(3).len() > 1);
+
+        let mut iter = v.iter();
+        // One iterator per file of level 0 and one per other level.
+        assert_eq!(iter.iters.len(), 5);
+        let entries: Vec<(String, String)> = SSIteratorIter::wrap(&mut iter)
+            .map(|(k, v)| (String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap()))
+            .collect();
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+
+        iter.seek(b"key149");
+        assert_eq!(iter.value(), Some(b"0100".to_vec()));
+        assert!(iter.advance());
+        assert_eq!(iter.current_key(), Some(&b"key150"[..]));
+        assert_eq!(iter.value(), Some(b"1key150".to_vec()));
+        assert!(iter.prev() && iter.prev());
+        assert_eq!(iter.current_key(), Some(&b"key148"[..]));
+    }
+
+    #[test]
+    fn test_version_iterator_corrupt_file() {
+        let mut opt = Options::default();
+        opt.block_size = 32;
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i)).collect();
+        let entries: Vec<(&str, &str)> = keys.iter().map(|k| (k.as_str(), "0")).collect();
+        let mut d = build_table_data(&opt, &entries);
+        let older: Vec<(&str, &str)> = keys.iter().map(|k| (k.as_str(), "1")).collect();
+        let mut v = Version::new(opt.clone());
+        v.add_file(1, file(&opt, &older)).unwrap();
+        // Corrupt a data block in the middle of the level-0 file.
+        let level0 = build_table(&opt, &entries);
+        let handle = level0.index_handles()[level0.index_handles().len() / 2].clone();
+        let mut block = level0.read_block(&handle).unwrap().iter();
+        block.advance();
+        let corrupt_key = block.current_key().unwrap().to_vec();
+        d[handle.offset() as usize] ^= 1;
+        let size = d.len() as u64;
+        let level0 = Table::new(opt.clone(), Box::new(d), size).unwrap();
+        v.add_file(
+            0,
+            LevelFile::with_range(level0, b"key000".to_vec(), b"key099".to_vec()),
+        )
+        .unwrap();
+
+        // The merge stops at the error instead of returning the older level's values.
+        let mut iter = v.iter();
+        let mut n = 0;
+        while iter.advance() {
+            assert_eq!(iter.value(), Some(b"0".to_vec()));
+            n += 1;
+        }
+        assert!(n > 0 && n < keys.len());
+        assert!(iter.status().unwrap().is_corruption());
+        assert!(!iter.advance());
+
+        iter.seek(&corrupt_key);
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap().is_corruption());
+        iter.seek(b"key099");
+        assert!(iter.valid());
+        assert!(iter.status().is_none());
+    }
+
+    #[test]
+    fn test_version_iterator_deletions() {
+        use crate::cmp::{DefaultCmp, InternalKeyCmp};
+        use crate::iter_adapters::{DedupUserKeys, DropTombstones};
+        use crate::key_types::{build_internal_key, parse_internal_key, ValueType};
+
+        let ucmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(DefaultCmp));
+        let mut opt = Options::default();
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(ucmp.clone())));
+        let internal_file = |entries: &[(&str, u64, ValueType)]| {
+            let entries: Vec<(Vec<u8>, &str)> = entries
+                .iter()
+                .map(|&(k, seq, t)| (build_internal_key(k.as_bytes(), seq, t), k))
+                .collect();
+            LevelFile::new(build_table(&opt, &entries)).unwrap()
+        };
+        let mut v = Version::new(opt.clone());
+        v.add_file(
+            1,
+            internal_file(&[
+                ("a", 1, ValueType::TypeValue),
+                ("b", 2, ValueType::TypeValue),
+                ("c", 3, ValueType::TypeValue),
+            ]),
+        )
+        .unwrap();
+        v.add_file(
+            0,
+            internal_file(&[
+                ("b", 5, ValueType::TypeDeletion),
+                ("d", 4, ValueType::TypeValue),
+            ]),
+        )
+        .unwrap();
+
+        let mut live = DropTombstones::new(DedupUserKeys::new(v.iter(), ucmp));
+        let keys: Vec<Vec<u8>> = SSIteratorIter::wrap(&mut live)
+            .map(|(k, _)| parse_internal_key(&k).unwrap().2.to_vec())
+            .collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+    }
+}
@@ -0,0 +1,312 @@
+//! A log of the changes to an LSM tree's set of tables, allowing reads as of any point in time.
+//! Every `VersionEdit` adds a table to or removes one from a level at a timestamp. Applications
+//! append the encoded edits to a file of their own as they write and compact tables, and load the
+//! log into a `VersionSet` when reopening; `VersionSet::at()` then returns the `Version` that was
+//! current at a given time, opening its tables through a `TableCache`. Removed tables must be
+//! kept around for as long as reads at earlier times are wanted.
+
+use crate::coding;
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::table_cache::TableCache;
+use crate::version::{LevelFile, Version};
+
+use std::collections::HashSet;
+
+const TAG_ADD_FILE: u8 = 1;
+const TAG_REMOVE_FILE: u8 = 2;
+
+/// A change to the set of tables of a `VersionSet`. Tables are identified by file number, as in a
+/// `TableCache`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionEdit {
+    /// Adds a table with the given key range to a level, as the newest table of level 0.
+    AddFile {
+        timestamp: u64,
+        level: usize,
+        file_no: u64,
+        smallest: Vec<u8>,
+        largest: Vec<u8>,
+    },
+    /// Removes a table from its level.
+    RemoveFile { timestamp: u64, file_no: u64 },
+}
+
+impl VersionEdit {
+    pub fn timestamp(&self) -> u64 {
+        match *self {
+            VersionEdit::AddFile { timestamp, .. } => timestamp,
+            VersionEdit::RemoveFile { timestamp, .. } => timestamp,
+        }
+    }
+
+    /// Appends the edit to `dst`. A log is the concatenation of encoded edits.
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        match self {
+            VersionEdit::AddFile {
+                timestamp,
+                level,
+                file_no,
+                smallest,
+                largest,
+            } => {
+                dst.push(TAG_ADD_FILE);
+                coding::put_varint64(dst, *timestamp);
+                coding::put_varint64(dst, *level as u64);
+                coding::put_varint64(dst, *file_no);
+                for key in [smallest, largest] {
+                    coding::put_varint64(dst, key.len() as u64);
+                    dst.extend_from_slice(key);
+                }
+            }
+            VersionEdit::RemoveFile { timestamp, file_no } => {
+                dst.push(TAG_REMOVE_FILE);
+                coding::put_varint64(dst, *timestamp);
+                coding::put_varint64(dst, *file_no);
+            }
+        }
+    }
+
+    /// Decodes the edit at the start of `src`, returning it and its encoded length.
+    fn decode(src: &[u8]) -> Result<(VersionEdit, usize)> {
+        let mut pos = 1;
+        let edit = match src[0] {
+            TAG_ADD_FILE => VersionEdit::AddFile {
+                timestamp: get_varint(src, &mut pos)?,
+                level: get_varint(src, &mut pos)? as usize,
+                file_no: get_varint(src, &mut pos)?,
+                smallest: get_key(src, &mut pos)?,
+                largest: get_key(src, &mut pos)?,
+            },
+            TAG_REMOVE_FILE => VersionEdit::RemoveFile {
+                timestamp: get_varint(src, &mut pos)?,
+                file_no: get_varint(src, &mut pos)?,
+            },
+            tag => {
+                return Err(Error::malformed(
+                    None,
+                    &format!("unknown version edit tag {}", tag),
+                ))
+            }
+        };
+        Ok((edit, pos))
+    }
+}
+
+fn get_varint(src: &[u8], pos: &mut usize) -> Result<u64> {
+    let (n, len) = coding::get_varint64(&src[*pos..])?;
+    *pos += len;
+    Ok(n)
+}
+
+fn get_key(src: &[u8], pos: &mut usize) -> Result<Vec<u8>> {
+    let len = get_varint(src, pos)? as usize;
+    let key = src
+        .get(*pos..pos.saturating_add(len))
+        .ok_or_else(truncated)?;
+    *pos += len;
+    Ok(key.to_vec())
+}
+
+fn truncated() -> Error {
+    Error::malformed(None, "truncated version edit")
+}
+
+/// A VersionSet holds the edits applied to a set of tables, in order, and reconstructs the set of
+/// tables at any time covered by them.
+pub struct VersionSet {
+    opt: Options,
+    edits: Vec<VersionEdit>,
+    // The file numbers of the tables currently in the set.
+    live: HashSet<u64>,
+}
+
+impl VersionSet {
+    /// Returns an empty VersionSet whose versions order keys by `opt.cmp`.
+    pub fn new(opt: Options) -> VersionSet {
+        VersionSet {
+            opt,
+            edits: vec![],
+            live: HashSet::new(),
+        }
+    }
+
+    /// Loads a log of edits written by `VersionEdit::encode()`.
+    pub fn decode(opt: Options, mut log: &[u8]) -> Result<VersionSet> {
+        let mut set = VersionSet::new(opt);
+        while !log.is_empty() {
+            let (edit, len) = VersionEdit::decode(log)?;
+            set.apply(edit)?;
+            log = &log[len..];
+        }
+        Ok(set)
+    }
+
+    /// Applies `edit`, failing if its timestamp precedes the one of the last edit, if it adds a
+    /// table that is already in the set or if it removes a table that isn't.
+    pub fn apply(&mut self, edit: VersionEdit) -> Result<()> {
+        if let Some(last) = self.edits.last() {
+            if edit.timestamp() < last.timestamp() {
+                return Err(Error::InvalidArgument(format!(
+                    "version edit at {} after edit at {}",
+                    edit.timestamp(),
+                    last.timestamp()
+                )));
+            }
+        }
+        match edit {
+            VersionEdit::AddFile { file_no, .. } => {
+                if !self.live.insert(file_no) {
+                    return Err(Error::InvalidArgument(format!(
+                        "table {} added twice",
+                        file_no
+                    )));
+                }
+            }
+            VersionEdit::RemoveFile { file_no, .. } => {
+                if !self.live.remove(&file_no) {
+                    return Err(Error::InvalidArgument(format!(
+                        "table {} removed, but not present",
+                        file_no
+                    )));
+                }
+            }
+        }
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Returns the edits applied so far, in order.
+    pub fn edits(&self) -> &[VersionEdit] {
+        &self.edits
+    }
+
+    /// Returns the version of the set at `timestamp`, i.e. after all edits at or before it, with
+    /// its tables opened through `tables`.
+    pub fn at(&self, timestamp: u64, tables: &TableCache) -> Result<Version> {
+        // The edits adding the tables present at `timestamp`, in order.
+        let mut added: Vec<&VersionEdit> = vec![];
+        for edit in self.edits.iter().take_while(|e| e.timestamp() <= timestamp) {
+            match edit {
+                VersionEdit::AddFile { .. } => added.push(edit),
+                VersionEdit::RemoveFile { file_no, .. } => added.retain(
+                    |e| !matches!(e, VersionEdit::AddFile { file_no: f, .. } if f == file_no),
+                ),
+            }
+        }
+
+        let mut version = Version::new(self.opt.clone());
+        for edit in added {
+            if let VersionEdit::AddFile {
+                level,
+                file_no,
+                smallest,
+                largest,
+                ..
+            } = edit
+            {
+                let table = tables.get_table(*file_no)?;
+                let file = LevelFile::with_range(table, smallest.clone(), largest.clone());
+                version.add_file(*level, file)?;
+            }
+        }
+        Ok(version)
+    }
+
+    /// Returns the version of the set after all edits.
+    pub fn current(&self, tables: &TableCache) -> Result<Version> {
+        self.at(u64::MAX, tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_builder::TableBuilder;
+    use crate::table_reader::Table;
+
+    fn add(timestamp: u64, level: usize, file_no: u64, range: (&str, &str)) -> VersionEdit {
+        VersionEdit::AddFile {
+            timestamp,
+            level,
+            file_no,
+            smallest: range.0.as_bytes().to_vec(),
+            largest: range.1.as_bytes().to_vec(),
+        }
+    }
+
+    fn remove(timestamp: u64, file_no: u64) -> VersionEdit {
+        VersionEdit::RemoveFile { timestamp, file_no }
+    }
+
+    // Table `n` maps the keys "a" to "d" to "<key><n>", except for table 3, which holds the merged
+    // contents of tables 1 and 2.
+    fn tables() -> TableCache {
+        TableCache::new(Options::default(), 10, |file_no, opt| {
+            let mut d = vec![];
+            {
+                let mut b = TableBuilder::new(opt.clone(), &mut d);
+                for k in ["a", "b", "c", "d"] {
+                    let n = if file_no == 3 { 2 } else { file_no };
+                    b.add(k.as_bytes(), format!("{}{}", k, n).as_bytes())?;
+                }
+                b.finish()?;
+            }
+            let size = d.len() as u64;
+            Table::new(opt, Box::new(d), size)
+        })
+    }
+
+    #[test]
+    fn test_version_set_at() {
+        let edits = vec![
+            add(10, 0, 1, ("a", "d")),
+            add(20, 0, 2, ("a", "d")),
+            // Compaction of tables 1 and 2 into 3.
+            add(30, 1, 3, ("a", "d")),
+            remove(30, 1),
+            remove(30, 2),
+            add(40, 0, 4, ("a", "d")),
+        ];
+        let mut log = vec![];
+        for e in edits.iter() {
+            e.encode(&mut log);
+        }
+        let set = VersionSet::decode(Options::default(), &log).unwrap();
+        assert_eq!(set.edits(), edits.as_slice());
+
+        let tables = tables();
+        let get = |timestamp| {
+            let v = set.at(timestamp, &tables).unwrap();
+            let files: Vec<usize> = (0..v.num_levels()).map(|l| v.files(l).len()).collect();
+            let val = v.get(b"b").unwrap().map(|v| String::from_utf8(v).unwrap());
+            (files, val)
+        };
+        assert_eq!(get(5), (vec![], None));
+        assert_eq!(get(10), (vec![1], Some("b1".to_string())));
+        assert_eq!(get(29), (vec![2], Some("b2".to_string())));
+        assert_eq!(get(30), (vec![0, 1], Some("b2".to_string())));
+        assert_eq!(
+            set.current(&tables).unwrap().get(b"d").unwrap(),
+            Some(b"d4".to_vec())
+        );
+
+        assert!(
+            VersionSet::decode(Options::default(), &log[..log.len() - 1])
+                .err()
+                .unwrap()
+                .is_corruption()
+        );
+    }
+
+    #[test]
+    fn test_version_set_apply_errors() {
+        let mut set = VersionSet::new(Options::default());
+        set.apply(add(10, 0, 1, ("a", "b"))).unwrap();
+        assert!(set.apply(add(5, 0, 2, ("a", "b"))).is_err());
+        assert!(set.apply(add(10, 1, 1, ("a", "b"))).is_err());
+        assert!(set.apply(remove(11, 2)).is_err());
+        set.apply(remove(11, 1)).unwrap();
+        assert_eq!(set.edits().len(), 2);
+    }
+}
@@ -0,0 +1,48 @@
+//! Reads the golden fixtures in `tests/golden`: tables written by C++ LevelDB, next to listings of
+//! their entries as LevelDB reads them (see `tests/golden/generate.cc`).
+
+use sstable::testutil::{check_golden_fixture, parse_entries};
+use sstable::{Options, Table};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> (PathBuf, PathBuf) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    (
+        dir.join(format!("{}.ldb", name)),
+        dir.join(format!("{}.txt", name)),
+    )
+}
+
+#[test]
+fn test_golden_leveldb_fixtures() {
+    for name in &["leveldb_snappy_bloom", "leveldb_uncompressed"] {
+        let (table, listing) = fixture(name);
+        check_golden_fixture(Options::default(), &table, &listing)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+    }
+}
+
+#[test]
+fn test_golden_leveldb_lookups() {
+    for name in &["leveldb_snappy_bloom", "leveldb_uncompressed"] {
+        let (path, listing) = fixture(name);
+        let entries = parse_entries(&fs::read_to_string(listing).unwrap()).unwrap();
+        let table = Table::new_from_file(Options::default(), &path).unwrap();
+        for (k, v) in &entries {
+            assert_eq!(table.get(k).unwrap().as_ref(), Some(v), "{}", name);
+        }
+    }
+
+    // The fixture's LevelDB bloom filter rules out most missing keys.
+    let (path, _) = fixture("leveldb_snappy_bloom");
+    let table = Table::new_from_file(Options::default(), &path).unwrap();
+    assert!(table.filter_block_handle().is_some());
+    let missing = (0..1500)
+        .filter(|i| i % 3 != 0)
+        .filter(|i| table.key_may_exist(format!("key{:05}", i).as_bytes()))
+        .count();
+    assert!(missing < 50, "{} false positives", missing);
+    assert_eq!(table.get(b"key00001").unwrap(), None);
+}
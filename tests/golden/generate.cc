@@ -0,0 +1,116 @@
+// Writes the golden fixture tables in this directory using LevelDB's TableBuilder, and lists their
+// entries as read back by LevelDB's Table (see `testutil::check_golden_fixture()`).
+//
+// Built against LevelDB 1.22 (with snappy 1.1.7) and run from this directory:
+//
+//   g++ -std=c++11 -I$LEVELDB/include generate.cc $LEVELDB/build/libleveldb.a -lsnappy \
+//       -lpthread -o generate && mkdir -p out && ./generate && mv out/* . && rmdir out
+#include <cstdio>
+#include <memory>
+#include <string>
+#include <vector>
+
+#include "leveldb/env.h"
+#include "leveldb/filter_policy.h"
+#include "leveldb/iterator.h"
+#include "leveldb/options.h"
+#include "leveldb/table.h"
+#include "leveldb/table_builder.h"
+
+using namespace leveldb;
+
+static std::string Hex(const Slice& s) {
+  static const char* digits = "0123456789abcdef";
+  std::string out;
+  for (size_t i = 0; i < s.size(); i++) {
+    unsigned char c = s[i];
+    out.push_back(digits[c >> 4]);
+    out.push_back(digits[c & 15]);
+  }
+  return out;
+}
+
+static void Check(const Status& s) {
+  if (!s.ok()) {
+    fprintf(stderr, "%s\n", s.ToString().c_str());
+    exit(1);
+  }
+}
+
+static void Write(const std::string& name, const Options& opt,
+                  const std::vector<std::pair<std::string, std::string>>& entries,
+                  const char* description) {
+  Env* env = Env::Default();
+  std::string path = "out/" + name + ".ldb";
+  WritableFile* file;
+  Check(env->NewWritableFile(path, &file));
+  TableBuilder b(opt, file);
+  for (auto& e : entries) b.Add(e.first, e.second);
+  Check(b.Finish());
+  Check(file->Close());
+  delete file;
+
+  uint64_t size;
+  Check(env->GetFileSize(path, &size));
+  RandomAccessFile* rfile;
+  Check(env->NewRandomAccessFile(path, &rfile));
+  Table* table;
+  Check(Table::Open(opt, rfile, size, &table));
+  FILE* listing = fopen(("out/" + name + ".txt").c_str(), "w");
+  fprintf(listing, "# %s\n# Written by LevelDB 1.22's TableBuilder, listed by its Table.\n",
+          description);
+  std::unique_ptr<Iterator> it(table->NewIterator(ReadOptions()));
+  size_t n = 0;
+  for (it->SeekToFirst(); it->Valid(); it->Next(), n++) {
+    fprintf(listing, "%s %s\n", Hex(it->key()).c_str(), Hex(it->value()).c_str());
+  }
+  Check(it->status());
+  fclose(listing);
+  if (n != entries.size()) {
+    fprintf(stderr, "%s: listed %zu of %zu entries\n", name.c_str(), n, entries.size());
+    exit(1);
+  }
+  it.reset();
+  delete table;
+  delete rfile;
+}
+
+int main() {
+  std::vector<std::pair<std::string, std::string>> entries;
+  char buf[64];
+  for (int i = 0; i < 500; i++) {
+    snprintf(buf, sizeof(buf), "key%05d", i * 3);
+    std::string value;
+    for (int j = 0; j <= i % 7; j++) {
+      snprintf(buf + 32, 32, "value%d.", i);
+      value += buf + 32;
+    }
+    entries.emplace_back(std::string(buf, 8), value);
+  }
+
+  Options snappy;
+  snappy.block_size = 256;
+  snappy.block_restart_interval = 4;
+  snappy.compression = kSnappyCompression;
+  std::unique_ptr<const FilterPolicy> bloom(NewBloomFilterPolicy(10));
+  snappy.filter_policy = bloom.get();
+  Write("leveldb_snappy_bloom", snappy, entries,
+        "500 entries, snappy compression, 10 bits per key bloom filter, 256 byte blocks, "
+        "restart interval 4.");
+
+  // Binary keys sharing long prefixes, and empty values.
+  std::vector<std::pair<std::string, std::string>> binary;
+  binary.emplace_back(std::string(1, '\0'), "zero byte key");
+  for (int i = 0; i < 256; i++) {
+    std::string key = std::string(40, 'p') + std::string(1, static_cast<char>(i)) +
+                      std::string(1, '\0');
+    binary.emplace_back(key, i % 2 ? std::string(i, static_cast<char>(i)) : std::string());
+  }
+  binary.emplace_back(std::string(3, '\xff'), std::string(5000, 'v'));
+  Options none;
+  none.compression = kNoCompression;
+  Write("leveldb_uncompressed", none, binary,
+        "258 entries with binary keys, empty values and a value larger than a block; no "
+        "compression, no filter, default block size and restart interval.");
+  return 0;
+}